@@ -0,0 +1,118 @@
+//! Benchmarks for each stage of the pipeline: lexing, parsing, and running
+//! a parsed program on every backend built in.
+//!
+//! `cargo bench` runs them all, and `cargo bench -- run` only the `run`
+//! group. Add `--features jit` to include the JIT in that group. Each backend
+//! is timed from a parsed program to a finished run, so its own compilation
+//! counts: bytecode for the VM, native code for the JIT. The report for the
+//! group puts the backends side by side for each program.
+//!
+//! To measure a change, save a baseline before it and compare against it
+//! after:
+//!
+//! ```text
+//! cargo bench -- --save-baseline before
+//! cargo bench -- --baseline before
+//! ```
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use ferrum::{bytecode, Arena, Interpreter, Lexer, Parser, Vm};
+use std::hint::black_box;
+use std::io;
+
+const RECURSION: &str = include_str!("programs/recursion.lang");
+const LOOPS: &str = include_str!("programs/loops.lang");
+const DEMO: &str = include_str!("../examples/demo.lang");
+
+/// `demo.lang` repeated until it is at least `bytes` long: a long stream
+/// of everyday tokens.
+fn long_source(bytes: usize) -> String {
+    DEMO.repeat(bytes.div_ceil(DEMO.len()))
+}
+
+/// A program binding one expression, a complete binary tree of `+` and `-`
+/// that is `depth` operators deep, and ending with its value.
+fn expression_tree(depth: u32) -> String {
+    fn tree(depth: u32, leaf: &mut u32, out: &mut String) {
+        if depth == 0 {
+            *leaf += 1;
+            out.push_str(&(*leaf % 10).to_string());
+            return;
+        }
+        out.push('(');
+        tree(depth - 1, leaf, out);
+        out.push_str(if depth.is_multiple_of(2) { " + " } else { " - " });
+        tree(depth - 1, leaf, out);
+        out.push(')');
+    }
+    let mut out = String::from("let value = ");
+    tree(depth, &mut 0, &mut out);
+    out.push_str(";\nvalue;\n");
+    out
+}
+
+fn inputs() -> Vec<(&'static str, String)> {
+    vec![
+        ("recursion", RECURSION.to_string()),
+        ("loops", LOOPS.to_string()),
+        ("long_tokens", long_source(256 * 1024)),
+        ("expression_tree", expression_tree(12)),
+    ]
+}
+
+fn lex(c: &mut Criterion) {
+    let mut group = c.benchmark_group("lex");
+    for (name, source) in inputs() {
+        group.throughput(Throughput::Bytes(source.len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(name), &source, |b, source| {
+            b.iter(|| Lexer::new(source).tokenize().expect("the input lexes"));
+        });
+    }
+    group.finish();
+}
+
+/// Parsing pulls tokens from the lexer as it goes, so this includes lexing.
+fn parse(c: &mut Criterion) {
+    let mut group = c.benchmark_group("parse");
+    for (name, source) in inputs() {
+        group.throughput(Throughput::Bytes(source.len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(name), &source, |b, source| {
+            b.iter(|| {
+                let arena = Arena::new();
+                let program = Parser::from_lexer(Lexer::new(source), &arena).parse_program().expect("the input parses");
+                black_box(program.len())
+            });
+        });
+    }
+    group.finish();
+}
+
+fn run(c: &mut Criterion) {
+    let mut group = c.benchmark_group("run");
+    let programs = [("recursion", RECURSION.to_string()), ("loops", LOOPS.to_string()), ("expression_tree", expression_tree(12))];
+    for (name, source) in &programs {
+        let arena = Arena::new();
+        let program = ferrum::compile_str(source, &arena).expect("the program parses");
+        group.bench_with_input(BenchmarkId::new("interpreter", name), &program, |b, program| {
+            b.iter(|| Interpreter::new().with_output(io::sink()).interpret(program).expect("the program runs"));
+        });
+        group.bench_with_input(BenchmarkId::new("vm", name), &program, |b, program| {
+            b.iter(|| {
+                let module = bytecode::compile(program).expect("the program compiles");
+                Vm::new().with_output(io::sink()).run(&module).expect("the program runs");
+            });
+        });
+        #[cfg(feature = "jit")]
+        {
+            let mut checker = ferrum::TypeChecker::new();
+            checker.check_program(&program).expect("the program type checks");
+            group.bench_with_input(BenchmarkId::new("jit", name), &program, |b, program| {
+                b.iter(|| ferrum::jit::run(program, &checker).expect("the program runs"));
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, lex, parse, run);
+criterion_main!(benches);
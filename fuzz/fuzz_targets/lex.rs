@@ -0,0 +1,12 @@
+//! Lexes arbitrary text, with and without error recovery. Any input may be
+//! rejected, but none may panic.
+
+#![no_main]
+
+use ferrum::Lexer;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|source: &str| {
+    let _ = Lexer::new(source).tokenize();
+    let _ = Lexer::new(source).with_recovery().tokenize();
+});
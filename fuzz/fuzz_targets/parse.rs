@@ -0,0 +1,16 @@
+//! Parses arbitrary text, both straight from the lexer and from tokens
+//! collected first, as the driver does with `--emit tokens`. Any input may
+//! be rejected, but none may panic.
+
+#![no_main]
+
+use ferrum::{Arena, Lexer, Parser};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|source: &str| {
+    let arena = Arena::new();
+    let _ = Parser::from_lexer(Lexer::new(source).with_recovery(), &arena).parse_program();
+    if let Ok(tokens) = Lexer::new(source).with_recovery().tokenize() {
+        let _ = Parser::new(tokens, &arena).parse_program();
+    }
+});
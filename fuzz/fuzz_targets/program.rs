@@ -0,0 +1,299 @@
+//! Builds programs out of the fuzzer's input, writes them as source and
+//! parses and type checks them. Nothing may panic or overflow the stack,
+//! and a program that parses must print as source that parses back to the
+//! same program. Number literals are as long as the input makes them, so
+//! many overflow `i64`, and chains of operators can be long enough to pass
+//! the parser's nesting limit.
+
+#![no_main]
+
+use arbitrary::Arbitrary;
+use ferrum::{printer, Arena, Lexer, Parser, TypeChecker};
+use libfuzzer_sys::fuzz_target;
+use std::fmt::{self, Display, Formatter};
+
+#[derive(Arbitrary, Debug)]
+struct Program(Vec<Item>);
+
+#[derive(Arbitrary, Debug)]
+enum Item {
+    Fn(Name, Vec<Name>, Vec<Stmt>),
+    Stmt(Stmt),
+}
+
+#[derive(Arbitrary, Debug)]
+enum Stmt {
+    Let(Name, Expr),
+    Assign(Name, Expr),
+    IndexAssign(Name, Expr, Expr),
+    Expr(Expr),
+    If(Expr, Vec<Stmt>, Vec<Stmt>),
+    While(Expr, Vec<Stmt>),
+    ForRange(Name, Expr, Expr, Vec<Stmt>),
+    Return(Option<Expr>),
+    Break,
+    Continue,
+}
+
+#[derive(Arbitrary, Debug)]
+enum Expr {
+    Number(Digits),
+    Float(u32, u32),
+    Str(String),
+    Char(char),
+    Bool(bool),
+    Null,
+    Variable(Name),
+    Neg(Box<Expr>),
+    BitNot(Box<Expr>),
+    Binary(Box<Expr>, Op, Box<Expr>),
+    Call(Name, Vec<Expr>),
+    Array(Vec<Expr>),
+    Index(Box<Expr>, Box<Expr>),
+    /// The variable repeated with the operator between, without
+    /// parentheses.
+    Chain(Name, Op, Length),
+    /// `[0]` after the variable as many times as the length says.
+    IndexChain(Name, Length),
+    If(Box<Expr>, Box<Expr>, Box<Expr>),
+}
+
+/// How many times a chain repeats, up to 1000.
+#[derive(Arbitrary, Debug)]
+struct Length(u16);
+
+/// The digits of an integer literal, each byte taken modulo 10.
+#[derive(Arbitrary, Debug)]
+struct Digits(Vec<u8>);
+
+#[derive(Arbitrary, Debug)]
+enum Name {
+    A,
+    B,
+    Count,
+    Total,
+}
+
+#[derive(Arbitrary, Debug)]
+enum Op {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Pow,
+    Lt,
+    Gt,
+    Le,
+    Ge,
+    Eq,
+    Neq,
+    And,
+    Or,
+    BitAnd,
+    BitOr,
+    BitXor,
+    Shl,
+    Shr,
+}
+
+fn list<T: Display>(f: &mut Formatter, items: &[T], separator: &str) -> fmt::Result {
+    for (i, item) in items.iter().enumerate() {
+        if i > 0 {
+            f.write_str(separator)?;
+        }
+        write!(f, "{}", item)?;
+    }
+    Ok(())
+}
+
+fn block(f: &mut Formatter, stmts: &[Stmt]) -> fmt::Result {
+    f.write_str("{ ")?;
+    list(f, stmts, " ")?;
+    f.write_str(" }")
+}
+
+/// Writes `c` as it appears inside a literal that ends at `delimiter`.
+fn escaped(f: &mut Formatter, c: char, delimiter: char) -> fmt::Result {
+    match c {
+        '\\' => f.write_str("\\\\"),
+        '\n' => f.write_str("\\n"),
+        '\t' => f.write_str("\\t"),
+        '\r' => f.write_str("\\r"),
+        '\0' => f.write_str("\\0"),
+        c if c == delimiter => write!(f, "\\{}", c),
+        c => write!(f, "{}", c),
+    }
+}
+
+impl Display for Program {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        list(f, &self.0, "\n")
+    }
+}
+
+impl Display for Item {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Item::Fn(name, params, body) => {
+                write!(f, "fn {}(", name)?;
+                list(f, params, ", ")?;
+                f.write_str(") ")?;
+                block(f, body)
+            }
+            Item::Stmt(stmt) => write!(f, "{}", stmt),
+        }
+    }
+}
+
+impl Display for Stmt {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Stmt::Let(name, value) => write!(f, "let {} = {};", name, value),
+            Stmt::Assign(name, value) => write!(f, "{} = {};", name, value),
+            Stmt::IndexAssign(name, index, value) => write!(f, "{}[{}] = {};", name, index, value),
+            // Parenthesized, so that an `if` expression is not read as an
+            // `if` statement.
+            Stmt::Expr(expr) => write!(f, "({});", expr),
+            Stmt::If(cond, then_block, else_block) => {
+                write!(f, "if ({}) ", cond)?;
+                block(f, then_block)?;
+                f.write_str(" else ")?;
+                block(f, else_block)
+            }
+            Stmt::While(cond, body) => {
+                write!(f, "while ({}) ", cond)?;
+                block(f, body)
+            }
+            Stmt::ForRange(var, start, end, body) => {
+                write!(f, "for {} in ({})..({}) ", var, start, end)?;
+                block(f, body)
+            }
+            Stmt::Return(None) => f.write_str("return;"),
+            Stmt::Return(Some(value)) => write!(f, "return {};", value),
+            Stmt::Break => f.write_str("break;"),
+            Stmt::Continue => f.write_str("continue;"),
+        }
+    }
+}
+
+impl Display for Expr {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Expr::Number(digits) => write!(f, "{}", digits),
+            Expr::Float(whole, fraction) => write!(f, "{}.{}", whole, fraction),
+            Expr::Str(text) => {
+                f.write_str("\"")?;
+                for c in text.chars() {
+                    escaped(f, c, '"')?;
+                }
+                f.write_str("\"")
+            }
+            Expr::Char(c) => {
+                f.write_str("'")?;
+                escaped(f, *c, '\'')?;
+                f.write_str("'")
+            }
+            Expr::Bool(value) => write!(f, "{}", value),
+            Expr::Null => f.write_str("null"),
+            Expr::Variable(name) => write!(f, "{}", name),
+            Expr::Neg(operand) => write!(f, "(-{})", operand),
+            Expr::BitNot(operand) => write!(f, "(~{})", operand),
+            Expr::Binary(lhs, op, rhs) => write!(f, "({} {} {})", lhs, op, rhs),
+            Expr::Call(name, args) => {
+                write!(f, "{}(", name)?;
+                list(f, args, ", ")?;
+                f.write_str(")")
+            }
+            Expr::Array(elements) => {
+                f.write_str("[")?;
+                list(f, elements, ", ")?;
+                f.write_str("]")
+            }
+            Expr::Index(array, index) => write!(f, "({})[{}]", array, index),
+            Expr::Chain(name, op, length) => {
+                write!(f, "({}", name)?;
+                for _ in 0..length.get() {
+                    write!(f, " {} {}", op, name)?;
+                }
+                f.write_str(")")
+            }
+            Expr::IndexChain(name, length) => {
+                write!(f, "{}", name)?;
+                for _ in 0..length.get() {
+                    f.write_str("[0]")?;
+                }
+                Ok(())
+            }
+            Expr::If(cond, then_value, else_value) => write!(f, "(if ({}) {{ {} }} else {{ {} }})", cond, then_value, else_value),
+        }
+    }
+}
+
+impl Length {
+    fn get(&self) -> u16 {
+        self.0 % 1001
+    }
+}
+
+impl Display for Digits {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        if self.0.is_empty() {
+            return f.write_str("0");
+        }
+        for digit in &self.0 {
+            write!(f, "{}", digit % 10)?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for Name {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Name::A => "a",
+            Name::B => "b",
+            Name::Count => "count",
+            Name::Total => "total",
+        })
+    }
+}
+
+impl Display for Op {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Op::Add => "+",
+            Op::Sub => "-",
+            Op::Mul => "*",
+            Op::Div => "/",
+            Op::Pow => "**",
+            Op::Lt => "<",
+            Op::Gt => ">",
+            Op::Le => "<=",
+            Op::Ge => ">=",
+            Op::Eq => "==",
+            Op::Neq => "!=",
+            Op::And => "&&",
+            Op::Or => "||",
+            Op::BitAnd => "&",
+            Op::BitOr => "|",
+            Op::BitXor => "^",
+            Op::Shl => "<<",
+            Op::Shr => ">>",
+        })
+    }
+}
+
+fuzz_target!(|program: Program| {
+    let source = program.to_string();
+    let arena = Arena::new();
+    let Ok(parsed) = Parser::from_lexer(Lexer::new(&source), &arena).parse_program() else {
+        return;
+    };
+    let _ = TypeChecker::new().check_program(&parsed);
+    let printed = printer::print_program(&parsed);
+    let reparsed = match Parser::from_lexer(Lexer::new(&printed), &arena).parse_program() {
+        Ok(reparsed) => reparsed,
+        Err(errors) => panic!("the printed program does not parse: {:?}\n{}", errors, printed),
+    };
+    assert_eq!(printed, printer::print_program(&reparsed), "printing is not stable for\n{}", source);
+});
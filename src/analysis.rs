@@ -0,0 +1,554 @@
+//! Control-flow analysis of the AST.
+//!
+//! [`Cfg::build`] turns a function body into a control-flow graph with one
+//! node per statement, and [`check_program`] uses the graphs of the top-level
+//! code and of every function to find code that can never run. It also
+//! reports variables and functions that are never used.
+
+use crate::ast::*;
+use crate::error::Diagnostic;
+use crate::span::Span;
+use crate::symbol::Symbol;
+use std::collections::HashSet;
+
+/// A statement, or the evaluation of a loop condition or `for` step.
+#[derive(Debug, Clone)]
+pub struct Node {
+    pub span: Span,
+    /// The nodes control may continue to after this one.
+    pub successors: Vec<usize>,
+    /// The node of the statement directly before this one in the same block.
+    pub previous: Option<usize>,
+    /// The node of the statement this one is nested in, within the same function.
+    pub parent: Option<usize>,
+    /// Whether the node is a statement rather than part of a loop's header.
+    pub is_statement: bool,
+}
+
+/// The control-flow graph of one function body or of the top-level code.
+/// Node 0 is where execution starts and [`Cfg::EXIT`] is reached when the
+/// body returns or falls off its end. Nested function declarations are
+/// single nodes; their bodies get graphs of their own.
+#[derive(Debug, Clone)]
+pub struct Cfg {
+    pub nodes: Vec<Node>,
+}
+
+/// Where `break` and `continue` go in the innermost enclosing loop.
+struct LoopTargets {
+    exit: usize,
+    next: usize,
+}
+
+struct Builder {
+    nodes: Vec<Node>,
+    loops: Vec<LoopTargets>,
+    parents: Vec<usize>,
+}
+
+impl Cfg {
+    pub const EXIT: usize = 1;
+
+    pub fn build(body: &[Stmt]) -> Cfg {
+        let mut builder = Builder { nodes: Vec::new(), loops: Vec::new(), parents: Vec::new() };
+        let entry = builder.node(Span::default(), None, false);
+        let exit = builder.node(Span::default(), None, false);
+        let first = builder.stmts(body, exit);
+        builder.nodes[entry].successors.push(first);
+        Cfg { nodes: builder.nodes }
+    }
+
+    /// Which nodes execution can reach, by node index.
+    pub fn reachable(&self) -> Vec<bool> {
+        let mut reached = vec![false; self.nodes.len()];
+        let mut pending = vec![0];
+        while let Some(node) = pending.pop() {
+            if !std::mem::replace(&mut reached[node], true) {
+                pending.extend(&self.nodes[node].successors);
+            }
+        }
+        reached
+    }
+}
+
+impl Builder {
+    fn node(&mut self, span: Span, previous: Option<usize>, is_statement: bool) -> usize {
+        let parent = self.parents.last().copied();
+        self.nodes.push(Node { span, successors: Vec::new(), previous, parent, is_statement });
+        self.nodes.len() - 1
+    }
+
+    /// Adds the nodes for `stmts`, which continue to `next` when they
+    /// finish, and returns the node control enters them through.
+    fn stmts(&mut self, stmts: &[Stmt], next: usize) -> usize {
+        // Every node is created before its successors are known, so control
+        // flow is wired once the whole list has nodes.
+        let mut previous = None;
+        let nodes: Vec<usize> = stmts
+            .iter()
+            .map(|stmt| {
+                let node = self.node(stmt.span, previous, true);
+                previous = Some(node);
+                node
+            })
+            .collect();
+        for (i, stmt) in stmts.iter().enumerate() {
+            let after = nodes.get(i + 1).copied().unwrap_or(next);
+            self.stmt(stmt, nodes[i], after);
+        }
+        nodes.first().copied().unwrap_or(next)
+    }
+
+    fn nested(&mut self, parent: usize, stmts: &[Stmt], next: usize) -> usize {
+        self.parents.push(parent);
+        let entry = self.stmts(stmts, next);
+        self.parents.pop();
+        entry
+    }
+
+    fn stmt(&mut self, stmt: &Stmt, node: usize, next: usize) {
+        let successors = match &stmt.node {
+            StmtKind::Let(..)
+            | StmtKind::Const(..)
+            | StmtKind::Assign(..)
+            | StmtKind::IndexAssign(..)
+            | StmtKind::FieldAssign(..)
+            | StmtKind::Expr(_)
+            | StmtKind::FnDecl(..)
+            | StmtKind::StructDecl(..)
+            | StmtKind::EnumDecl(..)
+            | StmtKind::TypeAlias(..)
+            | StmtKind::Import(..) => vec![next],
+            StmtKind::If(_, then_block, else_block) => {
+                vec![self.nested(node, then_block, next), self.nested(node, else_block, next)]
+            }
+            StmtKind::Block(stmts) => vec![self.nested(node, stmts, next)],
+            StmtKind::Match(_, arms) => {
+                // Arms after a wildcard never run.
+                let mut successors = Vec::new();
+                let mut exhaustive = false;
+                for arm in arms {
+                    let entry = self.nested(node, arm.body, next);
+                    if !exhaustive {
+                        successors.push(entry);
+                        exhaustive = matches!(arm.pattern, Pattern::Wildcard);
+                    }
+                }
+                if !exhaustive {
+                    successors.push(next);
+                }
+                successors
+            }
+            StmtKind::While(cond, body) => {
+                // The statement's own node is the condition check.
+                let body_entry = self.loop_body(node, body, next, node);
+                self.condition(cond, body_entry, next)
+            }
+            StmtKind::For(_, cond, step, body) => {
+                // The statement's node runs the initializer.
+                self.parents.push(node);
+                let header = self.node(cond.span, None, false);
+                let step_node = self.node(step.span, None, false);
+                self.parents.pop();
+                self.nodes[step_node].successors.push(header);
+                let body_entry = self.loop_body(node, body, next, step_node);
+                self.nodes[header].successors = self.condition(cond, body_entry, next);
+                vec![header]
+            }
+            StmtKind::ForRange(_, _, end, _, body) => {
+                // The statement's node evaluates the bounds; the header
+                // decides whether another iteration follows.
+                self.parents.push(node);
+                let header = self.node(end.span, None, false);
+                self.parents.pop();
+                let body_entry = self.loop_body(node, body, next, header);
+                self.nodes[header].successors = vec![body_entry, next];
+                vec![header]
+            }
+            StmtKind::DoWhile(body, cond) => {
+                self.parents.push(node);
+                let check = self.node(cond.span, None, false);
+                self.parents.pop();
+                let body_entry = self.loop_body(node, body, next, check);
+                self.nodes[check].successors = match constant_bool(cond) {
+                    Some(true) => vec![body_entry],
+                    Some(false) => vec![next],
+                    None => vec![body_entry, next],
+                };
+                vec![body_entry]
+            }
+            StmtKind::Return(_) => vec![Cfg::EXIT],
+            StmtKind::Break => self.loops.last().map(|targets| targets.exit).into_iter().collect(),
+            StmtKind::Continue => self.loops.last().map(|targets| targets.next).into_iter().collect(),
+        };
+        self.nodes[node].successors.extend(successors);
+    }
+
+    /// Adds the nodes for the body of the loop at `node`, which continues to
+    /// `repeat`, and returns the body's entry.
+    fn loop_body(&mut self, node: usize, body: &[Stmt], exit: usize, repeat: usize) -> usize {
+        self.loops.push(LoopTargets { exit, next: repeat });
+        let entry = self.nested(node, body, repeat);
+        self.loops.pop();
+        entry
+    }
+
+    /// Successors of a `while` or `for` condition. A condition that is always
+    /// true never leaves the loop. One that is always false is treated as
+    /// unknown: `check_program` reports the loop itself, not its body.
+    fn condition(&self, cond: &Expr, body: usize, exit: usize) -> Vec<usize> {
+        match constant_bool(cond) {
+            Some(true) => vec![body],
+            _ => vec![body, exit],
+        }
+    }
+}
+
+/// Whether evaluating `expr` can change anything: it calls a function or
+/// updates a variable with `++`/`--`.
+fn has_effect(expr: &Expr) -> bool {
+    match &expr.node {
+        ExprKind::Call(..) | ExprKind::Update(..) => true,
+        ExprKind::Number(_) | ExprKind::Float(_) | ExprKind::Str(_) | ExprKind::Char(_) | ExprKind::Bool(_) | ExprKind::Null | ExprKind::Variant(..) | ExprKind::Variable(_) => false,
+        ExprKind::Unary(_, operand) => has_effect(operand),
+        ExprKind::Binary(lhs, _, rhs) | ExprKind::Index(lhs, rhs) => has_effect(lhs) || has_effect(rhs),
+        ExprKind::Array(elements) => elements.iter().any(has_effect),
+        ExprKind::Map(entries) => entries.iter().any(|(key, value)| has_effect(key) || has_effect(value)),
+        ExprKind::StructLit(_, fields) => fields.iter().any(|(_, value)| has_effect(value)),
+        ExprKind::Field(object, _) => has_effect(object),
+        ExprKind::If(cond, then_value, else_value) => has_effect(cond) || has_effect(then_value) || has_effect(else_value),
+        ExprKind::Match(value, arms) => has_effect(value) || arms.iter().any(|arm| has_effect(&arm.value)),
+        // Its statements may do anything.
+        ExprKind::Block(..) => true,
+    }
+}
+
+/// The value of a condition built only from literals, if it has one.
+fn constant_bool(expr: &Expr) -> Option<bool> {
+    match &expr.node {
+        ExprKind::Bool(b) => Some(*b),
+        ExprKind::Binary(lhs, op, rhs) => match (op, constant_bool(lhs), constant_bool(rhs)) {
+            (BinOp::And, Some(false), _) => Some(false),
+            (BinOp::And, Some(true), r) => r,
+            (BinOp::Or, Some(true), _) => Some(true),
+            (BinOp::Or, Some(false), r) => r,
+            (BinOp::Eq, Some(l), Some(r)) => Some(l == r),
+            (BinOp::Neq, Some(l), Some(r)) => Some(l != r),
+            _ => {
+                let (ExprKind::Number(l), ExprKind::Number(r)) = (&lhs.node, &rhs.node) else {
+                    return None;
+                };
+                match op {
+                    BinOp::Gt => Some(l > r),
+                    BinOp::Lt => Some(l < r),
+                    BinOp::Ge => Some(l >= r),
+                    BinOp::Le => Some(l <= r),
+                    BinOp::Eq => Some(l == r),
+                    BinOp::Neq => Some(l != r),
+                    _ => None,
+                }
+            }
+        },
+        _ => None,
+    }
+}
+
+/// Warns about code that can never run: statements after a `return`,
+/// `break` or `continue`, after a loop that never ends or in a `match` arm
+/// after a `_` arm, and `while`/`for` loops whose condition is always false. Only the first statement of each
+/// unreachable stretch is reported. Also warns about `for` steps that change
+/// nothing, and about `let` bindings that are never read and functions that
+/// are never called, except for names starting with `_`. The warnings are in
+/// source order.
+pub fn check_program(program: &[Stmt]) -> Vec<Diagnostic> {
+    let mut warnings = Vec::new();
+    check_body(program, &mut warnings);
+    warnings.extend(check_unused(program));
+    warnings.sort_by_key(|warning| warning.span.map(|span| span.start));
+    warnings
+}
+
+fn check_body(body: &[Stmt], warnings: &mut Vec<Diagnostic>) {
+    let cfg = Cfg::build(body);
+    let reachable = cfg.reachable();
+    for (i, node) in cfg.nodes.iter().enumerate().skip(Cfg::EXIT + 1) {
+        let shown = |other: Option<usize>| other.is_none_or(|other| reachable[other]);
+        if node.is_statement && !reachable[i] && shown(node.previous) && shown(node.parent) {
+            warnings.push(Diagnostic::warning("W0001", "Unreachable statement".to_string(), Some(node.span)));
+        }
+    }
+    visit(body, &mut |stmt| match &stmt.node {
+        StmtKind::While(cond, _) | StmtKind::For(_, cond, ..) if constant_bool(cond) == Some(false) => {
+            warnings.push(Diagnostic::warning(
+                "W0002",
+                "Loop condition is always false, so the body never runs".to_string(),
+                Some(cond.span),
+            ));
+        }
+        // Steps used to be expressions whose value was assigned back to
+        // the loop variable; written that way now, they would loop forever.
+        StmtKind::For(_, _, step, _) if matches!(&step.node, StmtKind::Expr(expr) if !has_effect(expr)) => {
+            warnings.push(Diagnostic::warning(
+                "W0005",
+                "Loop step has no effect; assign the new value, as in `i = i + 1`".to_string(),
+                Some(step.span),
+            ));
+        }
+        StmtKind::FnDecl(_, _, _, body) => check_body(body, warnings),
+        _ => {}
+    });
+}
+
+/// Calls `f` on every statement in `stmts` and in blocks nested in them,
+/// but not inside function declarations.
+fn visit(stmts: &[Stmt], f: &mut impl FnMut(&Stmt)) {
+    for stmt in stmts {
+        f(stmt);
+        match &stmt.node {
+            StmtKind::If(_, then_block, else_block) => {
+                visit(then_block, f);
+                visit(else_block, f);
+            }
+            StmtKind::Match(_, arms) => {
+                for arm in arms {
+                    visit(arm.body, f);
+                }
+            }
+            StmtKind::While(_, body)
+            | StmtKind::DoWhile(body, _)
+            | StmtKind::For(.., body)
+            | StmtKind::ForRange(.., body)
+            | StmtKind::Block(body) => visit(body, f),
+            _ => {}
+        }
+    }
+}
+
+/// A `let` binding, parameter or loop variable seen by [`Usage`].
+struct Binding {
+    name: Symbol,
+    span: Span,
+    /// Parameters and loop variables are tracked for shadowing only.
+    reported: bool,
+    used: bool,
+}
+
+/// Resolves every variable read and function call in a program.
+struct Usage {
+    bindings: Vec<Binding>,
+    /// Block scopes of the body being walked, as indices into `bindings`,
+    /// innermost last. In the top-level code the outermost scope holds the
+    /// globals; in a function it holds the parameters.
+    scopes: Vec<Vec<usize>>,
+    globals: Vec<usize>,
+    /// Names read inside functions without a local binding there. Functions
+    /// may run at any point, so these count as reads of the globals.
+    free: HashSet<Symbol>,
+    functions: Vec<(Symbol, Span)>,
+    /// Names of called functions, except calls a function makes to itself.
+    called: HashSet<Symbol>,
+    current_function: Option<Symbol>,
+}
+
+fn check_unused(program: &[Stmt]) -> Vec<Diagnostic> {
+    let mut usage = Usage {
+        bindings: Vec::new(),
+        scopes: vec![Vec::new()],
+        globals: Vec::new(),
+        free: HashSet::new(),
+        functions: Vec::new(),
+        called: HashSet::new(),
+        current_function: None,
+    };
+    usage.stmts(program);
+    for &global in &usage.globals {
+        if usage.free.contains(&usage.bindings[global].name) {
+            usage.bindings[global].used = true;
+        }
+    }
+
+    let mut warnings = Vec::new();
+    for binding in &usage.bindings {
+        if binding.reported && !binding.used && !binding.name.starts_with('_') {
+            let warning = Diagnostic::warning("W0003", format!("Unused variable: {}", binding.name), Some(binding.span));
+            warnings.push(warning.with_note(format!("if this is intentional, prefix it with an underscore: _{}", binding.name)));
+        }
+    }
+    for (name, span) in &usage.functions {
+        // `ferrum test` calls the `test_` functions.
+        if !usage.called.contains(name) && !name.starts_with('_') && !name.starts_with("test_") {
+            let warning = Diagnostic::warning("W0004", format!("Unused function: {}", name), Some(*span));
+            warnings.push(warning.with_note(format!("if this is intentional, prefix it with an underscore: _{}", name)));
+        }
+    }
+    warnings
+}
+
+impl Usage {
+    fn bind(&mut self, name: Symbol, span: Span, reported: bool) {
+        self.bindings.push(Binding { name, span, reported, used: false });
+        let index = self.bindings.len() - 1;
+        if self.current_function.is_none() && self.scopes.len() == 1 {
+            self.globals.push(index);
+        }
+        self.scopes.last_mut().expect("a body always has a scope").push(index);
+    }
+
+    fn read(&mut self, name: Symbol) {
+        let found = self.scopes.iter().rev().flat_map(|scope| scope.iter().rev()).find(|&&i| self.bindings[i].name == name);
+        match found {
+            Some(&i) => self.bindings[i].used = true,
+            None if self.current_function.is_some() => {
+                self.free.insert(name);
+            }
+            None => {}
+        }
+    }
+
+    fn block(&mut self, stmts: &[Stmt]) {
+        self.scopes.push(Vec::new());
+        self.stmts(stmts);
+        self.scopes.pop();
+    }
+
+    fn stmts(&mut self, stmts: &[Stmt]) {
+        for stmt in stmts {
+            self.stmt(stmt);
+        }
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) {
+        match &stmt.node {
+            StmtKind::Let(name, _, expr) | StmtKind::Const(name, _, expr) => {
+                self.expr(expr);
+                self.bind(*name, stmt.span, true);
+            }
+            // Writing a variable does not use it.
+            StmtKind::Assign(_, expr) => self.expr(expr),
+            StmtKind::IndexAssign(array, index, value) => {
+                self.expr(array);
+                self.expr(index);
+                self.expr(value);
+            }
+            StmtKind::FieldAssign(object, _, value) => {
+                self.expr(object);
+                self.expr(value);
+            }
+            StmtKind::StructDecl(..) | StmtKind::EnumDecl(..) | StmtKind::TypeAlias(..) | StmtKind::Import(..) => {}
+            StmtKind::Expr(expr) => self.expr(expr),
+            StmtKind::If(cond, then_block, else_block) => {
+                self.expr(cond);
+                self.block(then_block);
+                self.block(else_block);
+            }
+            StmtKind::While(cond, body) => {
+                self.expr(cond);
+                self.block(body);
+            }
+            StmtKind::DoWhile(body, cond) => {
+                self.block(body);
+                self.expr(cond);
+            }
+            StmtKind::Match(value, arms) => {
+                self.expr(value);
+                for arm in arms {
+                    self.block(arm.body);
+                }
+            }
+            StmtKind::For(init, cond, step, body) => {
+                self.scopes.push(Vec::new());
+                self.stmt(init);
+                self.expr(cond);
+                self.block(body);
+                self.stmt(step);
+                self.scopes.pop();
+            }
+            StmtKind::ForRange(var, start, end, _, body) => {
+                self.expr(start);
+                self.expr(end);
+                self.scopes.push(Vec::new());
+                self.bind(*var, stmt.span, false);
+                self.block(body);
+                self.scopes.pop();
+            }
+            StmtKind::FnDecl(name, params, _, body) => {
+                self.functions.push((*name, stmt.span));
+                // A function sees its parameters and the globals, not the
+                // locals around its declaration.
+                let outer_scopes = std::mem::replace(&mut self.scopes, vec![Vec::new()]);
+                let outer_function = self.current_function.replace(*name);
+                for param in params {
+                    self.bind(param.name, stmt.span, false);
+                }
+                self.stmts(body);
+                self.scopes = outer_scopes;
+                self.current_function = outer_function;
+            }
+            StmtKind::Return(expr) => {
+                if let Some(expr) = expr {
+                    self.expr(expr);
+                }
+            }
+            StmtKind::Break | StmtKind::Continue => {}
+            StmtKind::Block(stmts) => self.block(stmts),
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr) {
+        match &expr.node {
+            ExprKind::Number(_) | ExprKind::Float(_) | ExprKind::Str(_) | ExprKind::Char(_) | ExprKind::Bool(_) | ExprKind::Null | ExprKind::Variant(..) => {}
+            ExprKind::Variable(name) | ExprKind::Update(name, ..) => self.read(*name),
+            ExprKind::Unary(_, operand) => self.expr(operand),
+            ExprKind::Binary(lhs, _, rhs) => {
+                self.expr(lhs);
+                self.expr(rhs);
+            }
+            ExprKind::Call(name, args) => {
+                if self.current_function.as_ref() != Some(name) {
+                    self.called.insert(*name);
+                }
+                for arg in args.iter() {
+                    self.expr(arg);
+                }
+            }
+            ExprKind::Array(elements) => {
+                for element in elements.iter() {
+                    self.expr(element);
+                }
+            }
+            ExprKind::Map(entries) => {
+                for (key, value) in entries {
+                    self.expr(key);
+                    self.expr(value);
+                }
+            }
+            ExprKind::Index(array, index) => {
+                self.expr(array);
+                self.expr(index);
+            }
+            ExprKind::StructLit(_, fields) => {
+                for (_, value) in fields {
+                    self.expr(value);
+                }
+            }
+            ExprKind::Field(object, _) => self.expr(object),
+            ExprKind::If(cond, then_value, else_value) => {
+                self.expr(cond);
+                self.expr(then_value);
+                self.expr(else_value);
+            }
+            ExprKind::Match(value, arms) => {
+                self.expr(value);
+                for arm in arms {
+                    self.expr(&arm.value);
+                }
+            }
+            ExprKind::Block(stmts, value) => {
+                self.scopes.push(Vec::new());
+                self.stmts(stmts);
+                self.expr(value);
+                self.scopes.pop();
+            }
+        }
+    }
+}
\ No newline at end of file
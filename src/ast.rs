@@ -1,36 +1,269 @@
+use crate::span::Span;
+use crate::symbol::Symbol;
+
+/// An AST node together with the source region it was parsed from.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Spanned<T> {
+    pub node: T,
+    pub span: Span,
+}
+
+impl<T> Spanned<T> {
+    pub fn new(node: T, span: Span) -> Self {
+        Self { node, span }
+    }
+}
+
+pub type Stmt<'a> = Spanned<StmtKind<'a>>;
+pub type Expr<'a> = Spanned<ExprKind<'a>>;
+
+/// Where the parser allocates statements and expressions. A program's
+/// nodes sit side by side in a few large chunks instead of one heap
+/// allocation each, and a node refers to its children with plain
+/// references that live as long as the arena. Keep the arena alive for as
+/// long as anything uses the program, such as an [`crate::Interpreter`]
+/// that has declared its functions.
+#[derive(Default)]
+pub struct Arena<'a> {
+    exprs: typed_arena::Arena<Expr<'a>>,
+    stmts: typed_arena::Arena<Stmt<'a>>,
+}
+
+impl<'a> Arena<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn expr(&'a self, expr: Expr<'a>) -> &'a Expr<'a> {
+        self.exprs.alloc(expr)
+    }
+
+    /// Allocates `exprs` next to each other, as the arguments of a call or
+    /// the elements of an array literal. They are built beforehand, since
+    /// the arena cannot allocate their children while it copies them in.
+    pub fn exprs(&'a self, exprs: Vec<Expr<'a>>) -> &'a [Expr<'a>] {
+        self.exprs.alloc_extend(exprs)
+    }
+
+    pub fn stmt(&'a self, stmt: Stmt<'a>) -> &'a Stmt<'a> {
+        self.stmts.alloc(stmt)
+    }
+
+    /// Allocates `stmts` next to each other, as a block.
+    pub fn stmts(&'a self, stmts: Vec<Stmt<'a>>) -> &'a [Stmt<'a>] {
+        self.stmts.alloc_extend(stmts)
+    }
+}
+
 #[allow(dead_code)]
 #[derive(Debug, Clone)]
-pub enum Stmt {
-    Let(String, Expr),
-    Assign(String, Expr),
-    Expr(Expr),
-    If(Expr, Vec<Stmt>, Vec<Stmt>),      // condition, then-block, else-block
-    While(Expr, Vec<Stmt>),               // condition, body
-    DoWhile(Vec<Stmt>, Expr),             // body, condition
-    For(String, Expr, Expr, Expr, Vec<Stmt>), // var, start, cond, step, body
-    FnDecl(String, Vec<String>, Vec<Stmt>),   // name, params, body
-    Return(Expr),
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum StmtKind<'a> {
+    Let(Symbol, Option<TypeExpr>, Expr<'a>),  // name, annotation, initializer
+    Const(Symbol, Option<TypeExpr>, Expr<'a>), // as `Let`, for a variable that is never reassigned
+    Assign(Symbol, Expr<'a>),
+    IndexAssign(&'a Expr<'a>, &'a Expr<'a>, Expr<'a>), // array, index, value
+    FieldAssign(&'a Expr<'a>, Symbol, Expr<'a>), // struct, field, value
+    Expr(Expr<'a>),
+    If(Expr<'a>, &'a [Stmt<'a>], &'a [Stmt<'a>]), // condition, then-block, else-block
+    While(Expr<'a>, &'a [Stmt<'a>]),      // condition, body
+    DoWhile(&'a [Stmt<'a>], Expr<'a>),    // body, condition
+    For(&'a Stmt<'a>, Expr<'a>, &'a Stmt<'a>, &'a [Stmt<'a>]), // init, condition, step, body
+    ForRange(Symbol, Expr<'a>, Expr<'a>, bool, &'a [Stmt<'a>]), // variable, start, end, inclusive, body
+    Match(Expr<'a>, Vec<MatchArm<'a>>),   // value, arms
+    FnDecl(Symbol, Vec<Param>, Option<TypeExpr>, &'a [Stmt<'a>]), // name, params, return type, body
+    StructDecl(Symbol, Vec<Field>),       // name, fields
+    EnumDecl(Symbol, Vec<Symbol>),        // name, variants
+    TypeAlias(Symbol, TypeExpr),          // name, the type it stands for
+    Import(String),                       // path of the file, relative to the importing one
+    Return(Option<Expr<'a>>),             // a function body's final value, if unterminated, spans just that value
+    Break,
+    Continue,
+    Block(&'a [Stmt<'a>]),
+}
+
+/// A function parameter and its optional type annotation.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Param {
+    pub name: Symbol,
+    pub ty: Option<TypeExpr>,
+}
+
+/// A field of a struct declaration.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Field {
+    pub name: Symbol,
+    pub ty: TypeExpr,
+}
+
+/// One `pattern => { ... }` arm of a `match`.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct MatchArm<'a> {
+    pub pattern: Pattern<'a>,
+    /// Where the pattern was written.
+    pub span: Span,
+    pub body: &'a [Stmt<'a>],
+}
+
+/// One `pattern => value` arm of a `match` used as a value.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct ValueArm<'a> {
+    pub pattern: Pattern<'a>,
+    /// Where the pattern was written.
+    pub span: Span,
+    /// A `{ ... }` arm is an `ExprKind::Block`, or its value alone if it
+    /// has no statements.
+    pub value: Expr<'a>,
+}
+
+/// What a `match` arm compares the value with.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum Pattern<'a> {
+    /// A number, string, character or bool literal, possibly negated; the
+    /// arm runs if the value equals it.
+    Literal(Expr<'a>),
+    /// `_`, which matches anything.
+    Wildcard,
+}
+
+/// A type as written in the source, such as `int`, `[string]`,
+/// `{string: int}` or `int?`. The type checker resolves the names; the
+/// parser accepts any identifier.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum TypeExpr {
+    Named(Symbol),
+    Array(Box<TypeExpr>),
+    Map(Box<TypeExpr>, Box<TypeExpr>), // key, value
+    /// `T?`: a `T` or `null`.
+    Option(Box<TypeExpr>),
+}
+
+impl std::fmt::Display for TypeExpr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TypeExpr::Named(name) => write!(f, "{}", name),
+            TypeExpr::Array(element) => write!(f, "[{}]", element),
+            TypeExpr::Map(key, value) => write!(f, "{{{}: {}}}", key, value),
+            TypeExpr::Option(inner) => write!(f, "{}?", inner),
+        }
+    }
 }
 
 #[allow(dead_code)]
 #[derive(Debug, Clone)]
-pub enum Expr {
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum ExprKind<'a> {
     Number(i64),
+    Float(f64),
+    Str(String),
+    Char(char),
     Bool(bool),
-    Variable(String),
-    Binary(Box<Expr>, BinOp, Box<Expr>),
-    Call(String, Vec<Expr>),
+    Null,
+    Variable(Symbol),
+    Unary(UnOp, &'a Expr<'a>),
+    Update(Symbol, UpdateOp, bool),       // variable, ++ or --, is prefix
+    Binary(&'a Expr<'a>, BinOp, &'a Expr<'a>),
+    Call(Symbol, &'a [Expr<'a>]),
+    Array(&'a [Expr<'a>]),
+    Map(Vec<(Expr<'a>, Expr<'a>)>),       // keys and values in source order
+    Index(&'a Expr<'a>, &'a Expr<'a>),    // array or map, index or key
+    If(&'a Expr<'a>, &'a Expr<'a>, &'a Expr<'a>), // condition, then-value, else-value
+    Block(&'a [Stmt<'a>], &'a Expr<'a>),  // statements, final value
+    Match(&'a Expr<'a>, Vec<ValueArm<'a>>), // value, arms
+    StructLit(Symbol, Vec<(Symbol, Expr<'a>)>), // struct name, field values in source order
+    Field(&'a Expr<'a>, Symbol),          // struct, field
+    Variant(Symbol, Symbol),              // enum, variant
 }
 
 #[allow(dead_code)]
 #[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum BinOp {
     Add,
     Sub,
     Mul,
     Div,
+    /// `**`, which binds tighter than unary minus and groups to the right.
+    Pow,
     Gt,      // Changed from Greater to Gt to match parser usage
     Lt,      // Changed from Less to Lt
     Eq,      // Changed from Equal to Eq
     Neq,     // Changed from NotEqual to Neq
+    Ge,
+    Le,
+    And,
+    Or,
+    BitAnd,
+    BitOr,
+    BitXor,
+    Shl,
+    Shr,
+}
+
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum UnOp {
+    Neg,
+    /// `~`, which flips every bit of an int.
+    BitNot,
+}
+
+/// `++` and `--`. The prefix form evaluates to the updated value, the
+/// postfix form to the value before the update.
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum UpdateOp {
+    Inc,
+    Dec,
+}
+
+impl std::fmt::Display for UpdateOp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UpdateOp::Inc => write!(f, "++"),
+            UpdateOp::Dec => write!(f, "--"),
+        }
+    }
+}
+
+impl std::fmt::Display for UnOp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UnOp::Neg => write!(f, "-"),
+            UnOp::BitNot => write!(f, "~"),
+        }
+    }
+}
+
+impl std::fmt::Display for BinOp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let symbol = match self {
+            BinOp::Add => "+",
+            BinOp::Sub => "-",
+            BinOp::Mul => "*",
+            BinOp::Div => "/",
+            BinOp::Pow => "**",
+            BinOp::Gt => ">",
+            BinOp::Lt => "<",
+            BinOp::Eq => "==",
+            BinOp::Neq => "!=",
+            BinOp::Ge => ">=",
+            BinOp::Le => "<=",
+            BinOp::And => "&&",
+            BinOp::Or => "||",
+            BinOp::BitAnd => "&",
+            BinOp::BitOr => "|",
+            BinOp::BitXor => "^",
+            BinOp::Shl => "<<",
+            BinOp::Shr => ">>",
+        };
+        write!(f, "{}", symbol)
+    }
 }
\ No newline at end of file
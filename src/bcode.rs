@@ -0,0 +1,440 @@
+//! The `.bcode` file format: a compiled [`Module`] on disk, so a program can
+//! be compiled once and run many times.
+//!
+//! All integers are little-endian; counts, indices and span fields are `u32`.
+//!
+//! ```text
+//! header     "FRBC" magic, u16 format version
+//! constants  u32 count, then per constant a u8 tag and its payload:
+//!            0 int (i64), 1 float (f64 bits), 2 bool (u8), 3 string,
+//!            4 null (no payload), 5 enum variant (enum name, variant name),
+//!            6 char (u32 code point)
+//! globals    u32 count, then each name as a string
+//! structs    u32 count, then per struct its name, u32 field count and
+//!            each field name
+//! main       u32 index into the function table
+//! functions  u32 count, then per function: name, u32 arity, u32 locals,
+//!            u32 instruction count, each instruction as a u8 opcode and
+//!            its operands, then one span (start, end, line, col, file)
+//!            per instruction
+//! ```
+//!
+//! Strings are a `u32` byte length followed by UTF-8.
+
+use crate::ast::{BinOp, UpdateOp};
+use crate::builtins::BUILTINS;
+use crate::bytecode::{Function, Instr, Module, StructLayout};
+use crate::error::CompilerError;
+use crate::span::Span;
+use crate::symbol::Symbol;
+use crate::type_checker::Type;
+use crate::value::Value;
+
+pub const MAGIC: &[u8; 4] = b"FRBC";
+
+/// Bumped whenever the layout or the instruction set changes; files with a
+/// different version are rejected rather than misread.
+pub const VERSION: u16 = 13;
+
+const BIN_OPS: [BinOp; 18] = [
+    BinOp::Add,
+    BinOp::Sub,
+    BinOp::Mul,
+    BinOp::Div,
+    BinOp::Gt,
+    BinOp::Lt,
+    BinOp::Eq,
+    BinOp::Neq,
+    BinOp::Ge,
+    BinOp::Le,
+    BinOp::And,
+    BinOp::Or,
+    BinOp::Pow,
+    BinOp::BitAnd,
+    BinOp::BitOr,
+    BinOp::BitXor,
+    BinOp::Shl,
+    BinOp::Shr,
+];
+
+fn bin_op_code(op: BinOp) -> u8 {
+    match op {
+        BinOp::Add => 0,
+        BinOp::Sub => 1,
+        BinOp::Mul => 2,
+        BinOp::Div => 3,
+        BinOp::Gt => 4,
+        BinOp::Lt => 5,
+        BinOp::Eq => 6,
+        BinOp::Neq => 7,
+        BinOp::Ge => 8,
+        BinOp::Le => 9,
+        BinOp::And => 10,
+        BinOp::Or => 11,
+        BinOp::Pow => 12,
+        BinOp::BitAnd => 13,
+        BinOp::BitOr => 14,
+        BinOp::BitXor => 15,
+        BinOp::Shl => 16,
+        BinOp::Shr => 17,
+    }
+}
+
+/// Serializes `module` in the `.bcode` format.
+pub fn encode(module: &Module) -> Vec<u8> {
+    let mut w = Writer { out: Vec::new() };
+    w.out.extend_from_slice(MAGIC);
+    w.out.extend_from_slice(&VERSION.to_le_bytes());
+
+    w.len(module.constants.len());
+    for constant in &module.constants {
+        match constant {
+            Value::Int(n) => {
+                w.out.push(0);
+                w.out.extend_from_slice(&n.to_le_bytes());
+            }
+            Value::Float(n) => {
+                w.out.push(1);
+                w.out.extend_from_slice(&n.to_bits().to_le_bytes());
+            }
+            Value::Bool(b) => {
+                w.out.push(2);
+                w.out.push(*b as u8);
+            }
+            Value::Str(s) => {
+                w.out.push(3);
+                w.str(s);
+            }
+            Value::Null => w.out.push(4),
+            Value::Enum(name, variant) => {
+                w.out.push(5);
+                w.str(name);
+                w.str(variant);
+            }
+            Value::Char(c) => {
+                w.out.push(6);
+                w.out.extend_from_slice(&u32::from(*c).to_le_bytes());
+            }
+            Value::Array(_) | Value::Struct(_) | Value::Map(_) => unreachable!("arrays, structs and maps are built at runtime, never pooled"),
+        }
+    }
+
+    w.len(module.globals.len());
+    for name in &module.globals {
+        w.str(name);
+    }
+    w.len(module.structs.len());
+    for layout in &module.structs {
+        w.str(&layout.name);
+        w.len(layout.fields.len());
+        for field in &layout.fields {
+            w.str(field);
+        }
+    }
+    w.len(module.main);
+
+    w.len(module.functions.len());
+    for function in &module.functions {
+        w.str(&function.name);
+        w.len(function.arity);
+        w.len(function.locals);
+        w.len(function.code.len());
+        for instr in &function.code {
+            w.instr(instr);
+        }
+        for span in &function.spans {
+            for field in [span.start, span.end, span.line, span.col, span.file] {
+                w.len(field);
+            }
+        }
+    }
+    w.out
+}
+
+/// Loads a module written by [`encode`]. Malformed files are reported as
+/// syntax errors. Every index an instruction carries is checked against the
+/// module, every call against the arity of what it calls, and every
+/// instruction that can run against the depth of the stack, so a damaged
+/// file is rejected here instead of crashing the VM.
+pub fn decode(bytes: &[u8]) -> Result<Module, CompilerError> {
+    let mut r = Reader { bytes, pos: 0 };
+    if r.take(4)? != MAGIC {
+        return Err(invalid("not a bytecode file"));
+    }
+    let version = u16::from_le_bytes(r.array()?);
+    if version != VERSION {
+        return Err(invalid(&format!("unsupported format version {} (expected {})", version, VERSION)));
+    }
+
+    let mut constants = Vec::new();
+    for _ in 0..r.len()? {
+        constants.push(match r.byte()? {
+            0 => Value::Int(i64::from_le_bytes(r.array()?)),
+            1 => Value::Float(f64::from_bits(u64::from_le_bytes(r.array()?))),
+            2 => Value::Bool(r.byte()? != 0),
+            3 => Value::Str(r.str()?),
+            4 => Value::Null,
+            5 => Value::Enum(r.symbol()?, r.symbol()?),
+            6 => Value::Char(char::from_u32(u32::from_le_bytes(r.array()?)).ok_or_else(|| invalid("char constant is not a Unicode scalar value"))?),
+            tag => return Err(invalid(&format!("unknown constant tag {}", tag))),
+        });
+    }
+
+    let mut globals = Vec::new();
+    for _ in 0..r.len()? {
+        globals.push(r.str()?);
+    }
+    let mut structs = Vec::new();
+    for _ in 0..r.len()? {
+        let name = r.symbol()?;
+        let mut fields = Vec::new();
+        for _ in 0..r.len()? {
+            fields.push(r.symbol()?);
+        }
+        structs.push(StructLayout { name, fields });
+    }
+    let main = r.len()?;
+
+    let mut functions = Vec::new();
+    for _ in 0..r.len()? {
+        let name = r.str()?;
+        let arity = r.len()?;
+        let locals = r.len()?;
+        let mut code = Vec::new();
+        for _ in 0..r.len()? {
+            code.push(r.instr()?);
+        }
+        let mut spans = Vec::new();
+        for _ in 0..code.len() {
+            spans.push(Span { start: r.len()?, end: r.len()?, line: r.len()?, col: r.len()?, file: r.len()? });
+        }
+        if locals < arity {
+            return Err(invalid(&format!("function {} has fewer locals than parameters", name)));
+        }
+        functions.push(Function { name, arity, locals, code, spans });
+    }
+    if r.pos != bytes.len() {
+        return Err(invalid("trailing bytes after the function table"));
+    }
+
+    let module = Module { constants, functions, globals, structs, main };
+    validate(&module)?;
+    Ok(module)
+}
+
+fn invalid(msg: &str) -> CompilerError {
+    CompilerError::SyntaxError(format!("Invalid bytecode file: {}", msg), None)
+}
+
+fn validate(module: &Module) -> Result<(), CompilerError> {
+    if module.main >= module.functions.len() {
+        return Err(invalid("main function index out of range"));
+    }
+    for function in &module.functions {
+        let in_range = |instr: &Instr| match *instr {
+            Instr::Const(i) => i < module.constants.len(),
+            Instr::Load(slot) | Instr::Store(slot) => slot < function.locals,
+            Instr::LoadGlobal(i) | Instr::StoreGlobal(i) | Instr::DefineGlobal(i) => i < module.globals.len(),
+            Instr::Jump(target) | Instr::JumpIfFalse(target) => target < function.code.len(),
+            Instr::Call(f, _) => f < module.functions.len(),
+            Instr::CallBuiltin(b, _) => b < BUILTINS.len(),
+            Instr::MakeStruct(s) => s < module.structs.len(),
+            Instr::GetField(i) | Instr::SetField(i) => matches!(module.constants.get(i), Some(Value::Str(_))),
+            _ => true,
+        };
+        if let Some(instr) = function.code.iter().find(|instr| !in_range(instr)) {
+            return Err(invalid(&format!("operand out of range in {}: {:?}", function.name, instr)));
+        }
+        if !matches!(function.code.last(), Some(Instr::Return | Instr::Jump(_))) {
+            return Err(invalid(&format!("function {} does not end in a return", function.name)));
+        }
+        let arity_matches = |instr: &Instr| match *instr {
+            Instr::Call(f, argc) => module.functions[f].arity == argc,
+            // A builtin's check accepts the arguments it takes when their
+            // types are unknown.
+            Instr::CallBuiltin(b, argc) => (BUILTINS[b].check)(&vec![Type::Error; argc]).is_ok(),
+            _ => true,
+        };
+        if let Some(instr) = function.code.iter().find(|instr| !arity_matches(instr)) {
+            return Err(invalid(&format!("wrong number of arguments in {}: {:?}", function.name, instr)));
+        }
+        check_stack(module, function)?;
+    }
+    Ok(())
+}
+
+/// Follows every path through `function` from its first instruction and
+/// checks that no instruction on one pops more values than the function
+/// has pushed, and that all paths to an instruction leave the stack equally
+/// deep. Code no path reaches is not checked, as it never runs.
+fn check_stack(module: &Module, function: &Function) -> Result<(), CompilerError> {
+    let mut depths = vec![None; function.code.len()];
+    let mut pending = vec![(0, 0)];
+    while let Some((at, depth)) = pending.pop() {
+        match depths[at] {
+            Some(seen) if seen == depth => continue,
+            Some(_) => return Err(invalid(&format!("paths to instruction {} of {} leave the stack at different depths", at, function.name))),
+            None => depths[at] = Some(depth),
+        }
+        let instr = &function.code[at];
+        let (pops, pushes) = stack_effect(module, instr);
+        if pops > depth {
+            return Err(invalid(&format!("stack underflow at instruction {} of {}: {:?}", at, function.name, instr)));
+        }
+        let depth = depth - pops + pushes;
+        // The last instruction is a return or a jump, so `at + 1` is in range.
+        match *instr {
+            Instr::Return => {}
+            Instr::Jump(target) => pending.push((target, depth)),
+            Instr::JumpIfFalse(target) => pending.extend([(target, depth), (at + 1, depth)]),
+            _ => pending.push((at + 1, depth)),
+        }
+    }
+    Ok(())
+}
+
+/// How many values `instr` pops, and how many it pushes after that.
+fn stack_effect(module: &Module, instr: &Instr) -> (usize, usize) {
+    match *instr {
+        Instr::Const(_) | Instr::Load(_) | Instr::LoadGlobal(_) => (0, 1),
+        Instr::Pop | Instr::Store(_) | Instr::StoreGlobal(_) | Instr::DefineGlobal(_) | Instr::JumpIfFalse(_) | Instr::Return => (1, 0),
+        Instr::Dup => (1, 2),
+        Instr::Neg | Instr::BitNot | Instr::Update(_) | Instr::GetField(_) => (1, 1),
+        Instr::Binary(_) | Instr::Index => (2, 1),
+        Instr::SetField(_) => (2, 0),
+        Instr::StoreIndex => (3, 0),
+        Instr::Jump(_) => (0, 0),
+        Instr::Call(_, n) | Instr::CallBuiltin(_, n) | Instr::MakeArray(n) => (n, 1),
+        Instr::MakeMap(pairs) => (pairs.saturating_mul(2), 1),
+        Instr::MakeStruct(s) => (module.structs[s].fields.len(), 1),
+    }
+}
+
+struct Writer {
+    out: Vec<u8>,
+}
+
+impl Writer {
+    fn len(&mut self, n: usize) {
+        let n = u32::try_from(n).expect("bytecode counts and indices fit in 32 bits");
+        self.out.extend_from_slice(&n.to_le_bytes());
+    }
+
+    fn str(&mut self, s: &str) {
+        self.len(s.len());
+        self.out.extend_from_slice(s.as_bytes());
+    }
+
+    fn instr(&mut self, instr: &Instr) {
+        match instr {
+            Instr::Const(i) => self.op(0, &[*i]),
+            Instr::Pop => self.op(1, &[]),
+            Instr::Dup => self.op(2, &[]),
+            Instr::Load(slot) => self.op(3, &[*slot]),
+            Instr::Store(slot) => self.op(4, &[*slot]),
+            Instr::LoadGlobal(i) => self.op(5, &[*i]),
+            Instr::StoreGlobal(i) => self.op(6, &[*i]),
+            Instr::DefineGlobal(i) => self.op(7, &[*i]),
+            Instr::Binary(op) => {
+                self.out.push(8);
+                self.out.push(bin_op_code(*op));
+            }
+            Instr::Neg => self.op(9, &[]),
+            Instr::Update(op) => {
+                self.out.push(10);
+                self.out.push(matches!(op, UpdateOp::Dec) as u8);
+            }
+            Instr::Jump(target) => self.op(11, &[*target]),
+            Instr::JumpIfFalse(target) => self.op(12, &[*target]),
+            Instr::Call(f, argc) => self.op(13, &[*f, *argc]),
+            Instr::CallBuiltin(b, argc) => self.op(14, &[*b, *argc]),
+            Instr::Return => self.op(15, &[]),
+            Instr::MakeArray(n) => self.op(16, &[*n]),
+            Instr::Index => self.op(17, &[]),
+            Instr::StoreIndex => self.op(18, &[]),
+            Instr::MakeStruct(s) => self.op(19, &[*s]),
+            Instr::GetField(i) => self.op(20, &[*i]),
+            Instr::SetField(i) => self.op(21, &[*i]),
+            Instr::MakeMap(n) => self.op(22, &[*n]),
+            Instr::BitNot => self.op(23, &[]),
+        }
+    }
+
+    fn op(&mut self, opcode: u8, operands: &[usize]) {
+        self.out.push(opcode);
+        for &operand in operands {
+            self.len(operand);
+        }
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl Reader<'_> {
+    fn take(&mut self, n: usize) -> Result<&[u8], CompilerError> {
+        let end = self.pos.checked_add(n).filter(|&end| end <= self.bytes.len());
+        let end = end.ok_or_else(|| invalid("unexpected end of file"))?;
+        let bytes = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], CompilerError> {
+        Ok(self.take(N)?.try_into().expect("take returns exactly N bytes"))
+    }
+
+    fn byte(&mut self) -> Result<u8, CompilerError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn len(&mut self) -> Result<usize, CompilerError> {
+        Ok(u32::from_le_bytes(self.array()?) as usize)
+    }
+
+    fn str(&mut self) -> Result<String, CompilerError> {
+        let len = self.len()?;
+        let bytes = self.take(len)?.to_vec();
+        String::from_utf8(bytes).map_err(|_| invalid("string is not valid UTF-8"))
+    }
+
+    fn symbol(&mut self) -> Result<Symbol, CompilerError> {
+        Ok(Symbol::intern(&self.str()?))
+    }
+
+    fn instr(&mut self) -> Result<Instr, CompilerError> {
+        Ok(match self.byte()? {
+            0 => Instr::Const(self.len()?),
+            1 => Instr::Pop,
+            2 => Instr::Dup,
+            3 => Instr::Load(self.len()?),
+            4 => Instr::Store(self.len()?),
+            5 => Instr::LoadGlobal(self.len()?),
+            6 => Instr::StoreGlobal(self.len()?),
+            7 => Instr::DefineGlobal(self.len()?),
+            8 => {
+                let code = self.byte()?;
+                let op = BIN_OPS.get(code as usize).ok_or_else(|| invalid(&format!("unknown operator {}", code)))?;
+                Instr::Binary(*op)
+            }
+            9 => Instr::Neg,
+            10 => Instr::Update(if self.byte()? == 0 { UpdateOp::Inc } else { UpdateOp::Dec }),
+            11 => Instr::Jump(self.len()?),
+            12 => Instr::JumpIfFalse(self.len()?),
+            13 => Instr::Call(self.len()?, self.len()?),
+            14 => Instr::CallBuiltin(self.len()?, self.len()?),
+            15 => Instr::Return,
+            16 => Instr::MakeArray(self.len()?),
+            17 => Instr::Index,
+            18 => Instr::StoreIndex,
+            19 => Instr::MakeStruct(self.len()?),
+            20 => Instr::GetField(self.len()?),
+            21 => Instr::SetField(self.len()?),
+            22 => Instr::MakeMap(self.len()?),
+            23 => Instr::BitNot,
+            opcode => return Err(invalid(&format!("unknown opcode {}", opcode))),
+        })
+    }
+}
\ No newline at end of file
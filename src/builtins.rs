@@ -0,0 +1,768 @@
+use crate::ops;
+use crate::type_checker::Type;
+use crate::value::Value;
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::rc::Rc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The program's standard streams, shared by every execution backend.
+pub struct Io {
+    /// Where `print` and `println` write.
+    pub(crate) output: Box<dyn Write>,
+    /// Where `read_int` and `read_line` read from; `None` reads standard
+    /// input through its shared buffer, so other readers of stdin (such as
+    /// the REPL) do not lose lines to a second buffer.
+    pub(crate) input: Option<Box<dyn BufRead>>,
+    /// State of the generator behind `random`, seeded from the clock unless
+    /// the embedder picks a seed.
+    pub(crate) random: u64,
+}
+
+impl Default for Io {
+    fn default() -> Self {
+        let seed = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |now| now.as_nanos() as u64);
+        Self { output: Box::new(io::stdout()), input: None, random: seed }
+    }
+}
+
+impl Io {
+    pub(crate) fn set_output(&mut self, output: impl Write + 'static) {
+        self.output = Box::new(output);
+    }
+
+    pub(crate) fn set_input(&mut self, input: impl Read + 'static) {
+        self.input = Some(Box::new(BufReader::new(input)));
+    }
+
+    pub(crate) fn flush(&mut self) -> Result<(), String> {
+        self.output.flush().map_err(|e| format!("Failed to write output: {}", e))
+    }
+
+    /// Makes `random` return the same numbers on every run with this seed.
+    pub(crate) fn set_seed(&mut self, seed: u64) {
+        self.random = seed;
+    }
+
+    /// The next number from the generator, SplitMix64, which is fast and
+    /// accepts any seed.
+    fn next_random(&mut self) -> u64 {
+        self.random = self.random.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.random;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+}
+
+/// A function provided by the runtime rather than declared in the program.
+/// Both the type checker and the interpreter resolve calls through
+/// [`lookup`], so adding a builtin means adding one entry to [`BUILTINS`].
+pub struct Builtin {
+    pub name: &'static str,
+    /// Validates the argument types and returns the result type.
+    pub check: fn(&[Type]) -> Result<Type, String>,
+    /// Runs the builtin on already evaluated arguments.
+    pub call: fn(&mut Io, Vec<Value>) -> Result<Value, String>,
+}
+
+pub static BUILTINS: &[Builtin] = &[
+    Builtin { name: "len", check: check_len, call: call_len },
+    Builtin { name: "print", check: check_print, call: call_print },
+    Builtin { name: "println", check: check_print, call: call_println },
+    Builtin { name: "read_int", check: check_read_int, call: call_read_int },
+    Builtin { name: "read_line", check: check_read_line, call: call_read_line },
+    Builtin { name: "map", check: check_map, call: call_map },
+    Builtin { name: "contains", check: check_contains, call: call_contains },
+    Builtin { name: "remove", check: check_remove, call: call_remove },
+    Builtin { name: "push", check: check_push, call: call_push },
+    Builtin { name: "pop", check: check_pop, call: call_pop },
+    Builtin { name: "slice", check: check_slice, call: call_slice },
+    Builtin { name: "sort", check: check_sort, call: call_sort },
+    Builtin { name: "int", check: check_int, call: call_int },
+    Builtin { name: "float", check: check_float, call: call_float },
+    Builtin { name: "bool", check: check_bool, call: call_bool },
+    Builtin { name: "char", check: check_char, call: call_char },
+    Builtin { name: "str", check: check_str, call: call_str },
+    Builtin { name: "typeof", check: check_typeof, call: call_typeof },
+    Builtin { name: "format", check: check_format, call: call_format },
+    Builtin { name: "substring", check: check_substring, call: call_substring },
+    Builtin { name: "split", check: check_split, call: call_split },
+    Builtin { name: "find", check: check_find, call: call_find },
+    Builtin { name: "to_upper", check: check_to_upper, call: call_to_upper },
+    Builtin { name: "to_lower", check: check_to_lower, call: call_to_lower },
+    Builtin { name: "sqrt", check: check_sqrt, call: call_sqrt },
+    Builtin { name: "pow", check: check_pow, call: call_pow },
+    Builtin { name: "abs", check: check_abs, call: call_abs },
+    Builtin { name: "min", check: check_min, call: call_min },
+    Builtin { name: "max", check: check_max, call: call_max },
+    Builtin { name: "random", check: check_random, call: call_random },
+    Builtin { name: "assert", check: check_assert, call: call_assert },
+];
+
+/// Finds the builtin called `name`, if there is one.
+pub fn lookup(name: &str) -> Option<&'static Builtin> {
+    BUILTINS.iter().find(|builtin| builtin.name == name)
+}
+
+fn arity(name: &str, args: &[Type], expected: usize) -> Result<(), String> {
+    if args.len() == expected {
+        Ok(())
+    } else {
+        Err(format!("{} takes {} argument(s), found {}", name, expected, args.len()))
+    }
+}
+
+fn check_len(args: &[Type]) -> Result<Type, String> {
+    arity("len", args, 1)?;
+    match &args[0] {
+        Type::Array(_) | Type::Map(_) | Type::String | Type::Error => Ok(Type::Int),
+        other => Err(format!("len expects an array, a map or a string, found {}", other)),
+    }
+}
+
+fn call_len(_: &mut Io, args: Vec<Value>) -> Result<Value, String> {
+    match args.as_slice() {
+        [Value::Array(elements)] => Ok(Value::Int(elements.borrow().len() as i64)),
+        [Value::Map(entries)] => Ok(Value::Int(entries.borrow().len() as i64)),
+        [Value::Str(s)] => Ok(Value::Int(s.chars().count() as i64)),
+        _ => Err("len expects an array, a map or a string".to_string()),
+    }
+}
+
+/// `map()` is an empty map whose value type comes from where it is stored,
+/// like `[]`.
+fn check_map(args: &[Type]) -> Result<Type, String> {
+    arity("map", args, 0)?;
+    Ok(Type::Map(Box::new(Type::Error)))
+}
+
+fn call_map(_: &mut Io, _: Vec<Value>) -> Result<Value, String> {
+    Ok(Value::Map(Rc::new(RefCell::new(BTreeMap::new()))))
+}
+
+/// Checks the `(map, key)` arguments shared by `contains` and `remove`.
+fn check_map_key(name: &str, args: &[Type]) -> Result<Type, String> {
+    arity(name, args, 2)?;
+    if !matches!(args[0], Type::Map(_) | Type::Error) {
+        return Err(format!("{} expects a map, found {}", name, args[0]));
+    }
+    if !Type::String.accepts(&args[1]) {
+        return Err(format!("Map key must be a string, found {}", args[1]));
+    }
+    Ok(Type::Bool)
+}
+
+/// `contains(m, key)` is whether a map has an entry for `key`, and
+/// `contains(a, x)` whether an array has an element equal to `x`.
+fn check_contains(args: &[Type]) -> Result<Type, String> {
+    match args {
+        [Type::Array(element), x] => {
+            if !element.accepts(x) && !x.accepts(element) {
+                return Err(format!("Cannot look for a value of type {} in an array of {}", x, element));
+            }
+            Ok(Type::Bool)
+        }
+        _ => check_map_key("contains", args),
+    }
+}
+
+fn call_contains(_: &mut Io, args: Vec<Value>) -> Result<Value, String> {
+    match args.as_slice() {
+        [Value::Map(entries), Value::Str(key)] => Ok(Value::Bool(entries.borrow().contains_key(key))),
+        [Value::Array(elements), x] => Ok(Value::Bool(elements.borrow().contains(x))),
+        _ => Err("contains expects a map and a string, or an array and a value".to_string()),
+    }
+}
+
+/// `remove(m, key)` returns whether `key` was in the map.
+fn check_remove(args: &[Type]) -> Result<Type, String> {
+    check_map_key("remove", args)
+}
+
+fn call_remove(_: &mut Io, args: Vec<Value>) -> Result<Value, String> {
+    match args.as_slice() {
+        [Value::Map(entries), Value::Str(key)] => Ok(Value::Bool(entries.borrow_mut().remove(key).is_some())),
+        _ => Err("remove expects a map and a string".to_string()),
+    }
+}
+
+/// The element type of the array `args[0]`, or an error naming `name`.
+fn array_element(name: &str, args: &[Type]) -> Result<Type, String> {
+    match &args[0] {
+        Type::Array(element) => Ok((**element).clone()),
+        Type::Error => Ok(Type::Error),
+        other => Err(format!("{} expects an array, found {}", name, other)),
+    }
+}
+
+/// `push(a, x)` adds `x` to the end of `a`, changing it in place.
+fn check_push(args: &[Type]) -> Result<Type, String> {
+    arity("push", args, 2)?;
+    let element = array_element("push", args)?;
+    if !element.accepts(&args[1]) {
+        return Err(format!("Cannot push a value of type {} onto an array of {}", args[1], element));
+    }
+    Ok(Type::Void)
+}
+
+fn call_push(_: &mut Io, args: Vec<Value>) -> Result<Value, String> {
+    let mut args = args.into_iter();
+    match (args.next(), args.next()) {
+        (Some(Value::Array(elements)), Some(x)) => {
+            elements.borrow_mut().push(x);
+            Ok(Value::Int(0))
+        }
+        _ => Err("push expects an array and a value".to_string()),
+    }
+}
+
+/// `pop(a)` removes the last element of `a` and returns it; popping an
+/// empty array is a runtime error.
+fn check_pop(args: &[Type]) -> Result<Type, String> {
+    arity("pop", args, 1)?;
+    array_element("pop", args)
+}
+
+fn call_pop(_: &mut Io, args: Vec<Value>) -> Result<Value, String> {
+    match args.as_slice() {
+        [Value::Array(elements)] => elements.borrow_mut().pop().ok_or_else(|| "Cannot pop from an empty array".to_string()),
+        _ => Err("pop expects an array".to_string()),
+    }
+}
+
+/// `slice(a, start, end)` is a new array of the elements of `a` from
+/// `start` up to but not including `end`.
+fn check_slice(args: &[Type]) -> Result<Type, String> {
+    arity("slice", args, 3)?;
+    let element = array_element("slice", args)?;
+    check_params("slice", args, &[args[0].clone(), Type::Int, Type::Int], Type::Array(Box::new(element)))
+}
+
+fn call_slice(_: &mut Io, args: Vec<Value>) -> Result<Value, String> {
+    match args.as_slice() {
+        [Value::Array(elements), Value::Int(start), Value::Int(end)] => {
+            let elements = elements.borrow();
+            match (usize::try_from(*start), usize::try_from(*end)) {
+                (Ok(from), Ok(to)) if from <= to && to <= elements.len() => Ok(Value::Array(Rc::new(RefCell::new(elements[from..to].to_vec())))),
+                _ => Err(format!("Range {}..{} out of bounds for array of length {}", start, end, elements.len())),
+            }
+        }
+        _ => Err("slice expects an array and two ints".to_string()),
+    }
+}
+
+/// `sort(a)` puts the elements of `a` in ascending order in place. They
+/// must be ints, floats, strings or chars; NaN sorts after every other
+/// float.
+fn check_sort(args: &[Type]) -> Result<Type, String> {
+    arity("sort", args, 1)?;
+    match array_element("sort", args)? {
+        Type::Int | Type::Float | Type::String | Type::Char | Type::Error => Ok(Type::Void),
+        other => Err(format!("sort expects an array of ints, floats, strings or chars, found [{}]", other)),
+    }
+}
+
+fn call_sort(_: &mut Io, args: Vec<Value>) -> Result<Value, String> {
+    let [Value::Array(elements)] = args.as_slice() else {
+        return Err("sort expects an array".to_string());
+    };
+    let mut elements = elements.borrow_mut();
+    let mut unordered = None;
+    elements.sort_by(|a, b| match (a, b) {
+        (Value::Int(a), Value::Int(b)) => a.cmp(b),
+        (Value::Float(a), Value::Float(b)) => a.partial_cmp(b).unwrap_or_else(|| a.is_nan().cmp(&b.is_nan())),
+        (Value::Str(a), Value::Str(b)) => a.cmp(b),
+        (Value::Char(a), Value::Char(b)) => a.cmp(b),
+        _ => {
+            unordered.get_or_insert((a.type_name(), b.type_name()));
+            Ordering::Equal
+        }
+    });
+    match unordered {
+        Some((a, b)) if a == b => Err(format!("Cannot sort values of type {}", a)),
+        Some((a, b)) => Err(format!("Cannot sort {} and {} values together", a, b)),
+        None => Ok(Value::Int(0)),
+    }
+}
+
+/// Checks the one argument of `float` and `bool`, which convert from an
+/// int, a float, a bool or a string.
+fn check_conversion(name: &str, args: &[Type], result: Type) -> Result<Type, String> {
+    arity(name, args, 1)?;
+    match &args[0] {
+        Type::Int | Type::Float | Type::Bool | Type::String | Type::Error => Ok(result),
+        other => Err(format!("{} expects an int, a float, a bool or a string, found {}", name, other)),
+    }
+}
+
+/// `int` also takes a char, which converts to its code point.
+fn check_int(args: &[Type]) -> Result<Type, String> {
+    arity("int", args, 1)?;
+    match &args[0] {
+        Type::Int | Type::Float | Type::Bool | Type::Char | Type::String | Type::Error => Ok(Type::Int),
+        other => Err(format!("int expects an int, a float, a bool, a char or a string, found {}", other)),
+    }
+}
+
+/// Floats are truncated toward zero; strings must hold a whole number.
+fn call_int(_: &mut Io, args: Vec<Value>) -> Result<Value, String> {
+    match args.as_slice() {
+        [Value::Int(n)] => Ok(Value::Int(*n)),
+        // `i64::MAX as f64` is 2^63, one past the largest int.
+        [Value::Float(n)] if n.is_finite() && *n >= i64::MIN as f64 && *n < i64::MAX as f64 => Ok(Value::Int(n.trunc() as i64)),
+        [Value::Float(n)] => Err(format!("Cannot convert {:?} to int", n)),
+        [Value::Bool(b)] => Ok(Value::Int(*b as i64)),
+        [Value::Char(c)] => Ok(Value::Int(u32::from(*c).into())),
+        [Value::Str(s)] => s.trim().parse().map(Value::Int).map_err(|_| format!("Cannot convert {:?} to int", s)),
+        _ => Err("int expects an int, a float, a bool, a char or a string".to_string()),
+    }
+}
+
+fn check_float(args: &[Type]) -> Result<Type, String> {
+    check_conversion("float", args, Type::Float)
+}
+
+fn call_float(_: &mut Io, args: Vec<Value>) -> Result<Value, String> {
+    match args.as_slice() {
+        [Value::Int(n)] => Ok(Value::Float(*n as f64)),
+        [Value::Float(n)] => Ok(Value::Float(*n)),
+        [Value::Bool(b)] => Ok(Value::Float(if *b { 1.0 } else { 0.0 })),
+        [Value::Str(s)] => s.trim().parse().map(Value::Float).map_err(|_| format!("Cannot convert {:?} to float", s)),
+        _ => Err("float expects an int, a float, a bool or a string".to_string()),
+    }
+}
+
+fn check_bool(args: &[Type]) -> Result<Type, String> {
+    check_conversion("bool", args, Type::Bool)
+}
+
+/// Numbers are true unless zero; strings must be `true` or `false`.
+fn call_bool(_: &mut Io, args: Vec<Value>) -> Result<Value, String> {
+    match args.as_slice() {
+        [Value::Int(n)] => Ok(Value::Bool(*n != 0)),
+        [Value::Float(n)] => Ok(Value::Bool(*n != 0.0)),
+        [Value::Bool(b)] => Ok(Value::Bool(*b)),
+        [Value::Str(s)] => match s.trim() {
+            "true" => Ok(Value::Bool(true)),
+            "false" => Ok(Value::Bool(false)),
+            _ => Err(format!("Cannot convert {:?} to bool", s)),
+        },
+        _ => Err("bool expects an int, a float, a bool or a string".to_string()),
+    }
+}
+
+/// `char(n)` is the character with code point `n`.
+fn check_char(args: &[Type]) -> Result<Type, String> {
+    arity("char", args, 1)?;
+    match &args[0] {
+        Type::Int | Type::Char | Type::Error => Ok(Type::Char),
+        other => Err(format!("char expects an int or a char, found {}", other)),
+    }
+}
+
+/// Ints that are not a Unicode scalar value, such as surrogates, are a
+/// runtime error.
+fn call_char(_: &mut Io, args: Vec<Value>) -> Result<Value, String> {
+    match args.as_slice() {
+        [Value::Int(n)] => u32::try_from(*n)
+            .ok()
+            .and_then(char::from_u32)
+            .map(Value::Char)
+            .ok_or_else(|| format!("Cannot convert {} to char", n)),
+        [Value::Char(c)] => Ok(Value::Char(*c)),
+        _ => Err("char expects an int or a char".to_string()),
+    }
+}
+
+/// `str` turns any value into the text `print` would write for it.
+fn check_str(args: &[Type]) -> Result<Type, String> {
+    arity("str", args, 1)?;
+    match &args[0] {
+        Type::Void => Err("Cannot convert a value of type void to a string".to_string()),
+        _ => Ok(Type::String),
+    }
+}
+
+fn call_str(_: &mut Io, args: Vec<Value>) -> Result<Value, String> {
+    match args.as_slice() {
+        [value] => Ok(Value::Str(value.to_string())),
+        _ => Err("str takes 1 argument(s)".to_string()),
+    }
+}
+
+/// `typeof(x)` names the kind of value `x` holds when the call runs, as
+/// [`Value::type_name`] does: `"int"`, `"string"`, `"array"`, `"null"` and
+/// so on.
+fn check_typeof(args: &[Type]) -> Result<Type, String> {
+    arity("typeof", args, 1)?;
+    match &args[0] {
+        Type::Void => Err("typeof expects a value, found void".to_string()),
+        _ => Ok(Type::String),
+    }
+}
+
+fn call_typeof(_: &mut Io, args: Vec<Value>) -> Result<Value, String> {
+    match args.as_slice() {
+        [value] => Ok(Value::Str(value.type_name().to_string())),
+        _ => Err("typeof takes 1 argument(s)".to_string()),
+    }
+}
+
+/// `format(template, ...)` replaces each `{}` in `template` with the text
+/// `str` gives for the next argument; `{{` and `}}` stand for braces. The
+/// type checker counts the placeholders of a literal template through
+/// [`format_placeholders`], so only a computed template can fail to match
+/// its arguments at run time.
+fn check_format(args: &[Type]) -> Result<Type, String> {
+    let Some((template, values)) = args.split_first() else {
+        return Err("format takes a format string and the values to fill it with".to_string());
+    };
+    if !Type::String.accepts(template) {
+        return Err(format!("format expects a string as its first argument, found {}", template));
+    }
+    match values.iter().find(|t| **t == Type::Void) {
+        Some(_) => Err("Cannot format a value of type void".to_string()),
+        None => Ok(Type::String),
+    }
+}
+
+fn call_format(_: &mut Io, args: Vec<Value>) -> Result<Value, String> {
+    let Some((Value::Str(template), values)) = args.split_first() else {
+        return Err("format expects a string as its first argument".to_string());
+    };
+    let expected = format_placeholders(template)?;
+    if expected != values.len() {
+        return Err(placeholder_mismatch(expected, values.len()));
+    }
+    let mut out = String::with_capacity(template.len());
+    let mut values = values.iter();
+    let mut chars = template.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.as_str().starts_with('{') => {
+                chars.next();
+                out.push('{');
+            }
+            '}' if chars.as_str().starts_with('}') => {
+                chars.next();
+                out.push('}');
+            }
+            '{' => {
+                chars.next();
+                if let Some(value) = values.next() {
+                    out.push_str(&value.to_string());
+                }
+            }
+            c => out.push(c),
+        }
+    }
+    Ok(Value::Str(out))
+}
+
+/// The number of `{}` placeholders in a `format` template, or an error for
+/// a brace that neither opens a placeholder nor is doubled.
+pub(crate) fn format_placeholders(template: &str) -> Result<usize, String> {
+    let mut count = 0;
+    let mut chars = template.chars();
+    while let Some(c) = chars.next() {
+        match (c, chars.clone().next()) {
+            ('{', Some('{')) | ('}', Some('}')) => {
+                chars.next();
+            }
+            ('{', Some('}')) => {
+                chars.next();
+                count += 1;
+            }
+            ('{', _) => return Err("Unmatched '{' in format string; write {{ for a literal brace".to_string()),
+            ('}', _) => return Err("Unmatched '}' in format string; write }} for a literal brace".to_string()),
+            _ => {}
+        }
+    }
+    Ok(count)
+}
+
+pub(crate) fn placeholder_mismatch(expected: usize, found: usize) -> String {
+    format!("The format string has {} placeholder(s) but {} value(s) were given", expected, found)
+}
+
+/// Checks the arguments of a builtin whose parameters have the fixed types
+/// `params`.
+fn check_params(name: &str, args: &[Type], params: &[Type], result: Type) -> Result<Type, String> {
+    arity(name, args, params.len())?;
+    for (i, (arg, param)) in args.iter().zip(params).enumerate() {
+        if !param.accepts(arg) {
+            return Err(format!("Argument {} of {} must be {}, found {}", i + 1, name, param, arg));
+        }
+    }
+    Ok(result)
+}
+
+/// The byte offset in `s` of the character at index `i`, where `i` may be
+/// the length of `s` in characters. Strings are indexed by character, as
+/// `len` counts them.
+fn char_offset(s: &str, i: i64) -> Option<usize> {
+    let i = usize::try_from(i).ok()?;
+    s.char_indices().map(|(offset, _)| offset).chain([s.len()]).nth(i)
+}
+
+/// `substring(s, start, end)` is the characters of `s` from `start` up to
+/// but not including `end`.
+fn check_substring(args: &[Type]) -> Result<Type, String> {
+    check_params("substring", args, &[Type::String, Type::Int, Type::Int], Type::String)
+}
+
+fn call_substring(_: &mut Io, args: Vec<Value>) -> Result<Value, String> {
+    match args.as_slice() {
+        [Value::Str(s), Value::Int(start), Value::Int(end)] => match (char_offset(s, *start), char_offset(s, *end)) {
+            (Some(from), Some(to)) if from <= to => Ok(Value::Str(s[from..to].to_string())),
+            _ => Err(format!("Range {}..{} out of bounds for string of length {}", start, end, s.chars().count())),
+        },
+        _ => Err("substring expects a string and two ints".to_string()),
+    }
+}
+
+/// `split(s, separator)` is the array of the pieces of `s` between
+/// occurrences of `separator`, which may not be empty.
+fn check_split(args: &[Type]) -> Result<Type, String> {
+    check_params("split", args, &[Type::String, Type::String], Type::Array(Box::new(Type::String)))
+}
+
+fn call_split(_: &mut Io, args: Vec<Value>) -> Result<Value, String> {
+    match args.as_slice() {
+        [Value::Str(_), Value::Str(separator)] if separator.is_empty() => Err("split needs a separator that is not empty".to_string()),
+        [Value::Str(s), Value::Str(separator)] => {
+            let pieces = s.split(separator.as_str()).map(|piece| Value::Str(piece.to_string())).collect();
+            Ok(Value::Array(Rc::new(RefCell::new(pieces))))
+        }
+        _ => Err("split expects two strings".to_string()),
+    }
+}
+
+/// `find(s, needle)` is the index of the first character of the first
+/// occurrence of `needle` in `s`, or `null` if there is none.
+fn check_find(args: &[Type]) -> Result<Type, String> {
+    check_params("find", args, &[Type::String, Type::String], Type::Option(Box::new(Type::Int)))
+}
+
+fn call_find(_: &mut Io, args: Vec<Value>) -> Result<Value, String> {
+    match args.as_slice() {
+        [Value::Str(s), Value::Str(needle)] => Ok(match s.find(needle.as_str()) {
+            Some(offset) => Value::Int(s[..offset].chars().count() as i64),
+            None => Value::Null,
+        }),
+        _ => Err("find expects two strings".to_string()),
+    }
+}
+
+fn check_to_upper(args: &[Type]) -> Result<Type, String> {
+    check_params("to_upper", args, &[Type::String], Type::String)
+}
+
+fn call_to_upper(_: &mut Io, args: Vec<Value>) -> Result<Value, String> {
+    match args.as_slice() {
+        [Value::Str(s)] => Ok(Value::Str(s.to_uppercase())),
+        _ => Err("to_upper expects a string".to_string()),
+    }
+}
+
+fn check_to_lower(args: &[Type]) -> Result<Type, String> {
+    check_params("to_lower", args, &[Type::String], Type::String)
+}
+
+fn call_to_lower(_: &mut Io, args: Vec<Value>) -> Result<Value, String> {
+    match args.as_slice() {
+        [Value::Str(s)] => Ok(Value::Str(s.to_lowercase())),
+        _ => Err("to_lower expects a string".to_string()),
+    }
+}
+
+/// Checks the arguments of `pow`, `abs`, `min` and `max`: all ints or all
+/// floats, with a result of the same type.
+fn check_numbers(name: &str, args: &[Type], expected: usize) -> Result<Type, String> {
+    arity(name, args, expected)?;
+    let mut result = Type::Error;
+    for arg in args {
+        match arg {
+            Type::Error => {}
+            Type::Int | Type::Float if result == Type::Error => result = arg.clone(),
+            Type::Int | Type::Float if *arg == result => {}
+            Type::Int | Type::Float => return Err(format!("{} expects arguments of one type, found {} and {}", name, result, arg)),
+            other => return Err(format!("{} expects an int or a float, found {}", name, other)),
+        }
+    }
+    Ok(result)
+}
+
+/// `sqrt` takes an int or a float and always returns a float; the root of
+/// a negative number is NaN.
+fn check_sqrt(args: &[Type]) -> Result<Type, String> {
+    arity("sqrt", args, 1)?;
+    match &args[0] {
+        Type::Int | Type::Float | Type::Error => Ok(Type::Float),
+        other => Err(format!("sqrt expects an int or a float, found {}", other)),
+    }
+}
+
+fn call_sqrt(_: &mut Io, args: Vec<Value>) -> Result<Value, String> {
+    match args.as_slice() {
+        [Value::Int(n)] => Ok(Value::Float((*n as f64).sqrt())),
+        [Value::Float(n)] => Ok(Value::Float(n.sqrt())),
+        _ => Err("sqrt expects an int or a float".to_string()),
+    }
+}
+
+/// `pow(base, exponent)`; for ints the exponent may not be negative.
+fn check_pow(args: &[Type]) -> Result<Type, String> {
+    check_numbers("pow", args, 2)
+}
+
+fn call_pow(_: &mut Io, args: Vec<Value>) -> Result<Value, String> {
+    match args.as_slice() {
+        [Value::Int(_), Value::Int(exponent)] if *exponent < 0 => Err(format!("pow of ints needs an exponent of at least 0, found {}", exponent)),
+        [Value::Int(base), Value::Int(exponent)] => ops::checked_pow(*base, *exponent).map(Value::Int).ok_or_else(|| "Integer overflow in pow".to_string()),
+        [Value::Float(base), Value::Float(exponent)] => Ok(Value::Float(base.powf(*exponent))),
+        _ => Err("pow expects two ints or two floats".to_string()),
+    }
+}
+
+fn check_abs(args: &[Type]) -> Result<Type, String> {
+    check_numbers("abs", args, 1)
+}
+
+fn call_abs(_: &mut Io, args: Vec<Value>) -> Result<Value, String> {
+    match args.as_slice() {
+        [Value::Int(n)] => n.checked_abs().map(Value::Int).ok_or_else(|| "Integer overflow in abs".to_string()),
+        [Value::Float(n)] => Ok(Value::Float(n.abs())),
+        _ => Err("abs expects an int or a float".to_string()),
+    }
+}
+
+fn check_min(args: &[Type]) -> Result<Type, String> {
+    check_numbers("min", args, 2)
+}
+
+fn call_min(_: &mut Io, args: Vec<Value>) -> Result<Value, String> {
+    match args.as_slice() {
+        [Value::Int(a), Value::Int(b)] => Ok(Value::Int(*a.min(b))),
+        [Value::Float(a), Value::Float(b)] => Ok(Value::Float(a.min(*b))),
+        _ => Err("min expects two ints or two floats".to_string()),
+    }
+}
+
+fn check_max(args: &[Type]) -> Result<Type, String> {
+    check_numbers("max", args, 2)
+}
+
+fn call_max(_: &mut Io, args: Vec<Value>) -> Result<Value, String> {
+    match args.as_slice() {
+        [Value::Int(a), Value::Int(b)] => Ok(Value::Int(*a.max(b))),
+        [Value::Float(a), Value::Float(b)] => Ok(Value::Float(a.max(*b))),
+        _ => Err("max expects two ints or two floats".to_string()),
+    }
+}
+
+/// `random(n)` is an int from 0 up to but not including `n`. The sequence
+/// is fixed by the seed, so a program run with `--seed` repeats itself.
+fn check_random(args: &[Type]) -> Result<Type, String> {
+    arity("random", args, 1)?;
+    match &args[0] {
+        Type::Int | Type::Error => Ok(Type::Int),
+        other => Err(format!("random expects an int, found {}", other)),
+    }
+}
+
+fn call_random(io: &mut Io, args: Vec<Value>) -> Result<Value, String> {
+    match args.as_slice() {
+        [Value::Int(n)] if *n > 0 => {
+            // Scaling a 64-bit draw keeps every result about equally likely.
+            let scaled = (u128::from(io.next_random()) * *n as u128) >> 64;
+            Ok(Value::Int(scaled as i64))
+        }
+        [Value::Int(n)] => Err(format!("random needs a bound of at least 1, found {}", n)),
+        _ => Err("random expects an int".to_string()),
+    }
+}
+
+fn check_assert(args: &[Type]) -> Result<Type, String> {
+    arity("assert", args, 1)?;
+    match &args[0] {
+        Type::Bool | Type::Error => Ok(Type::Void),
+        other => Err(format!("assert expects a bool, found {}", other)),
+    }
+}
+
+/// A false condition is a runtime error at the call, so the report quotes
+/// the assertion that failed.
+fn call_assert(_: &mut Io, args: Vec<Value>) -> Result<Value, String> {
+    match args.as_slice() {
+        [Value::Bool(true)] => Ok(Value::Int(0)),
+        [Value::Bool(false)] => Err("Assertion failed".to_string()),
+        _ => Err("assert expects a bool".to_string()),
+    }
+}
+
+/// `print` and `println` take any number of arguments of any value type.
+fn check_print(args: &[Type]) -> Result<Type, String> {
+    match args.iter().find(|t| **t == Type::Void) {
+        Some(_) => Err("Cannot print a value of type void".to_string()),
+        None => Ok(Type::Void),
+    }
+}
+
+fn write_args(io: &mut Io, args: &[Value], end: &str) -> Result<Value, String> {
+    let text = args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>().join(" ");
+    write!(io.output, "{}{}", text, end).map_err(|e| format!("Failed to write output: {}", e))?;
+    Ok(Value::Int(0))
+}
+
+fn call_print(io: &mut Io, args: Vec<Value>) -> Result<Value, String> {
+    write_args(io, &args, "")
+}
+
+fn call_println(io: &mut Io, args: Vec<Value>) -> Result<Value, String> {
+    write_args(io, &args, "\n")
+}
+
+fn check_read_int(args: &[Type]) -> Result<Type, String> {
+    arity("read_int", args, 0)?;
+    Ok(Type::Int)
+}
+
+fn check_read_line(args: &[Type]) -> Result<Type, String> {
+    arity("read_line", args, 0)?;
+    Ok(Type::String)
+}
+
+/// Reads one line without its line ending, or `None` at end of input.
+fn read_input_line(io: &mut Io) -> Result<Option<String>, String> {
+    // Flush first so a prompt printed without a newline is visible.
+    io.flush()?;
+    let mut line = String::new();
+    let read = match &mut io.input {
+        Some(input) => input.read_line(&mut line),
+        None => io::stdin().read_line(&mut line),
+    };
+    let read = read.map_err(|e| format!("Failed to read input: {}", e))?;
+    if read == 0 {
+        return Ok(None);
+    }
+    let trimmed = line.trim_end_matches(['\n', '\r']).len();
+    line.truncate(trimmed);
+    Ok(Some(line))
+}
+
+fn call_read_int(io: &mut Io, _: Vec<Value>) -> Result<Value, String> {
+    let line = read_input_line(io)?.ok_or_else(|| "read_int reached the end of input".to_string())?;
+    line.trim()
+        .parse()
+        .map(Value::Int)
+        .map_err(|_| format!("read_int expected an integer, found '{}'", line.trim()))
+}
+
+/// Returns an empty string at end of input.
+fn call_read_line(io: &mut Io, _: Vec<Value>) -> Result<Value, String> {
+    Ok(Value::Str(read_input_line(io)?.unwrap_or_default()))
+}
\ No newline at end of file
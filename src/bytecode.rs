@@ -0,0 +1,873 @@
+use crate::ast::*;
+use crate::bcode;
+use crate::builtins;
+use crate::cache::{self, CacheKey, CacheStats, CompileCache};
+use crate::error::CompilerError;
+use crate::span::Span;
+use crate::symbol::Symbol;
+use crate::value::Value;
+use std::collections::HashMap;
+
+/// One instruction of the stack machine run by [`crate::vm::Vm`]. Operands
+/// are popped from and results pushed onto the value stack; jump targets
+/// are instruction indices within the same function.
+#[derive(Debug, Clone)]
+pub enum Instr {
+    /// Pushes `constants[i]`.
+    Const(usize),
+    Pop,
+    Dup,
+    /// Pushes the local in slot `i` of the current frame.
+    Load(usize),
+    /// Pops into the local in slot `i`.
+    Store(usize),
+    /// Pushes global `i`, failing if it has not been defined yet.
+    LoadGlobal(usize),
+    /// Pops into global `i`, which must already be defined.
+    StoreGlobal(usize),
+    /// Pops into global `i`, defining it if needed.
+    DefineGlobal(usize),
+    /// Pops the right then the left operand and pushes the result.
+    Binary(BinOp),
+    Neg,
+    BitNot,
+    /// Replaces the int on top of the stack with its `++`/`--` successor.
+    Update(UpdateOp),
+    Jump(usize),
+    /// Pops a bool and jumps if it is false.
+    JumpIfFalse(usize),
+    /// Calls `functions[f]` with the top `argc` values as arguments.
+    Call(usize, usize),
+    /// Calls `builtins::BUILTINS[b]` with the top `argc` values.
+    CallBuiltin(usize, usize),
+    /// Pops the result and returns from the current function.
+    Return,
+    /// Pops `n` values into a new array, first element deepest.
+    MakeArray(usize),
+    /// Pops an index then an array and pushes the element; works the same
+    /// with a key and a map.
+    Index,
+    /// Pops a value, an index and an array and stores the element, or a
+    /// value, a key and a map and stores the entry.
+    StoreIndex,
+    /// Pops `n` key and value pairs into a new map, first pair deepest.
+    MakeMap(usize),
+    /// Pops one value per field of `structs[s]`, first field deepest, into
+    /// a new struct.
+    MakeStruct(usize),
+    /// Pops a struct and pushes the field named by `constants[i]`.
+    GetField(usize),
+    /// Pops a value and a struct and stores the field named by `constants[i]`.
+    SetField(usize),
+}
+
+/// A compiled function. Parameters occupy the first `arity` local slots.
+#[derive(Debug, Clone)]
+pub struct Function {
+    pub name: String,
+    pub arity: usize,
+    pub locals: usize,
+    pub code: Vec<Instr>,
+    /// Source location of each instruction, for runtime errors.
+    pub spans: Vec<Span>,
+}
+
+/// A declared struct: its name and its fields in declaration order.
+#[derive(Debug, Clone)]
+pub struct StructLayout {
+    pub name: Symbol,
+    pub fields: Vec<Symbol>,
+}
+
+/// A whole compiled program. `functions[main]` holds the top-level
+/// statements; global variables are numbered by their index in `globals`.
+#[derive(Debug, Clone)]
+pub struct Module {
+    pub constants: Vec<Value>,
+    pub functions: Vec<Function>,
+    pub globals: Vec<String>,
+    pub structs: Vec<StructLayout>,
+    pub main: usize,
+}
+
+/// Compiles `program` to bytecode. Every function is resolved up front, so
+/// calls may precede declarations anywhere in the program.
+pub fn compile(program: &[Stmt]) -> Result<Module, CompilerError> {
+    Compiler::new(None).compile(program).map(|(module, _)| module)
+}
+
+/// Like [`compile`], but a function that `cache` holds bytecode for, under
+/// its [`cache::declaration_hash`], is linked in from there instead of being
+/// compiled again, and the others are stored there for the next
+/// compilation. Functions that declare functions of their own are always
+/// compiled.
+pub fn compile_cached(program: &[Stmt], cache: &CompileCache) -> Result<(Module, CacheStats), CompilerError> {
+    Compiler::new(Some(cache)).compile(program)
+}
+
+/// What besides its own declaration decides a cached function's entry: the
+/// compiler that produced it and the format it is stored in.
+fn cache_options() -> String {
+    format!("ferrum {} bcode {}", env!("CARGO_PKG_VERSION"), bcode::VERSION)
+}
+
+/// The index of `item` in `items`, appending it first if it is not there.
+fn relocate<T: PartialEq>(items: &mut Vec<T>, item: T) -> usize {
+    match items.iter().position(|other| *other == item) {
+        Some(i) => i,
+        None => {
+            items.push(item);
+            items.len() - 1
+        }
+    }
+}
+
+/// A loop being compiled: the jumps that still need its exit and its
+/// continue target patched in.
+struct Loop {
+    breaks: Vec<usize>,
+    continues: Vec<usize>,
+}
+
+/// Per-function compilation state.
+struct FnState {
+    code: Vec<Instr>,
+    spans: Vec<Span>,
+    /// Block scopes mapping names to local slots, innermost last. At the
+    /// outermost level of the main function names are globals instead.
+    scopes: Vec<Vec<(Symbol, usize)>>,
+    locals: usize,
+    loops: Vec<Loop>,
+    is_main: bool,
+}
+
+impl FnState {
+    fn new(is_main: bool) -> Self {
+        Self { code: Vec::new(), spans: Vec::new(), scopes: Vec::new(), locals: 0, loops: Vec::new(), is_main }
+    }
+
+    fn emit(&mut self, instr: Instr, span: Span) -> usize {
+        self.code.push(instr);
+        self.spans.push(span);
+        self.code.len() - 1
+    }
+
+    /// Points the jump at `at` to the next instruction.
+    fn patch(&mut self, at: usize) {
+        let target = self.code.len();
+        match &mut self.code[at] {
+            Instr::Jump(t) | Instr::JumpIfFalse(t) => *t = target,
+            other => unreachable!("patching a non-jump instruction {:?}", other),
+        }
+    }
+
+    fn declare_local(&mut self, name: Symbol) -> usize {
+        let slot = self.locals;
+        self.locals += 1;
+        self.scopes.last_mut().expect("locals live in a scope").push((name, slot));
+        slot
+    }
+
+    fn local(&self, name: Symbol) -> Option<usize> {
+        self.scopes.iter().rev().flat_map(|scope| scope.iter().rev()).find(|(n, _)| *n == name).map(|(_, slot)| *slot)
+    }
+
+    fn at_global_level(&self) -> bool {
+        self.is_main && self.scopes.is_empty()
+    }
+}
+
+struct Compiler<'c> {
+    constants: Vec<Value>,
+    functions: Vec<Option<Function>>,
+    /// Function index for each name; a later declaration replaces an earlier one.
+    function_ids: HashMap<Symbol, usize>,
+    /// Function index for each declaration, keyed by its span.
+    declarations: HashMap<Span, usize>,
+    globals: Vec<String>,
+    structs: Vec<StructLayout>,
+    /// Index into `structs` for each name; a later declaration replaces an earlier one.
+    struct_ids: HashMap<Symbol, usize>,
+    /// The variants of each declared enum. Variants are constants, so the
+    /// module keeps no record of the enums themselves.
+    enums: HashMap<Symbol, Vec<Symbol>>,
+    cache: Option<&'c CompileCache>,
+    stats: CacheStats,
+}
+
+impl<'c> Compiler<'c> {
+    fn new(cache: Option<&'c CompileCache>) -> Self {
+        Self {
+            constants: Vec::new(),
+            functions: Vec::new(),
+            function_ids: HashMap::new(),
+            declarations: HashMap::new(),
+            globals: Vec::new(),
+            structs: Vec::new(),
+            struct_ids: HashMap::new(),
+            enums: HashMap::new(),
+            cache,
+            stats: CacheStats::default(),
+        }
+    }
+
+    fn compile(mut self, program: &[Stmt]) -> Result<(Module, CacheStats), CompilerError> {
+        self.declare_functions(program);
+        let main = self.functions.len();
+        self.functions.push(None);
+        let mut state = FnState::new(true);
+        self.stmts(&mut state, program)?;
+        self.finish(state, "<main>", 0, main, Span::default());
+        let module = Module {
+            constants: self.constants,
+            functions: self.functions.into_iter().map(|f| f.expect("every declared function is compiled")).collect(),
+            globals: self.globals,
+            structs: self.structs,
+            main,
+        };
+        Ok((module, self.stats))
+    }
+
+    /// Reserves an index for every function declared anywhere in `stmts`,
+    /// lays out every struct and records every enum.
+    fn declare_functions(&mut self, stmts: &[Stmt]) {
+        for stmt in stmts {
+            match &stmt.node {
+                StmtKind::FnDecl(name, _, _, body) => {
+                    let id = self.functions.len();
+                    self.functions.push(None);
+                    self.function_ids.insert(*name, id);
+                    self.declarations.insert(stmt.span, id);
+                    self.declare_functions(body);
+                }
+                StmtKind::StructDecl(name, fields) => {
+                    let fields = fields.iter().map(|field| field.name).collect();
+                    self.struct_ids.insert(*name, self.structs.len());
+                    self.structs.push(StructLayout { name: *name, fields });
+                }
+                StmtKind::EnumDecl(name, variants) => {
+                    self.enums.insert(*name, variants.clone());
+                }
+                StmtKind::If(_, then_block, else_block) => {
+                    self.declare_functions(then_block);
+                    self.declare_functions(else_block);
+                }
+                StmtKind::Match(_, arms) => {
+                    for arm in arms {
+                        self.declare_functions(arm.body);
+                    }
+                }
+                StmtKind::While(_, body)
+                | StmtKind::DoWhile(body, _)
+                | StmtKind::For(.., body)
+                | StmtKind::ForRange(.., body)
+                | StmtKind::Block(body) => self.declare_functions(body),
+                _ => {}
+            }
+        }
+    }
+
+    fn finish(&mut self, mut state: FnState, name: &str, arity: usize, id: usize, span: Span) {
+        // Falling off the end returns 0, as in the interpreter.
+        let zero = self.constant(Value::Int(0));
+        state.emit(Instr::Const(zero), span);
+        state.emit(Instr::Return, span);
+        self.functions[id] = Some(Function {
+            name: name.to_string(),
+            arity,
+            locals: state.locals,
+            code: state.code,
+            spans: state.spans,
+        });
+    }
+
+    /// Stores the bytecode just compiled for `decl` as function `id` in
+    /// `cache`, as a module of its own: `functions[0]` is the function,
+    /// every other function a stub named after one it calls, with the
+    /// number of arguments it is called with, and each span
+    /// the position of its node in [`cache::node_spans`].
+    fn store_cached(&self, cache: &CompileCache, decl: &Stmt, id: usize) {
+        let Some(nodes) = cache::node_spans(decl) else {
+            return;
+        };
+        let function = self.functions[id].as_ref().expect("compiled before it is cached");
+        let mut entry = Module { constants: Vec::new(), functions: Vec::new(), globals: Vec::new(), structs: Vec::new(), main: 0 };
+        let (mut constants, mut structs, mut callees) = (Vec::new(), Vec::new(), Vec::new());
+        let mut code = Vec::new();
+        for instr in &function.code {
+            code.push(match *instr {
+                Instr::Const(i) => Instr::Const(relocate(&mut constants, i)),
+                Instr::GetField(i) => Instr::GetField(relocate(&mut constants, i)),
+                Instr::SetField(i) => Instr::SetField(relocate(&mut constants, i)),
+                Instr::LoadGlobal(i) => Instr::LoadGlobal(relocate(&mut entry.globals, self.globals[i].clone())),
+                Instr::StoreGlobal(i) => Instr::StoreGlobal(relocate(&mut entry.globals, self.globals[i].clone())),
+                Instr::DefineGlobal(i) => Instr::DefineGlobal(relocate(&mut entry.globals, self.globals[i].clone())),
+                Instr::MakeStruct(s) => Instr::MakeStruct(relocate(&mut structs, s)),
+                Instr::Call(f, argc) => {
+                    let (name, _) = self.function_ids.iter().find(|&(_, &other)| other == f).expect("calls go to declared functions");
+                    Instr::Call(1 + relocate(&mut callees, (name.to_string(), argc)), argc)
+                }
+                Instr::CallBuiltin(b, argc) => Instr::Call(1 + relocate(&mut callees, (builtins::BUILTINS[b].name.to_string(), argc)), argc),
+                ref other => other.clone(),
+            });
+        }
+        let mut first_node = HashMap::new();
+        for (i, span) in nodes.iter().enumerate().rev() {
+            first_node.insert(*span, i);
+        }
+        let Some(spans) = function.spans.iter().map(|span| first_node.get(span).map(|&start| Span { start, ..Span::default() })).collect() else {
+            return;
+        };
+        entry.constants = constants.into_iter().map(|i| self.constants[i].clone()).collect();
+        entry.structs = structs.into_iter().map(|s| self.structs[s].clone()).collect();
+        entry.functions.push(Function { name: function.name.clone(), arity: function.arity, locals: function.locals, code, spans });
+        for (name, arity) in callees {
+            let code = vec![Instr::Load(0), Instr::Return];
+            entry.functions.push(Function { name, arity, locals: arity.max(1), code, spans: vec![Span::default(); 2] });
+        }
+        let key = CacheKey::new(cache::declaration_hash(decl), &cache_options());
+        // An entry that cannot be written only costs the next compilation
+        // the time to compile the function again.
+        let _ = cache.put(&key, &bcode::encode(&entry));
+    }
+
+    /// Links the bytecode `cache` holds for `decl` in as function `id`,
+    /// returning false, with nothing changed, if there is none or it no
+    /// longer fits the program: a function it calls is gone, or a struct or
+    /// enum it uses was declared differently.
+    fn link_cached(&mut self, cache: &CompileCache, decl: &Stmt, id: usize) -> bool {
+        let Some(nodes) = cache::node_spans(decl) else {
+            return false;
+        };
+        let key = CacheKey::new(cache::declaration_hash(decl), &cache_options());
+        let Some(entry) = cache.get(&key).and_then(|bytes| bcode::decode(&bytes).ok()) else {
+            return false;
+        };
+        let Some((function, callees)) = entry.functions.split_first() else {
+            return false;
+        };
+        let builtin = |name: &str| builtins::BUILTINS.iter().position(|builtin| builtin.name == name);
+        let resolves = |callee: &Function| self.function_ids.contains_key(&Symbol::intern(&callee.name)) || builtin(&callee.name).is_some();
+        let laid_out = |layout: &StructLayout| self.struct_ids.get(&layout.name).is_some_and(|&s| self.structs[s].fields == layout.fields);
+        let declared = |constant: &Value| match constant {
+            Value::Enum(name, variant) => self.enums.get(name).is_some_and(|variants| variants.contains(variant)),
+            _ => true,
+        };
+        let fits = callees.iter().all(resolves)
+            && entry.structs.iter().all(laid_out)
+            && entry.constants.iter().all(declared)
+            && function.spans.iter().all(|span| span.start < nodes.len())
+            && function.code.iter().all(|instr| !matches!(instr, Instr::Call(0, _) | Instr::CallBuiltin(..)));
+        if !fits {
+            return false;
+        }
+        let mut code = Vec::new();
+        for instr in &function.code {
+            code.push(match *instr {
+                Instr::Const(i) => Instr::Const(self.constant(entry.constants[i].clone())),
+                Instr::GetField(i) => Instr::GetField(self.constant(entry.constants[i].clone())),
+                Instr::SetField(i) => Instr::SetField(self.constant(entry.constants[i].clone())),
+                Instr::LoadGlobal(i) => Instr::LoadGlobal(self.global(&entry.globals[i])),
+                Instr::StoreGlobal(i) => Instr::StoreGlobal(self.global(&entry.globals[i])),
+                Instr::DefineGlobal(i) => Instr::DefineGlobal(self.global(&entry.globals[i])),
+                Instr::MakeStruct(s) => Instr::MakeStruct(self.struct_ids[&entry.structs[s].name]),
+                // Resolved as `ExprKind::Call` resolves a name.
+                Instr::Call(f, argc) => match self.function_ids.get(&Symbol::intern(&callees[f - 1].name)) {
+                    Some(&id) => Instr::Call(id, argc),
+                    None => Instr::CallBuiltin(builtin(&callees[f - 1].name).expect("checked above"), argc),
+                },
+                ref other => other.clone(),
+            });
+        }
+        let spans = function.spans.iter().map(|span| nodes[span.start]).collect();
+        self.functions[id] = Some(Function { name: function.name.clone(), arity: function.arity, locals: function.locals, code, spans });
+        true
+    }
+
+    fn constant(&mut self, value: Value) -> usize {
+        // Floats compare by bits so `0.0` and `-0.0` stay distinct.
+        let same = |c: &Value| match (c, &value) {
+            (Value::Float(a), Value::Float(b)) => a.to_bits() == b.to_bits(),
+            (c, value) => c == value,
+        };
+        if let Some(i) = self.constants.iter().position(same) {
+            return i;
+        }
+        self.constants.push(value);
+        self.constants.len() - 1
+    }
+
+    fn global(&mut self, name: &str) -> usize {
+        if let Some(i) = self.globals.iter().position(|g| g == name) {
+            return i;
+        }
+        self.globals.push(name.to_string());
+        self.globals.len() - 1
+    }
+
+    fn error(msg: &str, span: Span) -> CompilerError {
+        CompilerError::RuntimeError(msg.to_string(), Some(span))
+    }
+
+    fn block(&mut self, state: &mut FnState, stmts: &[Stmt]) -> Result<(), CompilerError> {
+        state.scopes.push(Vec::new());
+        let result = self.stmts(state, stmts);
+        state.scopes.pop();
+        result
+    }
+
+    fn stmts(&mut self, state: &mut FnState, stmts: &[Stmt]) -> Result<(), CompilerError> {
+        for stmt in stmts {
+            self.stmt(state, stmt)?;
+        }
+        Ok(())
+    }
+
+    fn store(&mut self, state: &mut FnState, name: Symbol, span: Span) {
+        match state.local(name) {
+            Some(slot) => state.emit(Instr::Store(slot), span),
+            None => {
+                let global = self.global(&name);
+                state.emit(Instr::StoreGlobal(global), span)
+            }
+        };
+    }
+
+    /// Evaluates the fields of a struct literal in source order and leaves
+    /// them on the stack in declaration order for `MakeStruct`.
+    fn struct_lit(&mut self, state: &mut FnState, name: Symbol, fields: &[(Symbol, Expr)], span: Span) -> Result<(), CompilerError> {
+        let Some(&id) = self.struct_ids.get(&name) else {
+            return Err(Self::error(&format!("Unknown struct: {}", name), span));
+        };
+        let declared = self.structs[id].fields.clone();
+        let mut order = Vec::new();
+        for (field, value) in fields {
+            match declared.iter().position(|name| name == field) {
+                Some(i) if !order.contains(&i) => order.push(i),
+                Some(_) => return Err(Self::error(&format!("Field {} is given twice", field), value.span)),
+                None => return Err(Self::error(&format!("Struct {} has no field {}", name, field), value.span)),
+            }
+        }
+        if let Some(missing) = declared.iter().enumerate().find(|(i, _)| !order.contains(i)) {
+            return Err(Self::error(&format!("Missing {} in {} literal", missing.1, name), span));
+        }
+        if order.iter().enumerate().all(|(i, &field)| i == field) {
+            for (_, value) in fields {
+                self.expr(state, value)?;
+            }
+        } else {
+            // Park each value in a hidden local, then load them back in
+            // declaration order.
+            state.scopes.push(Vec::new());
+            let mut slots = vec![0; declared.len()];
+            for ((_, value), &field) in fields.iter().zip(&order) {
+                self.expr(state, value)?;
+                slots[field] = state.declare_local(Symbol::intern("{field}"));
+                state.emit(Instr::Store(slots[field]), value.span);
+            }
+            for slot in slots {
+                state.emit(Instr::Load(slot), span);
+            }
+            state.scopes.pop();
+        }
+        state.emit(Instr::MakeStruct(id), span);
+        Ok(())
+    }
+
+    fn loop_body(&mut self, state: &mut FnState, body: &[Stmt]) -> Result<Loop, CompilerError> {
+        state.loops.push(Loop { breaks: Vec::new(), continues: Vec::new() });
+        let result = self.block(state, body);
+        let lp = state.loops.pop().expect("loop pushed above");
+        result.map(|()| lp)
+    }
+
+    fn stmt(&mut self, state: &mut FnState, stmt: &Stmt) -> Result<(), CompilerError> {
+        let span = stmt.span;
+        match &stmt.node {
+            StmtKind::Let(name, _, expr) | StmtKind::Const(name, _, expr) => {
+                self.expr(state, expr)?;
+                if state.at_global_level() {
+                    let global = self.global(name);
+                    state.emit(Instr::DefineGlobal(global), span);
+                } else {
+                    let slot = state.declare_local(*name);
+                    state.emit(Instr::Store(slot), span);
+                }
+            }
+            StmtKind::Assign(name, expr) => {
+                self.expr(state, expr)?;
+                self.store(state, *name, span);
+            }
+            StmtKind::IndexAssign(array, index, value) => {
+                self.expr(state, array)?;
+                self.expr(state, index)?;
+                self.expr(state, value)?;
+                state.emit(Instr::StoreIndex, index.span);
+            }
+            StmtKind::FieldAssign(object, field, value) => {
+                self.expr(state, object)?;
+                self.expr(state, value)?;
+                let name = self.constant(Value::Str(field.to_string()));
+                state.emit(Instr::SetField(name), span);
+            }
+            StmtKind::StructDecl(..) | StmtKind::EnumDecl(..) | StmtKind::TypeAlias(..) | StmtKind::Import(..) => {}
+            StmtKind::Expr(expr) => {
+                self.expr(state, expr)?;
+                state.emit(Instr::Pop, span);
+            }
+            StmtKind::If(cond, then_block, else_block) => {
+                self.expr(state, cond)?;
+                let to_else = state.emit(Instr::JumpIfFalse(0), cond.span);
+                self.block(state, then_block)?;
+                let to_end = state.emit(Instr::Jump(0), span);
+                state.patch(to_else);
+                self.block(state, else_block)?;
+                state.patch(to_end);
+            }
+            StmtKind::While(cond, body) => {
+                let top = state.code.len();
+                self.expr(state, cond)?;
+                let exit = state.emit(Instr::JumpIfFalse(0), cond.span);
+                let lp = self.loop_body(state, body)?;
+                for at in lp.continues {
+                    state.code[at] = Instr::Jump(top);
+                }
+                state.emit(Instr::Jump(top), span);
+                state.patch(exit);
+                for at in lp.breaks {
+                    state.patch(at);
+                }
+            }
+            StmtKind::DoWhile(body, cond) => {
+                let top = state.code.len();
+                let lp = self.loop_body(state, body)?;
+                for at in lp.continues {
+                    state.patch(at);
+                }
+                self.expr(state, cond)?;
+                let exit = state.emit(Instr::JumpIfFalse(0), cond.span);
+                state.emit(Instr::Jump(top), span);
+                state.patch(exit);
+                for at in lp.breaks {
+                    state.patch(at);
+                }
+            }
+            StmtKind::For(init, cond, step, body) => {
+                // A variable declared by the initializer lives in its own
+                // scope around the body.
+                state.scopes.push(Vec::new());
+                self.stmt(state, init)?;
+                let top = state.code.len();
+                self.expr(state, cond)?;
+                let exit = state.emit(Instr::JumpIfFalse(0), cond.span);
+                let lp = self.loop_body(state, body)?;
+                for at in lp.continues {
+                    state.patch(at);
+                }
+                self.stmt(state, step)?;
+                state.emit(Instr::Jump(top), span);
+                state.patch(exit);
+                for at in lp.breaks {
+                    state.patch(at);
+                }
+                state.scopes.pop();
+            }
+            StmtKind::ForRange(var, start, end, inclusive, body) => {
+                // The next value and the end live in hidden slots, so
+                // assigning to the variable does not change the iteration.
+                state.scopes.push(Vec::new());
+                self.expr(state, start)?;
+                let next = state.declare_local(Symbol::intern("{next}"));
+                state.emit(Instr::Store(next), span);
+                self.expr(state, end)?;
+                let last = state.declare_local(Symbol::intern("{end}"));
+                state.emit(Instr::Store(last), span);
+                let slot = state.declare_local(*var);
+                let top = state.code.len();
+                state.emit(Instr::Load(next), span);
+                state.emit(Instr::Load(last), span);
+                state.emit(Instr::Binary(if *inclusive { BinOp::Le } else { BinOp::Lt }), span);
+                let mut exits = vec![state.emit(Instr::JumpIfFalse(0), span)];
+                state.emit(Instr::Load(next), span);
+                state.emit(Instr::Store(slot), span);
+                let lp = self.loop_body(state, body)?;
+                for at in lp.continues {
+                    state.patch(at);
+                }
+                // Stepping past an inclusive end of `i64::MAX` would overflow.
+                if *inclusive {
+                    state.emit(Instr::Load(next), span);
+                    state.emit(Instr::Load(last), span);
+                    state.emit(Instr::Binary(BinOp::Lt), span);
+                    exits.push(state.emit(Instr::JumpIfFalse(0), span));
+                }
+                state.emit(Instr::Load(next), span);
+                state.emit(Instr::Update(UpdateOp::Inc), span);
+                state.emit(Instr::Store(next), span);
+                state.emit(Instr::Jump(top), span);
+                for at in exits.into_iter().chain(lp.breaks) {
+                    state.patch(at);
+                }
+                state.scopes.pop();
+            }
+            StmtKind::Match(value, arms) => {
+                self.expr(state, value)?;
+                state.scopes.push(Vec::new());
+                let slot = state.declare_local(Symbol::intern("{match}"));
+                state.emit(Instr::Store(slot), span);
+                let mut to_end = Vec::new();
+                for arm in arms {
+                    let Pattern::Literal(literal) = &arm.pattern else {
+                        self.block(state, arm.body)?;
+                        break;
+                    };
+                    state.emit(Instr::Load(slot), arm.span);
+                    self.expr(state, literal)?;
+                    state.emit(Instr::Binary(BinOp::Eq), arm.span);
+                    let to_next = state.emit(Instr::JumpIfFalse(0), arm.span);
+                    self.block(state, arm.body)?;
+                    to_end.push(state.emit(Instr::Jump(0), span));
+                    state.patch(to_next);
+                }
+                for at in to_end {
+                    state.patch(at);
+                }
+                state.scopes.pop();
+            }
+            StmtKind::FnDecl(name, params, _, body) => {
+                let id = self.declarations[&span];
+                if let Some(cache) = self.cache
+                    && self.link_cached(cache, stmt, id)
+                {
+                    self.stats.reused += 1;
+                    return Ok(());
+                }
+                let mut inner = FnState::new(false);
+                inner.scopes.push(Vec::new());
+                for param in params {
+                    inner.declare_local(param.name);
+                }
+                self.stmts(&mut inner, body)?;
+                self.finish(inner, name, params.len(), id, span);
+                if let Some(cache) = self.cache {
+                    self.stats.compiled += 1;
+                    self.store_cached(cache, stmt, id);
+                }
+            }
+            StmtKind::Return(expr) => {
+                match expr {
+                    Some(expr) => self.expr(state, expr)?,
+                    None => {
+                        let zero = self.constant(Value::Int(0));
+                        state.emit(Instr::Const(zero), span);
+                    }
+                }
+                state.emit(Instr::Return, span);
+            }
+            StmtKind::Break | StmtKind::Continue => {
+                let at = state.emit(Instr::Jump(0), span);
+                let Some(lp) = state.loops.last_mut() else {
+                    return Err(Self::error("'break' or 'continue' outside of a loop", span));
+                };
+                if matches!(stmt.node, StmtKind::Break) {
+                    lp.breaks.push(at);
+                } else {
+                    lp.continues.push(at);
+                }
+            }
+            StmtKind::Block(stmts) => self.block(state, stmts)?,
+        }
+        Ok(())
+    }
+
+    fn expr(&mut self, state: &mut FnState, expr: &Expr) -> Result<(), CompilerError> {
+        let span = expr.span;
+        match &expr.node {
+            ExprKind::Number(n) => {
+                let c = self.constant(Value::Int(*n));
+                state.emit(Instr::Const(c), span);
+            }
+            ExprKind::Float(n) => {
+                let c = self.constant(Value::Float(*n));
+                state.emit(Instr::Const(c), span);
+            }
+            ExprKind::Str(s) => {
+                let c = self.constant(Value::Str(s.clone()));
+                state.emit(Instr::Const(c), span);
+            }
+            ExprKind::Char(c) => {
+                let c = self.constant(Value::Char(*c));
+                state.emit(Instr::Const(c), span);
+            }
+            ExprKind::Bool(b) => {
+                let c = self.constant(Value::Bool(*b));
+                state.emit(Instr::Const(c), span);
+            }
+            ExprKind::Null => {
+                let c = self.constant(Value::Null);
+                state.emit(Instr::Const(c), span);
+            }
+            ExprKind::Variant(name, variant) => {
+                match self.enums.get(name) {
+                    Some(variants) if variants.contains(variant) => {}
+                    Some(_) => return Err(Self::error(&format!("Enum {} has no variant {}", name, variant), span)),
+                    None => return Err(Self::error(&format!("Unknown enum: {}", name), span)),
+                }
+                let c = self.constant(Value::Enum(*name, *variant));
+                state.emit(Instr::Const(c), span);
+            }
+            ExprKind::Variable(name) => {
+                match state.local(*name) {
+                    Some(slot) => state.emit(Instr::Load(slot), span),
+                    None => {
+                        let global = self.global(name);
+                        state.emit(Instr::LoadGlobal(global), span)
+                    }
+                };
+            }
+            ExprKind::Unary(UnOp::Neg, operand) => {
+                self.expr(state, operand)?;
+                state.emit(Instr::Neg, span);
+            }
+            ExprKind::Unary(UnOp::BitNot, operand) => {
+                self.expr(state, operand)?;
+                state.emit(Instr::BitNot, span);
+            }
+            ExprKind::Update(name, op, prefix) => {
+                self.expr(state, &Expr::new(ExprKind::Variable(*name), span))?;
+                // Leave the expression's value under the one stored back.
+                if *prefix {
+                    state.emit(Instr::Update(*op), span);
+                    state.emit(Instr::Dup, span);
+                } else {
+                    state.emit(Instr::Dup, span);
+                    state.emit(Instr::Update(*op), span);
+                }
+                self.store(state, *name, span);
+            }
+            ExprKind::Binary(lhs, op @ (BinOp::And | BinOp::Or), rhs) => {
+                // Short-circuit: `a && b` is `if a { b } else { false }` and
+                // `a || b` is `if a { true } else { b }`, with both operands
+                // checked to be bools by the jumps.
+                let (t, f) = (self.constant(Value::Bool(true)), self.constant(Value::Bool(false)));
+                self.expr(state, lhs)?;
+                let lhs_false = state.emit(Instr::JumpIfFalse(0), lhs.span);
+                let mut to_false = Vec::new();
+                let mut to_end = Vec::new();
+                if matches!(op, BinOp::And) {
+                    self.expr(state, rhs)?;
+                    to_false.push(state.emit(Instr::JumpIfFalse(0), rhs.span));
+                    state.emit(Instr::Const(t), span);
+                    to_end.push(state.emit(Instr::Jump(0), span));
+                    to_false.push(lhs_false);
+                } else {
+                    state.emit(Instr::Const(t), span);
+                    to_end.push(state.emit(Instr::Jump(0), span));
+                    state.patch(lhs_false);
+                    self.expr(state, rhs)?;
+                    to_false.push(state.emit(Instr::JumpIfFalse(0), rhs.span));
+                    state.emit(Instr::Const(t), span);
+                    to_end.push(state.emit(Instr::Jump(0), span));
+                }
+                for at in to_false {
+                    state.patch(at);
+                }
+                state.emit(Instr::Const(f), span);
+                for at in to_end {
+                    state.patch(at);
+                }
+            }
+            ExprKind::Binary(lhs, op, rhs) => {
+                self.expr(state, lhs)?;
+                self.expr(state, rhs)?;
+                state.emit(Instr::Binary(*op), span);
+            }
+            ExprKind::Array(elements) => {
+                for element in elements.iter() {
+                    self.expr(state, element)?;
+                }
+                state.emit(Instr::MakeArray(elements.len()), span);
+            }
+            ExprKind::Map(entries) => {
+                for (key, value) in entries {
+                    self.expr(state, key)?;
+                    self.expr(state, value)?;
+                }
+                state.emit(Instr::MakeMap(entries.len()), span);
+            }
+            ExprKind::Index(array, index) => {
+                self.expr(state, array)?;
+                self.expr(state, index)?;
+                state.emit(Instr::Index, index.span);
+            }
+            ExprKind::If(cond, then_value, else_value) => {
+                self.expr(state, cond)?;
+                let to_else = state.emit(Instr::JumpIfFalse(0), cond.span);
+                self.expr(state, then_value)?;
+                let to_end = state.emit(Instr::Jump(0), span);
+                state.patch(to_else);
+                self.expr(state, else_value)?;
+                state.patch(to_end);
+            }
+            ExprKind::Match(value, arms) => {
+                self.expr(state, value)?;
+                state.scopes.push(Vec::new());
+                let slot = state.declare_local(Symbol::intern("{match}"));
+                state.emit(Instr::Store(slot), span);
+                let mut to_end = Vec::new();
+                for (i, arm) in arms.iter().enumerate() {
+                    // The checker made sure the arms cover every value, so
+                    // the last one is taken without a test.
+                    let to_next = match &arm.pattern {
+                        Pattern::Literal(literal) if i + 1 < arms.len() => {
+                            state.emit(Instr::Load(slot), arm.span);
+                            self.expr(state, literal)?;
+                            state.emit(Instr::Binary(BinOp::Eq), arm.span);
+                            Some(state.emit(Instr::JumpIfFalse(0), arm.span))
+                        }
+                        _ => None,
+                    };
+                    self.expr(state, &arm.value)?;
+                    let Some(to_next) = to_next else {
+                        break;
+                    };
+                    to_end.push(state.emit(Instr::Jump(0), span));
+                    state.patch(to_next);
+                }
+                for at in to_end {
+                    state.patch(at);
+                }
+                state.scopes.pop();
+            }
+            ExprKind::StructLit(name, fields) => self.struct_lit(state, *name, fields, span)?,
+            ExprKind::Field(object, field) => {
+                self.expr(state, object)?;
+                let name = self.constant(Value::Str(field.to_string()));
+                state.emit(Instr::GetField(name), span);
+            }
+            ExprKind::Block(stmts, value) => {
+                // A jump out would leave the values computed so far on the
+                // stack, so loops around the block are hidden from it.
+                let loops = std::mem::take(&mut state.loops);
+                state.scopes.push(Vec::new());
+                let result = self.stmts(state, stmts).and_then(|()| self.expr(state, value));
+                state.scopes.pop();
+                state.loops = loops;
+                result?;
+            }
+            ExprKind::Call(name, args) => {
+                for arg in args.iter() {
+                    self.expr(state, arg)?;
+                }
+                if let Some(&id) = self.function_ids.get(name) {
+                    state.emit(Instr::Call(id, args.len()), span);
+                } else if let Some(b) = builtins::BUILTINS.iter().position(|builtin| *name == builtin.name) {
+                    state.emit(Instr::CallBuiltin(b, args.len()), span);
+                } else {
+                    return Err(Self::error(&format!("Undefined function: {}", name), span));
+                }
+            }
+        }
+        Ok(())
+    }
+}
\ No newline at end of file
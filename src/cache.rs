@@ -0,0 +1,222 @@
+use crate::ast::{ExprKind, Expr, Pattern, Stmt, StmtKind};
+use crate::printer::print_program;
+use crate::span::Span;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+/// FNV-1a: unlike `DefaultHasher`, its output is stable across Rust releases,
+/// so hashes written to disk stay valid.
+fn stable_hash(bytes: &[u8]) -> u64 {
+    let mut hash = 0xcbf29ce484222325u64;
+    for &b in bytes {
+        hash ^= b as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+/// Content hash of every top-level function, computed from its AST as the
+/// printer lays it out, so formatting does not invalidate cached output.
+/// Names count: which variable a name refers to decides the bytecode.
+pub fn function_hashes(program: &[Stmt]) -> Vec<(String, u64)> {
+    program
+        .iter()
+        .filter_map(|stmt| match &stmt.node {
+            StmtKind::FnDecl(name, ..) => Some((name.to_string(), declaration_hash(stmt))),
+            _ => None,
+        })
+        .collect()
+}
+
+/// The hash `function_hashes` gives the function declared by `decl`.
+pub fn declaration_hash(decl: &Stmt) -> u64 {
+    stable_hash(print_program(std::slice::from_ref(decl)).as_bytes())
+}
+
+/// The span of every statement, expression and match arm in `decl`, in the
+/// order a walk of the tree meets them, or `None` if `decl` declares
+/// functions of its own. Cached bytecode records its spans as positions in
+/// this list, so they can be found again in a declaration that prints the
+/// same but sits elsewhere in the source.
+pub fn node_spans(decl: &Stmt) -> Option<Vec<Span>> {
+    let mut spans = Vec::new();
+    match &decl.node {
+        StmtKind::FnDecl(.., body) => {
+            spans.push(decl.span);
+            stmt_spans(body, &mut spans)?;
+            Some(spans)
+        }
+        _ => None,
+    }
+}
+
+fn stmt_spans(stmts: &[Stmt], spans: &mut Vec<Span>) -> Option<()> {
+    for stmt in stmts {
+        spans.push(stmt.span);
+        match &stmt.node {
+            StmtKind::Let(_, _, expr)
+            | StmtKind::Const(_, _, expr)
+            | StmtKind::Assign(_, expr)
+            | StmtKind::Expr(expr)
+            | StmtKind::Return(Some(expr)) => expr_spans(expr, spans)?,
+            StmtKind::IndexAssign(array, index, value) => {
+                expr_spans(array, spans)?;
+                expr_spans(index, spans)?;
+                expr_spans(value, spans)?;
+            }
+            StmtKind::FieldAssign(object, _, value) => {
+                expr_spans(object, spans)?;
+                expr_spans(value, spans)?;
+            }
+            StmtKind::If(cond, then_block, else_block) => {
+                expr_spans(cond, spans)?;
+                stmt_spans(then_block, spans)?;
+                stmt_spans(else_block, spans)?;
+            }
+            StmtKind::While(cond, body) | StmtKind::DoWhile(body, cond) => {
+                expr_spans(cond, spans)?;
+                stmt_spans(body, spans)?;
+            }
+            StmtKind::For(init, cond, step, body) => {
+                stmt_spans(std::slice::from_ref(*init), spans)?;
+                expr_spans(cond, spans)?;
+                stmt_spans(std::slice::from_ref(*step), spans)?;
+                stmt_spans(body, spans)?;
+            }
+            StmtKind::ForRange(_, start, end, _, body) => {
+                expr_spans(start, spans)?;
+                expr_spans(end, spans)?;
+                stmt_spans(body, spans)?;
+            }
+            StmtKind::Match(value, arms) => {
+                expr_spans(value, spans)?;
+                for arm in arms {
+                    spans.push(arm.span);
+                    if let Pattern::Literal(literal) = &arm.pattern {
+                        expr_spans(literal, spans)?;
+                    }
+                    stmt_spans(arm.body, spans)?;
+                }
+            }
+            StmtKind::Block(body) => stmt_spans(body, spans)?,
+            StmtKind::FnDecl(..) => return None,
+            StmtKind::StructDecl(..)
+            | StmtKind::EnumDecl(..)
+            | StmtKind::TypeAlias(..)
+            | StmtKind::Import(..)
+            | StmtKind::Return(None)
+            | StmtKind::Break
+            | StmtKind::Continue => {}
+        }
+    }
+    Some(())
+}
+
+fn expr_spans(expr: &Expr, spans: &mut Vec<Span>) -> Option<()> {
+    spans.push(expr.span);
+    match &expr.node {
+        ExprKind::Unary(_, operand) | ExprKind::Field(operand, _) => expr_spans(operand, spans)?,
+        ExprKind::Binary(lhs, _, rhs) | ExprKind::Index(lhs, rhs) => {
+            expr_spans(lhs, spans)?;
+            expr_spans(rhs, spans)?;
+        }
+        ExprKind::Call(_, elements) | ExprKind::Array(elements) => {
+            for element in elements.iter() {
+                expr_spans(element, spans)?;
+            }
+        }
+        ExprKind::Map(entries) => {
+            for (key, value) in entries {
+                expr_spans(key, spans)?;
+                expr_spans(value, spans)?;
+            }
+        }
+        ExprKind::If(cond, then_value, else_value) => {
+            expr_spans(cond, spans)?;
+            expr_spans(then_value, spans)?;
+            expr_spans(else_value, spans)?;
+        }
+        ExprKind::Block(stmts, value) => {
+            stmt_spans(stmts, spans)?;
+            expr_spans(value, spans)?;
+        }
+        ExprKind::Match(value, arms) => {
+            expr_spans(value, spans)?;
+            for arm in arms {
+                spans.push(arm.span);
+                if let Pattern::Literal(literal) = &arm.pattern {
+                    expr_spans(literal, spans)?;
+                }
+                expr_spans(&arm.value, spans)?;
+            }
+        }
+        ExprKind::StructLit(_, fields) => {
+            for (_, value) in fields {
+                expr_spans(value, spans)?;
+            }
+        }
+        ExprKind::Number(_)
+        | ExprKind::Float(_)
+        | ExprKind::Str(_)
+        | ExprKind::Char(_)
+        | ExprKind::Bool(_)
+        | ExprKind::Null
+        | ExprKind::Variable(_)
+        | ExprKind::Update(..)
+        | ExprKind::Variant(..) => {}
+    }
+    Some(())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CacheKey {
+    pub function_hash: u64,
+    pub options_hash: u64,
+}
+
+impl CacheKey {
+    /// `options` is any rendering of the compiler options that affect codegen.
+    pub fn new(function_hash: u64, options: &str) -> Self {
+        Self {
+            function_hash,
+            options_hash: stable_hash(options.as_bytes()),
+        }
+    }
+
+    fn file_name(&self) -> String {
+        format!("{:016x}-{:016x}.bin", self.function_hash, self.options_hash)
+    }
+}
+
+/// On-disk store of per-function compiled artifacts keyed by `CacheKey`.
+pub struct CompileCache {
+    dir: PathBuf,
+}
+
+impl CompileCache {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    pub fn get(&self, key: &CacheKey) -> Option<Vec<u8>> {
+        fs::read(self.dir.join(key.file_name())).ok()
+    }
+
+    pub fn put(&self, key: &CacheKey, artifact: &[u8]) -> io::Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        // Write then rename so a concurrent reader never sees a partial entry.
+        let path = self.dir.join(key.file_name());
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, artifact)?;
+        fs::rename(tmp, path)
+    }
+}
+
+/// How one compilation used a `CompileCache`: how many functions
+/// it linked in from there and how many it had to compile.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub reused: usize,
+    pub compiled: usize,
+}
\ No newline at end of file
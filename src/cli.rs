@@ -0,0 +1,364 @@
+use ferrum::InterpreterOptions;
+
+pub const USAGE: &str = "\
+usage: ferrum [options] <file.lang>...
+       ferrum --repl
+       ferrum compile [-o <out.bcode>] [options] <file.lang>...
+       ferrum run <file.bcode>
+       ferrum debug [options] <file.lang>
+       ferrum test [options] <file.lang>...
+       ferrum diff <old.lang> <new.lang>
+       ferrum fmt [--write] <file.lang>...
+       ferrum lsp
+
+options:
+  --emit <what>   also print tokens, ast, typed-ast, ast-json, ir or none (the default);
+                  c prints the program translated to C instead of running it
+  --repl          start an interactive session instead of running files
+  --verbose       print a phase-by-phase account of compilation to stderr
+  --no-typecheck  skip static type checking and let errors surface at runtime
+  --no-prelude    do not load the prelude's clamp function; --emit c, --target wasm and
+                  --backend jit still load its abs, min, max and pow on ints
+  --deny-warnings treat warnings as errors and stop before running
+  --error-format <format>
+                  print diagnostics as human (the default) or json, one object per line
+  --trace         log each statement the interpreter runs, with the values it uses, to stderr
+  --trace-file <path>
+                  write that log to a file instead
+  --profile       count calls to each function and the time spent in it, and print
+                  a report to stderr when the program ends
+  --coverage      print the source annotated with how often each line ran to stderr
+                  when the program ends (one input file only)
+  --coverage-lcov <path>
+                  write the counts to an LCOV tracefile instead
+  --exit-code     exit with the program's result, the int it returns at top level or
+                  its final expression, as the status
+  --max-steps <n> stop the interpreter after n statements and expressions
+  --max-millis <ms>
+                  stop the interpreter after ms milliseconds
+  --max-memory <bytes>
+                  stop the interpreter when its strings and arrays take more than bytes
+  --seed <n>      seed `random` so that every run draws the same numbers
+  --vm            compile to bytecode and run it on the virtual machine
+  --cache-dir <dir>
+                  keep each function's bytecode in dir and reuse it for as long as the
+                  function is unchanged (with compile or --vm)
+  --target wasm   print the program as a WebAssembly text module instead of running it
+  --backend jit   compile the program to native code with Cranelift and run it
+                  (needs the `jit` feature)
+
+`ferrum test` runs the program's top-level code, then calls each function whose
+name starts with test_ in the input files and prints which passed and which failed.
+`ferrum lsp` serves the Language Server Protocol on stdio (needs the `lsp` feature).";
+
+pub enum Command {
+    Run(Options),
+    /// Start an interactive session; the flag is whether to load the prelude.
+    Repl(bool),
+    /// Compile to a `.bcode` file; `output` defaults to the first input with
+    /// its extension replaced.
+    Compile { options: Options, output: Option<String> },
+    RunBytecode(String),
+    /// Run one file under the interactive debugger.
+    Debug(Options),
+    /// Run the `test_` functions of the input files.
+    Test(Options),
+    Diff(String, String),
+    Fmt { files: Vec<String>, write: bool },
+    #[cfg(feature = "lsp")]
+    Lsp,
+}
+
+/// What `--emit` prints before the program runs.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Emit {
+    None,
+    Tokens,
+    Ast,
+    TypedAst,
+    AstJson,
+    Ir,
+    C,
+}
+
+/// How `--error-format` prints warnings and errors.
+#[derive(Clone, Copy, PartialEq)]
+pub enum ErrorFormat {
+    /// Rendered with a source snippet, for reading in a terminal.
+    Human,
+    /// One JSON object per line, for editors and CI tools.
+    Json,
+}
+
+/// Where `--trace` logs the statements the interpreter runs.
+pub enum TraceTarget {
+    Stderr,
+    File(String),
+}
+
+/// How `--coverage` reports which statements ran.
+pub enum CoverageReport {
+    /// The annotated source, on stderr.
+    Text,
+    /// An LCOV tracefile at this path.
+    Lcov(String),
+}
+
+pub struct Options {
+    pub files: Vec<String>,
+    pub verbose: bool,
+    pub typecheck: bool,
+    /// Load the functions of `ferrum::prelude` ahead of the program.
+    pub prelude: bool,
+    /// Fail when the analysis pass reports any warning.
+    pub deny_warnings: bool,
+    pub error_format: ErrorFormat,
+    pub trace: Option<TraceTarget>,
+    pub profile: bool,
+    pub coverage: Option<CoverageReport>,
+    /// Use the program's result as the exit status.
+    pub exit_code: bool,
+    /// `--max-steps`, `--max-millis` and `--max-memory`.
+    pub limits: InterpreterOptions,
+    /// Seed for `random`; the clock when `None`.
+    pub seed: Option<u64>,
+    pub emit: Emit,
+    /// Run on the bytecode VM instead of the tree-walking interpreter.
+    pub vm: bool,
+    /// `--cache-dir`: where compiled functions are kept between runs.
+    pub cache_dir: Option<String>,
+    /// `--target wasm`: print a WebAssembly module instead of running.
+    pub wasm: bool,
+    /// `--backend jit`: run natively through the Cranelift JIT.
+    pub jit: bool,
+}
+
+impl Options {
+    /// Whether the interpreter is asked to watch the program as it runs.
+    fn instrumented(&self) -> bool {
+        self.trace.is_some() || self.profile || self.coverage.is_some()
+    }
+}
+
+pub fn parse_args(args: &[String]) -> Result<Command, String> {
+    if args.first().map(String::as_str) == Some("diff") {
+        return match &args[1..] {
+            [old, new] => Ok(Command::Diff(old.clone(), new.clone())),
+            _ => Err("diff expects exactly two files".to_string()),
+        };
+    }
+    if args.first().map(String::as_str) == Some("compile") {
+        let mut output = None;
+        let mut rest = Vec::new();
+        let mut args = args[1..].iter();
+        while let Some(arg) = args.next() {
+            if arg == "-o" {
+                output = Some(args.next().ok_or("-o expects a file name")?.clone());
+            } else {
+                rest.push(arg.clone());
+            }
+        }
+        return match parse_options(&rest)? {
+            (options, false) if !options.vm && !options.wasm && !options.jit && !options.instrumented() && !options.exit_code && options.seed.is_none() => {
+                Ok(Command::Compile { options, output })
+            }
+            _ => Err("compile does not take --repl, --vm, --target, --backend, --trace, --profile, --coverage, --exit-code or --seed".to_string()),
+        };
+    }
+    if args.first().map(String::as_str) == Some("debug") {
+        return match parse_options(&args[1..])? {
+            (options, false) if options.vm || options.cache_dir.is_some() || options.wasm || options.jit || options.emit == Emit::C => {
+                Err("debug runs the interpreter and does not take --vm, --cache-dir, --target, --backend or --emit c".to_string())
+            }
+            (options, false) if options.instrumented() => Err("debug does not take --trace, --profile or --coverage".to_string()),
+            (options, false) if options.files.len() == 1 => Ok(Command::Debug(options)),
+            (_, false) => Err("debug expects exactly one file".to_string()),
+            (_, true) => Err("debug does not take --repl".to_string()),
+        };
+    }
+    if args.first().map(String::as_str) == Some("test") {
+        return match parse_options(&args[1..])? {
+            (options, false) if options.vm || options.cache_dir.is_some() || options.wasm || options.jit || options.emit == Emit::C => {
+                Err("test runs the interpreter and does not take --vm, --cache-dir, --target, --backend or --emit c".to_string())
+            }
+            (options, false) if options.instrumented() || options.exit_code => {
+                Err("test does not take --trace, --profile, --coverage or --exit-code".to_string())
+            }
+            (options, false) => Ok(Command::Test(options)),
+            (_, true) => Err("test does not take --repl".to_string()),
+        };
+    }
+    if args.first().map(String::as_str) == Some("run") {
+        return match &args[1..] {
+            [file] => Ok(Command::RunBytecode(file.clone())),
+            _ => Err("run expects exactly one .bcode file".to_string()),
+        };
+    }
+    if args.first().map(String::as_str) == Some("fmt") {
+        let write = args[1..].iter().any(|arg| arg == "--write");
+        let files: Vec<String> = args[1..].iter().filter(|arg| *arg != "--write").cloned().collect();
+        if let Some(flag) = files.iter().find(|file| file.starts_with("--")) {
+            return Err(format!("unknown option: {}", flag));
+        }
+        if files.is_empty() {
+            return Err("fmt expects at least one file".to_string());
+        }
+        return Ok(Command::Fmt { files, write });
+    }
+
+    if args.first().map(String::as_str) == Some("lsp") {
+        // Editors commonly pass `--stdio`, which is the only transport anyway.
+        if let Some(arg) = args[1..].iter().find(|arg| *arg != "--stdio") {
+            return Err(format!("lsp does not take {}", arg));
+        }
+        #[cfg(feature = "lsp")]
+        return Ok(Command::Lsp);
+        #[cfg(not(feature = "lsp"))]
+        return Err("lsp needs the `lsp` feature".to_string());
+    }
+
+    let (options, repl) = parse_options(args)?;
+    if options.cache_dir.is_some() && !options.vm {
+        return Err("--cache-dir caches bytecode and needs --vm or compile".to_string());
+    }
+    if repl {
+        return match options.files.first() {
+            Some(file) => Err(format!("--repl does not take input files, found {}", file)),
+            None => Ok(Command::Repl(options.prelude)),
+        };
+    }
+    Ok(Command::Run(options))
+}
+
+/// Parses the options shared by running and compiling source files; the
+/// flag is whether `--repl` was given.
+fn parse_options(args: &[String]) -> Result<(Options, bool), String> {
+    let mut repl = false;
+    let mut options = Options {
+        files: Vec::new(),
+        verbose: false,
+        typecheck: true,
+        prelude: true,
+        deny_warnings: false,
+        error_format: ErrorFormat::Human,
+        trace: None,
+        profile: false,
+        coverage: None,
+        exit_code: false,
+        limits: InterpreterOptions::default(),
+        seed: None,
+        emit: Emit::None,
+        vm: false,
+        cache_dir: None,
+        wasm: false,
+        jit: false,
+    };
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--emit" => {
+                options.emit = match args.next().map(String::as_str) {
+                    Some("none") => Emit::None,
+                    Some("tokens") => Emit::Tokens,
+                    Some("ast") => Emit::Ast,
+                    Some("typed-ast") => Emit::TypedAst,
+                    Some("ast-json") if cfg!(feature = "serde") => Emit::AstJson,
+                    Some("ast-json") => return Err("--emit ast-json needs the `serde` feature".to_string()),
+                    Some("ir") => Emit::Ir,
+                    Some("c") => Emit::C,
+                    Some(other) => return Err(format!("unknown --emit kind: {}", other)),
+                    None => return Err("--emit expects tokens, ast, typed-ast, ast-json, ir, c or none".to_string()),
+                }
+            }
+            "--repl" => repl = true,
+            "--verbose" => options.verbose = true,
+            "--no-typecheck" => options.typecheck = false,
+            "--no-prelude" => options.prelude = false,
+            "--deny-warnings" => options.deny_warnings = true,
+            "--error-format" => match args.next().map(String::as_str) {
+                Some("human") => options.error_format = ErrorFormat::Human,
+                Some("json") => options.error_format = ErrorFormat::Json,
+                Some(other) => return Err(format!("unknown --error-format: {} (expected human or json)", other)),
+                None => return Err("--error-format expects human or json".to_string()),
+            },
+            "--trace" => options.trace = Some(TraceTarget::Stderr),
+            "--trace-file" => match args.next() {
+                Some(path) => options.trace = Some(TraceTarget::File(path.clone())),
+                None => return Err("--trace-file expects a file name".to_string()),
+            },
+            "--profile" => options.profile = true,
+            "--coverage" => options.coverage = Some(CoverageReport::Text),
+            "--coverage-lcov" => match args.next() {
+                Some(path) => options.coverage = Some(CoverageReport::Lcov(path.clone())),
+                None => return Err("--coverage-lcov expects a file name".to_string()),
+            },
+            "--exit-code" => options.exit_code = true,
+            "--max-steps" => options.limits.max_steps = Some(parse_limit("--max-steps", args.next())?),
+            "--max-millis" => options.limits.max_millis = Some(parse_limit("--max-millis", args.next())?),
+            "--max-memory" => options.limits.max_memory = Some(parse_limit("--max-memory", args.next())?),
+            "--seed" => options.seed = Some(parse_limit("--seed", args.next())?),
+            "--vm" => options.vm = true,
+            "--cache-dir" => match args.next() {
+                Some(dir) => options.cache_dir = Some(dir.clone()),
+                None => return Err("--cache-dir expects a directory".to_string()),
+            },
+            "--target" => match args.next().map(String::as_str) {
+                Some("wasm") => options.wasm = true,
+                Some(other) => return Err(format!("unknown --target: {} (expected wasm)", other)),
+                None => return Err("--target expects wasm".to_string()),
+            },
+            "--backend" => match args.next().map(String::as_str) {
+                Some("jit") if cfg!(feature = "jit") => options.jit = true,
+                Some("jit") => return Err("--backend jit needs the `jit` feature".to_string()),
+                Some(other) => return Err(format!("unknown --backend: {} (expected jit)", other)),
+                None => return Err("--backend expects jit".to_string()),
+            },
+            flag if flag.starts_with("--") => return Err(format!("unknown option: {}", flag)),
+            file => options.files.push(file.to_string()),
+        }
+    }
+    if repl {
+        return Ok((options, true));
+    }
+    if options.files.is_empty() {
+        return Err("no input files".to_string());
+    }
+    if matches!(options.emit, Emit::TypedAst | Emit::C) && !options.typecheck {
+        return Err("--emit typed-ast and --emit c cannot be combined with --no-typecheck".to_string());
+    }
+    if options.wasm && !options.typecheck {
+        return Err("--target wasm cannot be combined with --no-typecheck".to_string());
+    }
+    if options.jit && !options.typecheck {
+        return Err("--backend jit cannot be combined with --no-typecheck".to_string());
+    }
+    if options.jit && (options.vm || options.wasm || options.emit == Emit::C) {
+        return Err("--backend jit cannot be combined with --vm, --target or --emit c".to_string());
+    }
+    if options.instrumented() && (options.vm || options.wasm || options.jit || options.emit == Emit::C) {
+        return Err(
+            "--trace, --profile and --coverage need the interpreter and cannot be combined with --vm, --target, --backend or --emit c"
+                .to_string(),
+        );
+    }
+    if options.limits != InterpreterOptions::default() && (options.vm || options.wasm || options.jit || options.emit == Emit::C) {
+        return Err(
+            "--max-steps, --max-millis and --max-memory limit the interpreter and cannot be combined with --vm, --target, --backend or --emit c"
+                .to_string(),
+        );
+    }
+    if options.seed.is_some() && (options.wasm || options.jit || options.emit == Emit::C) {
+        return Err("--seed needs the interpreter or --vm and cannot be combined with --target, --backend or --emit c".to_string());
+    }
+    if options.exit_code && (options.vm || options.wasm || options.jit || options.emit == Emit::C) {
+        return Err("--exit-code needs the interpreter and cannot be combined with --vm, --target, --backend or --emit c".to_string());
+    }
+    if options.coverage.is_some() && options.files.len() > 1 {
+        return Err("--coverage reports on a single input file".to_string());
+    }
+    Ok((options, false))
+}
+
+fn parse_limit<T: std::str::FromStr>(flag: &str, value: Option<&String>) -> Result<T, String> {
+    value.and_then(|value| value.parse().ok()).ok_or_else(|| format!("{} expects a whole number", flag))
+}
\ No newline at end of file
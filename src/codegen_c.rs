@@ -0,0 +1,653 @@
+use crate::ast::*;
+use crate::error::CompilerError;
+use crate::span::Span;
+use crate::type_checker::{Type, TypeChecker};
+use std::collections::HashMap;
+
+/// Support code every generated file starts with. Integer arithmetic is
+/// checked like the interpreter's default mode, and runtime errors are
+/// reported the same way with the same exit status.
+const PRELUDE: &str = r#"#include <inttypes.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+
+static void ferrum_fail(const char *msg, int line, int col) {
+    fflush(stdout);
+    fprintf(stderr, "Runtime error at line %d, column %d: %s\n", line, col, msg);
+    exit(4);
+}
+
+static inline int64_t ferrum_add(int64_t a, int64_t b, int line, int col) {
+    if ((b > 0 && a > INT64_MAX - b) || (b < 0 && a < INT64_MIN - b)) ferrum_fail("Integer overflow in '+'", line, col);
+    return a + b;
+}
+
+static inline int64_t ferrum_sub(int64_t a, int64_t b, int line, int col) {
+    if ((b < 0 && a > INT64_MAX + b) || (b > 0 && a < INT64_MIN + b)) ferrum_fail("Integer overflow in '-'", line, col);
+    return a - b;
+}
+
+static inline int64_t ferrum_mul(int64_t a, int64_t b, int line, int col) {
+    if (a != 0 && b != 0
+        && (a > 0 ? (b > 0 ? a > INT64_MAX / b : b < INT64_MIN / a) : (b > 0 ? a < INT64_MIN / b : a < INT64_MAX / b)))
+        ferrum_fail("Integer overflow in '*'", line, col);
+    return a * b;
+}
+
+static inline int64_t ferrum_div(int64_t a, int64_t b, int line, int col) {
+    if (b == 0) ferrum_fail("Division by zero", line, col);
+    if (a == INT64_MIN && b == -1) ferrum_fail("Integer overflow in '/'", line, col);
+    return a / b;
+}
+
+static inline int64_t ferrum_post_inc(int64_t *x, int line, int col) {
+    int64_t old = *x;
+    *x = ferrum_add(old, 1, line, col);
+    return old;
+}
+
+static inline int64_t ferrum_post_dec(int64_t *x, int line, int col) {
+    int64_t old = *x;
+    *x = ferrum_sub(old, 1, line, col);
+    return old;
+}
+
+static inline int64_t ferrum_read_int(int line, int col) {
+    char buf[256];
+    char *end;
+    fflush(stdout);
+    if (!fgets(buf, sizeof buf, stdin)) ferrum_fail("read_int reached the end of input", line, col);
+    int64_t n = strtoll(buf, &end, 10);
+    while (*end == ' ' || *end == '\t' || *end == '\r' || *end == '\n') end++;
+    if (end == buf || *end != '\0') ferrum_fail("read_int expected an integer", line, col);
+    return n;
+}
+"#;
+
+/// Translates a type-checked program to a standalone C99 file that builds
+/// with `cc -std=c99`. `checker` must already have checked `program`.
+///
+/// Ints, bools, string literals, functions and all control flow are
+/// supported; floats, arrays, `len` and `read_line` are reported as
+/// [`CompilerError::UnsupportedError`]. Top-level `let`s become C globals and
+/// nested functions are lifted to file scope, so a nested function may only
+/// use its own locals and globals. Operands and arguments are evaluated in
+/// whatever order the C compiler picks.
+pub fn generate(program: &[Stmt], checker: &TypeChecker) -> Result<String, CompilerError> {
+    let mut generator = Generator { types: checker, globals: HashMap::new(), functions: Vec::new() };
+    generator.declare_globals(program)?;
+    let mut definitions = Vec::new();
+    collect_functions(program, &mut definitions);
+
+    let mut prototypes = Vec::new();
+    let mut bodies = Vec::new();
+    for stmt in &definitions {
+        let StmtKind::FnDecl(name, params, _, body) = &stmt.node else {
+            unreachable!("collect_functions only returns declarations");
+        };
+        if generator.functions.iter().any(|function| *name == function.as_str()) {
+            return Err(unsupported(&format!("two functions named {}", name), stmt.span));
+        }
+        generator.functions.push(name.to_string());
+        let (param_types, return_type) = checker.function_type(name).cloned().expect("the checker saw every function");
+
+        let mut state = FnState::new(false);
+        let mut c_params = Vec::new();
+        for (param, t) in params.iter().zip(&param_types) {
+            c_params.push(declare(generator.c_type(t, stmt.span)?, &state.bind(&param.name, t.clone())));
+        }
+        let c_params = if c_params.is_empty() { "void".to_string() } else { c_params.join(", ") };
+        let signature = format!("static {}({})", declare(generator.c_type(&return_type, stmt.span)?, &format!("f_{}", name)), c_params);
+
+        state.indent = 1;
+        generator.stmts(&mut state, body)?;
+        prototypes.push(format!("{};\n", signature));
+        bodies.push(format!("{} {{\n{}}}\n", signature, state.out));
+    }
+
+    let mut main = FnState::new(true);
+    main.indent = 1;
+    generator.stmts(&mut main, program)?;
+
+    let mut out = String::from("/* Generated by ferrum. */\n");
+    out.push_str(PRELUDE);
+    let mut globals: Vec<_> = generator.globals.iter().collect();
+    globals.sort_by_key(|(name, _)| *name);
+    if !globals.is_empty() {
+        out.push('\n');
+    }
+    for (name, t) in globals {
+        out.push_str(&format!("static {};\n", declare(generator.c_type(t, Span::default())?, &format!("g_{}", name))));
+    }
+    if !prototypes.is_empty() {
+        out.push('\n');
+    }
+    for prototype in &prototypes {
+        out.push_str(prototype);
+    }
+    for body in &bodies {
+        out.push('\n');
+        out.push_str(body);
+    }
+    out.push_str(&format!("\nint main(void) {{\n{}    return 0;\n}}\n", main.out));
+    Ok(out)
+}
+
+fn unsupported(what: &str, span: Span) -> CompilerError {
+    CompilerError::UnsupportedError(format!("The C backend does not support {}", what), Some(span))
+}
+
+/// C leaves the evaluation order of operands and arguments unspecified, so
+/// `++`/`--` is rejected where another operand could observe it.
+fn check_unsequenced(operands: &[&Expr]) -> Result<(), CompilerError> {
+    if operands.len() < 2 {
+        return Ok(());
+    }
+    match operands.iter().find_map(|operand| find_update(operand)) {
+        Some(span) => Err(unsupported("'++' or '--' next to another operand or argument", span)),
+        None => Ok(()),
+    }
+}
+
+fn find_update(expr: &Expr) -> Option<Span> {
+    match &expr.node {
+        ExprKind::Update(..) => Some(expr.span),
+        ExprKind::Unary(_, operand) => find_update(operand),
+        ExprKind::Binary(lhs, _, rhs) | ExprKind::Index(lhs, rhs) => find_update(lhs).or_else(|| find_update(rhs)),
+        ExprKind::If(cond, then_value, else_value) => {
+            find_update(cond).or_else(|| find_update(then_value)).or_else(|| find_update(else_value))
+        }
+        ExprKind::Call(_, args) | ExprKind::Array(args) => args.iter().find_map(find_update),
+        _ => None,
+    }
+}
+
+/// A C declaration of `name` with type `c_type`.
+fn declare(c_type: &str, name: &str) -> String {
+    if c_type.ends_with('*') {
+        format!("{}{}", c_type, name)
+    } else {
+        format!("{} {}", c_type, name)
+    }
+}
+
+/// The type of `expr` for code generation; `variable` gives the type of a
+/// name in scope. The checker's record is not enough on its own: a call
+/// checked while its callee's result type was still being inferred, such as
+/// a recursive call, was recorded as `Error`.
+pub(crate) fn expr_type(checker: &TypeChecker, expr: &Expr, variable: &dyn Fn(&str) -> Option<Type>) -> Type {
+    match &expr.node {
+        ExprKind::Variable(name) => {
+            if let Some(t) = variable(name) {
+                return t;
+            }
+        }
+        ExprKind::Unary(_, operand) => return expr_type(checker, operand, variable),
+        ExprKind::Binary(lhs, BinOp::Add | BinOp::Sub | BinOp::Mul | BinOp::Div | BinOp::Pow | BinOp::BitAnd | BinOp::BitOr | BinOp::BitXor | BinOp::Shl | BinOp::Shr, rhs) => {
+            return match expr_type(checker, lhs, variable) {
+                Type::Error => expr_type(checker, rhs, variable),
+                t => t,
+            };
+        }
+        ExprKind::Binary(..) => return Type::Bool,
+        ExprKind::If(_, then_value, else_value) => {
+            return match expr_type(checker, then_value, variable) {
+                Type::Error => expr_type(checker, else_value, variable),
+                t => t,
+            };
+        }
+        ExprKind::Match(_, arms) => {
+            return arms.iter().map(|arm| expr_type(checker, &arm.value, variable)).find(|t| *t != Type::Error).unwrap_or(Type::Error);
+        }
+        ExprKind::Call(name, _) => {
+            if let Some((_, t)) = checker.function_type(name) {
+                return t.clone();
+            }
+        }
+        _ => {}
+    }
+    checker.expr_type(expr.span).cloned().unwrap_or(Type::Error)
+}
+
+/// Every function declared in `stmts`, at any depth, outermost first.
+pub(crate) fn collect_functions<'a>(stmts: &'a [Stmt<'a>], out: &mut Vec<&'a Stmt<'a>>) {
+    for stmt in stmts {
+        match &stmt.node {
+            StmtKind::FnDecl(.., body) => {
+                out.push(stmt);
+                collect_functions(body, out);
+            }
+            StmtKind::If(_, then_block, else_block) => {
+                collect_functions(then_block, out);
+                collect_functions(else_block, out);
+            }
+            StmtKind::Match(_, arms) => {
+                for arm in arms {
+                    collect_functions(arm.body, out);
+                }
+            }
+            StmtKind::While(_, body)
+            | StmtKind::DoWhile(body, _)
+            | StmtKind::For(.., body)
+            | StmtKind::ForRange(.., body)
+            | StmtKind::Block(body) => collect_functions(body, out),
+            _ => {}
+        }
+    }
+}
+
+/// Quotes `s` as a C string literal. Anything outside printable ASCII is
+/// written as an octal escape so the file's encoding does not matter.
+fn c_string(s: &str) -> String {
+    let mut out = String::from("\"");
+    for b in s.bytes() {
+        match b {
+            b'\n' => out.push_str("\\n"),
+            b'\t' => out.push_str("\\t"),
+            b'\\' => out.push_str("\\\\"),
+            b'"' => out.push_str("\\\""),
+            // `??x` would otherwise be read as a trigraph.
+            b'?' => out.push_str("\\?"),
+            0x20..=0x7e => out.push(b as char),
+            _ => out.push_str(&format!("\\{:03o}", b)),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Per-function state: the C text so far and the names in scope.
+struct FnState {
+    out: String,
+    indent: usize,
+    /// Block scopes mapping source names to C names and types, innermost last.
+    scopes: Vec<Vec<(String, String, Type)>>,
+    /// How many locals of each name the function has declared, so each gets
+    /// a distinct C name even where C would reject the redeclaration.
+    declared: HashMap<String, usize>,
+    is_main: bool,
+}
+
+impl FnState {
+    fn new(is_main: bool) -> Self {
+        Self { out: String::new(), indent: 0, scopes: vec![Vec::new()], declared: HashMap::new(), is_main }
+    }
+
+    fn line(&mut self, text: &str) {
+        for _ in 0..self.indent {
+            self.out.push_str("    ");
+        }
+        self.out.push_str(text);
+        self.out.push('\n');
+    }
+
+    /// Declares a local in the innermost scope and returns its C name.
+    fn bind(&mut self, name: &str, t: Type) -> String {
+        let count = self.declared.entry(name.to_string()).or_insert(0);
+        let c_name = if *count == 0 { format!("v_{}", name) } else { format!("v_{}_{}", name, count) };
+        *count += 1;
+        self.scopes.last_mut().expect("a function always has a scope").push((name.to_string(), c_name.clone(), t));
+        c_name
+    }
+
+    fn local(&self, name: &str) -> Option<(&str, &Type)> {
+        self.scopes.iter().rev().flat_map(|scope| scope.iter().rev()).find(|(n, ..)| n == name).map(|(_, c, t)| (c.as_str(), t))
+    }
+
+    fn at_global_level(&self) -> bool {
+        self.is_main && self.scopes.len() == 1
+    }
+}
+
+struct Generator<'a> {
+    types: &'a TypeChecker,
+    /// Top-level variables and their types.
+    globals: HashMap<String, Type>,
+    /// Names of the functions generated so far.
+    functions: Vec<String>,
+}
+
+impl Generator<'_> {
+    fn declare_globals(&mut self, program: &[Stmt]) -> Result<(), CompilerError> {
+        let top_level = FnState::new(true);
+        for stmt in program {
+            if let StmtKind::Let(name, _, expr) | StmtKind::Const(name, _, expr) = &stmt.node {
+                let t = self.type_of(&top_level, expr);
+                match self.globals.get(name.as_str()) {
+                    Some(existing) if *existing != t => {
+                        return Err(unsupported(&format!("redeclaring global {} with a different type", name), stmt.span));
+                    }
+                    _ => {
+                        self.globals.insert(name.to_string(), t);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn type_of(&self, state: &FnState, expr: &Expr) -> Type {
+        let variable = |name: &str| state.local(name).map(|(_, t)| t.clone()).or_else(|| self.globals.get(name).cloned());
+        expr_type(self.types, expr, &variable)
+    }
+
+    fn c_type(&self, t: &Type, span: Span) -> Result<&'static str, CompilerError> {
+        match t {
+            Type::Int => Ok("int64_t"),
+            Type::Bool => Ok("bool"),
+            Type::String => Ok("const char *"),
+            Type::Void => Ok("void"),
+            Type::Error => Err(unsupported("a value whose type could not be inferred", span)),
+            other => Err(unsupported(&format!("values of type {}", other), span)),
+        }
+    }
+
+    fn variable(&self, state: &FnState, name: &str, span: Span) -> Result<String, CompilerError> {
+        if let Some((c_name, _)) = state.local(name) {
+            return Ok(c_name.to_string());
+        }
+        if self.globals.contains_key(name) {
+            return Ok(format!("g_{}", name));
+        }
+        Err(unsupported(&format!("using {} from an enclosing function", name), span))
+    }
+
+    fn block(&mut self, state: &mut FnState, stmts: &[Stmt]) -> Result<(), CompilerError> {
+        state.scopes.push(Vec::new());
+        state.indent += 1;
+        self.stmts(state, stmts)?;
+        state.indent -= 1;
+        state.scopes.pop();
+        Ok(())
+    }
+
+    fn stmts(&mut self, state: &mut FnState, stmts: &[Stmt]) -> Result<(), CompilerError> {
+        for stmt in stmts {
+            self.stmt(state, stmt)?;
+        }
+        Ok(())
+    }
+
+    /// A `let`, assignment or expression statement as C without its `;`,
+    /// so it can also go in the head of a `for` loop.
+    fn simple_stmt(&mut self, state: &mut FnState, stmt: &Stmt) -> Result<String, CompilerError> {
+        match &stmt.node {
+            StmtKind::Let(name, _, expr) | StmtKind::Const(name, _, expr) => {
+                let value = self.expr(state, expr)?;
+                if state.at_global_level() {
+                    Ok(format!("g_{} = {}", name, value))
+                } else {
+                    let t = self.type_of(state, expr);
+                    let c_type = self.c_type(&t, expr.span)?;
+                    let c_name = state.bind(name, t);
+                    Ok(format!("{} = {}", declare(c_type, &c_name), value))
+                }
+            }
+            StmtKind::Assign(name, expr) => {
+                let value = self.expr(state, expr)?;
+                let target = self.variable(state, name, stmt.span)?;
+                Ok(format!("{} = {}", target, value))
+            }
+            StmtKind::Expr(expr) => self.expr(state, expr),
+            _ => Err(unsupported("arrays", stmt.span)),
+        }
+    }
+
+    fn stmt(&mut self, state: &mut FnState, stmt: &Stmt) -> Result<(), CompilerError> {
+        match &stmt.node {
+            StmtKind::Let(..) | StmtKind::Const(..) | StmtKind::Assign(..) | StmtKind::Expr(_) => {
+                let code = self.simple_stmt(state, stmt)?;
+                state.line(&format!("{};", code));
+            }
+            StmtKind::IndexAssign(..) => return Err(unsupported("arrays", stmt.span)),
+            StmtKind::FieldAssign(..) => return Err(unsupported("structs", stmt.span)),
+            StmtKind::If(cond, then_block, else_block) => {
+                let cond = self.condition(state, cond)?;
+                state.line(&format!("if ({}) {{", cond));
+                self.block(state, then_block)?;
+                if !else_block.is_empty() {
+                    state.line("} else {");
+                    self.block(state, else_block)?;
+                }
+                state.line("}");
+            }
+            StmtKind::While(cond, body) => {
+                let cond = self.condition(state, cond)?;
+                state.line(&format!("while ({}) {{", cond));
+                self.block(state, body)?;
+                state.line("}");
+            }
+            StmtKind::DoWhile(body, cond) => {
+                state.line("do {");
+                self.block(state, body)?;
+                let cond = self.condition(state, cond)?;
+                state.line(&format!("}} while ({});", cond));
+            }
+            StmtKind::For(init, cond, step, body) => {
+                // A variable declared by the initializer lives in its own
+                // scope around the body.
+                state.scopes.push(Vec::new());
+                let init = self.simple_stmt(state, init)?;
+                let cond = self.condition(state, cond)?;
+                let step = self.simple_stmt(state, step)?;
+                state.line(&format!("for ({}; {}; {}) {{", init, cond, step));
+                self.block(state, body)?;
+                state.line("}");
+                state.scopes.pop();
+            }
+            StmtKind::ForRange(var, start, end, inclusive, body) => {
+                let start = self.expr(state, start)?;
+                let end = self.expr(state, end)?;
+                // The loop counts in hidden locals, so assigning to the
+                // variable does not change the iteration. Their names cannot
+                // clash with those of source variables, which start `v_`.
+                let count = state.declared.entry("{range}".to_string()).or_insert(0);
+                let (next, last, more) = (format!("r{}_next", count), format!("r{}_end", count), format!("r{}_more", count));
+                *count += 1;
+                if *inclusive {
+                    // `more` stops `next` from stepping past an end of INT64_MAX.
+                    state.line(&format!(
+                        "for (int64_t {next} = {start}, {last} = {end}, {more} = {next} <= {last}; {more}; {more} = {next} < {last}, {next} += {more}) {{"
+                    ));
+                } else {
+                    state.line(&format!("for (int64_t {next} = {start}, {last} = {end}; {next} < {last}; {next}++) {{"));
+                }
+                state.scopes.push(Vec::new());
+                state.indent += 1;
+                let c_name = state.bind(var, Type::Int);
+                state.line(&format!("int64_t {} = {};", c_name, next));
+                state.indent -= 1;
+                self.block(state, body)?;
+                state.scopes.pop();
+                state.line("}");
+            }
+            // An `if` chain on a hidden local rather than a `switch`, where
+            // `break` would leave the switch instead of the enclosing loop.
+            StmtKind::Match(value, arms) => {
+                let t = self.type_of(state, value);
+                if !matches!(t, Type::Int | Type::Bool) {
+                    return Err(unsupported(&format!("matching on {}", t), value.span));
+                }
+                let code = self.expr(state, value)?;
+                let count = state.declared.entry("{match}".to_string()).or_insert(0);
+                let hidden = format!("m{}_value", count);
+                *count += 1;
+                state.line(&format!("{} = {};", declare(self.c_type(&t, value.span)?, &hidden), code));
+                let mut open = false;
+                for arm in arms {
+                    match &arm.pattern {
+                        Pattern::Literal(literal) => {
+                            let pattern = self.expr(state, literal)?;
+                            let keyword = if open { "} else if" } else { "if" };
+                            state.line(&format!("{} ({} == {}) {{", keyword, hidden, pattern));
+                        }
+                        Pattern::Wildcard => state.line(if open { "} else {" } else { "{" }),
+                    }
+                    open = true;
+                    self.block(state, arm.body)?;
+                    if matches!(arm.pattern, Pattern::Wildcard) {
+                        break;
+                    }
+                }
+                if open {
+                    state.line("}");
+                }
+            }
+            // Lifted to file scope by `generate`.
+            StmtKind::FnDecl(..) => {}
+            StmtKind::StructDecl(..) | StmtKind::EnumDecl(..) | StmtKind::TypeAlias(..) | StmtKind::Import(..) => {}
+            StmtKind::Return(expr) => match (expr, state.is_main) {
+                (Some(expr), true) => {
+                    let value = self.expr(state, expr)?;
+                    state.line(&format!("(void)({});", value));
+                    state.line("return 0;");
+                }
+                (Some(expr), false) => {
+                    let value = self.expr(state, expr)?;
+                    state.line(&format!("return {};", value));
+                }
+                (None, true) => state.line("return 0;"),
+                (None, false) => state.line("return;"),
+            },
+            StmtKind::Break => state.line("break;"),
+            StmtKind::Continue => state.line("continue;"),
+            StmtKind::Block(stmts) => {
+                state.line("{");
+                self.block(state, stmts)?;
+                state.line("}");
+            }
+        }
+        Ok(())
+    }
+
+    fn expr(&mut self, state: &mut FnState, expr: &Expr) -> Result<String, CompilerError> {
+        let at = format!("{}, {}", expr.span.line, expr.span.col);
+        match &expr.node {
+            ExprKind::Number(n) if *n == i64::MIN => Ok("INT64_MIN".to_string()),
+            ExprKind::Number(n) => Ok(format!("INT64_C({})", n)),
+            ExprKind::Float(_) => Err(unsupported("values of type float", expr.span)),
+            ExprKind::Char(_) => Err(unsupported("values of type char", expr.span)),
+            ExprKind::Str(s) => Ok(c_string(s)),
+            ExprKind::Bool(b) => Ok(b.to_string()),
+            ExprKind::Variable(name) => self.variable(state, name, expr.span),
+            ExprKind::Unary(UnOp::Neg, operand) => {
+                let t = self.type_of(state, operand);
+                if t != Type::Int {
+                    return Err(unsupported(&format!("'-' on {}", t), expr.span));
+                }
+                Ok(format!("ferrum_sub(0, {}, {})", self.expr(state, operand)?, at))
+            }
+            ExprKind::Unary(UnOp::BitNot, _) => Err(unsupported("'~'", expr.span)),
+            ExprKind::Update(name, op, prefix) => {
+                let target = self.variable(state, name, expr.span)?;
+                Ok(match (op, prefix) {
+                    (UpdateOp::Inc, true) => format!("({} = ferrum_add({}, 1, {}))", target, target, at),
+                    (UpdateOp::Dec, true) => format!("({} = ferrum_sub({}, 1, {}))", target, target, at),
+                    (UpdateOp::Inc, false) => format!("ferrum_post_inc(&{}, {})", target, at),
+                    (UpdateOp::Dec, false) => format!("ferrum_post_dec(&{}, {})", target, at),
+                })
+            }
+            ExprKind::Binary(lhs, op, rhs) => {
+                check_unsequenced(&[lhs, rhs])?;
+                let operand_type = match self.type_of(state, lhs) {
+                    Type::Error => self.type_of(state, rhs),
+                    t => t,
+                };
+                let l = self.expr(state, lhs)?;
+                let r = self.expr(state, rhs)?;
+                let helper = match op {
+                    BinOp::Add => "ferrum_add",
+                    BinOp::Sub => "ferrum_sub",
+                    BinOp::Mul => "ferrum_mul",
+                    BinOp::Div => "ferrum_div",
+                    BinOp::And => return Ok(format!("({} && {})", l, r)),
+                    BinOp::Or => return Ok(format!("({} || {})", l, r)),
+                    BinOp::Pow | BinOp::BitAnd | BinOp::BitOr | BinOp::BitXor | BinOp::Shl | BinOp::Shr => {
+                        return Err(unsupported(&format!("'{}' on {}", op, operand_type), expr.span));
+                    }
+                    BinOp::Eq | BinOp::Neq if operand_type == Type::Bool => return Ok(format!("({} {} {})", l, op, r)),
+                    _ if operand_type == Type::Int => return Ok(format!("({} {} {})", l, op, r)),
+                    _ => return Err(unsupported(&format!("'{}' on {}", op, operand_type), expr.span)),
+                };
+                if operand_type != Type::Int {
+                    return Err(unsupported(&format!("'{}' on {}", op, operand_type), expr.span));
+                }
+                Ok(format!("{}({}, {}, {})", helper, l, r, at))
+            }
+            ExprKind::Call(name, args) if self.types.function_type(name).is_some() => {
+                check_unsequenced(&args.iter().collect::<Vec<_>>())?;
+                let args = args.iter().map(|arg| self.expr(state, arg)).collect::<Result<Vec<_>, _>>()?;
+                Ok(format!("f_{}({})", name, args.join(", ")))
+            }
+            ExprKind::Call(name, args) => match name.as_str() {
+                "print" | "println" => self.print(state, args, name == "println"),
+                "read_int" => Ok(format!("ferrum_read_int({})", at)),
+                _ => Err(unsupported(&format!("the builtin {}", name), expr.span)),
+            },
+            ExprKind::Array(_) | ExprKind::Index(..) => Err(unsupported("arrays", expr.span)),
+            ExprKind::Map(_) => Err(unsupported("maps", expr.span)),
+            ExprKind::Null => Err(unsupported("null", expr.span)),
+            ExprKind::Variant(..) => Err(unsupported("enums", expr.span)),
+            ExprKind::StructLit(..) | ExprKind::Field(..) => Err(unsupported("structs", expr.span)),
+            ExprKind::If(cond, then_value, else_value) => {
+                let cond = self.condition(state, cond)?;
+                let then_value = self.expr(state, then_value)?;
+                let else_value = self.expr(state, else_value)?;
+                Ok(format!("({} ? {} : {})", cond, then_value, else_value))
+            }
+            // C99 has no statements inside expressions.
+            ExprKind::Block(..) => Err(unsupported("blocks with statements used as values", expr.span)),
+            // A chain of `?:` would evaluate the value once per arm.
+            ExprKind::Match(..) => Err(unsupported("'match' used as a value", expr.span)),
+        }
+    }
+
+    /// An expression in a position C already parenthesizes, without the
+    /// parentheses a comparison would otherwise bring.
+    fn condition(&mut self, state: &mut FnState, cond: &Expr) -> Result<String, CompilerError> {
+        let code = self.expr(state, cond)?;
+        match cond.node {
+            ExprKind::Binary(..) if code.starts_with('(') => Ok(code[1..code.len() - 1].to_string()),
+            _ => Ok(code),
+        }
+    }
+
+    /// `print`/`println` as one `printf` with a format built from the
+    /// argument types.
+    fn print(&mut self, state: &mut FnState, args: &[Expr], newline: bool) -> Result<String, CompilerError> {
+        check_unsequenced(&args.iter().collect::<Vec<_>>())?;
+        let mut format = String::from("\"");
+        let mut values = Vec::new();
+        for (i, arg) in args.iter().enumerate() {
+            if i > 0 {
+                format.push(' ');
+            }
+            let value = self.expr(state, arg)?;
+            match self.type_of(state, arg) {
+                Type::Int => {
+                    format.push_str("%\" PRId64 \"");
+                    values.push(value);
+                }
+                Type::Bool => {
+                    format.push_str("%s");
+                    values.push(format!("({} ? \"true\" : \"false\")", value));
+                }
+                Type::String => {
+                    format.push_str("%s");
+                    values.push(value);
+                }
+                other => return Err(unsupported(&format!("printing values of type {}", other), arg.span)),
+            }
+        }
+        if newline {
+            format.push_str("\\n");
+        }
+        format.push('"');
+        if values.is_empty() {
+            return Ok(if newline { "putchar('\\n')".to_string() } else { "(void)0".to_string() });
+        }
+        Ok(format!("printf({}, {})", format, values.join(", ")))
+    }
+}
\ No newline at end of file
@@ -0,0 +1,783 @@
+use crate::ast::*;
+use crate::codegen_c::{collect_functions, expr_type};
+use crate::error::CompilerError;
+use crate::span::Span;
+use crate::symbol::Symbol;
+use crate::type_checker::{Type, TypeChecker};
+use std::collections::HashMap;
+
+/// Where string data starts. The memory below is scratch space for the
+/// runtime: the `fd_write` iovec at 0 and the digits of a number being
+/// printed, written backwards from 48.
+const DATA_START: u32 = 64;
+
+/// Output, number formatting, runtime errors and checked arithmetic. Errors
+/// are reported like the interpreter's and exit with the same status.
+const RUNTIME: &str = r#"
+  (func $write (param $fd i32) (param $ptr i32) (param $len i32)
+    (i32.store (i32.const 0) (local.get $ptr))
+    (i32.store (i32.const 4) (local.get $len))
+    (drop (call $fd_write (local.get $fd) (i32.const 0) (i32.const 1) (i32.const 8))))
+
+  (func $print_i64 (param $fd i32) (param $n i64)
+    (local $pos i32) (local $m i64)
+    (local.set $pos (i32.const 48))
+    (local.set $m (local.get $n))
+    (if (i64.lt_s (local.get $n) (i64.const 0))
+      (then (local.set $m (i64.sub (i64.const 0) (local.get $n)))))
+    (loop $digits
+      (local.set $pos (i32.sub (local.get $pos) (i32.const 1)))
+      (i32.store8 (local.get $pos) (i32.add (i32.const 48) (i32.wrap_i64 (i64.rem_u (local.get $m) (i64.const 10)))))
+      (local.set $m (i64.div_u (local.get $m) (i64.const 10)))
+      (br_if $digits (i64.ne (local.get $m) (i64.const 0))))
+    (if (i64.lt_s (local.get $n) (i64.const 0))
+      (then
+        (local.set $pos (i32.sub (local.get $pos) (i32.const 1)))
+        (i32.store8 (local.get $pos) (i32.const 45))))
+    (call $write (local.get $fd) (local.get $pos) (i32.sub (i32.const 48) (local.get $pos))))
+
+  (func $print_bool (param $b i32)
+    (if (local.get $b)
+      (then (call $write (i32.const 1) (i32.const {TRUE}) (i32.const 4)))
+      (else (call $write (i32.const 1) (i32.const {FALSE}) (i32.const 5)))))
+
+  (func $fail (param $msg i32) (param $len i32) (param $line i32) (param $col i32)
+    (call $write (i32.const 2) (i32.const {AT_LINE}) (i32.const {AT_LINE_LEN}))
+    (call $print_i64 (i32.const 2) (i64.extend_i32_u (local.get $line)))
+    (call $write (i32.const 2) (i32.const {COLUMN}) (i32.const {COLUMN_LEN}))
+    (call $print_i64 (i32.const 2) (i64.extend_i32_u (local.get $col)))
+    (call $write (i32.const 2) (i32.const {COLON}) (i32.const 2))
+    (call $write (i32.const 2) (local.get $msg) (local.get $len))
+    (call $write (i32.const 2) (i32.const {NEWLINE}) (i32.const 1))
+    (call $proc_exit (i32.const 4))
+    (unreachable))
+
+  (func $add (param $a i64) (param $b i64) (param $line i32) (param $col i32) (result i64)
+    (local $r i64)
+    (local.set $r (i64.add (local.get $a) (local.get $b)))
+    (if (i64.lt_s (i64.and (i64.xor (local.get $a) (local.get $r)) (i64.xor (local.get $b) (local.get $r))) (i64.const 0))
+      (then (call $fail (i32.const {ADD}) (i32.const {ADD_LEN}) (local.get $line) (local.get $col))))
+    (local.get $r))
+
+  (func $sub (param $a i64) (param $b i64) (param $line i32) (param $col i32) (result i64)
+    (local $r i64)
+    (local.set $r (i64.sub (local.get $a) (local.get $b)))
+    (if (i64.lt_s (i64.and (i64.xor (local.get $a) (local.get $b)) (i64.xor (local.get $a) (local.get $r))) (i64.const 0))
+      (then (call $fail (i32.const {SUB}) (i32.const {SUB_LEN}) (local.get $line) (local.get $col))))
+    (local.get $r))
+
+  (func $mul (param $a i64) (param $b i64) (param $line i32) (param $col i32) (result i64)
+    (local $r i64)
+    (if (i64.eqz (local.get $a)) (then (return (i64.const 0))))
+    (if (i32.and (i64.eq (local.get $a) (i64.const -1)) (i64.eq (local.get $b) (i64.const 0x8000000000000000)))
+      (then (call $fail (i32.const {MUL}) (i32.const {MUL_LEN}) (local.get $line) (local.get $col))))
+    (local.set $r (i64.mul (local.get $a) (local.get $b)))
+    (if (i64.ne (i64.div_s (local.get $r) (local.get $a)) (local.get $b))
+      (then (call $fail (i32.const {MUL}) (i32.const {MUL_LEN}) (local.get $line) (local.get $col))))
+    (local.get $r))
+
+  (func $div (param $a i64) (param $b i64) (param $line i32) (param $col i32) (result i64)
+    (if (i64.eqz (local.get $b))
+      (then (call $fail (i32.const {DIV_ZERO}) (i32.const {DIV_ZERO_LEN}) (local.get $line) (local.get $col))))
+    (if (i32.and (i64.eq (local.get $a) (i64.const 0x8000000000000000)) (i64.eq (local.get $b) (i64.const -1)))
+      (then (call $fail (i32.const {DIV}) (i32.const {DIV_LEN}) (local.get $line) (local.get $col))))
+    (i64.div_s (local.get $a) (local.get $b)))
+"#;
+
+/// Translates a type-checked program to a WebAssembly text module.
+/// `checker` must already have checked `program`.
+///
+/// The module imports `fd_write` and `proc_exit` from WASI, so it runs
+/// as-is under wasmtime or any other WASI host. The top-level code is
+/// exported as `_start` and every function under its own name. Ints are
+/// `i64` and bools `i32`; strings may only appear as literal `print`
+/// arguments, and floats, arrays and the input builtins are reported as
+/// [`CompilerError::UnsupportedError`].
+pub fn generate(program: &[Stmt], checker: &TypeChecker) -> Result<String, CompilerError> {
+    let mut generator = Generator {
+        types: checker,
+        globals: HashMap::new(),
+        data: Vec::new(),
+        strings: HashMap::new(),
+    };
+    generator.declare_globals(program)?;
+    let mut definitions = Vec::new();
+    collect_functions(program, &mut definitions);
+
+    let mut functions = Vec::new();
+    let mut names: Vec<Symbol> = Vec::new();
+    for stmt in &definitions {
+        let StmtKind::FnDecl(name, params, _, body) = &stmt.node else {
+            unreachable!("collect_functions only returns declarations");
+        };
+        if names.contains(name) {
+            return Err(unsupported(&format!("two functions named {}", name), stmt.span));
+        }
+        if name == "_start" || name == "memory" {
+            return Err(unsupported(&format!("a function named {}, which is a reserved export", name), stmt.span));
+        }
+        names.push(*name);
+        let (param_types, return_type) = checker.function_type(name).cloned().expect("the checker saw every function");
+
+        let mut state = FnState::new(false);
+        let mut header = format!("  (func ${} (export \"{}\")", wat_name("f", name), name);
+        for (param, t) in params.iter().zip(&param_types) {
+            let val_type = val_type(t, stmt.span)?;
+            header.push_str(&format!(" (param ${} {})", state.bind(&param.name, t.clone(), false), val_type));
+        }
+        if return_type != Type::Void {
+            header.push_str(&format!(" (result {})", val_type(&return_type, stmt.span)?));
+        }
+        generator.stmts(&mut state, body)?;
+        // Every path already returned; this only satisfies the validator.
+        if return_type != Type::Void {
+            state.line(&format!("{}.const 0", val_type(&return_type, stmt.span)?));
+        }
+        functions.push(state.finish(header));
+    }
+
+    let mut main = FnState::new(true);
+    generator.stmts(&mut main, program)?;
+    functions.push(main.finish("  (func $main (export \"_start\")".to_string()));
+
+    let offsets = generator.runtime_strings();
+    let mut runtime = RUNTIME.to_string();
+    for (placeholder, value) in offsets {
+        runtime = runtime.replace(&format!("{{{}}}", placeholder), &value.to_string());
+    }
+
+    let mut out = String::from("(module\n");
+    out.push_str("  (import \"wasi_snapshot_preview1\" \"fd_write\" (func $fd_write (param i32 i32 i32 i32) (result i32)))\n");
+    out.push_str("  (import \"wasi_snapshot_preview1\" \"proc_exit\" (func $proc_exit (param i32)))\n");
+    out.push_str("  (memory (export \"memory\") 1)\n");
+    out.push_str(&format!("  (data (i32.const {}) \"{}\")\n", DATA_START, escape_data(&generator.data)));
+    let mut globals: Vec<_> = generator.globals.iter().collect();
+    globals.sort_by_key(|(name, _)| *name);
+    for (name, t) in globals {
+        let val_type = val_type(t, Span::default())?;
+        out.push_str(&format!("  (global ${} (mut {}) ({}.const 0))\n", wat_name("g", name), val_type, val_type));
+    }
+    out.push_str(&runtime);
+    for function in functions {
+        out.push('\n');
+        out.push_str(&function);
+    }
+    out.push_str(")\n");
+    Ok(out)
+}
+
+fn unsupported(what: &str, span: Span) -> CompilerError {
+    CompilerError::UnsupportedError(format!("The WebAssembly backend does not support {}", what), Some(span))
+}
+
+fn val_type(t: &Type, span: Span) -> Result<&'static str, CompilerError> {
+    match t {
+        Type::Int => Ok("i64"),
+        Type::Bool => Ok("i32"),
+        Type::Error => Err(unsupported("a value whose type could not be inferred", span)),
+        other => Err(unsupported(&format!("values of type {}", other), span)),
+    }
+}
+
+/// The WAT name, without its `$`, of the function, global or variable
+/// `name`, after `prefix` and an underscore. Plain WAT names are ASCII, so
+/// one for a name that is not is quoted: `$"v_größe"`.
+fn wat_name(prefix: &str, name: &str) -> String {
+    if name.is_ascii() {
+        format!("{}_{}", prefix, name)
+    } else {
+        format!("\"{}_{}\"", prefix, name)
+    }
+}
+
+/// Writes `bytes` as the contents of a WAT string, hex-escaping anything
+/// that is not printable ASCII.
+fn escape_data(bytes: &[u8]) -> String {
+    let mut out = String::new();
+    for &b in bytes {
+        match b {
+            b'"' | b'\\' => out.push_str(&format!("\\{:02x}", b)),
+            0x20..=0x7e => out.push(b as char),
+            _ => out.push_str(&format!("\\{:02x}", b)),
+        }
+    }
+    out
+}
+
+/// Per-function state: the instructions so far, the locals they use and
+/// the labels of the enclosing loops.
+struct FnState {
+    out: String,
+    indent: usize,
+    /// Block scopes mapping source names to WAT names and types, innermost
+    /// last.
+    scopes: Vec<Vec<(String, String, Type)>>,
+    declared: HashMap<String, usize>,
+    /// Locals other than parameters, declared at the top of the function.
+    locals: Vec<(String, &'static str)>,
+    /// Break and continue labels of the enclosing loops, innermost last.
+    loops: Vec<(String, String)>,
+    labels: usize,
+    is_main: bool,
+}
+
+impl FnState {
+    fn new(is_main: bool) -> Self {
+        Self {
+            out: String::new(),
+            indent: 2,
+            scopes: vec![Vec::new()],
+            declared: HashMap::new(),
+            locals: Vec::new(),
+            loops: Vec::new(),
+            labels: 0,
+            is_main,
+        }
+    }
+
+    fn line(&mut self, text: &str) {
+        for _ in 0..self.indent {
+            self.out.push_str("  ");
+        }
+        self.out.push_str(text);
+        self.out.push('\n');
+    }
+
+    /// A fresh unnamed local, for holding intermediate values.
+    fn temp(&mut self, val_type: &'static str) -> String {
+        let name = format!("tmp_{}", self.locals.len());
+        self.locals.push((name.clone(), val_type));
+        name
+    }
+
+    /// Declares a variable in the innermost scope and returns its WAT name.
+    /// `local` is false for parameters, which the function header declares.
+    fn bind(&mut self, name: &str, t: Type, local: bool) -> String {
+        let count = self.declared.entry(name.to_string()).or_insert(0);
+        let wat_name = if *count == 0 { wat_name("v", name) } else { wat_name("v", &format!("{}_{}", name, count)) };
+        *count += 1;
+        if local {
+            let val_type = if t == Type::Bool { "i32" } else { "i64" };
+            self.locals.push((wat_name.clone(), val_type));
+        }
+        self.scopes.last_mut().expect("a function always has a scope").push((name.to_string(), wat_name.clone(), t));
+        wat_name
+    }
+
+    fn local(&self, name: &str) -> Option<(&str, &Type)> {
+        self.scopes.iter().rev().flat_map(|scope| scope.iter().rev()).find(|(n, ..)| n == name).map(|(_, w, t)| (w.as_str(), t))
+    }
+
+    fn at_global_level(&self) -> bool {
+        self.is_main && self.scopes.len() == 1
+    }
+
+    fn label(&mut self, kind: &str) -> String {
+        self.labels += 1;
+        format!("${}_{}", kind, self.labels)
+    }
+
+    fn finish(self, header: String) -> String {
+        let mut out = header;
+        for (name, val_type) in &self.locals {
+            out.push_str(&format!(" (local ${} {})", name, val_type));
+        }
+        out.push('\n');
+        out.push_str(&self.out);
+        out.push_str("  )\n");
+        out
+    }
+}
+
+struct Generator<'a> {
+    types: &'a TypeChecker,
+    /// Top-level variables and their types.
+    globals: HashMap<String, Type>,
+    /// The data segment: every string the module writes.
+    data: Vec<u8>,
+    strings: HashMap<String, u32>,
+}
+
+impl Generator<'_> {
+    /// Places `s` in the data segment once and returns its address and length.
+    fn string(&mut self, s: &str) -> (u32, u32) {
+        let len = s.len() as u32;
+        if let Some(&offset) = self.strings.get(s) {
+            return (offset, len);
+        }
+        let offset = DATA_START + self.data.len() as u32;
+        self.data.extend_from_slice(s.as_bytes());
+        self.strings.insert(s.to_string(), offset);
+        (offset, len)
+    }
+
+    /// Values for the `{NAME}` and `{NAME_LEN}` placeholders in [`RUNTIME`]:
+    /// the address and length of each string the runtime writes.
+    fn runtime_strings(&mut self) -> Vec<(String, u32)> {
+        let mut offsets = Vec::new();
+        for (name, text) in [
+            ("TRUE", "true"),
+            ("FALSE", "false"),
+            ("AT_LINE", "Runtime error at line "),
+            ("COLUMN", ", column "),
+            ("COLON", ": "),
+            ("NEWLINE", "\n"),
+            ("ADD", "Integer overflow in '+'"),
+            ("SUB", "Integer overflow in '-'"),
+            ("MUL", "Integer overflow in '*'"),
+            ("DIV", "Integer overflow in '/'"),
+            ("DIV_ZERO", "Division by zero"),
+        ] {
+            let (offset, len) = self.string(text);
+            offsets.push((name.to_string(), offset));
+            offsets.push((format!("{}_LEN", name), len));
+        }
+        offsets
+    }
+
+    fn declare_globals(&mut self, program: &[Stmt]) -> Result<(), CompilerError> {
+        let top_level = FnState::new(true);
+        for stmt in program {
+            if let StmtKind::Let(name, _, expr) | StmtKind::Const(name, _, expr) = &stmt.node {
+                let t = self.type_of(&top_level, expr);
+                match self.globals.get(name.as_str()) {
+                    Some(existing) if *existing != t => {
+                        return Err(unsupported(&format!("redeclaring global {} with a different type", name), stmt.span));
+                    }
+                    _ => {
+                        self.globals.insert(name.to_string(), t);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn type_of(&self, state: &FnState, expr: &Expr) -> Type {
+        let variable = |name: &str| state.local(name).map(|(_, t)| t.clone()).or_else(|| self.globals.get(name).cloned());
+        expr_type(self.types, expr, &variable)
+    }
+
+    /// The instructions that read and write `name`.
+    fn variable(&self, state: &FnState, name: &str, span: Span) -> Result<(String, String), CompilerError> {
+        if let Some((wat_name, _)) = state.local(name) {
+            return Ok((format!("local.get ${}", wat_name), format!("local.set ${}", wat_name)));
+        }
+        if self.globals.contains_key(name) {
+            let global = wat_name("g", name);
+            return Ok((format!("global.get ${}", global), format!("global.set ${}", global)));
+        }
+        Err(unsupported(&format!("using {} from an enclosing function", name), span))
+    }
+
+    fn block(&mut self, state: &mut FnState, stmts: &[Stmt]) -> Result<(), CompilerError> {
+        state.scopes.push(Vec::new());
+        state.indent += 1;
+        self.stmts(state, stmts)?;
+        state.indent -= 1;
+        state.scopes.pop();
+        Ok(())
+    }
+
+    fn stmts(&mut self, state: &mut FnState, stmts: &[Stmt]) -> Result<(), CompilerError> {
+        for stmt in stmts {
+            self.stmt(state, stmt)?;
+        }
+        Ok(())
+    }
+
+    fn stmt(&mut self, state: &mut FnState, stmt: &Stmt) -> Result<(), CompilerError> {
+        match &stmt.node {
+            StmtKind::Let(name, _, expr) | StmtKind::Const(name, _, expr) => {
+                self.expr(state, expr)?;
+                if state.at_global_level() {
+                    state.line(&format!("global.set ${}", wat_name("g", name)));
+                } else {
+                    let t = self.type_of(state, expr);
+                    val_type(&t, expr.span)?;
+                    let wat_name = state.bind(name, t, true);
+                    state.line(&format!("local.set ${}", wat_name));
+                }
+            }
+            StmtKind::Assign(name, expr) => {
+                self.expr(state, expr)?;
+                let (_, set) = self.variable(state, name, stmt.span)?;
+                state.line(&set);
+            }
+            StmtKind::IndexAssign(..) => return Err(unsupported("arrays", stmt.span)),
+            StmtKind::FieldAssign(..) => return Err(unsupported("structs", stmt.span)),
+            StmtKind::Expr(expr) => {
+                self.expr(state, expr)?;
+                if self.type_of(state, expr) != Type::Void {
+                    state.line("drop");
+                }
+            }
+            StmtKind::If(cond, then_block, else_block) => {
+                self.expr(state, cond)?;
+                state.line("if");
+                self.block(state, then_block)?;
+                if !else_block.is_empty() {
+                    state.line("else");
+                    self.block(state, else_block)?;
+                }
+                state.line("end");
+            }
+            // A `while` is an `if` around a loop that tests at the bottom.
+            StmtKind::While(cond, body) => {
+                self.expr(state, cond)?;
+                state.line("if");
+                state.indent += 1;
+                self.loop_until(state, body, |generator, state| generator.expr(state, cond))?;
+                state.indent -= 1;
+                state.line("end");
+            }
+            StmtKind::DoWhile(body, cond) => {
+                self.loop_until(state, body, |generator, state| generator.expr(state, cond))?;
+            }
+            StmtKind::For(init, cond, step, body) => {
+                // A variable declared by the initializer lives in its own
+                // scope around the body.
+                state.scopes.push(Vec::new());
+                self.stmt(state, init)?;
+                self.expr(state, cond)?;
+                state.line("if");
+                state.indent += 1;
+                self.loop_until(state, body, |generator, state| {
+                    generator.stmt(state, step)?;
+                    generator.expr(state, cond)
+                })?;
+                state.indent -= 1;
+                state.line("end");
+                state.scopes.pop();
+            }
+            StmtKind::ForRange(var, start, end, inclusive, body) => {
+                // The loop counts in hidden locals, so assigning to the
+                // variable does not change the iteration.
+                let (next, last) = (state.temp("i64"), state.temp("i64"));
+                self.expr(state, start)?;
+                state.line(&format!("local.set ${}", next));
+                self.expr(state, end)?;
+                state.line(&format!("local.set ${}", last));
+                state.scopes.push(Vec::new());
+                let wat_name = state.bind(var, Type::Int, true);
+                let compare = if *inclusive { "i64.le_s" } else { "i64.lt_s" };
+                state.line(&format!("local.get ${}", next));
+                state.line(&format!("local.get ${}", last));
+                state.line(compare);
+                state.line("if");
+                state.indent += 1;
+                state.line(&format!("local.get ${}", next));
+                state.line(&format!("local.set ${}", wat_name));
+                self.loop_until(state, body, |_, state| {
+                    // An inclusive range decides before stepping, as `next`
+                    // would wrap past an end of `i64::MAX`.
+                    if *inclusive {
+                        state.line(&format!("local.get ${}", next));
+                        state.line(&format!("local.get ${}", last));
+                        state.line("i64.lt_s");
+                    }
+                    state.line(&format!("local.get ${}", next));
+                    state.line("i64.const 1");
+                    state.line("i64.add");
+                    state.line(&format!("local.tee ${}", next));
+                    state.line(&format!("local.set ${}", wat_name));
+                    if !*inclusive {
+                        state.line(&format!("local.get ${}", next));
+                        state.line(&format!("local.get ${}", last));
+                        state.line("i64.lt_s");
+                    }
+                    Ok(())
+                })?;
+                state.indent -= 1;
+                state.line("end");
+                state.scopes.pop();
+            }
+            // Each arm that does not match falls to the `else` of its test.
+            StmtKind::Match(value, arms) => {
+                let t = self.type_of(state, value);
+                let (val_type, eq) = match t {
+                    Type::Int => ("i64", "i64.eq"),
+                    Type::Bool => ("i32", "i32.eq"),
+                    _ => return Err(unsupported(&format!("matching on {}", t), value.span)),
+                };
+                let hidden = state.temp(val_type);
+                self.expr(state, value)?;
+                state.line(&format!("local.set ${}", hidden));
+                let mut open = 0;
+                for arm in arms {
+                    let Pattern::Literal(literal) = &arm.pattern else {
+                        self.block(state, arm.body)?;
+                        break;
+                    };
+                    state.line(&format!("local.get ${}", hidden));
+                    self.expr(state, literal)?;
+                    state.line(eq);
+                    state.line("if");
+                    self.block(state, arm.body)?;
+                    state.line("else");
+                    state.indent += 1;
+                    open += 1;
+                }
+                for _ in 0..open {
+                    state.indent -= 1;
+                    state.line("end");
+                }
+            }
+            // Emitted separately by `generate`.
+            StmtKind::FnDecl(..) => {}
+            StmtKind::StructDecl(..) | StmtKind::EnumDecl(..) | StmtKind::TypeAlias(..) | StmtKind::Import(..) => {}
+            StmtKind::Return(expr) => {
+                if let Some(expr) = expr {
+                    self.expr(state, expr)?;
+                    if state.is_main {
+                        state.line("drop");
+                    }
+                }
+                state.line("return");
+            }
+            StmtKind::Break | StmtKind::Continue => {
+                let (exit, next) = state.loops.last().cloned().expect("the checker rejects break outside a loop");
+                let target = if matches!(stmt.node, StmtKind::Break) { exit } else { next };
+                state.line(&format!("br {}", target));
+            }
+            StmtKind::Block(stmts) => self.block(state, stmts)?,
+        }
+        Ok(())
+    }
+
+    /// A loop that runs `body`, then `tail`, and repeats while the bool
+    /// `tail` leaves on the stack is true. `continue` jumps to `tail`.
+    fn loop_until(
+        &mut self,
+        state: &mut FnState,
+        body: &[Stmt],
+        tail: impl FnOnce(&mut Self, &mut FnState) -> Result<(), CompilerError>,
+    ) -> Result<(), CompilerError> {
+        let (exit, repeat, next) = (state.label("break"), state.label("loop"), state.label("continue"));
+        state.line(&format!("block {}", exit));
+        state.indent += 1;
+        state.line(&format!("loop {}", repeat));
+        state.indent += 1;
+        state.line(&format!("block {}", next));
+        state.loops.push((exit.clone(), next.clone()));
+        self.block(state, body)?;
+        state.loops.pop();
+        state.line("end");
+        tail(self, state)?;
+        state.line(&format!("br_if {}", repeat));
+        state.indent -= 1;
+        state.line("end");
+        state.indent -= 1;
+        state.line("end");
+        Ok(())
+    }
+
+    /// Pushes the line and column runtime errors report for `span`.
+    fn position(state: &mut FnState, span: Span) {
+        state.line(&format!("i32.const {}", span.line));
+        state.line(&format!("i32.const {}", span.col));
+    }
+
+    fn expr(&mut self, state: &mut FnState, expr: &Expr) -> Result<(), CompilerError> {
+        match &expr.node {
+            ExprKind::Number(n) => state.line(&format!("i64.const {}", n)),
+            ExprKind::Float(_) => return Err(unsupported("values of type float", expr.span)),
+            ExprKind::Char(_) => return Err(unsupported("values of type char", expr.span)),
+            ExprKind::Str(_) => return Err(unsupported("strings outside print", expr.span)),
+            ExprKind::Bool(b) => state.line(&format!("i32.const {}", *b as i32)),
+            ExprKind::Variable(name) => {
+                let (get, _) = self.variable(state, name, expr.span)?;
+                state.line(&get);
+            }
+            ExprKind::Unary(UnOp::Neg, operand) => {
+                let t = self.type_of(state, operand);
+                if t != Type::Int {
+                    return Err(unsupported(&format!("'-' on {}", t), expr.span));
+                }
+                state.line("i64.const 0");
+                self.expr(state, operand)?;
+                Self::position(state, expr.span);
+                state.line("call $sub");
+            }
+            ExprKind::Unary(UnOp::BitNot, _) => return Err(unsupported("'~'", expr.span)),
+            ExprKind::Update(name, op, prefix) => {
+                let (get, set) = self.variable(state, name, expr.span)?;
+                let helper = if matches!(op, UpdateOp::Inc) { "call $add" } else { "call $sub" };
+                // The postfix form leaves the old value underneath the update.
+                if !prefix {
+                    state.line(&get);
+                }
+                state.line(&get);
+                state.line("i64.const 1");
+                Self::position(state, expr.span);
+                state.line(helper);
+                state.line(&set);
+                if *prefix {
+                    state.line(&get);
+                }
+            }
+            ExprKind::Binary(lhs, op, rhs) => {
+                let operand_type = match self.type_of(state, lhs) {
+                    Type::Error => self.type_of(state, rhs),
+                    t => t,
+                };
+                self.expr(state, lhs)?;
+                match (op, &operand_type) {
+                    (BinOp::And, _) => {
+                        state.line("if (result i32)");
+                        self.nested_expr(state, rhs)?;
+                        state.line("else");
+                        state.line("  i32.const 0");
+                        state.line("end");
+                        return Ok(());
+                    }
+                    (BinOp::Or, _) => {
+                        state.line("if (result i32)");
+                        state.line("  i32.const 1");
+                        state.line("else");
+                        self.nested_expr(state, rhs)?;
+                        state.line("end");
+                        return Ok(());
+                    }
+                    _ => {}
+                }
+                self.expr(state, rhs)?;
+                let instr = match (op, &operand_type) {
+                    (BinOp::Add, Type::Int) => "call $add",
+                    (BinOp::Sub, Type::Int) => "call $sub",
+                    (BinOp::Mul, Type::Int) => "call $mul",
+                    (BinOp::Div, Type::Int) => "call $div",
+                    (BinOp::Eq, Type::Int) => "i64.eq",
+                    (BinOp::Neq, Type::Int) => "i64.ne",
+                    (BinOp::Gt, Type::Int) => "i64.gt_s",
+                    (BinOp::Lt, Type::Int) => "i64.lt_s",
+                    (BinOp::Ge, Type::Int) => "i64.ge_s",
+                    (BinOp::Le, Type::Int) => "i64.le_s",
+                    (BinOp::Eq, Type::Bool) => "i32.eq",
+                    (BinOp::Neq, Type::Bool) => "i32.ne",
+                    _ => return Err(unsupported(&format!("'{}' on {}", op, operand_type), expr.span)),
+                };
+                if instr.starts_with("call") {
+                    Self::position(state, expr.span);
+                }
+                state.line(instr);
+            }
+            ExprKind::Call(name, args) if self.types.function_type(name).is_some() => {
+                for arg in args.iter() {
+                    self.expr(state, arg)?;
+                }
+                state.line(&format!("call ${}", wat_name("f", name)));
+            }
+            ExprKind::Call(name, args) => match name.as_str() {
+                "print" | "println" => self.print(state, args, name == "println")?,
+                _ => return Err(unsupported(&format!("the builtin {}", name), expr.span)),
+            },
+            ExprKind::Array(_) | ExprKind::Index(..) => return Err(unsupported("arrays", expr.span)),
+            ExprKind::Map(_) => return Err(unsupported("maps", expr.span)),
+            ExprKind::Null => return Err(unsupported("null", expr.span)),
+            ExprKind::Variant(..) => return Err(unsupported("enums", expr.span)),
+            ExprKind::StructLit(..) | ExprKind::Field(..) => return Err(unsupported("structs", expr.span)),
+            ExprKind::If(cond, then_value, else_value) => {
+                let result = val_type(&self.type_of(state, expr), expr.span)?;
+                self.expr(state, cond)?;
+                state.line(&format!("if (result {})", result));
+                self.nested_expr(state, then_value)?;
+                state.line("else");
+                self.nested_expr(state, else_value)?;
+                state.line("end");
+            }
+            // Like a `match` statement, with each test leaving its arm's value.
+            ExprKind::Match(value, arms) => {
+                let t = self.type_of(state, value);
+                let (value_type, eq) = match t {
+                    Type::Int => ("i64", "i64.eq"),
+                    Type::Bool => ("i32", "i32.eq"),
+                    _ => return Err(unsupported(&format!("matching on {}", t), value.span)),
+                };
+                let result = val_type(&self.type_of(state, expr), expr.span)?;
+                let hidden = state.temp(value_type);
+                self.expr(state, value)?;
+                state.line(&format!("local.set ${}", hidden));
+                let mut open = 0;
+                for (i, arm) in arms.iter().enumerate() {
+                    // The checker made sure the arms cover every value, so
+                    // the last one is taken without a test.
+                    let literal = match &arm.pattern {
+                        Pattern::Literal(literal) if i + 1 < arms.len() => literal,
+                        _ => {
+                            self.expr(state, &arm.value)?;
+                            break;
+                        }
+                    };
+                    state.line(&format!("local.get ${}", hidden));
+                    self.expr(state, literal)?;
+                    state.line(eq);
+                    state.line(&format!("if (result {})", result));
+                    self.nested_expr(state, &arm.value)?;
+                    state.line("else");
+                    state.indent += 1;
+                    open += 1;
+                }
+                for _ in 0..open {
+                    state.indent -= 1;
+                    state.line("end");
+                }
+            }
+            ExprKind::Block(stmts, value) => {
+                state.scopes.push(Vec::new());
+                self.stmts(state, stmts)?;
+                self.expr(state, value)?;
+                state.scopes.pop();
+            }
+        }
+        Ok(())
+    }
+
+    fn nested_expr(&mut self, state: &mut FnState, expr: &Expr) -> Result<(), CompilerError> {
+        state.indent += 1;
+        self.expr(state, expr)?;
+        state.indent -= 1;
+        Ok(())
+    }
+
+    /// `print`/`println`: like the interpreter, every argument is evaluated
+    /// before anything is written, so output from calls among the
+    /// arguments comes first.
+    fn print(&mut self, state: &mut FnState, args: &[Expr], newline: bool) -> Result<(), CompilerError> {
+        let mut temps = Vec::new();
+        for arg in args {
+            let t = self.type_of(state, arg);
+            if let ExprKind::Str(_) = arg.node {
+                temps.push(None);
+                continue;
+            }
+            let temp = match t {
+                Type::Int | Type::Bool => state.temp(val_type(&t, arg.span)?),
+                other => return Err(unsupported(&format!("printing values of type {}", other), arg.span)),
+            };
+            self.expr(state, arg)?;
+            state.line(&format!("local.set ${}", temp));
+            temps.push(Some((temp, t)));
+        }
+        for (i, (arg, temp)) in args.iter().zip(temps).enumerate() {
+            if i > 0 {
+                self.write(state, " ");
+            }
+            match (&arg.node, temp) {
+                (ExprKind::Str(s), _) => self.write(state, s),
+                (_, Some((temp, Type::Int))) => state.line(&format!("(call $print_i64 (i32.const 1) (local.get ${}))", temp)),
+                (_, Some((temp, _))) => state.line(&format!("(call $print_bool (local.get ${}))", temp)),
+                (_, None) => unreachable!("only string literals have no temporary"),
+            }
+        }
+        if newline {
+            self.write(state, "\n");
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, state: &mut FnState, s: &str) {
+        let (offset, len) = self.string(s);
+        state.line(&format!("(call $write (i32.const 1) (i32.const {}) (i32.const {}))", offset, len));
+    }
+}
\ No newline at end of file
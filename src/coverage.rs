@@ -0,0 +1,196 @@
+use crate::ast::{Stmt, StmtKind};
+use crate::environment::Environment;
+use crate::error::CompilerError;
+use crate::interpreter::Hook;
+use crate::span::Span;
+use crate::value::Value;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+use std::rc::Rc;
+
+#[derive(Default)]
+struct Counts {
+    stmts: HashMap<Span, u64>,
+    functions: HashMap<String, u64>,
+}
+
+/// Records how often each statement and function runs, for `--coverage`.
+/// Install a clone with [`crate::Interpreter::with_hook`] and keep one to
+/// write a report once the program ends. Lines are those of the program's
+/// first source file, so a report covers that file alone; statements from
+/// files it imports are left out.
+#[derive(Clone, Default)]
+pub struct Coverage {
+    counts: Rc<RefCell<Counts>>,
+}
+
+/// A function declared in the program, with how often it was called.
+struct FunctionCount<'a> {
+    name: &'a str,
+    line: usize,
+    calls: u64,
+}
+
+impl Coverage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// How many times the statement at `span` ran.
+    pub fn count(&self, span: Span) -> u64 {
+        self.counts.borrow().stmts.get(&span).copied().unwrap_or(0)
+    }
+
+    /// Each line on which a statement of `program` starts, with the most
+    /// times any of those statements ran.
+    fn line_counts(&self, program: &[Stmt]) -> BTreeMap<usize, u64> {
+        let mut lines = BTreeMap::new();
+        visit(program, &mut |stmt| {
+            let count = lines.entry(stmt.span.line).or_insert(0);
+            *count = (*count).max(self.count(stmt.span));
+        });
+        lines
+    }
+
+    fn function_counts<'a>(&self, program: &'a [Stmt]) -> Vec<FunctionCount<'a>> {
+        let counts = self.counts.borrow();
+        let mut functions = Vec::new();
+        visit_all(program, &mut |stmt| {
+            if let StmtKind::FnDecl(name, ..) = &stmt.node {
+                let calls = counts.functions.get(name.as_str()).copied().unwrap_or(0);
+                functions.push(FunctionCount { name, line: stmt.span.line, calls });
+            }
+        });
+        functions
+    }
+
+    /// `source` with each line prefixed by how often it ran, `#####` if
+    /// it never did and `-` if no statement starts on it, followed by a
+    /// summary:
+    ///
+    /// ```text
+    ///      3 |    2 |     let r = n * n;
+    ///  ##### |    3 |     println("never");
+    ///      - |    4 | }
+    /// statements: 9 of 10 (90.0%), functions: 2 of 2 (100.0%)
+    /// ```
+    pub fn report(&self, program: &[Stmt], source: &str) -> String {
+        let lines = self.line_counts(program);
+        let mut out = String::new();
+        for (i, text) in source.lines().enumerate() {
+            let count = match lines.get(&(i + 1)) {
+                Some(0) => "#####".to_string(),
+                Some(count) => count.to_string(),
+                None => "-".to_string(),
+            };
+            let _ = writeln!(out, "{:>6} | {:>4} | {}", count, i + 1, text);
+        }
+        let mut stmts = (0, 0);
+        visit(program, &mut |stmt| {
+            stmts.0 += usize::from(self.count(stmt.span) > 0);
+            stmts.1 += 1;
+        });
+        let functions = self.function_counts(program);
+        let called = functions.iter().filter(|function| function.calls > 0).count();
+        let _ = writeln!(
+            out,
+            "statements: {} of {} ({}), functions: {} of {} ({})",
+            stmts.0,
+            stmts.1,
+            percent(stmts.0, stmts.1),
+            called,
+            functions.len(),
+            percent(called, functions.len())
+        );
+        out
+    }
+
+    /// The counts in LCOV's tracefile format, as read by `genhtml` and
+    /// most CI coverage services, with `path` as the source file.
+    pub fn lcov(&self, program: &[Stmt], path: &str) -> String {
+        let mut out = format!("TN:\nSF:{}\n", path);
+        let functions = self.function_counts(program);
+        for function in &functions {
+            let _ = writeln!(out, "FN:{},{}", function.line, function.name);
+        }
+        for function in &functions {
+            let _ = writeln!(out, "FNDA:{},{}", function.calls, function.name);
+        }
+        let called = functions.iter().filter(|function| function.calls > 0).count();
+        let _ = writeln!(out, "FNF:{}\nFNH:{}", functions.len(), called);
+        let lines = self.line_counts(program);
+        for (line, count) in &lines {
+            let _ = writeln!(out, "DA:{},{}", line, count);
+        }
+        let hit = lines.values().filter(|&&count| count > 0).count();
+        let _ = writeln!(out, "LF:{}\nLH:{}\nend_of_record", lines.len(), hit);
+        out
+    }
+}
+
+impl Hook for Coverage {
+    fn before_stmt(&mut self, stmt: &Stmt, _env: &Environment<Value>, _call_depth: usize) -> Result<(), CompilerError> {
+        *self.counts.borrow_mut().stmts.entry(stmt.span).or_insert(0) += 1;
+        Ok(())
+    }
+
+    fn enter_function(&mut self, name: &str) {
+        *self.counts.borrow_mut().functions.entry(name.to_string()).or_insert(0) += 1;
+    }
+}
+
+fn percent(part: usize, whole: usize) -> String {
+    if whole == 0 {
+        return "-".to_string();
+    }
+    format!("{:.1}%", part as f64 * 100.0 / whole as f64)
+}
+
+/// Calls `f` on every statement that can run, nested ones included.
+/// Declarations run nothing themselves, so only function bodies count.
+fn visit(stmts: &[Stmt], f: &mut impl FnMut(&Stmt)) {
+    visit_all(stmts, &mut |stmt| {
+        if !matches!(stmt.node, StmtKind::FnDecl(..) | StmtKind::StructDecl(..) | StmtKind::EnumDecl(..) | StmtKind::TypeAlias(..) | StmtKind::Import(..)) {
+            f(stmt);
+        }
+    });
+}
+
+/// Calls `f` on every statement of the first source file, nested ones
+/// included.
+fn visit_all<'a>(stmts: &'a [Stmt], f: &mut impl FnMut(&'a Stmt)) {
+    for stmt in stmts.iter().filter(|stmt| stmt.span.file == 0) {
+        f(stmt);
+        match &stmt.node {
+            StmtKind::If(_, then_block, else_block) => {
+                visit_all(then_block, f);
+                visit_all(else_block, f);
+            }
+            StmtKind::Match(_, arms) => {
+                for arm in arms {
+                    visit_all(arm.body, f);
+                }
+            }
+            StmtKind::While(_, body)
+            | StmtKind::DoWhile(body, _)
+            | StmtKind::For(.., body)
+            | StmtKind::ForRange(.., body)
+            | StmtKind::FnDecl(.., body)
+            | StmtKind::Block(body) => visit_all(body, f),
+            StmtKind::Let(..)
+            | StmtKind::Const(..)
+            | StmtKind::Assign(..)
+            | StmtKind::IndexAssign(..)
+            | StmtKind::FieldAssign(..)
+            | StmtKind::StructDecl(..)
+            | StmtKind::EnumDecl(..)
+            | StmtKind::TypeAlias(..)
+            | StmtKind::Import(..)
+            | StmtKind::Expr(_)
+            | StmtKind::Return(_)
+            | StmtKind::Break
+            | StmtKind::Continue => {}
+        }
+    }
+}
\ No newline at end of file
@@ -0,0 +1,214 @@
+use crate::ast::{Stmt, StmtKind};
+use crate::environment::Environment;
+use crate::error::CompilerError;
+use crate::interpreter::Hook;
+use crate::source_map::SourceMap;
+use crate::symbol::Symbol;
+use crate::value::Value;
+use std::collections::BTreeSet;
+use std::io::{self, BufRead, Write};
+
+const HELP: &str = "\
+commands:
+  s, step          run to the next statement, entering function calls
+  n, next          run to the next statement in this function or its callers
+  c, continue      run to the next breakpoint
+  b, break <line>  stop whenever a statement on <line> is about to run;
+                   <file>:<line> names a line of an imported file
+  d, delete <line> remove the breakpoint on <line>
+  p, print <name>  show the value of a variable
+  v, vars          show every variable in scope, innermost first
+  l, list          show the source around the current line
+  q, quit          stop the program
+  h, help          show this list
+An empty line repeats the previous command.";
+
+/// How far to run before stopping again.
+enum Mode {
+    Step,
+    /// Stop at a statement no deeper in calls than this.
+    Next(usize),
+    Continue,
+}
+
+/// An interactive debugger for the tree-walking interpreter. Install it
+/// with [`crate::Interpreter::with_hook`]; it stops before the first
+/// statement and then whenever a step ends or a breakpoint is reached,
+/// reading commands until one resumes the program.
+pub struct Debugger {
+    /// The lines of each source file, by file number.
+    lines: Vec<Vec<String>>,
+    names: Vec<String>,
+    /// File and line of each breakpoint.
+    breakpoints: BTreeSet<(usize, usize)>,
+    mode: Mode,
+    last_command: String,
+    /// Where commands come from; `None` reads standard input through its
+    /// shared buffer, so the program's own `read_line` does not lose input.
+    input: Option<Box<dyn BufRead>>,
+    output: Box<dyn Write>,
+}
+
+impl Debugger {
+    /// A debugger for a program parsed from `source`, which is quoted when
+    /// the program stops.
+    pub fn new(source: &str) -> Self {
+        let mut sources = SourceMap::new();
+        sources.add("", source);
+        Self::for_files(&sources)
+    }
+
+    /// A debugger for a program loaded from the files of `sources`, such as
+    /// one that imports others.
+    pub fn for_files(sources: &SourceMap) -> Self {
+        Self {
+            lines: sources.files().iter().map(|file| file.source.lines().map(str::to_string).collect()).collect(),
+            names: sources.files().iter().map(|file| file.name.clone()).collect(),
+            breakpoints: BTreeSet::new(),
+            mode: Mode::Step,
+            last_command: String::new(),
+            input: None,
+            output: Box::new(io::stdout()),
+        }
+    }
+
+    /// Reads commands from `input` instead of standard input.
+    pub fn with_input(mut self, input: impl BufRead + 'static) -> Self {
+        self.input = Some(Box::new(input));
+        self
+    }
+
+    /// Writes prompts and answers to `output` instead of standard output.
+    pub fn with_output(mut self, output: impl Write + 'static) -> Self {
+        self.output = Box::new(output);
+        self
+    }
+
+    fn read_command(&mut self) -> io::Result<Option<String>> {
+        write!(self.output, "(debug) ")?;
+        self.output.flush()?;
+        let mut line = String::new();
+        let read = match self.input.as_mut() {
+            Some(input) => input.read_line(&mut line)?,
+            None => io::stdin().read_line(&mut line)?,
+        };
+        Ok((read > 0).then(|| line.trim().to_string()))
+    }
+
+    fn show_line(&mut self, file: usize, line: usize) -> io::Result<()> {
+        let text = line_text(&self.lines, file, line).unwrap_or("");
+        writeln!(self.output, "{:>4} | {}", line, text)
+    }
+
+    /// A line as the user names it: `line 4` in the first file, which is
+    /// the one being debugged, and `utils.lang:4` in any other.
+    fn describe(&self, (file, line): (usize, usize)) -> String {
+        match self.names.get(file).filter(|_| file > 0) {
+            Some(name) => format!("{}:{}", name, line),
+            None => format!("line {}", line),
+        }
+    }
+
+    /// The file and line a `break` or `delete` argument names: a line of
+    /// the first file, or `<file>:<line>` with the file's path or just its
+    /// name.
+    fn parse_line(&self, arg: &str) -> Option<(usize, usize)> {
+        let Some((name, line)) = arg.rsplit_once(':') else {
+            return Some((0, arg.parse().ok()?));
+        };
+        let file = self.names.iter().position(|path| path == name || std::path::Path::new(path).file_name().is_some_and(|file| file == name))?;
+        Some((file, line.parse().ok()?))
+    }
+
+    /// Reads and runs commands until one resumes the program. Returns false
+    /// if the program should stop.
+    fn prompt(&mut self, stmt: &Stmt, env: &Environment<Value>, call_depth: usize) -> io::Result<bool> {
+        let at = (stmt.span.file, stmt.span.line);
+        writeln!(self.output, "stopped at {}", self.describe(at))?;
+        self.show_line(at.0, at.1)?;
+        loop {
+            let Some(mut command) = self.read_command()? else {
+                return Ok(false);
+            };
+            if command.is_empty() {
+                command = self.last_command.clone();
+            }
+            self.last_command = command.clone();
+            let (name, arg) = command.split_once(' ').map_or((command.as_str(), ""), |(name, arg)| (name, arg.trim()));
+            match name {
+                "s" | "step" => {
+                    self.mode = Mode::Step;
+                    return Ok(true);
+                }
+                "n" | "next" => {
+                    self.mode = Mode::Next(call_depth);
+                    return Ok(true);
+                }
+                "c" | "continue" => {
+                    self.mode = Mode::Continue;
+                    return Ok(true);
+                }
+                "q" | "quit" => return Ok(false),
+                "b" | "break" => match self.parse_line(arg) {
+                    Some(line) => {
+                        self.breakpoints.insert(line);
+                        writeln!(self.output, "breakpoint on {}", self.describe(line))?;
+                    }
+                    None => writeln!(self.output, "break expects a line number, or a file and line such as utils.lang:4")?,
+                },
+                "d" | "delete" => match self.parse_line(arg) {
+                    Some(line) if self.breakpoints.remove(&line) => writeln!(self.output, "removed the breakpoint on {}", self.describe(line))?,
+                    Some(line) => writeln!(self.output, "no breakpoint on {}", self.describe(line))?,
+                    None => writeln!(self.output, "delete expects a line number, or a file and line such as utils.lang:4")?,
+                },
+                "p" | "print" => match env.get(Symbol::intern(arg)) {
+                    Some(value) => writeln!(self.output, "{} = {}", arg, value)?,
+                    None => writeln!(self.output, "no variable named `{}` in scope", arg)?,
+                },
+                "v" | "vars" => {
+                    for (name, value) in env.bindings() {
+                        writeln!(self.output, "{} = {}", name, value)?;
+                    }
+                }
+                "l" | "list" => {
+                    let (file, line) = at;
+                    for shown in line.saturating_sub(3).max(1)..=line + 3 {
+                        if let Some(text) = line_text(&self.lines, file, shown) {
+                            let marker = if shown == line { '>' } else { ' ' };
+                            writeln!(self.output, "{}{:>3} | {}", marker, shown, text)?;
+                        }
+                    }
+                }
+                "h" | "help" => writeln!(self.output, "{}", HELP)?,
+                _ => writeln!(self.output, "unknown command `{}`; type `help` for a list", command)?,
+            }
+        }
+    }
+}
+
+/// Line `line`, counting from 1, of file `file`.
+fn line_text(lines: &[Vec<String>], file: usize, line: usize) -> Option<&str> {
+    lines.get(file)?.get(line.wrapping_sub(1)).map(String::as_str)
+}
+
+impl Hook for Debugger {
+    fn before_stmt(&mut self, stmt: &Stmt, env: &Environment<Value>, call_depth: usize) -> Result<(), CompilerError> {
+        // Declarations run nothing, so there is nothing to stop before.
+        if matches!(stmt.node, StmtKind::FnDecl(..) | StmtKind::StructDecl(..) | StmtKind::EnumDecl(..) | StmtKind::TypeAlias(..) | StmtKind::Import(..)) {
+            return Ok(());
+        }
+        let stop = match self.mode {
+            Mode::Step => true,
+            Mode::Next(depth) => call_depth <= depth,
+            Mode::Continue => false,
+        };
+        if !stop && !self.breakpoints.contains(&(stmt.span.file, stmt.span.line)) {
+            return Ok(());
+        }
+        match self.prompt(stmt, env, call_depth) {
+            Ok(true) => Ok(()),
+            Ok(false) => Err(CompilerError::Stopped("stopped by the debugger".to_string(), Some(stmt.span))),
+            Err(e) => Err(CompilerError::RuntimeError(format!("debugger I/O failed: {}", e), Some(stmt.span))),
+        }
+    }
+}
\ No newline at end of file
@@ -0,0 +1,163 @@
+use crate::error::Diagnostic;
+use crate::source_map::SourceMap;
+use std::fmt::Write;
+
+/// Renders `diagnostic` rustc-style: a header with the severity and code,
+/// the location, the offending source line with the span underlined by
+/// carets, and any notes.
+///
+/// ```text
+/// warning[W0003]: Unused variable: total
+///  --> demo.lang:4:1
+///   |
+/// 4 | let total = 0;
+///   | ^^^^^^^^^^^^^^
+///   = note: if this is intentional, prefix it with an underscore: _total
+/// ```
+///
+/// Diagnostics without a span render as the header line and notes alone.
+pub fn render(diagnostic: &Diagnostic, source: &str, path: &str) -> String {
+    let mut out = format!("{}[{}]: {}\n", diagnostic.severity, diagnostic.code, diagnostic.message);
+    let gutter = match diagnostic.span {
+        Some(span) => {
+            let gutter = " ".repeat(span.line.to_string().len());
+            snippet(&mut out, span, source, path, &gutter);
+            gutter
+        }
+        None => String::new(),
+    };
+    for note in &diagnostic.notes {
+        let _ = writeln!(out, "{} = note: {}", gutter, note);
+    }
+    out
+}
+
+/// Renders `diagnostic` with [`render`], quoting the file of `sources` its
+/// span is in, so `utils.lang:4:7` points into `utils.lang`. One that
+/// cannot be placed in a file renders as a single line.
+pub fn render_in(diagnostic: &Diagnostic, sources: &SourceMap) -> String {
+    match sources.file_of(diagnostic.span) {
+        Some(file) => render(diagnostic, &file.source, &file.name),
+        None => format!("{}\n", diagnostic),
+    }
+}
+
+/// Renders `diagnostic` with [`render_json`], naming the file of `sources`
+/// its span is in.
+pub fn render_json_in(diagnostic: &Diagnostic, sources: &SourceMap) -> String {
+    render_json(diagnostic, sources.file_of(diagnostic.span).map(|file| file.name.as_str()))
+}
+
+/// Renders `diagnostic` as a single line of JSON for editors and CI tools:
+///
+/// ```text
+/// {"code":"E0002","severity":"error","message":"Undeclared variable: x","file":"demo.lang","span":{"start":4,"end":5,"line":1,"col":5},"notes":[]}
+/// ```
+///
+/// `file` and `span` are `null` when unknown.
+pub fn render_json(diagnostic: &Diagnostic, path: Option<&str>) -> String {
+    let mut out = String::from("{");
+    let _ = write!(out, "\"code\":{},", json_string(diagnostic.code));
+    let _ = write!(out, "\"severity\":{},", json_string(&diagnostic.severity.to_string()));
+    let _ = write!(out, "\"message\":{},", json_string(&diagnostic.message));
+    let _ = write!(out, "\"file\":{},", path.map_or("null".to_string(), json_string));
+    match diagnostic.span {
+        Some(span) => {
+            let _ = write!(
+                out,
+                "\"span\":{{\"start\":{},\"end\":{},\"line\":{},\"col\":{}}},",
+                span.start, span.end, span.line, span.col
+            );
+        }
+        None => out.push_str("\"span\":null,"),
+    }
+    let notes: Vec<String> = diagnostic.notes.iter().map(|note| json_string(note)).collect();
+    let _ = write!(out, "\"notes\":[{}]}}", notes.join(","));
+    out
+}
+
+/// `s` as a quoted JSON string.
+fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if u32::from(c) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", u32::from(c));
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn snippet(out: &mut String, span: crate::span::Span, source: &str, path: &str, gutter: &str) {
+    let Some(line) = source.lines().nth(span.line.saturating_sub(1)) else {
+        let _ = writeln!(out, " --> {}:{}:{}", path, span.line, span.col);
+        return;
+    };
+
+    let _ = writeln!(out, "{}--> {}:{}:{}", gutter, path, span.line, span.col);
+    let _ = writeln!(out, "{} |", gutter);
+    let _ = writeln!(out, "{} | {}", span.line, line);
+
+    // Underline up to the end of the line for spans covering several lines.
+    let line_len = line.chars().count();
+    let start = span.col.saturating_sub(1).min(line_len);
+    let width = source
+        .get(span.start..span.end)
+        .map_or(1, |text| text.lines().next().unwrap_or("").chars().count())
+        .min(line_len - start)
+        .max(1);
+    let _ = writeln!(out, "{} | {}{}", gutter, " ".repeat(start), "^".repeat(width));
+}
+
+/// Appends "; did you mean `x`?" to `message` when one of `candidates` is
+/// within a third of `name`'s length in edit distance.
+pub fn with_suggestion<'a>(message: String, name: &str, candidates: impl IntoIterator<Item = &'a str>) -> String {
+    match suggest(name, candidates) {
+        Some(close) => format!("{}; did you mean `{}`?", message, close),
+        None => message,
+    }
+}
+
+/// The candidate closest to `name` by edit distance, if any is close enough
+/// to be a plausible typo. Ties go to the alphabetically first candidate so
+/// the result does not depend on hash map order.
+pub fn suggest<'a>(name: &str, candidates: impl IntoIterator<Item = &'a str>) -> Option<&'a str> {
+    let limit = (name.chars().count() / 3).max(1);
+    candidates
+        .into_iter()
+        .filter(|candidate| *candidate != name)
+        .map(|candidate| (edit_distance(name, candidate), candidate))
+        .filter(|(distance, _)| *distance <= limit)
+        .min()
+        .map(|(_, candidate)| candidate)
+}
+
+/// Edit distance between `a` and `b`, counted in chars, where inserting,
+/// deleting or replacing a char and swapping two adjacent chars each cost one.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let mut before: Vec<usize> = Vec::new();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for i in 1..=a.len() {
+        let mut row = vec![i; b.len() + 1];
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            row[j] = (previous[j - 1] + cost).min(previous[j] + 1).min(row[j - 1] + 1);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                row[j] = row[j].min(before[j - 2] + 1);
+            }
+        }
+        before = std::mem::replace(&mut previous, row);
+    }
+    previous[b.len()]
+}
\ No newline at end of file
@@ -0,0 +1,381 @@
+use crate::ast::*;
+use std::collections::HashMap;
+use std::fmt;
+
+/// A structural difference between two versions of a program.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AstChange {
+    FunctionAdded(String),
+    FunctionRemoved(String),
+    FunctionRenamed(String, String),   // old name, new name
+    SignatureChanged(String),
+    BodyChanged(String),
+    TopLevelChanged,
+}
+
+impl fmt::Display for AstChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AstChange::FunctionAdded(name) => write!(f, "+ fn {}", name),
+            AstChange::FunctionRemoved(name) => write!(f, "- fn {}", name),
+            AstChange::FunctionRenamed(old, new) => write!(f, "~ fn {} renamed to {}", old, new),
+            AstChange::SignatureChanged(name) => write!(f, "~ fn {}: signature changed", name),
+            AstChange::BodyChanged(name) => write!(f, "~ fn {}: body changed", name),
+            AstChange::TopLevelChanged => write!(f, "~ top-level statements changed"),
+        }
+    }
+}
+
+/// A function reduced to what matters for comparison: its signature and a
+/// canonical rendering of its body in which parameters and locals are
+/// numbered by binding order, so renaming them does not count as a change.
+pub(crate) struct CanonicalFn {
+    pub name: String,
+    pub signature: String,
+    pub body: String,
+}
+
+/// Compares two parsed programs and lists functions added, removed, renamed
+/// or changed, plus whether the remaining top-level statements differ.
+pub fn diff_programs(old: &[Stmt], new: &[Stmt]) -> Vec<AstChange> {
+    let (old_fns, old_top) = canonicalize_program(old);
+    let (new_fns, new_top) = canonicalize_program(new);
+    let mut changes = Vec::new();
+
+    let mut removed: Vec<&CanonicalFn> = Vec::new();
+    for old_fn in &old_fns {
+        match new_fns.iter().find(|f| f.name == old_fn.name) {
+            Some(new_fn) if new_fn.signature != old_fn.signature => {
+                changes.push(AstChange::SignatureChanged(old_fn.name.clone()));
+            }
+            Some(new_fn) if new_fn.body != old_fn.body => {
+                changes.push(AstChange::BodyChanged(old_fn.name.clone()));
+            }
+            Some(_) => {}
+            None => removed.push(old_fn),
+        }
+    }
+
+    let mut added: Vec<&CanonicalFn> = new_fns
+        .iter()
+        .filter(|new_fn| !old_fns.iter().any(|f| f.name == new_fn.name))
+        .collect();
+
+    // A removed function whose body reappears under a new name is a rename.
+    for old_fn in removed {
+        let renamed = added
+            .iter()
+            .position(|new_fn| new_fn.signature == old_fn.signature && new_fn.body == old_fn.body);
+        match renamed {
+            Some(i) => {
+                let new_fn = added.remove(i);
+                changes.push(AstChange::FunctionRenamed(old_fn.name.clone(), new_fn.name.clone()));
+            }
+            None => changes.push(AstChange::FunctionRemoved(old_fn.name.clone())),
+        }
+    }
+    for new_fn in added {
+        changes.push(AstChange::FunctionAdded(new_fn.name.clone()));
+    }
+
+    if old_top != new_top {
+        changes.push(AstChange::TopLevelChanged);
+    }
+    changes
+}
+
+pub(crate) fn canonicalize_program(program: &[Stmt]) -> (Vec<CanonicalFn>, String) {
+    let mut functions = Vec::new();
+    let mut top_level = Canonicalizer::globals();
+    for stmt in program {
+        if let StmtKind::FnDecl(name, params, return_type, body) = &stmt.node {
+            let mut canon = Canonicalizer::function(params);
+            canon.block(body);
+            functions.push(CanonicalFn {
+                name: name.to_string(),
+                signature: signature(params, return_type),
+                body: canon.out,
+            });
+        } else {
+            top_level.stmt(stmt);
+        }
+    }
+    (functions, top_level.out)
+}
+
+/// Renders parameter and return types, e.g. `(int _) [int]`, with `_` for
+/// a missing annotation. Parameter names are not part of the signature.
+fn signature(params: &[Param], return_type: &Option<TypeExpr>) -> String {
+    let annotation = |ty: &Option<TypeExpr>| ty.as_ref().map_or("_".to_string(), |ty| ty.to_string());
+    let params: Vec<String> = params.iter().map(|param| annotation(&param.ty)).collect();
+    format!("({}) {}", params.join(" "), annotation(return_type))
+}
+
+struct Canonicalizer {
+    // None at top level, where names are globals visible to every function
+    // and must keep their identity.
+    locals: Option<HashMap<String, usize>>,
+    out: String,
+}
+
+impl Canonicalizer {
+    fn globals() -> Self {
+        Self { locals: None, out: String::new() }
+    }
+
+    fn function(params: &[Param]) -> Self {
+        let mut canon = Self { locals: Some(HashMap::new()), out: String::new() };
+        for param in params {
+            canon.bind(&param.name);
+        }
+        canon
+    }
+
+    fn bind(&mut self, name: &str) {
+        if let Some(locals) = &mut self.locals {
+            let next = locals.len();
+            locals.entry(name.to_string()).or_insert(next);
+        }
+    }
+
+    fn name(&mut self, name: &str) {
+        match self.locals.as_ref().and_then(|locals| locals.get(name)) {
+            Some(index) => self.out.push_str(&format!("${}", index)),
+            None => self.out.push_str(name),
+        }
+    }
+
+    fn block(&mut self, stmts: &[Stmt]) {
+        self.out.push('{');
+        for stmt in stmts {
+            self.stmt(stmt);
+        }
+        self.out.push('}');
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) {
+        match &stmt.node {
+            StmtKind::Let(name, ty, expr) | StmtKind::Const(name, ty, expr) => {
+                self.out.push_str(if matches!(stmt.node, StmtKind::Const(..)) { "(const " } else { "(let " });
+                if let Some(ty) = ty {
+                    self.out.push_str(&format!("{} ", ty));
+                }
+                self.expr(expr);
+                self.bind(name);
+                self.out.push(' ');
+                self.name(name);
+            }
+            StmtKind::Assign(name, expr) => {
+                self.out.push_str("(set ");
+                self.name(name);
+                self.out.push(' ');
+                self.expr(expr);
+            }
+            StmtKind::IndexAssign(array, index, value) => {
+                self.out.push_str("(set-index ");
+                self.expr(array);
+                self.out.push(' ');
+                self.expr(index);
+                self.out.push(' ');
+                self.expr(value);
+            }
+            StmtKind::FieldAssign(object, field, value) => {
+                self.out.push_str("(set-field ");
+                self.expr(object);
+                self.out.push_str(&format!(" {} ", field));
+                self.expr(value);
+            }
+            StmtKind::Expr(expr) => {
+                self.out.push_str("(expr ");
+                self.expr(expr);
+            }
+            StmtKind::If(cond, then_block, else_block) => {
+                self.out.push_str("(if ");
+                self.expr(cond);
+                self.block(then_block);
+                self.block(else_block);
+            }
+            StmtKind::While(cond, body) => {
+                self.out.push_str("(while ");
+                self.expr(cond);
+                self.block(body);
+            }
+            StmtKind::DoWhile(body, cond) => {
+                self.out.push_str("(do ");
+                self.block(body);
+                self.expr(cond);
+            }
+            StmtKind::For(init, cond, step, body) => {
+                self.out.push_str("(for ");
+                self.stmt(init);
+                self.out.push(' ');
+                self.expr(cond);
+                self.out.push(' ');
+                self.stmt(step);
+                self.block(body);
+            }
+            StmtKind::ForRange(var, start, end, inclusive, body) => {
+                self.out.push_str(if *inclusive { "(for-range-inclusive " } else { "(for-range " });
+                self.expr(start);
+                self.out.push(' ');
+                self.expr(end);
+                self.bind(var);
+                self.out.push(' ');
+                self.name(var);
+                self.block(body);
+            }
+            StmtKind::Match(value, arms) => {
+                self.out.push_str("(match ");
+                self.expr(value);
+                for arm in arms {
+                    self.out.push_str(" (arm ");
+                    match &arm.pattern {
+                        Pattern::Literal(literal) => self.expr(literal),
+                        Pattern::Wildcard => self.out.push('_'),
+                    }
+                    self.block(arm.body);
+                    self.out.push(')');
+                }
+            }
+            StmtKind::FnDecl(name, params, return_type, body) => {
+                self.out.push_str(&format!("(fn {} {}", name, signature(params, return_type)));
+                for param in params {
+                    self.bind(&param.name);
+                }
+                self.block(body);
+            }
+            StmtKind::StructDecl(name, fields) => {
+                self.out.push_str(&format!("(struct {}", name));
+                for field in fields {
+                    self.out.push_str(&format!(" ({} {})", field.name, field.ty));
+                }
+            }
+            StmtKind::EnumDecl(name, variants) => self.out.push_str(&format!("(enum {} {}", name, variants.iter().map(|variant| variant.as_str()).collect::<Vec<_>>().join(" "))),
+            StmtKind::TypeAlias(name, ty) => self.out.push_str(&format!("(type {} {}", name, ty)),
+            StmtKind::Import(path) => self.out.push_str(&format!("(import {:?}", path)),
+            StmtKind::Return(expr) => {
+                self.out.push_str("(return");
+                if let Some(expr) = expr {
+                    self.out.push(' ');
+                    self.expr(expr);
+                }
+            }
+            StmtKind::Break => self.out.push_str("(break"),
+            StmtKind::Continue => self.out.push_str("(continue"),
+            StmtKind::Block(stmts) => {
+                self.out.push_str("(block ");
+                self.block(stmts);
+            }
+        }
+        self.out.push(')');
+    }
+
+    fn expr(&mut self, expr: &Expr) {
+        match &expr.node {
+            ExprKind::Number(n) => self.out.push_str(&n.to_string()),
+            ExprKind::Float(n) => self.out.push_str(&format!("{:?}", n)),
+            ExprKind::Str(s) => self.out.push_str(&format!("{:?}", s)),
+            ExprKind::Char(c) => self.out.push_str(&format!("{:?}", c)),
+            ExprKind::Bool(b) => self.out.push_str(&b.to_string()),
+            ExprKind::Null => self.out.push_str("null"),
+            ExprKind::Variant(name, variant) => self.out.push_str(&format!("{}::{}", name, variant)),
+            ExprKind::Variable(name) => self.name(name),
+            ExprKind::Unary(op, operand) => {
+                self.out.push_str(&format!("({:?} ", op));
+                self.expr(operand);
+                self.out.push(')');
+            }
+            ExprKind::Update(name, op, prefix) => {
+                self.out.push_str(&format!("({:?} {} ", op, prefix));
+                self.name(name);
+                self.out.push(')');
+            }
+            ExprKind::Binary(lhs, op, rhs) => {
+                self.out.push_str(&format!("({:?} ", op));
+                self.expr(lhs);
+                self.out.push(' ');
+                self.expr(rhs);
+                self.out.push(')');
+            }
+            ExprKind::Call(name, args) => {
+                self.out.push_str(&format!("(call {}", name));
+                for arg in args.iter() {
+                    self.out.push(' ');
+                    self.expr(arg);
+                }
+                self.out.push(')');
+            }
+            ExprKind::Array(elements) => {
+                self.out.push('[');
+                for element in elements.iter() {
+                    self.expr(element);
+                    self.out.push(' ');
+                }
+                self.out.push(']');
+            }
+            ExprKind::Map(entries) => {
+                self.out.push_str("(map");
+                for (key, value) in entries {
+                    self.out.push_str(" (");
+                    self.expr(key);
+                    self.out.push(' ');
+                    self.expr(value);
+                    self.out.push(')');
+                }
+                self.out.push(')');
+            }
+            ExprKind::Index(array, index) => {
+                self.out.push_str("(index ");
+                self.expr(array);
+                self.out.push(' ');
+                self.expr(index);
+                self.out.push(')');
+            }
+            ExprKind::StructLit(name, fields) => {
+                self.out.push_str(&format!("(new {}", name));
+                for (field, value) in fields {
+                    self.out.push_str(&format!(" ({} ", field));
+                    self.expr(value);
+                    self.out.push(')');
+                }
+                self.out.push(')');
+            }
+            ExprKind::Field(object, field) => {
+                self.out.push_str("(field ");
+                self.expr(object);
+                self.out.push_str(&format!(" {})", field));
+            }
+            ExprKind::If(cond, then_value, else_value) => {
+                self.out.push_str("(if ");
+                self.expr(cond);
+                self.out.push(' ');
+                self.expr(then_value);
+                self.out.push(' ');
+                self.expr(else_value);
+                self.out.push(')');
+            }
+            ExprKind::Block(stmts, value) => {
+                self.out.push_str("(block-value ");
+                self.block(stmts);
+                self.out.push(' ');
+                self.expr(value);
+                self.out.push(')');
+            }
+            ExprKind::Match(value, arms) => {
+                self.out.push_str("(match-value ");
+                self.expr(value);
+                for arm in arms {
+                    self.out.push_str(" (arm ");
+                    match &arm.pattern {
+                        Pattern::Literal(literal) => self.expr(literal),
+                        Pattern::Wildcard => self.out.push('_'),
+                    }
+                    self.out.push(' ');
+                    self.expr(&arm.value);
+                    self.out.push(')');
+                }
+                self.out.push(')');
+            }
+        }
+    }
+}
\ No newline at end of file
@@ -0,0 +1,134 @@
+use crate::symbol::Symbol;
+use std::collections::{HashMap, HashSet};
+
+/// Lexically scoped bindings from names to `T`. Each scope's parent is the
+/// one below it on the stack; the bottom scope holds the globals. Lookups
+/// walk from the innermost scope outwards, skipping the scopes of callers
+/// hidden by `enter_call`.
+#[derive(Debug, Clone)]
+pub struct Environment<T> {
+    scopes: Vec<HashMap<Symbol, T>>,
+    /// Where the scopes of the running function call start; those between
+    /// the globals and here belong to its callers.
+    frame: usize,
+    /// The `frame` of each caller, innermost last.
+    frames: Vec<usize>,
+}
+
+impl<T> Default for Environment<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Environment<T> {
+    pub fn new() -> Self {
+        Self {
+            scopes: vec![HashMap::new()],
+            frame: 1,
+            frames: Vec::new(),
+        }
+    }
+
+    pub fn push_scope(&mut self) {
+        self.scopes.push(HashMap::new());
+    }
+
+    pub fn pop_scope(&mut self) {
+        if self.scopes.len() > 1 {
+            self.scopes.pop();
+        }
+    }
+
+    /// Starts a function call: hides every scope except the globals and
+    /// opens a fresh scope for the callee. The caller's scopes stay on the
+    /// stack, so nothing is moved or copied; `exit_call` uncovers them.
+    pub fn enter_call(&mut self) {
+        self.frames.push(self.frame);
+        self.frame = self.scopes.len();
+        self.push_scope();
+    }
+
+    /// Ends the call started by the matching `enter_call`, dropping the
+    /// callee's scopes.
+    pub fn exit_call(&mut self) {
+        self.scopes.truncate(self.frame);
+        self.frame = self.frames.pop().expect("exit_call follows enter_call");
+    }
+
+    /// The scopes lookups see, outermost first: the globals, then the
+    /// running call's.
+    fn visible(&self) -> impl DoubleEndedIterator<Item = &HashMap<Symbol, T>> {
+        self.scopes[..1].iter().chain(&self.scopes[self.frame..])
+    }
+
+    /// Binds `name` in the innermost scope.
+    pub fn define(&mut self, name: Symbol, value: T) {
+        self.scopes
+            .last_mut()
+            .expect("environment always has a global scope")
+            .insert(name, value);
+    }
+
+    pub fn get(&self, name: Symbol) -> Option<&T> {
+        self.visible().rev().find_map(|scope| scope.get(&name))
+    }
+
+    /// How many scopes are on the stack, the globals and hidden ones
+    /// included.
+    pub fn depth(&self) -> usize {
+        self.scopes.len()
+    }
+
+    /// The position on the stack of the scope `get` finds `name` in,
+    /// counting the globals as 0.
+    pub fn scope_of(&self, name: Symbol) -> Option<usize> {
+        if let Some(scope) = self.scopes[self.frame..].iter().rposition(|scope| scope.contains_key(&name)) {
+            return Some(self.frame + scope);
+        }
+        self.scopes[0].contains_key(&name).then_some(0)
+    }
+
+    /// Every name visible from the innermost scope, shadowed ones included.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.visible().flat_map(|scope| scope.keys().map(|name| name.as_str()))
+    }
+
+    /// Every bound value, shadowed ones and those of hidden callers included.
+    pub fn values(&self) -> impl Iterator<Item = &T> {
+        self.scopes.iter().flat_map(HashMap::values)
+    }
+
+    pub fn values_mut(&mut self) -> impl Iterator<Item = &mut T> {
+        self.scopes.iter_mut().flat_map(HashMap::values_mut)
+    }
+
+    /// The bindings `get` can see, innermost scope first and sorted by name
+    /// within a scope.
+    pub fn bindings(&self) -> Vec<(&str, &T)> {
+        let mut seen = HashSet::new();
+        let mut bindings = Vec::new();
+        for scope in self.visible().rev() {
+            let mut names: Vec<&Symbol> = scope.keys().collect();
+            names.sort();
+            for name in names {
+                if seen.insert(name) {
+                    bindings.push((name.as_str(), &scope[name]));
+                }
+            }
+        }
+        bindings
+    }
+
+    /// Rebinds the nearest existing `name`. Returns false if it is not bound.
+    pub fn assign(&mut self, name: Symbol, value: T) -> bool {
+        let (globals, locals) = self.scopes.split_at_mut(1);
+        match globals.iter_mut().chain(&mut locals[self.frame - 1..]).rev().find_map(|scope| scope.get_mut(&name)) {
+            Some(slot) => {
+                *slot = value;
+                true
+            }
+            None => false,
+        }
+    }
+}
\ No newline at end of file
@@ -1,19 +1,215 @@
-#[allow(dead_code)]
+use crate::span::Span;
+
+#[allow(dead_code, clippy::enum_variant_names)]
 #[derive(Debug)]
 pub enum CompilerError {
-    SyntaxError(String),
-    TypeError(String),
-    RuntimeError(String),
+    SyntaxError(String, Option<Span>),
+    TypeError(String, Option<Span>),
+    RuntimeError(String, Option<Span>),
+    /// A valid program uses something a code generation backend cannot
+    /// translate.
+    UnsupportedError(String, Option<Span>),
+    /// The program ran past a step or time limit set by the host; see
+    /// [`crate::interpreter::InterpreterOptions`].
+    LimitExceeded(String, Option<Span>),
+    /// A [`crate::interpreter::Hook`] stopped the program on request, as
+    /// the debugger's `quit` does. The program did not fail.
+    Stopped(String, Option<Span>),
 }
 
 impl std::fmt::Display for CompilerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let kind = match self {
+            CompilerError::SyntaxError(..) => "Syntax error",
+            CompilerError::TypeError(..) => "Type error",
+            CompilerError::RuntimeError(..) => "Runtime error",
+            CompilerError::UnsupportedError(..) => "Unsupported",
+            CompilerError::LimitExceeded(..) => "Limit exceeded",
+            CompilerError::Stopped(..) => "Stopped",
+        };
+        match self.span() {
+            Some(span) => write!(f, "{} at {}: {}", kind, span, self.message()),
+            None => write!(f, "{}: {}", kind, self.message()),
+        }
+    }
+}
+
+impl CompilerError {
+    pub fn message(&self) -> &str {
+        match self {
+            CompilerError::SyntaxError(msg, _)
+            | CompilerError::TypeError(msg, _)
+            | CompilerError::RuntimeError(msg, _)
+            | CompilerError::UnsupportedError(msg, _)
+            | CompilerError::LimitExceeded(msg, _)
+            | CompilerError::Stopped(msg, _) => msg,
+        }
+    }
+
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            CompilerError::SyntaxError(_, span)
+            | CompilerError::TypeError(_, span)
+            | CompilerError::RuntimeError(_, span)
+            | CompilerError::UnsupportedError(_, span)
+            | CompilerError::LimitExceeded(_, span)
+            | CompilerError::Stopped(_, span) => *span,
+        }
+    }
+
+    /// Stable identifier printed with diagnostics, one per kind of error.
+    pub fn code(&self) -> &'static str {
+        match self {
+            CompilerError::SyntaxError(..) => "E0001",
+            CompilerError::TypeError(..) => "E0002",
+            CompilerError::RuntimeError(..) => "E0003",
+            CompilerError::UnsupportedError(..) => "E0004",
+            CompilerError::LimitExceeded(..) => "E0005",
+            CompilerError::Stopped(..) => "E0006",
+        }
+    }
+
+    /// Process exit status used by the CLI for this kind of error.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            CompilerError::SyntaxError(..) => 2,
+            CompilerError::TypeError(..) => 3,
+            CompilerError::RuntimeError(..) => 4,
+            CompilerError::UnsupportedError(..) => 5,
+            CompilerError::LimitExceeded(..) => 7,
+            CompilerError::Stopped(..) => 0,
+        }
+    }
+}
+
+impl std::error::Error for CompilerError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl std::fmt::Display for Severity {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            CompilerError::SyntaxError(msg) => write!(f, "Syntax error: {}", msg),
-            CompilerError::TypeError(msg) => write!(f, "Type error: {}", msg),
-            CompilerError::RuntimeError(msg) => write!(f, "Runtime error: {}", msg),
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
         }
     }
 }
 
-impl std::error::Error for CompilerError {}
\ No newline at end of file
+/// One reportable problem. Errors stop compilation and arrive as
+/// [`CompilerError`]s, which convert into diagnostics for rendering;
+/// warnings are produced as diagnostics directly.
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// Stable identifier: `E....` for errors, `W....` for warnings.
+    pub code: &'static str,
+    pub message: String,
+    pub span: Option<Span>,
+    /// Extra remarks shown after the source snippet, such as how to fix it.
+    pub notes: Vec<String>,
+}
+
+impl Diagnostic {
+    pub fn warning(code: &'static str, message: String, span: Option<Span>) -> Self {
+        Self { severity: Severity::Warning, code, message, span, notes: Vec::new() }
+    }
+
+    pub fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push(note.into());
+        self
+    }
+}
+
+impl From<&CompilerError> for Diagnostic {
+    fn from(error: &CompilerError) -> Self {
+        Self {
+            severity: Severity::Error,
+            code: error.code(),
+            message: error.message().to_string(),
+            span: error.span(),
+            notes: Vec::new(),
+        }
+    }
+}
+
+impl std::fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}[{}]", self.severity, self.code)?;
+        if let Some(span) = self.span {
+            write!(f, " at {}", span)?;
+        }
+        write!(f, ": {}", self.message)?;
+        for note in &self.notes {
+            write!(f, "\n  = note: {}", note)?;
+        }
+        Ok(())
+    }
+}
+
+/// Diagnostics collected over a run, in the order they were found, so that
+/// every problem can be reported together instead of stopping at the first.
+#[derive(Debug, Default)]
+pub struct DiagnosticBag {
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl DiagnosticBag {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, diagnostic: Diagnostic) {
+        self.diagnostics.push(diagnostic);
+    }
+
+    pub fn error(&mut self, error: &CompilerError) {
+        self.push(error.into());
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.diagnostics.is_empty()
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.error_count() > 0
+    }
+
+    pub fn error_count(&self) -> usize {
+        self.count(Severity::Error)
+    }
+
+    pub fn warning_count(&self) -> usize {
+        self.count(Severity::Warning)
+    }
+
+    fn count(&self, severity: Severity) -> usize {
+        self.diagnostics.iter().filter(|d| d.severity == severity).count()
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, Diagnostic> {
+        self.diagnostics.iter()
+    }
+
+    pub fn into_vec(self) -> Vec<Diagnostic> {
+        self.diagnostics
+    }
+}
+
+impl Extend<Diagnostic> for DiagnosticBag {
+    fn extend<I: IntoIterator<Item = Diagnostic>>(&mut self, iter: I) {
+        self.diagnostics.extend(iter);
+    }
+}
+
+impl<'a> IntoIterator for &'a DiagnosticBag {
+    type Item = &'a Diagnostic;
+    type IntoIter = std::slice::Iter<'a, Diagnostic>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.diagnostics.iter()
+    }
+}
\ No newline at end of file
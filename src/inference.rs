@@ -0,0 +1,339 @@
+//! Inference of unannotated parameter types.
+//!
+//! Before any function is checked, [`gather`] walks the program once and
+//! collects evidence for the type of every parameter written without one:
+//! how the body uses it (`n + 1`, `if (flag)`, `p.x`, passing it to an
+//! annotated parameter) and what the calls to the function pass for it.
+//! The type checker settles each parameter from that evidence.
+
+use crate::ast::*;
+use crate::environment::Environment;
+use crate::span::Span;
+use crate::symbol::Symbol;
+use crate::type_checker::{Type, TypeChecker};
+use std::collections::HashMap;
+
+/// A parameter, as the function's name and the parameter's position.
+pub(crate) type ParamKey = (Symbol, usize);
+
+/// What the program says about its unannotated parameters. Each list is in
+/// source order, with the span of the expression that gave the type.
+#[derive(Default)]
+pub(crate) struct Evidence {
+    /// Types demanded by uses of the parameter in the function's body.
+    pub uses: HashMap<ParamKey, Vec<(Type, Span)>>,
+    /// Types of the arguments calls pass for the parameter.
+    pub calls: HashMap<ParamKey, Vec<(Type, Span)>>,
+}
+
+/// What a name in scope is known to hold while walking.
+#[derive(Clone)]
+enum Binding {
+    /// The unannotated parameter at this position of the current function.
+    Param(usize),
+    Known(Type),
+    Unknown,
+}
+
+struct Walker<'a> {
+    /// The checker, with every type declared in the program registered.
+    checker: &'a TypeChecker,
+    /// Every declared function's parameter types, `None` where unannotated.
+    signatures: HashMap<Symbol, Vec<Option<Type>>>,
+    /// Declared result types, where written.
+    results: HashMap<Symbol, Type>,
+    /// The function whose body is being walked; `None` at top level.
+    function: Option<Symbol>,
+    env: Environment<Binding>,
+    evidence: Evidence,
+}
+
+/// Collects the evidence for every unannotated parameter of the functions
+/// declared in `program`, once `checker` has registered its types.
+pub(crate) fn gather<'a>(program: &'a [Stmt], checker: &'a TypeChecker) -> Evidence {
+    let mut walker = Walker {
+        checker,
+        signatures: HashMap::new(),
+        results: HashMap::new(),
+        function: None,
+        env: Environment::new(),
+        evidence: Evidence::default(),
+    };
+    for stmt in program {
+        if let StmtKind::FnDecl(name, params, result, _) = &stmt.node {
+            let types = params.iter().map(|param| param.ty.as_ref().and_then(|ty| walker.known_type(ty))).collect();
+            walker.signatures.insert(*name, types);
+            if let Some(ty) = result.as_ref().and_then(|ty| walker.known_type(ty)) {
+                walker.results.insert(*name, ty);
+            }
+        }
+    }
+    walker.stmts(program);
+    walker.evidence
+}
+
+impl<'a> Walker<'a> {
+    /// `ty` as a `Type`, or `None` if it names anything unknown. Unlike the
+    /// type checker's resolution this reports nothing; the checker does that.
+    fn known_type(&self, ty: &TypeExpr) -> Option<Type> {
+        Some(match ty {
+            TypeExpr::Named(name) => self.checker.named_type(*name)?,
+            TypeExpr::Array(element) => Type::Array(Box::new(self.known_type(element)?)),
+            TypeExpr::Map(_, value) => Type::Map(Box::new(self.known_type(value)?)),
+            TypeExpr::Option(value) => Type::Option(Box::new(self.known_type(value)?)),
+        })
+    }
+
+    /// The type of `expr` if it is evident without checking the program:
+    /// a literal, a variable of known type or a call with a declared result.
+    fn evident_type(&self, expr: &Expr) -> Option<Type> {
+        match &expr.node {
+            ExprKind::Number(_) => Some(Type::Int),
+            ExprKind::Float(_) => Some(Type::Float),
+            ExprKind::Str(_) => Some(Type::String),
+            ExprKind::Char(_) => Some(Type::Char),
+            ExprKind::Bool(_) => Some(Type::Bool),
+            ExprKind::Unary(UnOp::Neg, operand) => self.evident_type(operand),
+            ExprKind::Unary(UnOp::BitNot, _) => Some(Type::Int),
+            ExprKind::Variable(name) => match self.env.get(*name) {
+                Some(Binding::Known(t)) => Some(t.clone()),
+                _ => None,
+            },
+            ExprKind::StructLit(name, _) | ExprKind::Variant(name, _) => self.checker.named_type(*name),
+            ExprKind::Call(name, _) => self.results.get(name).cloned(),
+            _ => None,
+        }
+    }
+
+    /// Records that `expr`, if it names a parameter, is used as a `t`.
+    fn used_as(&mut self, expr: &Expr, t: Option<Type>) {
+        if let ExprKind::Variable(name) = &expr.node {
+            self.name_used_as(*name, expr.span, t);
+        }
+    }
+
+    /// Records that the variable `name` is used as a `t` at `span`, if it
+    /// is an unannotated parameter of the current function that no local
+    /// shadows.
+    fn name_used_as(&mut self, name: Symbol, span: Span, t: Option<Type>) {
+        if let (Some(Binding::Param(index)), Some(function), Some(t)) = (self.env.get(name), self.function, t) {
+            self.evidence.uses.entry((function, *index)).or_default().push((t, span));
+        }
+    }
+
+    fn stmts(&mut self, stmts: &'a [Stmt]) {
+        for stmt in stmts {
+            self.stmt(stmt);
+        }
+    }
+
+    fn block(&mut self, stmts: &'a [Stmt]) {
+        self.env.push_scope();
+        self.stmts(stmts);
+        self.env.pop_scope();
+    }
+
+    fn stmt(&mut self, stmt: &'a Stmt) {
+        match &stmt.node {
+            StmtKind::Let(name, ty, value) | StmtKind::Const(name, ty, value) => {
+                self.expr(value);
+                let declared = ty.as_ref().and_then(|ty| self.known_type(ty));
+                if declared.is_some() {
+                    self.used_as(value, declared.clone());
+                }
+                let binding = declared.or_else(|| self.evident_type(value)).map_or(Binding::Unknown, Binding::Known);
+                self.env.define(*name, binding);
+            }
+            StmtKind::Assign(name, value) => {
+                self.expr(value);
+                match self.env.get(*name).cloned() {
+                    Some(Binding::Param(_)) => {
+                        let t = self.evident_type(value);
+                        self.name_used_as(*name, stmt.span, t);
+                    }
+                    Some(Binding::Known(t)) => self.used_as(value, Some(t)),
+                    _ => {}
+                }
+            }
+            StmtKind::IndexAssign(container, index, value) => {
+                self.expr(container);
+                self.expr(index);
+                self.expr(value);
+            }
+            StmtKind::FieldAssign(object, field, value) => {
+                self.expr(object);
+                self.expr(value);
+                if let Some(Type::Struct(name)) = self.evident_type(object) {
+                    let t = self.field_type(name, *field);
+                    self.used_as(value, t);
+                }
+            }
+            StmtKind::If(cond, then_block, else_block) => {
+                self.condition(cond);
+                self.block(then_block);
+                self.block(else_block);
+            }
+            StmtKind::While(cond, body) | StmtKind::DoWhile(body, cond) => {
+                self.condition(cond);
+                self.block(body);
+            }
+            StmtKind::For(init, cond, step, body) => {
+                self.env.push_scope();
+                self.stmt(init);
+                self.condition(cond);
+                self.stmt(step);
+                self.block(body);
+                self.env.pop_scope();
+            }
+            StmtKind::ForRange(var, start, end, _, body) => {
+                for bound in [start, end] {
+                    self.expr(bound);
+                    self.used_as(bound, Some(Type::Int));
+                }
+                self.env.push_scope();
+                self.env.define(*var, Binding::Known(Type::Int));
+                self.block(body);
+                self.env.pop_scope();
+            }
+            StmtKind::Match(value, arms) => {
+                self.expr(value);
+                for arm in arms {
+                    if let Pattern::Literal(literal) = &arm.pattern {
+                        let t = self.evident_type(literal);
+                        self.used_as(value, t);
+                    }
+                    self.block(arm.body);
+                }
+            }
+            StmtKind::FnDecl(name, params, _, body) => {
+                self.env.enter_call();
+                let outer_function = self.function.replace(*name);
+                for (index, param) in params.iter().enumerate() {
+                    let binding = match &param.ty {
+                        None => Binding::Param(index),
+                        Some(ty) => self.known_type(ty).map_or(Binding::Unknown, Binding::Known),
+                    };
+                    self.env.define(param.name, binding);
+                }
+                self.stmts(body);
+                self.function = outer_function;
+                self.env.exit_call();
+            }
+            StmtKind::Return(Some(value)) => {
+                self.expr(value);
+                let t = self.function.and_then(|function| self.results.get(&function)).cloned();
+                self.used_as(value, t);
+            }
+            StmtKind::Expr(expr) => self.expr(expr),
+            StmtKind::Block(stmts) => self.block(stmts),
+            StmtKind::StructDecl(..) | StmtKind::EnumDecl(..) | StmtKind::TypeAlias(..) | StmtKind::Import(..) | StmtKind::Return(None) | StmtKind::Break | StmtKind::Continue => {}
+        }
+    }
+
+    fn condition(&mut self, cond: &'a Expr) {
+        self.expr(cond);
+        self.used_as(cond, Some(Type::Bool));
+    }
+
+    fn field_type(&self, name: Symbol, field: Symbol) -> Option<Type> {
+        let fields = self.checker.struct_fields(name)?;
+        fields.iter().find(|(declared, _)| *declared == field).map(|(_, t)| t.clone())
+    }
+
+    fn expr(&mut self, expr: &'a Expr) {
+        match &expr.node {
+            ExprKind::Number(_) | ExprKind::Float(_) | ExprKind::Str(_) | ExprKind::Char(_) | ExprKind::Bool(_) | ExprKind::Null | ExprKind::Variable(_) | ExprKind::Variant(..) => {}
+            ExprKind::Unary(op, operand) => {
+                self.expr(operand);
+                if matches!(op, UnOp::BitNot) {
+                    self.used_as(operand, Some(Type::Int));
+                }
+            }
+            ExprKind::Update(name, ..) => self.name_used_as(*name, expr.span, Some(Type::Int)),
+            ExprKind::Binary(lhs, op, rhs) => {
+                self.expr(lhs);
+                self.expr(rhs);
+                for (operand, other) in [(lhs, rhs), (rhs, lhs)] {
+                    let t = match op {
+                        BinOp::And | BinOp::Or => Some(Type::Bool),
+                        BinOp::BitAnd | BinOp::BitOr | BinOp::BitXor | BinOp::Shl | BinOp::Shr => Some(Type::Int),
+                        // Arithmetic and comparisons need both sides to
+                        // have the same type.
+                        _ => self.evident_type(other),
+                    };
+                    self.used_as(operand, t);
+                }
+            }
+            ExprKind::Call(name, args) => {
+                for arg in args.iter() {
+                    self.expr(arg);
+                }
+                let Some(params) = self.signatures.get(name).cloned() else {
+                    return;
+                };
+                for (index, (arg, param)) in args.iter().zip(params).enumerate() {
+                    match param {
+                        Some(t) => self.used_as(arg, Some(t)),
+                        None => {
+                            if let Some(t) = self.evident_type(arg) {
+                                self.evidence.calls.entry((*name, index)).or_default().push((t, arg.span));
+                            }
+                        }
+                    }
+                }
+            }
+            ExprKind::Array(elements) => {
+                for element in elements.iter() {
+                    self.expr(element);
+                }
+            }
+            ExprKind::Map(entries) => {
+                for (key, value) in entries {
+                    self.expr(key);
+                    self.expr(value);
+                }
+            }
+            ExprKind::Index(container, index) => {
+                self.expr(container);
+                self.expr(index);
+            }
+            ExprKind::If(cond, then_value, else_value) => {
+                self.condition(cond);
+                self.expr(then_value);
+                self.expr(else_value);
+            }
+            ExprKind::Block(stmts, value) => {
+                self.env.push_scope();
+                self.stmts(stmts);
+                self.expr(value);
+                self.env.pop_scope();
+            }
+            ExprKind::Match(value, arms) => {
+                self.expr(value);
+                for arm in arms {
+                    if let Pattern::Literal(literal) = &arm.pattern {
+                        let t = self.evident_type(literal);
+                        self.used_as(value, t);
+                    }
+                    self.expr(&arm.value);
+                }
+            }
+            ExprKind::StructLit(name, fields) => {
+                for (field, value) in fields {
+                    self.expr(value);
+                    let t = self.field_type(*name, *field);
+                    self.used_as(value, t);
+                }
+            }
+            ExprKind::Field(object, field) => {
+                self.expr(object);
+                // Only a struct with a field of that name can be meant, if
+                // there is just one.
+                let mut owners = self.checker.structs_with_field(*field);
+                if let (Some(owner), None) = (owners.next(), owners.next()) {
+                    self.used_as(object, Some(Type::Struct(owner)));
+                }
+            }
+        }
+    }
+}
\ No newline at end of file
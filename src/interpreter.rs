@@ -1,134 +1,685 @@
 use crate::ast::*;
+use crate::builtins::{self, Io};
+use crate::diagnostics::with_suggestion;
+use crate::environment::Environment;
 use crate::error::CompilerError;
-use std::collections::HashMap;
+use crate::ops::{self, OverflowMode};
+use crate::span::Span;
+use crate::symbol::Symbol;
+use crate::value::{Struct, Value};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::io::{Read, Write};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
 
-pub struct Interpreter {
-    env: HashMap<String, i64>,
-    functions: HashMap<String, (Vec<String>, Vec<Stmt>)>,
+/// How control leaves a statement: normally, or by unwinding to the
+/// enclosing loop or function.
+enum ControlFlow {
+    Normal,
+    Break,
+    Continue,
+    /// `None` for a `return` without a value.
+    Return(Option<Value>),
 }
 
-impl Interpreter {
+/// Watches a program as the interpreter runs it; see [`Interpreter::with_hook`].
+pub trait Hook {
+    /// Called before each statement runs, with the variables in scope and
+    /// the number of user function calls in progress. An error stops the
+    /// program; [`CompilerError::Stopped`] does so without it failing.
+    fn before_stmt(&mut self, _stmt: &Stmt, _env: &Environment<Value>, _call_depth: usize) -> Result<(), CompilerError> {
+        Ok(())
+    }
+
+    /// Called when a user function starts, once its arguments are evaluated.
+    fn enter_function(&mut self, _name: &str) {}
+
+    /// Called when a user function returns, however it ends.
+    fn exit_function(&mut self, _name: &str) {}
+}
+
+/// A host function callable from scripts; see [`Interpreter::register_native`].
+type NativeFn = Box<dyn FnMut(&[Value]) -> Result<Value, String>>;
+
+pub struct Interpreter<'a> {
+    env: Environment<Value>,
+    /// The parameters and body of each declared function, borrowed from
+    /// the program's arena.
+    functions: HashMap<Symbol, (Rc<[Symbol]>, &'a [Stmt<'a>])>,
+    /// The field names of each declared struct, in declaration order.
+    structs: HashMap<Symbol, Vec<Symbol>>,
+    /// The variants of each declared enum.
+    enums: HashMap<Symbol, Vec<Symbol>>,
+    natives: HashMap<Symbol, NativeFn>,
+    io: Io,
+    /// Number of user function calls currently in progress.
+    call_depth: usize,
+    max_call_depth: usize,
+    overflow: OverflowMode,
+    hooks: Vec<Box<dyn Hook>>,
+    options: InterpreterOptions,
+    /// Steps taken in the current run, and when it must end by.
+    steps: u64,
+    deadline: Option<Instant>,
+    /// Bytes of strings and arrays allocated since the heap was last
+    /// measured, plus what that measurement found; only kept with `max_memory`.
+    allocated: usize,
+}
+
+/// Limits for running untrusted scripts, set with
+/// [`Interpreter::with_options`]. `None` means no limit. Each call to
+/// `interpret` or `interpret_input` starts with a fresh budget; running past
+/// it fails with [`CompilerError::LimitExceeded`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct InterpreterOptions {
+    /// Most statements and expressions to evaluate. Every loop iteration
+    /// evaluates at least its condition, so this also bounds empty loops.
+    pub max_steps: Option<u64>,
+    /// Most wall-clock milliseconds to run for.
+    pub max_millis: Option<u64>,
+    /// Most bytes of strings and arrays the program may keep alive. The
+    /// count is approximate: it covers variables, not values still being
+    /// computed.
+    pub max_memory: Option<usize>,
+}
+
+/// How many steps run between looks at the clock for `max_millis`.
+const STEPS_PER_CLOCK_CHECK: u64 = 1024;
+
+/// Default for [`Interpreter::with_max_call_depth`]. Every call also nests
+/// several native frames, up to about 15 KiB in a debug build, so the
+/// `ferrum` binary runs programs on a thread with a larger stack; embedders
+/// on a small stack should lower the limit.
+pub const DEFAULT_MAX_CALL_DEPTH: usize = 1000;
+
+impl Default for Interpreter<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a> Interpreter<'a> {
     pub fn new() -> Self {
         Self {
-            env: HashMap::new(),
+            env: Environment::new(),
             functions: HashMap::new(),
+            structs: HashMap::new(),
+            enums: HashMap::new(),
+            natives: HashMap::new(),
+            io: Io::default(),
+            call_depth: 0,
+            max_call_depth: DEFAULT_MAX_CALL_DEPTH,
+            overflow: OverflowMode::default(),
+            hooks: Vec::new(),
+            options: InterpreterOptions::default(),
+            steps: 0,
+            deadline: None,
+            allocated: 0,
         }
     }
 
-    pub fn interpret(&mut self, program: &[Stmt]) -> Result<(), CompilerError> {
+    /// Limits how deeply user functions may recurse before the program fails
+    /// with a "stack overflow" runtime error.
+    pub fn with_max_call_depth(mut self, max_call_depth: usize) -> Self {
+        self.max_call_depth = max_call_depth;
+        self
+    }
+
+    /// Chooses how integer overflow is handled; checked by default.
+    pub fn with_overflow_mode(mut self, overflow: OverflowMode) -> Self {
+        self.overflow = overflow;
+        self
+    }
+
+    /// Sends `print`/`println` output to `output` instead of standard output.
+    pub fn with_output(mut self, output: impl Write + 'static) -> Self {
+        self.io.set_output(output);
+        self
+    }
+
+    /// Makes `read_int`/`read_line` read from `input` instead of standard input.
+    pub fn with_input(mut self, input: impl Read + 'static) -> Self {
+        self.io.set_input(input);
+        self
+    }
+
+    /// Seeds the generator behind `random`, so the program draws the same
+    /// numbers on every run; by default the seed comes from the clock.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.io.set_seed(seed);
+        self
+    }
+
+    /// Limits how long each run may take; see [`InterpreterOptions`].
+    pub fn with_options(mut self, options: InterpreterOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Lets `hook` watch the program run, for debuggers, tracers and
+    /// profilers. Hooks are called in the order they were added.
+    pub fn with_hook(mut self, hook: impl Hook + 'static) -> Self {
+        self.hooks.push(Box::new(hook));
+        self
+    }
+
+    /// Makes `f` callable from scripts as `name`. It gets the evaluated
+    /// arguments, and an error it returns becomes a runtime error at the
+    /// call. A user function of the same name takes precedence, and a
+    /// native shadows the built-in of that name. Register the signature
+    /// with [`crate::TypeChecker::register_native`] too, or checked
+    /// programs will not type-check.
+    pub fn register_native<E: fmt::Display>(&mut self, name: &str, mut f: impl FnMut(&[Value]) -> Result<Value, E> + 'static) {
+        let native: NativeFn = Box::new(move |args| f(args).map_err(|e| e.to_string()));
+        self.natives.insert(Symbol::intern(name), native);
+    }
+
+    /// Binds the global variable `name` to `value` before the program runs,
+    /// replacing any earlier binding. Scripts read it like any other
+    /// global; declare its type with [`crate::TypeChecker::declare_global`].
+    pub fn set_global(&mut self, name: &str, value: Value) {
+        // Between runs the global scope is the only one left.
+        self.env.define(Symbol::intern(name), value);
+    }
+
+    /// Runs the program to completion. A top-level `return` ends it early.
+    /// Returns the program's result: the value of a top-level `return`, or
+    /// else of a trailing expression statement.
+    pub fn interpret(&mut self, program: &[Stmt<'a>]) -> Result<Option<Value>, CompilerError> {
+        self.interpret_input(program)
+    }
+
+    /// Runs one more piece of a program, such as a REPL entry, on top of the
+    /// globals and functions left by earlier calls. Returns its result as
+    /// [`Interpreter::interpret`] does.
+    pub fn interpret_input(&mut self, input: &[Stmt<'a>]) -> Result<Option<Value>, CompilerError> {
+        self.steps = 0;
+        self.allocated = if self.options.max_memory.is_some() { self.heap_size() } else { 0 };
+        self.deadline = self.options.max_millis.map(|millis| Instant::now() + Duration::from_millis(millis));
+        let result = self.exec_program(input);
+        // Output printed before a runtime error still reaches the sink.
+        let flushed = self.io.flush();
+        let value = result?;
+        flushed.map_err(|msg| CompilerError::RuntimeError(msg, None))?;
+        Ok(value)
+    }
+
+    fn exec_program(&mut self, program: &[Stmt<'a>]) -> Result<Option<Value>, CompilerError> {
+        self.declare_functions(program);
+        let mut last = None;
         for stmt in program {
-            self.eval_stmt(stmt)?;
+            last = None;
+            self.before_stmt(stmt)?;
+            let flow = match &stmt.node {
+                StmtKind::Expr(expr) => {
+                    last = Some(self.eval_expr(expr)?);
+                    ControlFlow::Normal
+                }
+                _ => self.eval_stmt(stmt)?,
+            };
+            match flow {
+                ControlFlow::Normal => {}
+                ControlFlow::Return(value) => return Ok(value),
+                ControlFlow::Break | ControlFlow::Continue => return Err(Self::outside_loop(stmt.span)),
+            }
+        }
+        Ok(last)
+    }
+
+    /// Counts one step of the run at `span` against the limits.
+    fn step(&mut self, span: Span) -> Result<(), CompilerError> {
+        self.steps += 1;
+        if let Some(max_steps) = self.options.max_steps
+            && self.steps > max_steps
+        {
+            let msg = format!("step limit of {} exceeded", max_steps);
+            return Err(CompilerError::LimitExceeded(msg, Some(span)));
+        }
+        if let Some(deadline) = self.deadline
+            && self.steps.is_multiple_of(STEPS_PER_CLOCK_CHECK)
+            && Instant::now() >= deadline
+        {
+            let msg = format!("time limit of {} ms exceeded", self.options.max_millis.unwrap_or_default());
+            return Err(CompilerError::LimitExceeded(msg, Some(span)));
+        }
+        Ok(())
+    }
+
+    /// Counts `value`, just created by `expr`, against `max_memory`. When the
+    /// running total passes the limit, the heap is measured to see whether
+    /// enough has been freed since.
+    fn charge(&mut self, value: &Value, expr: &Expr) -> Result<(), CompilerError> {
+        let Some(max_memory) = self.options.max_memory else {
+            return Ok(());
+        };
+        self.allocated += match (value, &expr.node) {
+            (Value::Str(s), _) => s.capacity(),
+            (Value::Array(elements), ExprKind::Array(_) | ExprKind::Call(..)) => elements.borrow().capacity() * std::mem::size_of::<Value>(),
+            // `push` grows an array in place instead of making a value.
+            (_, ExprKind::Call(name, _)) if *name == "push" => std::mem::size_of::<Value>(),
+            (Value::Map(entries), ExprKind::Map(_)) => entries.borrow().len() * std::mem::size_of::<(String, Value)>(),
+            (Value::Struct(instance), ExprKind::StructLit(..)) => instance.borrow().fields.capacity() * std::mem::size_of::<(Symbol, Value)>(),
+            _ => 0,
+        };
+        if self.allocated > max_memory {
+            self.allocated = self.heap_size() + value.heap_size(&mut HashSet::new());
+            if self.allocated > max_memory {
+                let msg = format!("memory limit of {} bytes exceeded", max_memory);
+                return Err(CompilerError::LimitExceeded(msg, Some(expr.span)));
+            }
         }
         Ok(())
     }
 
-    fn eval_stmt(&mut self, stmt: &Stmt) -> Result<Option<i64>, CompilerError> {
-        match stmt {
-            Stmt::Let(name, expr) => {
+    /// Approximate bytes kept alive by every variable, callers' included.
+    fn heap_size(&self) -> usize {
+        let mut seen = HashSet::new();
+        self.env.values().map(|value| value.heap_size(&mut seen)).sum()
+    }
+
+    fn before_stmt(&mut self, stmt: &Stmt) -> Result<(), CompilerError> {
+        for hook in &mut self.hooks {
+            hook.before_stmt(stmt, &self.env, self.call_depth)?;
+        }
+        Ok(())
+    }
+
+    fn undefined_variable(&self, name: &str, span: Span) -> CompilerError {
+        let msg = with_suggestion(format!("Undefined variable: {}", name), name, self.env.names());
+        CompilerError::RuntimeError(msg, Some(span))
+    }
+
+    fn undefined_function(&self, name: &str, span: Span) -> CompilerError {
+        let candidates = self.functions.keys().chain(self.natives.keys()).map(|name| name.as_str());
+        let candidates = candidates.chain(builtins::BUILTINS.iter().map(|builtin| builtin.name));
+        let msg = with_suggestion(format!("Undefined function: {}", name), name, candidates);
+        CompilerError::RuntimeError(msg, Some(span))
+    }
+
+    fn outside_loop(span: Span) -> CompilerError {
+        CompilerError::RuntimeError("'break' or 'continue' outside of a loop".to_string(), Some(span))
+    }
+
+    /// Runs `stmts` in a new scope that is dropped however the block exits.
+    fn exec_block(&mut self, stmts: &[Stmt]) -> Result<ControlFlow, CompilerError> {
+        self.env.push_scope();
+        let flow = self.exec_stmts(stmts);
+        self.env.pop_scope();
+        flow
+    }
+
+    /// Registers every function, struct and enum declared directly in
+    /// `stmts` up front, so a call or literal may run before the
+    /// declaration is reached.
+    fn declare_functions(&mut self, stmts: &[Stmt<'a>]) {
+        for stmt in stmts {
+            match &stmt.node {
+                StmtKind::FnDecl(name, params, _, body) => {
+                    let params = params.iter().map(|param| param.name).collect();
+                    self.functions.insert(*name, (params, *body));
+                }
+                StmtKind::StructDecl(name, fields) => {
+                    let fields = fields.iter().map(|field| field.name).collect();
+                    self.structs.insert(*name, fields);
+                }
+                StmtKind::EnumDecl(name, variants) => {
+                    self.enums.insert(*name, variants.clone());
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn exec_stmts(&mut self, stmts: &[Stmt]) -> Result<ControlFlow, CompilerError> {
+        for stmt in stmts {
+            self.before_stmt(stmt)?;
+            let flow = self.eval_stmt(stmt)?;
+            if !matches!(flow, ControlFlow::Normal) {
+                return Ok(flow);
+            }
+        }
+        Ok(ControlFlow::Normal)
+    }
+
+    fn eval_stmt(&mut self, stmt: &Stmt) -> Result<ControlFlow, CompilerError> {
+        self.step(stmt.span)?;
+        match &stmt.node {
+            StmtKind::Let(name, _, expr) | StmtKind::Const(name, _, expr) => {
                 let value = self.eval_expr(expr)?;
-                self.env.insert(name.clone(), value);
+                self.env.define(*name, value);
             }
-            Stmt::Assign(name, expr) => {
+            StmtKind::Assign(name, expr) => {
                 let value = self.eval_expr(expr)?;
-                if self.env.contains_key(name) {
-                    self.env.insert(name.clone(), value);
-                } else {
-                    return Err(CompilerError::RuntimeError(format!("Undefined variable: {}", name)));
+                if !self.env.assign(*name, value) {
+                    return Err(self.undefined_variable(name, stmt.span));
                 }
             }
-            Stmt::If(cond, then_block, else_block) => {
-                if self.eval_expr(cond)? != 0 {
-                    for stmt in then_block {
-                        self.eval_stmt(stmt)?;
-                    }
+            StmtKind::IndexAssign(array, index, value) => {
+                let slot = self.eval_index(array, index)?;
+                let value = self.eval_expr(value)?;
+                slot.set(value);
+            }
+            StmtKind::FieldAssign(object, field, value) => {
+                let object = self.eval_expr(object)?;
+                let value = self.eval_expr(value)?;
+                ops::set_field(object, *field, value, stmt.span)?;
+            }
+            StmtKind::If(cond, then_block, else_block) => {
+                return if self.eval_cond(cond)? {
+                    self.exec_block(then_block)
                 } else {
-                    for stmt in else_block {
-                        self.eval_stmt(stmt)?;
-                    }
-                }
+                    self.exec_block(else_block)
+                };
             }
-            Stmt::While(cond, body) => {
-                while self.eval_expr(cond)? != 0 {
-                    for stmt in body {
-                        self.eval_stmt(stmt)?;
+            StmtKind::While(cond, body) => {
+                while self.eval_cond(cond)? {
+                    match self.exec_block(body)? {
+                        ControlFlow::Break => break,
+                        ControlFlow::Return(value) => return Ok(ControlFlow::Return(value)),
+                        ControlFlow::Normal | ControlFlow::Continue => {}
                     }
                 }
             }
-            Stmt::DoWhile(body, cond) => {
+            StmtKind::DoWhile(body, cond) => {
                 loop {
-                    for stmt in body {
-                        self.eval_stmt(stmt)?;
+                    match self.exec_block(body)? {
+                        ControlFlow::Break => break,
+                        ControlFlow::Return(value) => return Ok(ControlFlow::Return(value)),
+                        ControlFlow::Normal | ControlFlow::Continue => {}
                     }
-                    if self.eval_expr(cond)? == 0 {
+                    if !self.eval_cond(cond)? {
                         break;
                     }
                 }
             }
-            Stmt::For(var, start, cond, step, body) => {
-                let mut i = self.eval_expr(start)?;
-                self.env.insert(var.clone(), i);
-                while self.eval_expr(cond)? != 0 {
-                    for stmt in body {
-                        self.eval_stmt(stmt)?;
+            StmtKind::For(init, cond, step, body) => {
+                // A variable declared by the initializer lives in its own
+                // scope around the body.
+                self.env.push_scope();
+                let flow = self.eval_for(init, cond, step, body);
+                self.env.pop_scope();
+                return flow;
+            }
+            StmtKind::ForRange(var, start, end, inclusive, body) => {
+                let start = self.eval_bound(start)?;
+                let end = self.eval_bound(end)?;
+                self.env.push_scope();
+                let flow = self.eval_for_range(*var, start, end, *inclusive, body, stmt.span);
+                self.env.pop_scope();
+                return flow;
+            }
+            StmtKind::Match(value, arms) => {
+                let value = self.eval_expr(value)?;
+                for arm in arms {
+                    if self.matches(&value, &arm.pattern, arm.span)? {
+                        return self.exec_block(arm.body);
                     }
-                    i = self.eval_expr(step)?;
-                    self.env.insert(var.clone(), i);
                 }
             }
-            Stmt::FnDecl(name, params, body) => {
-                self.functions.insert(name.clone(), (params.clone(), body.clone()));
+            // Registered by `declare_functions` before the program runs.
+            StmtKind::FnDecl(..) | StmtKind::StructDecl(..) | StmtKind::EnumDecl(..) | StmtKind::TypeAlias(..) | StmtKind::Import(..) => {}
+            StmtKind::Return(expr) => {
+                let value = match expr {
+                    Some(expr) => Some(self.eval_expr(expr)?),
+                    None => None,
+                };
+                return Ok(ControlFlow::Return(value));
             }
-            Stmt::Return(expr) => {
-                return Ok(Some(self.eval_expr(expr)?));
-            }
-            Stmt::Expr(expr) => {
+            StmtKind::Block(stmts) => return self.exec_block(stmts),
+            StmtKind::Break => return Ok(ControlFlow::Break),
+            StmtKind::Continue => return Ok(ControlFlow::Continue),
+            StmtKind::Expr(expr) => {
                 self.eval_expr(expr)?;
             }
         }
-        Ok(None)
+        Ok(ControlFlow::Normal)
+    }
+
+    fn eval_for(&mut self, init: &Stmt, cond: &Expr, step: &Stmt, body: &[Stmt]) -> Result<ControlFlow, CompilerError> {
+        self.eval_stmt(init)?;
+        while self.eval_cond(cond)? {
+            match self.exec_block(body)? {
+                ControlFlow::Break => break,
+                ControlFlow::Return(value) => return Ok(ControlFlow::Return(value)),
+                ControlFlow::Normal | ControlFlow::Continue => {}
+            }
+            self.eval_stmt(step)?;
+        }
+        Ok(ControlFlow::Normal)
+    }
+
+    /// Runs `body` with `var` bound to each int from `start` up to `end`.
+    /// The bounds are evaluated once, and assigning to `var` in the body
+    /// does not change which value comes next.
+    fn eval_for_range(&mut self, var: Symbol, start: i64, end: i64, inclusive: bool, body: &[Stmt], span: Span) -> Result<ControlFlow, CompilerError> {
+        let mut i = start;
+        while i < end || (inclusive && i == end) {
+            // An empty body takes no steps of its own.
+            self.step(span)?;
+            self.env.define(var, Value::Int(i));
+            match self.exec_block(body)? {
+                ControlFlow::Break => break,
+                ControlFlow::Return(value) => return Ok(ControlFlow::Return(value)),
+                ControlFlow::Normal | ControlFlow::Continue => {}
+            }
+            // Stepping past an inclusive `end` of `i64::MAX` would overflow.
+            if i == end {
+                break;
+            }
+            i += 1;
+        }
+        Ok(ControlFlow::Normal)
+    }
+
+    fn eval_bound(&mut self, bound: &Expr) -> Result<i64, CompilerError> {
+        match self.eval_expr(bound)? {
+            Value::Int(n) => Ok(n),
+            other => Err(CompilerError::RuntimeError(format!("Range bounds must be int, found {}", other.type_name()), Some(bound.span))),
+        }
+    }
+
+    /// Evaluates the fields of a struct literal in source order into a
+    /// struct with its fields in declaration order.
+    fn eval_struct_lit(&mut self, name: Symbol, fields: &[(Symbol, Expr)], span: Span) -> Result<Value, CompilerError> {
+        let Some(declared) = self.structs.get(&name).cloned() else {
+            return Err(CompilerError::RuntimeError(format!("Unknown struct: {}", name), Some(span)));
+        };
+        let mut values: Vec<Option<Value>> = vec![None; declared.len()];
+        for (field, value) in fields {
+            let Some(i) = declared.iter().position(|name| name == field) else {
+                return Err(CompilerError::RuntimeError(format!("Struct {} has no field {}", name, field), Some(value.span)));
+            };
+            values[i] = Some(self.eval_expr(value)?);
+        }
+        let mut instance = Struct { name, fields: Vec::new() };
+        for (field, value) in declared.into_iter().zip(values) {
+            let Some(value) = value else {
+                return Err(CompilerError::RuntimeError(format!("Missing {} in {} literal", field, name), Some(span)));
+            };
+            instance.fields.push((field, value));
+        }
+        Ok(Value::Struct(Rc::new(RefCell::new(instance))))
+    }
+
+    /// Runs the statements of a block used as a value, then evaluates the
+    /// value it ends with, in the current scope.
+    fn eval_block_value(&mut self, stmts: &[Stmt], value: &Expr, span: Span) -> Result<Value, CompilerError> {
+        match self.exec_stmts(stmts)? {
+            ControlFlow::Normal => self.eval_expr(value),
+            _ => Err(CompilerError::RuntimeError("'return', 'break' or 'continue' cannot leave a block used as a value".to_string(), Some(span))),
+        }
+    }
+
+    /// Whether `value` matches the `pattern` of an arm written at `span`.
+    fn matches(&mut self, value: &Value, pattern: &Pattern, span: Span) -> Result<bool, CompilerError> {
+        match pattern {
+            Pattern::Literal(literal) => {
+                let pattern = self.eval_expr(literal)?;
+                Ok(ops::binary(self.overflow, BinOp::Eq, value.clone(), pattern, span)? == Value::Bool(true))
+            }
+            Pattern::Wildcard => Ok(true),
+        }
+    }
+
+    fn eval_cond(&mut self, cond: &Expr) -> Result<bool, CompilerError> {
+        match self.eval_expr(cond)? {
+            Value::Bool(b) => Ok(b),
+            other => Err(CompilerError::RuntimeError(format!("Condition must be a boolean, found {}", other.type_name()), Some(cond.span))),
+        }
     }
 
-    fn eval_expr(&mut self, expr: &Expr) -> Result<i64, CompilerError> {
-        match expr {
-            Expr::Number(n) => Ok(*n),
-            Expr::Bool(b) => Ok(if *b { 1 } else { 0 }),
-            Expr::Variable(name) => self.env.get(name).cloned().ok_or_else(|| CompilerError::RuntimeError(format!("Undefined variable: {}", name))),
-            Expr::Binary(lhs, op, rhs) => {
+    /// Evaluates `array[index]` down to the backing vector and a checked index.
+    fn eval_index(&mut self, array: &Expr, index: &Expr) -> Result<ops::Slot, CompilerError> {
+        let array_value = self.eval_expr(array)?;
+        let index_value = self.eval_expr(index)?;
+        ops::checked_index(array_value, index_value, array.span, index.span)
+    }
+
+    fn eval_expr(&mut self, expr: &Expr) -> Result<Value, CompilerError> {
+        self.step(expr.span)?;
+        let value = self.eval_expr_kind(expr)?;
+        self.charge(&value, expr)?;
+        Ok(value)
+    }
+
+    fn eval_expr_kind(&mut self, expr: &Expr) -> Result<Value, CompilerError> {
+        match &expr.node {
+            ExprKind::Number(n) => Ok(Value::Int(*n)),
+            ExprKind::Float(n) => Ok(Value::Float(*n)),
+            ExprKind::Str(s) => Ok(Value::Str(s.clone())),
+            ExprKind::Char(c) => Ok(Value::Char(*c)),
+            ExprKind::Bool(b) => Ok(Value::Bool(*b)),
+            ExprKind::Null => Ok(Value::Null),
+            ExprKind::Variable(name) => self.env.get(*name).cloned().ok_or_else(|| self.undefined_variable(name, expr.span)),
+            ExprKind::Unary(UnOp::Neg, operand) => {
+                let value = self.eval_expr(operand)?;
+                ops::negate(self.overflow, value, expr.span)
+            }
+            ExprKind::Unary(UnOp::BitNot, operand) => {
+                let value = self.eval_expr(operand)?;
+                ops::bit_not(value, expr.span)
+            }
+            ExprKind::Update(name, op, prefix) => {
+                let Some(old) = self.env.get(*name) else {
+                    return Err(self.undefined_variable(name, expr.span));
+                };
+                let old = old.clone();
+                let new = Value::Int(ops::update(self.overflow, *op, &old, expr.span)?);
+                self.env.assign(*name, new.clone());
+                Ok(if *prefix { new } else { old })
+            }
+            ExprKind::Binary(lhs, op @ (BinOp::And | BinOp::Or), rhs) => {
+                // The right operand only runs when the left does not decide the result.
+                let l = self.eval_cond(lhs)?;
+                let result = match op {
+                    BinOp::And => l && self.eval_cond(rhs)?,
+                    _ => l || self.eval_cond(rhs)?,
+                };
+                Ok(Value::Bool(result))
+            }
+            ExprKind::Binary(lhs, op, rhs) => {
                 let l = self.eval_expr(lhs)?;
                 let r = self.eval_expr(rhs)?;
-                match op {
-                    BinOp::Add => Ok(l + r),
-                    BinOp::Sub => Ok(l - r),
-                    BinOp::Mul => Ok(l * r),
-                    BinOp::Div => Ok(l / r),
-                    BinOp::Eq => Ok((l == r) as i64),
-                    BinOp::Neq => Ok((l != r) as i64),
-                    BinOp::Gt => Ok((l > r) as i64),
-                    BinOp::Lt => Ok((l < r) as i64),
+                ops::binary(self.overflow, *op, l, r, expr.span)
+            }
+            ExprKind::Array(elements) => {
+                let mut values = Vec::with_capacity(elements.len());
+                for element in elements.iter() {
+                    values.push(self.eval_expr(element)?);
+                }
+                Ok(Value::Array(Rc::new(RefCell::new(values))))
+            }
+            ExprKind::Index(array, index) => {
+                let slot = self.eval_index(array, index)?;
+                slot.get(index.span)
+            }
+            ExprKind::If(cond, then_value, else_value) => {
+                if self.eval_cond(cond)? {
+                    self.eval_expr(then_value)
+                } else {
+                    self.eval_expr(else_value)
+                }
+            }
+            ExprKind::Match(value, arms) => {
+                let value = self.eval_expr(value)?;
+                for arm in arms {
+                    if self.matches(&value, &arm.pattern, arm.span)? {
+                        return self.eval_expr(&arm.value);
+                    }
                 }
+                // Only possible when the program was not type checked.
+                Err(CompilerError::RuntimeError(format!("No arm of 'match' matches {}", value), Some(expr.span)))
             }
-            Expr::Call(name, args) => {
-                if let Some((params, body)) = self.functions.get(name) {
+            ExprKind::Map(entries) => {
+                let mut map = BTreeMap::new();
+                for (key, value) in entries {
+                    let key_value = ops::map_key(self.eval_expr(key)?, key.span)?;
+                    map.insert(key_value, self.eval_expr(value)?);
+                }
+                Ok(Value::Map(Rc::new(RefCell::new(map))))
+            }
+            ExprKind::StructLit(name, fields) => self.eval_struct_lit(*name, fields, expr.span),
+            ExprKind::Variant(name, variant) => match self.enums.get(name) {
+                Some(variants) if variants.contains(variant) => Ok(Value::Enum(*name, *variant)),
+                Some(_) => Err(CompilerError::RuntimeError(format!("Enum {} has no variant {}", name, variant), Some(expr.span))),
+                None => Err(CompilerError::RuntimeError(format!("Unknown enum: {}", name), Some(expr.span))),
+            },
+            ExprKind::Field(object, field) => {
+                let object = self.eval_expr(object)?;
+                ops::get_field(object, *field, expr.span)
+            }
+            ExprKind::Block(stmts, value) => {
+                self.env.push_scope();
+                let result = self.eval_block_value(stmts, value, expr.span);
+                self.env.pop_scope();
+                result
+            }
+            ExprKind::Call(name, args) => {
+                if let Some((params, body)) = self.functions.get(name).cloned() {
                     if args.len() != params.len() {
-                        return Err(CompilerError::RuntimeError("Incorrect argument count".to_string()));
+                        return Err(CompilerError::RuntimeError("Incorrect argument count".to_string(), Some(expr.span)));
+                    }
+                    let mut values = Vec::with_capacity(args.len());
+                    for arg in args.iter() {
+                        values.push(self.eval_expr(arg)?);
+                    }
+                    if self.call_depth >= self.max_call_depth {
+                        return Err(CompilerError::RuntimeError("stack overflow".to_string(), Some(expr.span)));
+                    }
+                    // The callee sees the globals and its own parameters, not the caller's locals.
+                    self.call_depth += 1;
+                    self.env.enter_call();
+                    for (param, value) in params.iter().zip(values) {
+                        self.env.define(*param, value);
+                    }
+                    self.hooks.iter_mut().for_each(|hook| hook.enter_function(name));
+                    let flow = self.exec_stmts(body);
+                    self.hooks.iter_mut().for_each(|hook| hook.exit_function(name));
+                    self.env.exit_call();
+                    self.call_depth -= 1;
+                    match flow? {
+                        // A function that returns no value evaluates to 0.
+                        ControlFlow::Return(result) => Ok(result.unwrap_or(Value::Int(0))),
+                        ControlFlow::Normal => Ok(Value::Int(0)),
+                        ControlFlow::Break | ControlFlow::Continue => Err(Self::outside_loop(expr.span)),
                     }
-                    let mut new_env = self.env.clone();
-                    for (param, arg) in params.iter().zip(args) {
-                        let value = self.eval_expr(arg)?;
-                        new_env.insert(param.clone(), value);
+                } else if self.natives.contains_key(name) {
+                    let mut values = Vec::with_capacity(args.len());
+                    for arg in args.iter() {
+                        values.push(self.eval_expr(arg)?);
                     }
-                    let mut new_interpreter = Interpreter {
-                        env: new_env,
-                        functions: self.functions.clone(),
-                    };
-                    for stmt in body {
-                        if let Ok(Some(result)) = new_interpreter.eval_stmt(stmt) {
-                            return Ok(result);
-                        }
+                    let native = self.natives.get_mut(name).expect("checked above");
+                    native(&values).map_err(|msg| CompilerError::RuntimeError(msg, Some(expr.span)))
+                } else if let Some(builtin) = builtins::lookup(name) {
+                    let mut values = Vec::with_capacity(args.len());
+                    for arg in args.iter() {
+                        values.push(self.eval_expr(arg)?);
                     }
-                    Ok(0)
+                    (builtin.call)(&mut self.io, values).map_err(|msg| CompilerError::RuntimeError(msg, Some(expr.span)))
                 } else {
-                    Err(CompilerError::RuntimeError(format!("Undefined function: {}", name)))
+                    Err(self.undefined_function(name, expr.span))
                 }
             }
         }
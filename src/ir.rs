@@ -0,0 +1,730 @@
+//! A mid-level intermediate representation: three-address code in basic
+//! blocks, lowered from the AST by [`lower`].
+//!
+//! Every instruction reads [`Operand`]s (constants or temporaries) and writes
+//! at most one temporary, and control flow only happens in a block's
+//! [`Terminator`]. `&&` and `||` are lowered to branches, so the operators
+//! left in [`Instr::Binary`] always evaluate both sides. Temporaries are
+//! assigned once, except for the result of `&&`/`||` or of an `if`
+//! expression, which is assigned on each incoming path.
+
+use crate::ast::*;
+use crate::builtins;
+use crate::error::CompilerError;
+use crate::span::Span;
+use crate::symbol::Symbol;
+use crate::value::Value;
+use std::collections::HashMap;
+use std::fmt;
+
+/// A temporary, numbered within its function.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Temp(pub usize);
+
+/// A basic block, numbered within its function; block 0 is the entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct BlockId(pub usize);
+
+#[derive(Debug, Clone)]
+pub enum Operand {
+    Const(Value),
+    Temp(Temp),
+}
+
+/// A named variable: a local slot of the current function or a global.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Place {
+    Local(usize),
+    Global(usize),
+}
+
+#[derive(Debug, Clone)]
+pub enum Instr {
+    Copy(Temp, Operand),
+    /// Reads a variable; reading an undefined global is a runtime error.
+    Load(Temp, Place),
+    /// Writes a variable that must already be defined.
+    Store(Place, Operand),
+    /// Writes global `i`, defining it if needed.
+    DefineGlobal(usize, Operand),
+    /// Any operator except `&&` and `||`.
+    Binary(Temp, BinOp, Operand, Operand),
+    Neg(Temp, Operand),
+    BitNot(Temp, Operand),
+    /// The `++`/`--` successor of an int.
+    Update(Temp, UpdateOp, Operand),
+    /// Calls `functions[f]`.
+    Call(Temp, usize, Vec<Operand>),
+    /// Calls `builtins::BUILTINS[b]`.
+    CallBuiltin(Temp, usize, Vec<Operand>),
+    MakeArray(Temp, Vec<Operand>),
+    /// A map with the given keys and values, listed in source order.
+    MakeMap(Temp, Vec<(Operand, Operand)>),
+    /// `array[index]` or `map[key]`.
+    Index(Temp, Operand, Operand),
+    /// `array[index] = value` or `map[key] = value`.
+    StoreIndex(Operand, Operand, Operand),
+    /// A struct with the named fields, listed in source order.
+    MakeStruct(Temp, Symbol, Vec<(Symbol, Operand)>),
+    /// `object.field`.
+    Field(Temp, Operand, Symbol),
+    /// `object.field = value`.
+    StoreField(Operand, Symbol, Operand),
+}
+
+#[derive(Debug, Clone)]
+pub enum Terminator {
+    Jump(BlockId),
+    /// Goes to the first block if the operand is true and the second if it
+    /// is false; anything else is a runtime error.
+    Branch(Operand, BlockId, BlockId),
+    Return(Operand),
+}
+
+#[derive(Debug, Clone)]
+pub struct Block {
+    pub instrs: Vec<Instr>,
+    /// Source location of each instruction, for runtime errors.
+    pub spans: Vec<Span>,
+    pub terminator: Terminator,
+    pub terminator_span: Span,
+}
+
+impl Block {
+    /// The blocks control may continue to after this one.
+    pub fn successors(&self) -> Vec<BlockId> {
+        match &self.terminator {
+            Terminator::Jump(target) => vec![*target],
+            Terminator::Branch(_, then_block, else_block) => vec![*then_block, *else_block],
+            Terminator::Return(_) => Vec::new(),
+        }
+    }
+}
+
+/// A lowered function. Parameters occupy the first `arity` local slots.
+#[derive(Debug, Clone)]
+pub struct Function {
+    pub name: String,
+    pub arity: usize,
+    pub locals: usize,
+    pub temps: usize,
+    pub blocks: Vec<Block>,
+}
+
+/// A whole lowered program. `functions[main]` holds the top-level
+/// statements; global variables are numbered by their index in `globals`.
+#[derive(Debug, Clone)]
+pub struct Program {
+    pub functions: Vec<Function>,
+    pub globals: Vec<String>,
+    pub main: usize,
+}
+
+/// Lowers `program` to IR. Every function is resolved up front, so calls
+/// may precede declarations anywhere in the program.
+pub fn lower(program: &[Stmt]) -> Result<Program, CompilerError> {
+    let mut lowerer = Lowerer { functions: Vec::new(), function_ids: HashMap::new(), declarations: HashMap::new(), globals: Vec::new() };
+    lowerer.declare_functions(program);
+    let main = lowerer.functions.len();
+    lowerer.functions.push(None);
+    let mut state = FnState::new(true);
+    lowerer.stmts(&mut state, program)?;
+    lowerer.finish(state, "<main>", 0, main, Span::default());
+    Ok(Program {
+        functions: lowerer.functions.into_iter().map(|f| f.expect("every declared function is lowered")).collect(),
+        globals: lowerer.globals,
+        main,
+    })
+}
+
+/// A block being built; its terminator is set when control leaves it.
+struct PartialBlock {
+    instrs: Vec<Instr>,
+    spans: Vec<Span>,
+    terminator: Option<(Terminator, Span)>,
+}
+
+/// Per-function lowering state.
+struct FnState {
+    blocks: Vec<PartialBlock>,
+    current: BlockId,
+    /// Block scopes mapping names to local slots, innermost last. At the
+    /// outermost level of the main function names are globals instead.
+    scopes: Vec<Vec<(Symbol, usize)>>,
+    locals: usize,
+    temps: usize,
+    /// Break and continue targets of the enclosing loops, innermost last.
+    loops: Vec<(BlockId, BlockId)>,
+    is_main: bool,
+}
+
+impl FnState {
+    fn new(is_main: bool) -> Self {
+        let mut state =
+            Self { blocks: Vec::new(), current: BlockId(0), scopes: Vec::new(), locals: 0, temps: 0, loops: Vec::new(), is_main };
+        state.current = state.new_block();
+        state
+    }
+
+    fn new_block(&mut self) -> BlockId {
+        self.blocks.push(PartialBlock { instrs: Vec::new(), spans: Vec::new(), terminator: None });
+        BlockId(self.blocks.len() - 1)
+    }
+
+    fn temp(&mut self) -> Temp {
+        self.temps += 1;
+        Temp(self.temps - 1)
+    }
+
+    fn emit(&mut self, instr: Instr, span: Span) {
+        let block = &mut self.blocks[self.current.0];
+        block.instrs.push(instr);
+        block.spans.push(span);
+    }
+
+    /// Ends the current block and continues in `next`. Code after a
+    /// `return`, `break` or `continue` lands in a fresh block nothing jumps to.
+    fn terminate(&mut self, terminator: Terminator, span: Span, next: Option<BlockId>) {
+        self.blocks[self.current.0].terminator = Some((terminator, span));
+        self.current = next.unwrap_or_else(|| self.new_block());
+    }
+
+    fn jump_to(&mut self, target: BlockId, span: Span) {
+        self.terminate(Terminator::Jump(target), span, Some(target));
+    }
+
+    /// Compares local slots `a` and `b` with `op`.
+    fn compare_slots(&mut self, a: usize, op: BinOp, b: usize, span: Span) -> Operand {
+        let (lhs, rhs, result) = (self.temp(), self.temp(), self.temp());
+        self.emit(Instr::Load(lhs, Place::Local(a)), span);
+        self.emit(Instr::Load(rhs, Place::Local(b)), span);
+        self.emit(Instr::Binary(result, op, Operand::Temp(lhs), Operand::Temp(rhs)), span);
+        Operand::Temp(result)
+    }
+
+    fn declare_local(&mut self, name: Symbol) -> usize {
+        let slot = self.locals;
+        self.locals += 1;
+        self.scopes.last_mut().expect("locals live in a scope").push((name, slot));
+        slot
+    }
+
+    fn local(&self, name: Symbol) -> Option<usize> {
+        self.scopes.iter().rev().flat_map(|scope| scope.iter().rev()).find(|(n, _)| *n == name).map(|(_, slot)| *slot)
+    }
+
+    fn at_global_level(&self) -> bool {
+        self.is_main && self.scopes.is_empty()
+    }
+}
+
+struct Lowerer {
+    functions: Vec<Option<Function>>,
+    /// Function index for each name; a later declaration replaces an earlier one.
+    function_ids: HashMap<Symbol, usize>,
+    /// Function index for each declaration, keyed by its span.
+    declarations: HashMap<Span, usize>,
+    globals: Vec<String>,
+}
+
+impl Lowerer {
+    /// Reserves an index for every function declared anywhere in `stmts`.
+    fn declare_functions(&mut self, stmts: &[Stmt]) {
+        for stmt in stmts {
+            match &stmt.node {
+                StmtKind::FnDecl(name, _, _, body) => {
+                    let id = self.functions.len();
+                    self.functions.push(None);
+                    self.function_ids.insert(*name, id);
+                    self.declarations.insert(stmt.span, id);
+                    self.declare_functions(body);
+                }
+                StmtKind::If(_, then_block, else_block) => {
+                    self.declare_functions(then_block);
+                    self.declare_functions(else_block);
+                }
+                StmtKind::Match(_, arms) => {
+                    for arm in arms {
+                        self.declare_functions(arm.body);
+                    }
+                }
+                StmtKind::While(_, body)
+                | StmtKind::DoWhile(body, _)
+                | StmtKind::For(.., body)
+                | StmtKind::ForRange(.., body)
+                | StmtKind::Block(body) => self.declare_functions(body),
+                _ => {}
+            }
+        }
+    }
+
+    fn finish(&mut self, state: FnState, name: &str, arity: usize, id: usize, span: Span) {
+        let blocks = state
+            .blocks
+            .into_iter()
+            .map(|block| {
+                // Falling off the end returns 0, as in the interpreter.
+                let (terminator, terminator_span) =
+                    block.terminator.unwrap_or((Terminator::Return(Operand::Const(Value::Int(0))), span));
+                Block { instrs: block.instrs, spans: block.spans, terminator, terminator_span }
+            })
+            .collect();
+        self.functions[id] =
+            Some(Function { name: name.to_string(), arity, locals: state.locals, temps: state.temps, blocks });
+    }
+
+    fn global(&mut self, name: &str) -> usize {
+        if let Some(i) = self.globals.iter().position(|g| g == name) {
+            return i;
+        }
+        self.globals.push(name.to_string());
+        self.globals.len() - 1
+    }
+
+    fn place(&mut self, state: &FnState, name: Symbol) -> Place {
+        match state.local(name) {
+            Some(slot) => Place::Local(slot),
+            None => Place::Global(self.global(&name)),
+        }
+    }
+
+    fn error(msg: &str, span: Span) -> CompilerError {
+        CompilerError::RuntimeError(msg.to_string(), Some(span))
+    }
+
+    fn block(&mut self, state: &mut FnState, stmts: &[Stmt]) -> Result<(), CompilerError> {
+        state.scopes.push(Vec::new());
+        let result = self.stmts(state, stmts);
+        state.scopes.pop();
+        result
+    }
+
+    fn stmts(&mut self, state: &mut FnState, stmts: &[Stmt]) -> Result<(), CompilerError> {
+        for stmt in stmts {
+            self.stmt(state, stmt)?;
+        }
+        Ok(())
+    }
+
+    fn loop_body(&mut self, state: &mut FnState, body: &[Stmt], exit: BlockId, next: BlockId) -> Result<(), CompilerError> {
+        state.loops.push((exit, next));
+        let result = self.block(state, body);
+        state.loops.pop();
+        result
+    }
+
+    fn stmt(&mut self, state: &mut FnState, stmt: &Stmt) -> Result<(), CompilerError> {
+        let span = stmt.span;
+        match &stmt.node {
+            StmtKind::Let(name, _, expr) | StmtKind::Const(name, _, expr) => {
+                let value = self.expr(state, expr)?;
+                if state.at_global_level() {
+                    let global = self.global(name);
+                    state.emit(Instr::DefineGlobal(global, value), span);
+                } else {
+                    let slot = state.declare_local(*name);
+                    state.emit(Instr::Store(Place::Local(slot), value), span);
+                }
+            }
+            StmtKind::Assign(name, expr) => {
+                let value = self.expr(state, expr)?;
+                let place = self.place(state, *name);
+                state.emit(Instr::Store(place, value), span);
+            }
+            StmtKind::IndexAssign(array, index, value) => {
+                let array = self.expr(state, array)?;
+                let index_value = self.expr(state, index)?;
+                let value = self.expr(state, value)?;
+                state.emit(Instr::StoreIndex(array, index_value, value), index.span);
+            }
+            StmtKind::FieldAssign(object, field, value) => {
+                let object = self.expr(state, object)?;
+                let value = self.expr(state, value)?;
+                state.emit(Instr::StoreField(object, *field, value), span);
+            }
+            StmtKind::Expr(expr) => {
+                self.expr(state, expr)?;
+            }
+            StmtKind::If(cond, then_block, else_block) => {
+                let cond_value = self.expr(state, cond)?;
+                let (then_b, else_b, end) = (state.new_block(), state.new_block(), state.new_block());
+                state.terminate(Terminator::Branch(cond_value, then_b, else_b), cond.span, Some(then_b));
+                self.block(state, then_block)?;
+                state.terminate(Terminator::Jump(end), span, Some(else_b));
+                self.block(state, else_block)?;
+                state.jump_to(end, span);
+            }
+            StmtKind::While(cond, body) => {
+                let (header, body_b, exit) = (state.new_block(), state.new_block(), state.new_block());
+                state.jump_to(header, span);
+                let cond_value = self.expr(state, cond)?;
+                state.terminate(Terminator::Branch(cond_value, body_b, exit), cond.span, Some(body_b));
+                self.loop_body(state, body, exit, header)?;
+                state.terminate(Terminator::Jump(header), span, Some(exit));
+            }
+            StmtKind::DoWhile(body, cond) => {
+                let (body_b, cond_b, exit) = (state.new_block(), state.new_block(), state.new_block());
+                state.jump_to(body_b, span);
+                self.loop_body(state, body, exit, cond_b)?;
+                state.jump_to(cond_b, span);
+                let cond_value = self.expr(state, cond)?;
+                state.terminate(Terminator::Branch(cond_value, body_b, exit), cond.span, Some(exit));
+            }
+            StmtKind::For(init, cond, step, body) => {
+                // A variable declared by the initializer lives in its own
+                // scope around the body.
+                state.scopes.push(Vec::new());
+                self.stmt(state, init)?;
+                let (header, body_b, step_b, exit) = (state.new_block(), state.new_block(), state.new_block(), state.new_block());
+                state.jump_to(header, span);
+                let cond_value = self.expr(state, cond)?;
+                state.terminate(Terminator::Branch(cond_value, body_b, exit), cond.span, Some(body_b));
+                self.loop_body(state, body, exit, step_b)?;
+                state.jump_to(step_b, span);
+                self.stmt(state, step)?;
+                state.terminate(Terminator::Jump(header), span, Some(exit));
+                state.scopes.pop();
+            }
+            StmtKind::ForRange(var, start, end, inclusive, body) => {
+                // The next value and the end live in hidden slots, so
+                // assigning to the variable does not change the iteration.
+                state.scopes.push(Vec::new());
+                let start = self.expr(state, start)?;
+                let next = state.declare_local(Symbol::intern("{next}"));
+                state.emit(Instr::Store(Place::Local(next), start), span);
+                let end = self.expr(state, end)?;
+                let last = state.declare_local(Symbol::intern("{end}"));
+                state.emit(Instr::Store(Place::Local(last), end), span);
+                let slot = state.declare_local(*var);
+                let (header, body_b, step_b, exit) = (state.new_block(), state.new_block(), state.new_block(), state.new_block());
+                state.jump_to(header, span);
+                let more = state.compare_slots(next, if *inclusive { BinOp::Le } else { BinOp::Lt }, last, span);
+                state.terminate(Terminator::Branch(more, body_b, exit), span, Some(body_b));
+                let value = state.temp();
+                state.emit(Instr::Load(value, Place::Local(next)), span);
+                state.emit(Instr::Store(Place::Local(slot), Operand::Temp(value)), span);
+                self.loop_body(state, body, exit, step_b)?;
+                state.jump_to(step_b, span);
+                // Stepping past an inclusive end of `i64::MAX` would overflow.
+                if *inclusive {
+                    let more = state.compare_slots(next, BinOp::Lt, last, span);
+                    let increment = state.new_block();
+                    state.terminate(Terminator::Branch(more, increment, exit), span, Some(increment));
+                }
+                let (value, successor) = (state.temp(), state.temp());
+                state.emit(Instr::Load(value, Place::Local(next)), span);
+                state.emit(Instr::Update(successor, UpdateOp::Inc, Operand::Temp(value)), span);
+                state.emit(Instr::Store(Place::Local(next), Operand::Temp(successor)), span);
+                state.terminate(Terminator::Jump(header), span, Some(exit));
+                state.scopes.pop();
+            }
+            StmtKind::Match(value, arms) => {
+                let value = self.expr(state, value)?;
+                let end = state.new_block();
+                for arm in arms {
+                    let Pattern::Literal(literal) = &arm.pattern else {
+                        self.block(state, arm.body)?;
+                        break;
+                    };
+                    let pattern = self.expr(state, literal)?;
+                    let matched = state.temp();
+                    state.emit(Instr::Binary(matched, BinOp::Eq, value.clone(), pattern), arm.span);
+                    let (arm_b, next) = (state.new_block(), state.new_block());
+                    state.terminate(Terminator::Branch(Operand::Temp(matched), arm_b, next), arm.span, Some(arm_b));
+                    self.block(state, arm.body)?;
+                    state.terminate(Terminator::Jump(end), span, Some(next));
+                }
+                state.jump_to(end, span);
+            }
+            StmtKind::FnDecl(name, params, _, body) => {
+                let id = self.declarations[&span];
+                let mut inner = FnState::new(false);
+                inner.scopes.push(Vec::new());
+                for param in params {
+                    inner.declare_local(param.name);
+                }
+                self.stmts(&mut inner, body)?;
+                self.finish(inner, name, params.len(), id, span);
+            }
+            StmtKind::StructDecl(..) | StmtKind::EnumDecl(..) | StmtKind::TypeAlias(..) | StmtKind::Import(..) => {}
+            StmtKind::Return(expr) => {
+                let value = match expr {
+                    Some(expr) => self.expr(state, expr)?,
+                    None => Operand::Const(Value::Int(0)),
+                };
+                state.terminate(Terminator::Return(value), span, None);
+            }
+            StmtKind::Break | StmtKind::Continue => {
+                let Some(&(exit, next)) = state.loops.last() else {
+                    return Err(Self::error("'break' or 'continue' outside of a loop", span));
+                };
+                let target = if matches!(stmt.node, StmtKind::Break) { exit } else { next };
+                state.terminate(Terminator::Jump(target), span, None);
+            }
+            StmtKind::Block(stmts) => self.block(state, stmts)?,
+        }
+        Ok(())
+    }
+
+    fn expr(&mut self, state: &mut FnState, expr: &Expr) -> Result<Operand, CompilerError> {
+        let span = expr.span;
+        let result = match &expr.node {
+            ExprKind::Number(n) => return Ok(Operand::Const(Value::Int(*n))),
+            ExprKind::Float(n) => return Ok(Operand::Const(Value::Float(*n))),
+            ExprKind::Str(s) => return Ok(Operand::Const(Value::Str(s.clone()))),
+            ExprKind::Char(c) => return Ok(Operand::Const(Value::Char(*c))),
+            ExprKind::Bool(b) => return Ok(Operand::Const(Value::Bool(*b))),
+            ExprKind::Null => return Ok(Operand::Const(Value::Null)),
+            ExprKind::Variant(name, variant) => return Ok(Operand::Const(Value::Enum(*name, *variant))),
+            ExprKind::Variable(name) => {
+                let place = self.place(state, *name);
+                let t = state.temp();
+                state.emit(Instr::Load(t, place), span);
+                t
+            }
+            ExprKind::Unary(UnOp::Neg, operand) => {
+                let value = self.expr(state, operand)?;
+                let t = state.temp();
+                state.emit(Instr::Neg(t, value), span);
+                t
+            }
+            ExprKind::Unary(UnOp::BitNot, operand) => {
+                let value = self.expr(state, operand)?;
+                let t = state.temp();
+                state.emit(Instr::BitNot(t, value), span);
+                t
+            }
+            ExprKind::Update(name, op, prefix) => {
+                let place = self.place(state, *name);
+                let (old, new) = (state.temp(), state.temp());
+                state.emit(Instr::Load(old, place), span);
+                state.emit(Instr::Update(new, *op, Operand::Temp(old)), span);
+                state.emit(Instr::Store(place, Operand::Temp(new)), span);
+                if *prefix { new } else { old }
+            }
+            ExprKind::Binary(lhs, op @ (BinOp::And | BinOp::Or), rhs) => {
+                // Short-circuit: `a && b` is `if a { b } else { false }` and
+                // `a || b` is `if a { true } else { b }`. Branching on `b`
+                // as well checks that it is a bool.
+                let t = state.temp();
+                let l = self.expr(state, lhs)?;
+                let (rhs_b, on_true, on_false, end) = (state.new_block(), state.new_block(), state.new_block(), state.new_block());
+                let lhs_branch = if matches!(op, BinOp::And) {
+                    Terminator::Branch(l, rhs_b, on_false)
+                } else {
+                    Terminator::Branch(l, on_true, rhs_b)
+                };
+                state.terminate(lhs_branch, lhs.span, Some(rhs_b));
+                let r = self.expr(state, rhs)?;
+                state.terminate(Terminator::Branch(r, on_true, on_false), rhs.span, Some(on_true));
+                state.emit(Instr::Copy(t, Operand::Const(Value::Bool(true))), span);
+                state.terminate(Terminator::Jump(end), span, Some(on_false));
+                state.emit(Instr::Copy(t, Operand::Const(Value::Bool(false))), span);
+                state.jump_to(end, span);
+                t
+            }
+            ExprKind::Binary(lhs, op, rhs) => {
+                let l = self.expr(state, lhs)?;
+                let r = self.expr(state, rhs)?;
+                let t = state.temp();
+                state.emit(Instr::Binary(t, *op, l, r), span);
+                t
+            }
+            ExprKind::Array(elements) => {
+                let mut values = Vec::new();
+                for element in elements.iter() {
+                    values.push(self.expr(state, element)?);
+                }
+                let t = state.temp();
+                state.emit(Instr::MakeArray(t, values), span);
+                t
+            }
+            ExprKind::Map(entries) => {
+                let mut values = Vec::new();
+                for (key, value) in entries {
+                    let key = self.expr(state, key)?;
+                    values.push((key, self.expr(state, value)?));
+                }
+                let t = state.temp();
+                state.emit(Instr::MakeMap(t, values), span);
+                t
+            }
+            ExprKind::Index(array, index) => {
+                let array = self.expr(state, array)?;
+                let index_value = self.expr(state, index)?;
+                let t = state.temp();
+                state.emit(Instr::Index(t, array, index_value), index.span);
+                t
+            }
+            ExprKind::StructLit(name, fields) => {
+                let mut values = Vec::new();
+                for (field, value) in fields {
+                    values.push((*field, self.expr(state, value)?));
+                }
+                let t = state.temp();
+                state.emit(Instr::MakeStruct(t, *name, values), span);
+                t
+            }
+            ExprKind::Field(object, field) => {
+                let object = self.expr(state, object)?;
+                let t = state.temp();
+                state.emit(Instr::Field(t, object, *field), span);
+                t
+            }
+            ExprKind::If(cond, then_value, else_value) => {
+                let t = state.temp();
+                let cond_value = self.expr(state, cond)?;
+                let (then_b, else_b, end) = (state.new_block(), state.new_block(), state.new_block());
+                state.terminate(Terminator::Branch(cond_value, then_b, else_b), cond.span, Some(then_b));
+                let value = self.expr(state, then_value)?;
+                state.emit(Instr::Copy(t, value), span);
+                state.terminate(Terminator::Jump(end), span, Some(else_b));
+                let value = self.expr(state, else_value)?;
+                state.emit(Instr::Copy(t, value), span);
+                state.jump_to(end, span);
+                t
+            }
+            ExprKind::Match(value, arms) => {
+                let t = state.temp();
+                let value = self.expr(state, value)?;
+                let end = state.new_block();
+                for (i, arm) in arms.iter().enumerate() {
+                    // The checker made sure the arms cover every value, so
+                    // the last one is taken without a test.
+                    let next = match &arm.pattern {
+                        Pattern::Literal(literal) if i + 1 < arms.len() => {
+                            let pattern = self.expr(state, literal)?;
+                            let matched = state.temp();
+                            state.emit(Instr::Binary(matched, BinOp::Eq, value.clone(), pattern), arm.span);
+                            let (arm_b, next) = (state.new_block(), state.new_block());
+                            state.terminate(Terminator::Branch(Operand::Temp(matched), arm_b, next), arm.span, Some(arm_b));
+                            Some(next)
+                        }
+                        _ => None,
+                    };
+                    let arm_value = self.expr(state, &arm.value)?;
+                    state.emit(Instr::Copy(t, arm_value), span);
+                    let Some(next) = next else {
+                        break;
+                    };
+                    state.terminate(Terminator::Jump(end), span, Some(next));
+                }
+                state.jump_to(end, span);
+                t
+            }
+            ExprKind::Block(stmts, value) => {
+                state.scopes.push(Vec::new());
+                let result = self.stmts(state, stmts).and_then(|()| self.expr(state, value));
+                state.scopes.pop();
+                return result;
+            }
+            ExprKind::Call(name, args) => {
+                let mut values = Vec::new();
+                for arg in args.iter() {
+                    values.push(self.expr(state, arg)?);
+                }
+                let t = state.temp();
+                if let Some(&id) = self.function_ids.get(name) {
+                    state.emit(Instr::Call(t, id, values), span);
+                } else if let Some(b) = builtins::BUILTINS.iter().position(|builtin| *name == builtin.name) {
+                    state.emit(Instr::CallBuiltin(t, b, values), span);
+                } else {
+                    return Err(Self::error(&format!("Undefined function: {}", name), span));
+                }
+                t
+            }
+        };
+        Ok(Operand::Temp(result))
+    }
+}
+
+impl fmt::Display for Temp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "t{}", self.0)
+    }
+}
+
+impl fmt::Display for BlockId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "b{}", self.0)
+    }
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Operand::Const(Value::Str(s)) => write!(f, "{:?}", s),
+            Operand::Const(Value::Char(c)) => write!(f, "{:?}", c),
+            Operand::Const(value) => write!(f, "{}", value),
+            Operand::Temp(t) => write!(f, "{}", t),
+        }
+    }
+}
+
+fn operands(values: &[Operand]) -> String {
+    values.iter().map(|value| value.to_string()).collect::<Vec<_>>().join(", ")
+}
+
+impl Program {
+    fn place(&self, place: Place) -> String {
+        match place {
+            Place::Local(slot) => format!("l{}", slot),
+            Place::Global(i) => format!("@{}", self.globals[i]),
+        }
+    }
+
+    fn instr(&self, instr: &Instr) -> String {
+        match instr {
+            Instr::Copy(t, value) => format!("{} = {}", t, value),
+            Instr::Load(t, place) => format!("{} = {}", t, self.place(*place)),
+            Instr::Store(place, value) => format!("{} = {}", self.place(*place), value),
+            Instr::DefineGlobal(i, value) => format!("let @{} = {}", self.globals[*i], value),
+            Instr::Binary(t, op, l, r) => format!("{} = {} {} {}", t, l, op, r),
+            Instr::Neg(t, value) => format!("{} = -{}", t, value),
+            Instr::BitNot(t, value) => format!("{} = ~{}", t, value),
+            Instr::Update(t, UpdateOp::Inc, value) => format!("{} = {} + 1", t, value),
+            Instr::Update(t, UpdateOp::Dec, value) => format!("{} = {} - 1", t, value),
+            Instr::Call(t, id, args) => format!("{} = call {}({})", t, self.functions[*id].name, operands(args)),
+            Instr::CallBuiltin(t, b, args) => format!("{} = call {}({})", t, builtins::BUILTINS[*b].name, operands(args)),
+            Instr::MakeArray(t, elements) => format!("{} = [{}]", t, operands(elements)),
+            Instr::MakeMap(t, entries) => {
+                let entries: Vec<String> = entries.iter().map(|(key, value)| format!("{}: {}", key, value)).collect();
+                format!("{} = {{{}}}", t, entries.join(", "))
+            }
+            Instr::Index(t, array, index) => format!("{} = {}[{}]", t, array, index),
+            Instr::StoreIndex(array, index, value) => format!("{}[{}] = {}", array, index, value),
+            Instr::MakeStruct(t, name, fields) => {
+                let fields: Vec<String> = fields.iter().map(|(field, value)| format!("{}: {}", field, value)).collect();
+                format!("{} = {} {{ {} }}", t, name, fields.join(", "))
+            }
+            Instr::Field(t, object, field) => format!("{} = {}.{}", t, object, field),
+            Instr::StoreField(object, field, value) => format!("{}.{} = {}", object, field, value),
+        }
+    }
+}
+
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, function) in self.functions.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            writeln!(f, "fn {}/{} (locals: {}, temps: {}) {{", function.name, function.arity, function.locals, function.temps)?;
+            for (id, block) in function.blocks.iter().enumerate() {
+                writeln!(f, "{}:", BlockId(id))?;
+                for instr in &block.instrs {
+                    writeln!(f, "    {}", self.instr(instr))?;
+                }
+                match &block.terminator {
+                    Terminator::Jump(target) => writeln!(f, "    jump {}", target)?,
+                    Terminator::Branch(cond, then_block, else_block) => {
+                        writeln!(f, "    branch {}, {}, {}", cond, then_block, else_block)?
+                    }
+                    Terminator::Return(value) => writeln!(f, "    return {}", value)?,
+                }
+            }
+            writeln!(f, "}}")?;
+        }
+        Ok(())
+    }
+}
\ No newline at end of file
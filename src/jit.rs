@@ -0,0 +1,923 @@
+//! Native execution through Cranelift, behind the `jit` feature.
+//!
+//! The type-checked program is lowered straight to Cranelift IR, compiled to
+//! machine code in memory and called. Integer arithmetic stays checked and
+//! runtime errors carry the same messages and spans as the interpreter's.
+//!
+//! Generated code shares a block of `i64` slots with the runtime: whether a
+//! runtime error has happened, the current call depth and then one slot per
+//! global. When an error happens the failing function returns at once and
+//! every caller, seeing the flag, does the same.
+
+use crate::ast::*;
+use crate::codegen_c::{collect_functions, expr_type};
+use crate::error::CompilerError;
+use crate::interpreter::DEFAULT_MAX_CALL_DEPTH;
+use crate::span::Span;
+use crate::type_checker::{Type, TypeChecker};
+use cranelift_codegen::ir::condcodes::IntCC;
+use cranelift_codegen::ir::{types, AbiParam, Block, BlockArg, FuncRef, InstBuilder, MemFlagsData, Signature, UserFuncName, Value};
+use cranelift_codegen::settings::{self, Configurable};
+use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext, Variable};
+use cranelift_jit::{JITBuilder, JITModule};
+use cranelift_module::{default_libcall_names, FuncId, Linkage, Module};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io::Write;
+
+const FAILED_SLOT: usize = 0;
+const DEPTH_SLOT: usize = 1;
+const GLOBALS_START: usize = 2;
+
+/// Runtime errors that generated code raises through `ferrum_jit_fail`.
+const FAILURES: [&str; 6] = [
+    "Integer overflow in '+'",
+    "Integer overflow in '-'",
+    "Integer overflow in '*'",
+    "Integer overflow in '/'",
+    "Division by zero",
+    "stack overflow",
+];
+const ADD_OVERFLOW: i64 = 0;
+const SUB_OVERFLOW: i64 = 1;
+const MUL_OVERFLOW: i64 = 2;
+const DIV_OVERFLOW: i64 = 3;
+const DIVISION_BY_ZERO: i64 = 4;
+const STACK_OVERFLOW: i64 = 5;
+
+thread_local! {
+    /// The first runtime error of the program running on this thread.
+    static ERROR: RefCell<Option<CompilerError>> = const { RefCell::new(None) };
+}
+
+fn record_error(slots: *mut i64, msg: String, span: Option<Span>) {
+    ERROR.with(|error| *error.borrow_mut() = Some(CompilerError::RuntimeError(msg, span)));
+    // SAFETY: `slots` is the slot block `run` keeps alive while code runs.
+    unsafe { *slots.add(FAILED_SLOT) = 1 };
+}
+
+/// Rebuilds a span that generated code passed as five `i64`s.
+fn span(start: i64, end: i64, line: i64, col: i64, file: i64) -> Option<Span> {
+    Some(Span { start: start as usize, end: end as usize, line: line as usize, col: col as usize, file: file as usize })
+}
+
+extern "C" fn ferrum_jit_fail(slots: *mut i64, failure: i64, start: i64, end: i64, line: i64, col: i64, file: i64) {
+    record_error(slots, FAILURES[failure as usize].to_string(), span(start, end, line, col, file));
+}
+
+fn write_output(slots: *mut i64, bytes: &[u8]) {
+    if let Err(e) = std::io::stdout().write_all(bytes) {
+        record_error(slots, format!("Failed to write output: {}", e), None);
+    }
+}
+
+extern "C" fn ferrum_jit_print_int(slots: *mut i64, n: i64) {
+    write_output(slots, n.to_string().as_bytes());
+}
+
+extern "C" fn ferrum_jit_print_bool(slots: *mut i64, b: i8) {
+    write_output(slots, if b != 0 { b"true" } else { b"false" });
+}
+
+extern "C" fn ferrum_jit_print_str(slots: *mut i64, ptr: *const u8, len: i64) {
+    // SAFETY: generated code only passes string literals `run` keeps alive.
+    let bytes = unsafe { std::slice::from_raw_parts(ptr, len as usize) };
+    write_output(slots, bytes);
+}
+
+extern "C" fn ferrum_jit_read_int(slots: *mut i64, start: i64, end: i64, line: i64, col: i64, file: i64) -> i64 {
+    let span = span(start, end, line, col, file);
+    let _ = std::io::stdout().flush();
+    let mut input = String::new();
+    match std::io::stdin().read_line(&mut input) {
+        Ok(0) => record_error(slots, "read_int reached the end of input".to_string(), span),
+        Ok(_) => match input.trim().parse() {
+            Ok(n) => return n,
+            Err(_) => record_error(slots, format!("read_int expected an integer, found '{}'", input.trim()), span),
+        },
+        Err(e) => record_error(slots, format!("Failed to read input: {}", e), span),
+    }
+    0
+}
+
+/// Compiles `program` to native code and runs it. `checker` must already
+/// have checked `program`.
+///
+/// Ints, bools, functions and control flow are supported, with strings only
+/// as literal `print` arguments. Floats, arrays, `len` and `read_line` are
+/// reported as [`CompilerError::UnsupportedError`] before anything runs.
+pub fn run(program: &[Stmt], checker: &TypeChecker) -> Result<(), CompilerError> {
+    run_with_max_call_depth(program, checker, DEFAULT_MAX_CALL_DEPTH)
+}
+
+/// Like [`run`], with a limit on how deeply user functions may recurse
+/// before the program fails with a "stack overflow" runtime error.
+pub fn run_with_max_call_depth(program: &[Stmt], checker: &TypeChecker, max_call_depth: usize) -> Result<(), CompilerError> {
+    let mut jit = Jit::new(checker, max_call_depth)?;
+    jit.declare_globals(program)?;
+    let main = jit.compile(program)?;
+    jit.module.finalize_definitions().map_err(|e| internal(&e.to_string()))?;
+    let code = jit.module.get_finalized_function(main);
+
+    ERROR.with(|error| error.borrow_mut().take());
+    // SAFETY: `main` was compiled with the signature `fn()`, and everything
+    // its code points at (`slots` and `strings`) lives in `jit` until the
+    // call returns.
+    unsafe {
+        let main: extern "C" fn() = std::mem::transmute(code);
+        main();
+    }
+    let flushed = std::io::stdout().flush();
+    if let Some(error) = ERROR.with(|error| error.borrow_mut().take()) {
+        return Err(error);
+    }
+    flushed.map_err(|e| CompilerError::RuntimeError(format!("Failed to write output: {}", e), None))
+}
+
+fn unsupported(what: &str, span: Span) -> CompilerError {
+    CompilerError::UnsupportedError(format!("The JIT backend does not support {}", what), Some(span))
+}
+
+/// Cranelift rejected what we generated: a bug in this module, not in the program.
+fn internal(msg: &str) -> CompilerError {
+    CompilerError::RuntimeError(format!("JIT compilation failed: {}", msg), None)
+}
+
+fn clif_type(t: &Type, span: Span) -> Result<types::Type, CompilerError> {
+    match t {
+        Type::Int => Ok(types::I64),
+        Type::Bool => Ok(types::I8),
+        Type::Error => Err(unsupported("a value whose type could not be inferred", span)),
+        other => Err(unsupported(&format!("values of type {}", other), span)),
+    }
+}
+
+/// Host functions generated code calls, with their signatures.
+struct Runtime {
+    fail: FuncId,
+    print_int: FuncId,
+    print_bool: FuncId,
+    print_str: FuncId,
+    read_int: FuncId,
+}
+
+struct Jit<'a> {
+    types: &'a TypeChecker,
+    module: JITModule,
+    runtime: Runtime,
+    max_call_depth: usize,
+    /// Runtime flags followed by the globals; see the module docs.
+    slots: Vec<i64>,
+    /// Top-level variables with their slot and type.
+    globals: HashMap<String, (usize, Type)>,
+    /// Every user function with its id and signature.
+    functions: HashMap<String, (FuncId, Vec<Type>, Type)>,
+    /// String literals, kept alive for the generated code to point at.
+    strings: Vec<Box<[u8]>>,
+}
+
+impl<'a> Jit<'a> {
+    fn new(types: &'a TypeChecker, max_call_depth: usize) -> Result<Self, CompilerError> {
+        let mut flags = settings::builder();
+        flags.set("opt_level", "speed").map_err(|e| internal(&e.to_string()))?;
+        let isa = cranelift_native::builder()
+            .map_err(|msg| internal(&format!("unsupported host: {}", msg)))?
+            .finish(settings::Flags::new(flags))
+            .map_err(|e| internal(&e.to_string()))?;
+        let mut builder = JITBuilder::with_isa(isa, default_libcall_names());
+        builder.symbol("ferrum_jit_fail", ferrum_jit_fail as *const u8);
+        builder.symbol("ferrum_jit_print_int", ferrum_jit_print_int as *const u8);
+        builder.symbol("ferrum_jit_print_bool", ferrum_jit_print_bool as *const u8);
+        builder.symbol("ferrum_jit_print_str", ferrum_jit_print_str as *const u8);
+        builder.symbol("ferrum_jit_read_int", ferrum_jit_read_int as *const u8);
+        let mut module = JITModule::new(builder);
+
+        let ptr = module.target_config().pointer_type();
+        let mut import = |name: &str, params: &[types::Type], returns: &[types::Type]| {
+            let mut sig = module.make_signature();
+            sig.params.push(AbiParam::new(ptr));
+            sig.params.extend(params.iter().map(|t| AbiParam::new(*t)));
+            sig.returns.extend(returns.iter().map(|t| AbiParam::new(*t)));
+            module.declare_function(name, Linkage::Import, &sig).map_err(|e| internal(&e.to_string()))
+        };
+        let runtime = Runtime {
+            fail: import("ferrum_jit_fail", &[types::I64; 6], &[])?,
+            print_int: import("ferrum_jit_print_int", &[types::I64], &[])?,
+            print_bool: import("ferrum_jit_print_bool", &[types::I8], &[])?,
+            print_str: import("ferrum_jit_print_str", &[ptr, types::I64], &[])?,
+            read_int: import("ferrum_jit_read_int", &[types::I64; 5], &[types::I64])?,
+        };
+        Ok(Self {
+            types,
+            module,
+            runtime,
+            max_call_depth,
+            slots: vec![0; GLOBALS_START],
+            globals: HashMap::new(),
+            functions: HashMap::new(),
+            strings: Vec::new(),
+        })
+    }
+
+    fn declare_globals(&mut self, program: &[Stmt]) -> Result<(), CompilerError> {
+        for stmt in program {
+            if let StmtKind::Let(name, _, expr) | StmtKind::Const(name, _, expr) = &stmt.node {
+                let variable = |name: &str| self.globals.get(name).map(|(_, t)| t.clone());
+                let t = expr_type(self.types, expr, &variable);
+                match self.globals.get(name.as_str()) {
+                    Some((_, existing)) if *existing != t => {
+                        return Err(unsupported(&format!("redeclaring global {} with a different type", name), stmt.span));
+                    }
+                    Some(_) => {}
+                    None => {
+                        clif_type(&t, expr.span)?;
+                        self.globals.insert(name.to_string(), (self.slots.len(), t));
+                        self.slots.push(0);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn signature(&self, params: &[Type], return_type: &Type, span: Span) -> Result<Signature, CompilerError> {
+        let mut sig = self.module.make_signature();
+        for t in params {
+            sig.params.push(AbiParam::new(clif_type(t, span)?));
+        }
+        if *return_type != Type::Void {
+            sig.returns.push(AbiParam::new(clif_type(return_type, span)?));
+        }
+        Ok(sig)
+    }
+
+    /// Compiles every function and the top-level code, returning the id of
+    /// the latter.
+    fn compile(&mut self, program: &[Stmt]) -> Result<FuncId, CompilerError> {
+        let mut definitions = Vec::new();
+        collect_functions(program, &mut definitions);
+        for stmt in &definitions {
+            let StmtKind::FnDecl(name, ..) = &stmt.node else {
+                unreachable!("collect_functions only returns declarations");
+            };
+            if self.functions.contains_key(name.as_str()) {
+                return Err(unsupported(&format!("two functions named {}", name), stmt.span));
+            }
+            let (params, return_type) = self.types.function_type(name).cloned().expect("the checker saw every function");
+            let sig = self.signature(&params, &return_type, stmt.span)?;
+            let id = self
+                .module
+                .declare_function(&format!("f_{}", name), Linkage::Local, &sig)
+                .map_err(|e| internal(&e.to_string()))?;
+            self.functions.insert(name.to_string(), (id, params, return_type));
+        }
+
+        let mut ctx = self.module.make_context();
+        let mut builder_ctx = FunctionBuilderContext::new();
+        for stmt in &definitions {
+            let StmtKind::FnDecl(name, params, _, body) = &stmt.node else {
+                unreachable!("collect_functions only returns declarations");
+            };
+            let (id, param_types, return_type) = self.functions[name.as_str()].clone();
+            ctx.func.signature = self.signature(&param_types, &return_type, stmt.span)?;
+            ctx.func.name = UserFuncName::user(0, id.as_u32());
+            let names: Vec<&str> = params.iter().map(|param| param.name.as_str()).collect();
+            self.function(&mut ctx.func, &mut builder_ctx, &names, &param_types, &return_type, body, false)?;
+            self.module.define_function(id, &mut ctx).map_err(|e| internal(&format!("{:?}", e)))?;
+            self.module.clear_context(&mut ctx);
+        }
+
+        let main = self
+            .module
+            .declare_function("main", Linkage::Local, &self.module.make_signature())
+            .map_err(|e| internal(&e.to_string()))?;
+        ctx.func.signature = self.module.make_signature();
+        ctx.func.name = UserFuncName::user(0, main.as_u32());
+        self.function(&mut ctx.func, &mut builder_ctx, &[], &[], &Type::Void, program, true)?;
+        self.module.define_function(main, &mut ctx).map_err(|e| internal(&format!("{:?}", e)))?;
+        self.module.clear_context(&mut ctx);
+        Ok(main)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn function(
+        &mut self,
+        func: &mut cranelift_codegen::ir::Function,
+        builder_ctx: &mut FunctionBuilderContext,
+        params: &[&str],
+        param_types: &[Type],
+        return_type: &Type,
+        body: &[Stmt],
+        is_main: bool,
+    ) -> Result<(), CompilerError> {
+        let mut builder = FunctionBuilder::new(func, builder_ctx);
+        let entry = builder.create_block();
+        builder.append_block_params_for_function_params(entry);
+        builder.switch_to_block(entry);
+
+        let mut refs = HashMap::new();
+        for (name, (id, ..)) in &self.functions {
+            refs.insert(name.clone(), self.module.declare_func_in_func(*id, builder.func));
+        }
+        let runtime = [
+            self.runtime.fail,
+            self.runtime.print_int,
+            self.runtime.print_bool,
+            self.runtime.print_str,
+            self.runtime.read_int,
+        ]
+        .map(|id| self.module.declare_func_in_func(id, builder.func));
+
+        let mut state = FnState {
+            builder,
+            scopes: vec![Vec::new()],
+            loops: Vec::new(),
+            functions: refs,
+            runtime,
+            return_type: return_type.clone(),
+            is_main,
+        };
+        let args = state.builder.block_params(entry).to_vec();
+        for ((name, t), arg) in params.iter().zip(param_types).zip(args) {
+            let var = state.builder.declare_var(clif_type(t, Span::default())?);
+            state.builder.def_var(var, arg);
+            state.scopes[0].push((name.to_string(), var, t.clone()));
+        }
+
+        self.stmts(&mut state, body)?;
+        // Only reachable in void functions; the checker makes every other
+        // function return on every path.
+        state.bail();
+        state.builder.seal_all_blocks();
+        state.builder.finalize(self.module.isa().frontend_config());
+        Ok(())
+    }
+
+    fn slot_address(&self, state: &mut FnState, slot: usize) -> Value {
+        let ptr = self.module.target_config().pointer_type();
+        // SAFETY: only the address is computed; `slots` never reallocates
+        // once compilation has started.
+        let address = unsafe { self.slots.as_ptr().add(slot) } as i64;
+        state.builder.ins().iconst(ptr, address)
+    }
+
+    fn load_slot(&self, state: &mut FnState, slot: usize) -> Value {
+        let address = self.slot_address(state, slot);
+        state.builder.ins().load(types::I64, MemFlagsData::trusted(), address, 0)
+    }
+
+    fn store_slot(&self, state: &mut FnState, slot: usize, value: Value) {
+        let address = self.slot_address(state, slot);
+        state.builder.ins().store(MemFlagsData::trusted(), value, address, 0);
+    }
+
+    fn slots_pointer(&self, state: &mut FnState) -> Value {
+        self.slot_address(state, 0)
+    }
+
+    /// Returns from the current function if a runtime error was recorded.
+    fn check_failed(&self, state: &mut FnState) {
+        let failed = self.load_slot(state, FAILED_SLOT);
+        let bail = state.builder.create_block();
+        let next = state.builder.create_block();
+        state.builder.ins().brif(failed, bail, &[], next, &[]);
+        state.builder.switch_to_block(bail);
+        state.bail();
+        state.builder.switch_to_block(next);
+    }
+
+    /// Raises `failure` at `span` when `cond` is non-zero.
+    fn fail_if(&self, state: &mut FnState, cond: Value, failure: i64, span: Span) {
+        let fail = state.builder.create_block();
+        let next = state.builder.create_block();
+        state.builder.ins().brif(cond, fail, &[], next, &[]);
+        state.builder.switch_to_block(fail);
+        let slots = self.slots_pointer(state);
+        let failure = state.builder.ins().iconst(types::I64, failure);
+        let mut args = vec![slots, failure];
+        args.extend(state.span(span));
+        let fail_fn = state.runtime[0];
+        state.builder.ins().call(fail_fn, &args);
+        state.bail();
+        state.builder.switch_to_block(next);
+    }
+
+    fn type_of(&self, state: &FnState, expr: &Expr) -> Type {
+        let variable = |name: &str| {
+            state.local(name).map(|(_, t)| t.clone()).or_else(|| self.globals.get(name).map(|(_, t)| t.clone()))
+        };
+        expr_type(self.types, expr, &variable)
+    }
+
+    fn read_variable(&self, state: &mut FnState, name: &str, span: Span) -> Result<Value, CompilerError> {
+        if let Some((var, _)) = state.local(name) {
+            return Ok(state.builder.use_var(var));
+        }
+        match self.globals.get(name) {
+            Some((slot, t)) => {
+                let value = self.load_slot(state, *slot);
+                Ok(if *t == Type::Bool { state.builder.ins().ireduce(types::I8, value) } else { value })
+            }
+            None => Err(unsupported(&format!("using {} from an enclosing function", name), span)),
+        }
+    }
+
+    fn write_variable(&self, state: &mut FnState, name: &str, value: Value, span: Span) -> Result<(), CompilerError> {
+        if let Some((var, _)) = state.local(name) {
+            state.builder.def_var(var, value);
+            return Ok(());
+        }
+        match self.globals.get(name) {
+            Some((slot, t)) => {
+                let value = if *t == Type::Bool { state.builder.ins().uextend(types::I64, value) } else { value };
+                self.store_slot(state, *slot, value);
+                Ok(())
+            }
+            None => Err(unsupported(&format!("using {} from an enclosing function", name), span)),
+        }
+    }
+
+    fn block(&mut self, state: &mut FnState, stmts: &[Stmt]) -> Result<(), CompilerError> {
+        state.scopes.push(Vec::new());
+        self.stmts(state, stmts)?;
+        state.scopes.pop();
+        Ok(())
+    }
+
+    fn stmts(&mut self, state: &mut FnState, stmts: &[Stmt]) -> Result<(), CompilerError> {
+        for stmt in stmts {
+            self.stmt(state, stmt)?;
+        }
+        Ok(())
+    }
+
+    fn stmt(&mut self, state: &mut FnState, stmt: &Stmt) -> Result<(), CompilerError> {
+        match &stmt.node {
+            StmtKind::Let(name, _, expr) | StmtKind::Const(name, _, expr) => {
+                let value = self.expr(state, expr)?;
+                if state.is_main && state.scopes.len() == 1 {
+                    self.write_variable(state, name, value, stmt.span)?;
+                } else {
+                    let t = self.type_of(state, expr);
+                    let var = state.builder.declare_var(clif_type(&t, expr.span)?);
+                    state.builder.def_var(var, value);
+                    state.scopes.last_mut().expect("a function always has a scope").push((name.to_string(), var, t));
+                }
+            }
+            StmtKind::Assign(name, expr) => {
+                let value = self.expr(state, expr)?;
+                self.write_variable(state, name, value, stmt.span)?;
+            }
+            StmtKind::IndexAssign(..) => return Err(unsupported("arrays", stmt.span)),
+            StmtKind::FieldAssign(..) => return Err(unsupported("structs", stmt.span)),
+            StmtKind::Expr(expr) => {
+                self.expr(state, expr)?;
+            }
+            StmtKind::If(cond, then_block, else_block) => {
+                let cond = self.expr(state, cond)?;
+                let (then_b, else_b, merge) = (state.builder.create_block(), state.builder.create_block(), state.builder.create_block());
+                state.builder.ins().brif(cond, then_b, &[], else_b, &[]);
+                state.builder.switch_to_block(then_b);
+                self.block(state, then_block)?;
+                state.builder.ins().jump(merge, &[]);
+                state.builder.switch_to_block(else_b);
+                self.block(state, else_block)?;
+                state.builder.ins().jump(merge, &[]);
+                state.builder.switch_to_block(merge);
+            }
+            StmtKind::While(cond, body) => {
+                let (header, body_b, exit) = (state.builder.create_block(), state.builder.create_block(), state.builder.create_block());
+                state.builder.ins().jump(header, &[]);
+                state.builder.switch_to_block(header);
+                let cond = self.expr(state, cond)?;
+                state.builder.ins().brif(cond, body_b, &[], exit, &[]);
+                state.builder.switch_to_block(body_b);
+                self.loop_body(state, body, exit, header)?;
+                state.builder.ins().jump(header, &[]);
+                state.builder.switch_to_block(exit);
+            }
+            StmtKind::DoWhile(body, cond) => {
+                let (body_b, cond_b, exit) = (state.builder.create_block(), state.builder.create_block(), state.builder.create_block());
+                state.builder.ins().jump(body_b, &[]);
+                state.builder.switch_to_block(body_b);
+                self.loop_body(state, body, exit, cond_b)?;
+                state.builder.ins().jump(cond_b, &[]);
+                state.builder.switch_to_block(cond_b);
+                let cond = self.expr(state, cond)?;
+                state.builder.ins().brif(cond, body_b, &[], exit, &[]);
+                state.builder.switch_to_block(exit);
+            }
+            StmtKind::For(init, cond, step, body) => {
+                // A variable declared by the initializer lives in its own
+                // scope around the body.
+                state.scopes.push(Vec::new());
+                self.stmt(state, init)?;
+                let (header, body_b, step_b, exit) = (
+                    state.builder.create_block(),
+                    state.builder.create_block(),
+                    state.builder.create_block(),
+                    state.builder.create_block(),
+                );
+                state.builder.ins().jump(header, &[]);
+                state.builder.switch_to_block(header);
+                let cond = self.expr(state, cond)?;
+                state.builder.ins().brif(cond, body_b, &[], exit, &[]);
+                state.builder.switch_to_block(body_b);
+                self.loop_body(state, body, exit, step_b)?;
+                state.builder.ins().jump(step_b, &[]);
+                state.builder.switch_to_block(step_b);
+                self.stmt(state, step)?;
+                state.builder.ins().jump(header, &[]);
+                state.builder.switch_to_block(exit);
+                state.scopes.pop();
+            }
+            StmtKind::ForRange(var, start, end, inclusive, body) => {
+                let start = self.expr(state, start)?;
+                let end = self.expr(state, end)?;
+                // The loop counts in hidden variables, so assigning to the
+                // loop variable does not change the iteration.
+                let (next, last) = (state.builder.declare_var(types::I64), state.builder.declare_var(types::I64));
+                state.builder.def_var(next, start);
+                state.builder.def_var(last, end);
+                let (header, body_b, step_b, exit) = (
+                    state.builder.create_block(),
+                    state.builder.create_block(),
+                    state.builder.create_block(),
+                    state.builder.create_block(),
+                );
+                state.builder.ins().jump(header, &[]);
+                state.builder.switch_to_block(header);
+                let (n, e) = (state.builder.use_var(next), state.builder.use_var(last));
+                let cc = if *inclusive { IntCC::SignedLessThanOrEqual } else { IntCC::SignedLessThan };
+                let more = state.builder.ins().icmp(cc, n, e);
+                state.builder.ins().brif(more, body_b, &[], exit, &[]);
+                state.builder.switch_to_block(body_b);
+                let loop_var = state.builder.declare_var(types::I64);
+                state.builder.def_var(loop_var, n);
+                state.scopes.push(vec![(var.to_string(), loop_var, Type::Int)]);
+                self.loop_body(state, body, exit, step_b)?;
+                state.scopes.pop();
+                state.builder.ins().jump(step_b, &[]);
+                state.builder.switch_to_block(step_b);
+                let n = state.builder.use_var(next);
+                // Stepping past an inclusive end of `i64::MAX` would overflow.
+                if *inclusive {
+                    let e = state.builder.use_var(last);
+                    let more = state.builder.ins().icmp(IntCC::SignedLessThan, n, e);
+                    let increment = state.builder.create_block();
+                    state.builder.ins().brif(more, increment, &[], exit, &[]);
+                    state.builder.switch_to_block(increment);
+                }
+                let successor = state.builder.ins().iadd_imm_s(n, 1);
+                state.builder.def_var(next, successor);
+                state.builder.ins().jump(header, &[]);
+                state.builder.switch_to_block(exit);
+            }
+            StmtKind::Match(value, arms) => {
+                let t = self.type_of(state, value);
+                if !matches!(t, Type::Int | Type::Bool) {
+                    return Err(unsupported(&format!("matching on {}", t), value.span));
+                }
+                let value = self.expr(state, value)?;
+                let merge = state.builder.create_block();
+                for arm in arms {
+                    let Pattern::Literal(literal) = &arm.pattern else {
+                        self.block(state, arm.body)?;
+                        break;
+                    };
+                    let pattern = self.expr(state, literal)?;
+                    let matched = state.builder.ins().icmp(IntCC::Equal, value, pattern);
+                    let (arm_b, next) = (state.builder.create_block(), state.builder.create_block());
+                    state.builder.ins().brif(matched, arm_b, &[], next, &[]);
+                    state.builder.switch_to_block(arm_b);
+                    self.block(state, arm.body)?;
+                    state.builder.ins().jump(merge, &[]);
+                    state.builder.switch_to_block(next);
+                }
+                state.builder.ins().jump(merge, &[]);
+                state.builder.switch_to_block(merge);
+            }
+            // Compiled separately by `compile`.
+            StmtKind::FnDecl(..) => {}
+            StmtKind::StructDecl(..) | StmtKind::EnumDecl(..) | StmtKind::TypeAlias(..) | StmtKind::Import(..) => {}
+            StmtKind::Return(expr) => {
+                let value = match expr {
+                    Some(expr) => Some(self.expr(state, expr)?),
+                    None => None,
+                };
+                match value {
+                    Some(value) if !state.is_main => {
+                        state.builder.ins().return_(&[value]);
+                    }
+                    _ => {
+                        state.builder.ins().return_(&[]);
+                    }
+                }
+                state.unreachable_block();
+            }
+            StmtKind::Break | StmtKind::Continue => {
+                let (exit, next) = *state.loops.last().expect("the checker rejects break outside a loop");
+                let target = if matches!(stmt.node, StmtKind::Break) { exit } else { next };
+                state.builder.ins().jump(target, &[]);
+                state.unreachable_block();
+            }
+            StmtKind::Block(stmts) => self.block(state, stmts)?,
+        }
+        Ok(())
+    }
+
+    fn loop_body(&mut self, state: &mut FnState, body: &[Stmt], exit: Block, next: Block) -> Result<(), CompilerError> {
+        state.loops.push((exit, next));
+        self.block(state, body)?;
+        state.loops.pop();
+        Ok(())
+    }
+
+    /// Compiles `expr`. Void calls produce a dummy value that is never used.
+    fn expr(&mut self, state: &mut FnState, expr: &Expr) -> Result<Value, CompilerError> {
+        let span = expr.span;
+        match &expr.node {
+            ExprKind::Number(n) => Ok(state.builder.ins().iconst(types::I64, *n)),
+            ExprKind::Bool(b) => Ok(state.builder.ins().iconst(types::I8, *b as i64)),
+            ExprKind::Float(_) => Err(unsupported("values of type float", span)),
+            ExprKind::Char(_) => Err(unsupported("values of type char", span)),
+            ExprKind::Str(_) => Err(unsupported("strings outside print", span)),
+            ExprKind::Variable(name) => self.read_variable(state, name, span),
+            ExprKind::Unary(UnOp::Neg, operand) => {
+                let t = self.type_of(state, operand);
+                if t != Type::Int {
+                    return Err(unsupported(&format!("'-' on {}", t), span));
+                }
+                let value = self.expr(state, operand)?;
+                let zero = state.builder.ins().iconst(types::I64, 0);
+                Ok(self.arith(state, BinOp::Sub, zero, value, span))
+            }
+            ExprKind::Unary(UnOp::BitNot, _) => Err(unsupported("'~'", span)),
+            ExprKind::Update(name, op, prefix) => {
+                let old = self.read_variable(state, name, span)?;
+                let one = state.builder.ins().iconst(types::I64, 1);
+                let op = if matches!(op, UpdateOp::Inc) { BinOp::Add } else { BinOp::Sub };
+                let new = self.arith(state, op, old, one, span);
+                self.write_variable(state, name, new, span)?;
+                Ok(if *prefix { new } else { old })
+            }
+            ExprKind::Binary(lhs, op @ (BinOp::And | BinOp::Or), rhs) => {
+                let l = self.expr(state, lhs)?;
+                let (rhs_b, merge) = (state.builder.create_block(), state.builder.create_block());
+                let result = state.builder.append_block_param(merge, types::I8);
+                // Short-circuit: the left value decides unless the right is needed.
+                if matches!(op, BinOp::And) {
+                    state.builder.ins().brif(l, rhs_b, &[], merge, &[BlockArg::Value(l)]);
+                } else {
+                    state.builder.ins().brif(l, merge, &[BlockArg::Value(l)], rhs_b, &[]);
+                }
+                state.builder.switch_to_block(rhs_b);
+                let r = self.expr(state, rhs)?;
+                state.builder.ins().jump(merge, &[BlockArg::Value(r)]);
+                state.builder.switch_to_block(merge);
+                Ok(result)
+            }
+            ExprKind::Binary(lhs, op, rhs) => {
+                let operand_type = match self.type_of(state, lhs) {
+                    Type::Error => self.type_of(state, rhs),
+                    t => t,
+                };
+                let l = self.expr(state, lhs)?;
+                let r = self.expr(state, rhs)?;
+                let cc = match op {
+                    BinOp::Eq => IntCC::Equal,
+                    BinOp::Neq => IntCC::NotEqual,
+                    BinOp::Gt => IntCC::SignedGreaterThan,
+                    BinOp::Lt => IntCC::SignedLessThan,
+                    BinOp::Ge => IntCC::SignedGreaterThanOrEqual,
+                    BinOp::Le => IntCC::SignedLessThanOrEqual,
+                    BinOp::Pow | BinOp::BitAnd | BinOp::BitOr | BinOp::BitXor | BinOp::Shl | BinOp::Shr => {
+                        return Err(unsupported(&format!("'{}' on {}", op, operand_type), span));
+                    }
+                    _ if operand_type == Type::Int => return Ok(self.arith(state, *op, l, r, span)),
+                    _ => return Err(unsupported(&format!("'{}' on {}", op, operand_type), span)),
+                };
+                match (&operand_type, op) {
+                    (Type::Int, _) | (Type::Bool, BinOp::Eq | BinOp::Neq) => Ok(state.builder.ins().icmp(cc, l, r)),
+                    _ => Err(unsupported(&format!("'{}' on {}", op, operand_type), span)),
+                }
+            }
+            ExprKind::Call(name, args) if self.functions.contains_key(name.as_str()) => self.call(state, name, args, span),
+            ExprKind::Call(name, args) => match name.as_str() {
+                "print" | "println" => {
+                    self.print(state, args, name == "println")?;
+                    Ok(state.builder.ins().iconst(types::I64, 0))
+                }
+                "read_int" => {
+                    let mut args = vec![self.slots_pointer(state)];
+                    args.extend(state.span(span));
+                    let read_int = state.runtime[4];
+                    let call = state.builder.ins().call(read_int, &args);
+                    let value = state.builder.inst_results(call)[0];
+                    self.check_failed(state);
+                    Ok(value)
+                }
+                _ => Err(unsupported(&format!("the builtin {}", name), span)),
+            },
+            ExprKind::Array(_) | ExprKind::Index(..) => Err(unsupported("arrays", span)),
+            ExprKind::Map(_) => Err(unsupported("maps", span)),
+            ExprKind::Null => Err(unsupported("null", span)),
+            ExprKind::Variant(..) => Err(unsupported("enums", span)),
+            ExprKind::StructLit(..) | ExprKind::Field(..) => Err(unsupported("structs", span)),
+            ExprKind::If(cond, then_value, else_value) => {
+                let t = self.type_of(state, expr);
+                let cond = self.expr(state, cond)?;
+                let (then_b, else_b, merge) = (state.builder.create_block(), state.builder.create_block(), state.builder.create_block());
+                let result = state.builder.append_block_param(merge, clif_type(&t, span)?);
+                state.builder.ins().brif(cond, then_b, &[], else_b, &[]);
+                state.builder.switch_to_block(then_b);
+                let value = self.expr(state, then_value)?;
+                state.builder.ins().jump(merge, &[BlockArg::Value(value)]);
+                state.builder.switch_to_block(else_b);
+                let value = self.expr(state, else_value)?;
+                state.builder.ins().jump(merge, &[BlockArg::Value(value)]);
+                state.builder.switch_to_block(merge);
+                Ok(result)
+            }
+            ExprKind::Match(value, arms) => {
+                let t = self.type_of(state, value);
+                if !matches!(t, Type::Int | Type::Bool) {
+                    return Err(unsupported(&format!("matching on {}", t), value.span));
+                }
+                let result_type = self.type_of(state, expr);
+                let value = self.expr(state, value)?;
+                let merge = state.builder.create_block();
+                let result = state.builder.append_block_param(merge, clif_type(&result_type, span)?);
+                for (i, arm) in arms.iter().enumerate() {
+                    // The checker made sure the arms cover every value, so
+                    // the last one is taken without a test.
+                    let next = match &arm.pattern {
+                        Pattern::Literal(literal) if i + 1 < arms.len() => {
+                            let pattern = self.expr(state, literal)?;
+                            let matched = state.builder.ins().icmp(IntCC::Equal, value, pattern);
+                            let (arm_b, next) = (state.builder.create_block(), state.builder.create_block());
+                            state.builder.ins().brif(matched, arm_b, &[], next, &[]);
+                            state.builder.switch_to_block(arm_b);
+                            Some(next)
+                        }
+                        _ => None,
+                    };
+                    let arm_value = self.expr(state, &arm.value)?;
+                    state.builder.ins().jump(merge, &[BlockArg::Value(arm_value)]);
+                    let Some(next) = next else {
+                        break;
+                    };
+                    state.builder.switch_to_block(next);
+                }
+                state.builder.switch_to_block(merge);
+                Ok(result)
+            }
+            ExprKind::Block(stmts, value) => {
+                state.scopes.push(Vec::new());
+                self.stmts(state, stmts)?;
+                let value = self.expr(state, value)?;
+                state.scopes.pop();
+                Ok(value)
+            }
+        }
+    }
+
+    /// Checked `+`, `-`, `*` or `/` on two ints.
+    fn arith(&self, state: &mut FnState, op: BinOp, l: Value, r: Value, span: Span) -> Value {
+        let ins = state.builder.ins();
+        let (result, overflow, failure) = match op {
+            BinOp::Add => {
+                let (result, overflow) = ins.sadd_overflow(l, r);
+                (result, overflow, ADD_OVERFLOW)
+            }
+            BinOp::Sub => {
+                let (result, overflow) = ins.ssub_overflow(l, r);
+                (result, overflow, SUB_OVERFLOW)
+            }
+            BinOp::Mul => {
+                let (result, overflow) = ins.smul_overflow(l, r);
+                (result, overflow, MUL_OVERFLOW)
+            }
+            BinOp::Div => {
+                let zero = state.builder.ins().icmp_imm_s(IntCC::Equal, r, 0);
+                self.fail_if(state, zero, DIVISION_BY_ZERO, span);
+                let min = state.builder.ins().icmp_imm_s(IntCC::Equal, l, i64::MIN);
+                let minus_one = state.builder.ins().icmp_imm_s(IntCC::Equal, r, -1);
+                let overflow = state.builder.ins().band(min, minus_one);
+                self.fail_if(state, overflow, DIV_OVERFLOW, span);
+                return state.builder.ins().sdiv(l, r);
+            }
+            _ => unreachable!("'{}' is not an arithmetic operator", op),
+        };
+        self.fail_if(state, overflow, failure, span);
+        result
+    }
+
+    fn call(&mut self, state: &mut FnState, name: &str, args: &[Expr], span: Span) -> Result<Value, CompilerError> {
+        let mut values = Vec::new();
+        for arg in args {
+            values.push(self.expr(state, arg)?);
+        }
+        let depth = self.load_slot(state, DEPTH_SLOT);
+        let too_deep = state.builder.ins().icmp_imm_s(IntCC::SignedGreaterThanOrEqual, depth, self.max_call_depth as i64);
+        self.fail_if(state, too_deep, STACK_OVERFLOW, span);
+        let deeper = state.builder.ins().iadd_imm_s(depth, 1);
+        self.store_slot(state, DEPTH_SLOT, deeper);
+
+        let callee = state.functions[name];
+        let call = state.builder.ins().call(callee, &values);
+        let result = state.builder.inst_results(call).first().copied();
+
+        let depth = self.load_slot(state, DEPTH_SLOT);
+        let shallower = state.builder.ins().iadd_imm_s(depth, -1);
+        self.store_slot(state, DEPTH_SLOT, shallower);
+        self.check_failed(state);
+        Ok(result.unwrap_or_else(|| state.builder.ins().iconst(types::I64, 0)))
+    }
+
+    /// `print`/`println`: like the interpreter, every argument is evaluated
+    /// before anything is written.
+    fn print(&mut self, state: &mut FnState, args: &[Expr], newline: bool) -> Result<(), CompilerError> {
+        let mut values = Vec::new();
+        for arg in args {
+            values.push(match (&arg.node, self.type_of(state, arg)) {
+                (ExprKind::Str(_), _) => None,
+                (_, t @ (Type::Int | Type::Bool)) => Some((self.expr(state, arg)?, t)),
+                (_, other) => return Err(unsupported(&format!("printing values of type {}", other), arg.span)),
+            });
+        }
+        for (i, (arg, value)) in args.iter().zip(values).enumerate() {
+            if i > 0 {
+                self.write_str(state, " ");
+            }
+            match (&arg.node, value) {
+                (ExprKind::Str(s), _) => self.write_str(state, s),
+                (_, Some((value, t))) => {
+                    let slots = self.slots_pointer(state);
+                    let print = if t == Type::Int { state.runtime[1] } else { state.runtime[2] };
+                    state.builder.ins().call(print, &[slots, value]);
+                }
+                (_, None) => unreachable!("only string literals have no value"),
+            }
+        }
+        if newline {
+            self.write_str(state, "\n");
+        }
+        self.check_failed(state);
+        Ok(())
+    }
+
+    fn write_str(&mut self, state: &mut FnState, s: &str) {
+        let bytes: Box<[u8]> = s.as_bytes().into();
+        let ptr_type = self.module.target_config().pointer_type();
+        let ptr = state.builder.ins().iconst(ptr_type, bytes.as_ptr() as i64);
+        let len = state.builder.ins().iconst(types::I64, bytes.len() as i64);
+        self.strings.push(bytes);
+        let slots = self.slots_pointer(state);
+        let print_str = state.runtime[3];
+        state.builder.ins().call(print_str, &[slots, ptr, len]);
+    }
+}
+
+/// Per-function compilation state.
+struct FnState<'f> {
+    builder: FunctionBuilder<'f>,
+    /// Block scopes mapping names to variables, innermost last.
+    scopes: Vec<Vec<(String, Variable, Type)>>,
+    /// Break and continue targets of the enclosing loops, innermost last.
+    loops: Vec<(Block, Block)>,
+    functions: HashMap<String, FuncRef>,
+    /// `fail`, `print_int`, `print_bool`, `print_str` and `read_int`.
+    runtime: [FuncRef; 5],
+    return_type: Type,
+    is_main: bool,
+}
+
+impl FnState<'_> {
+    fn local(&self, name: &str) -> Option<(Variable, &Type)> {
+        self.scopes.iter().rev().flat_map(|scope| scope.iter().rev()).find(|(n, ..)| n == name).map(|(_, var, t)| (*var, t))
+    }
+
+    /// `span` as constants, for passing to the runtime.
+    fn span(&mut self, span: Span) -> [Value; 5] {
+        [span.start, span.end, span.line, span.col, span.file].map(|field| self.builder.ins().iconst(types::I64, field as i64))
+    }
+
+    /// Returns from the function with a placeholder result.
+    fn bail(&mut self) {
+        if self.is_main || self.return_type == Type::Void {
+            self.builder.ins().return_(&[]);
+        } else {
+            let t = if self.return_type == Type::Bool { types::I8 } else { types::I64 };
+            let zero = self.builder.ins().iconst(t, 0);
+            self.builder.ins().return_(&[zero]);
+        }
+    }
+
+    /// Continues in a fresh block after a jump or return, so code following
+    /// it has somewhere to go. Nothing branches there.
+    fn unreachable_block(&mut self) {
+        let block = self.builder.create_block();
+        self.builder.switch_to_block(block);
+    }
+}
\ No newline at end of file
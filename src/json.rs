@@ -0,0 +1,203 @@
+use crate::ast::{self, Arena, BinOp, Field, Param, Spanned, TypeExpr, UnOp, UpdateOp};
+use crate::error::CompilerError;
+use crate::symbol::Symbol;
+use crate::Program;
+use serde::Deserialize;
+
+/// Serializes `program` as pretty-printed JSON for tools outside Rust. Each
+/// statement and expression is an object with `node` and `span`; enum
+/// variants use serde's externally tagged form, e.g. `{"Number": 1}`.
+pub fn program_to_json(program: &Program) -> String {
+    serde_json::to_string_pretty(program).expect("the AST always serializes")
+}
+
+/// Loads a program written by [`program_to_json`], allocating its nodes in
+/// `arena`. Malformed input is a syntax error located at the offending JSON
+/// line and column.
+pub fn program_from_json<'a>(json: &str, arena: &'a Arena<'a>) -> Result<Program<'a>, CompilerError> {
+    let program: Vec<Stmt> = serde_json::from_str(json).map_err(|e| {
+        let span = crate::span::Span { start: 0, end: 0, line: e.line(), col: e.column(), file: 0 };
+        CompilerError::SyntaxError(format!("Invalid AST JSON: {}", e), Some(span))
+    })?;
+    Ok(program.into_iter().map(|stmt| stmt.into_arena(arena)).collect())
+}
+
+// The AST with owned children, in the same shape as the one in `ast`, so
+// serde can read it before it is moved into an arena.
+
+type Stmt = Spanned<StmtKind>;
+type Expr = Spanned<ExprKind>;
+
+#[derive(Deserialize)]
+enum StmtKind {
+    Let(Symbol, Option<TypeExpr>, Expr),
+    Const(Symbol, Option<TypeExpr>, Expr),
+    Assign(Symbol, Expr),
+    IndexAssign(Box<Expr>, Box<Expr>, Expr),
+    FieldAssign(Box<Expr>, Symbol, Expr),
+    Expr(Expr),
+    If(Expr, Vec<Stmt>, Vec<Stmt>),
+    While(Expr, Vec<Stmt>),
+    DoWhile(Vec<Stmt>, Expr),
+    For(Box<Stmt>, Expr, Box<Stmt>, Vec<Stmt>),
+    ForRange(Symbol, Expr, Expr, bool, Vec<Stmt>),
+    Match(Expr, Vec<MatchArm>),
+    FnDecl(Symbol, Vec<Param>, Option<TypeExpr>, Vec<Stmt>),
+    StructDecl(Symbol, Vec<Field>),
+    EnumDecl(Symbol, Vec<Symbol>),
+    TypeAlias(Symbol, TypeExpr),
+    Import(String),
+    Return(Option<Expr>),
+    Break,
+    Continue,
+    Block(Vec<Stmt>),
+}
+
+#[derive(Deserialize)]
+struct MatchArm {
+    pattern: Pattern,
+    span: crate::span::Span,
+    body: Vec<Stmt>,
+}
+
+#[derive(Deserialize)]
+struct ValueArm {
+    pattern: Pattern,
+    span: crate::span::Span,
+    value: Expr,
+}
+
+#[derive(Deserialize)]
+enum Pattern {
+    Literal(Expr),
+    Wildcard,
+}
+
+#[derive(Deserialize)]
+enum ExprKind {
+    Number(i64),
+    Float(f64),
+    Str(String),
+    Char(char),
+    Bool(bool),
+    Null,
+    Variable(Symbol),
+    Unary(UnOp, Box<Expr>),
+    Update(Symbol, UpdateOp, bool),
+    Binary(Box<Expr>, BinOp, Box<Expr>),
+    Call(Symbol, Vec<Expr>),
+    Array(Vec<Expr>),
+    Map(Vec<(Expr, Expr)>),
+    Index(Box<Expr>, Box<Expr>),
+    If(Box<Expr>, Box<Expr>, Box<Expr>),
+    Block(Vec<Stmt>, Box<Expr>),
+    Match(Box<Expr>, Vec<ValueArm>),
+    StructLit(Symbol, Vec<(Symbol, Expr)>),
+    Field(Box<Expr>, Symbol),
+    Variant(Symbol, Symbol),
+}
+
+impl Stmt {
+    fn into_arena<'a>(self, arena: &'a Arena<'a>) -> ast::Stmt<'a> {
+        let block = |stmts: Vec<Stmt>| arena.stmts(stmts.into_iter().map(|stmt| stmt.into_arena(arena)).collect());
+        let node = match self.node {
+            StmtKind::Let(name, ty, value) => ast::StmtKind::Let(name, ty, value.into_arena(arena)),
+            StmtKind::Const(name, ty, value) => ast::StmtKind::Const(name, ty, value.into_arena(arena)),
+            StmtKind::Assign(name, value) => ast::StmtKind::Assign(name, value.into_arena(arena)),
+            StmtKind::IndexAssign(array, index, value) => {
+                ast::StmtKind::IndexAssign(array.alloc(arena), index.alloc(arena), value.into_arena(arena))
+            }
+            StmtKind::FieldAssign(object, field, value) => ast::StmtKind::FieldAssign(object.alloc(arena), field, value.into_arena(arena)),
+            StmtKind::Expr(expr) => ast::StmtKind::Expr(expr.into_arena(arena)),
+            StmtKind::If(cond, then_block, else_block) => ast::StmtKind::If(cond.into_arena(arena), block(then_block), block(else_block)),
+            StmtKind::While(cond, body) => ast::StmtKind::While(cond.into_arena(arena), block(body)),
+            StmtKind::DoWhile(body, cond) => ast::StmtKind::DoWhile(block(body), cond.into_arena(arena)),
+            StmtKind::For(init, cond, step, body) => ast::StmtKind::For(
+                arena.stmt(init.into_arena(arena)),
+                cond.into_arena(arena),
+                arena.stmt(step.into_arena(arena)),
+                block(body),
+            ),
+            StmtKind::ForRange(var, start, end, inclusive, body) => {
+                ast::StmtKind::ForRange(var, start.into_arena(arena), end.into_arena(arena), inclusive, block(body))
+            }
+            StmtKind::Match(value, arms) => {
+                let arms = arms
+                    .into_iter()
+                    .map(|arm| ast::MatchArm {
+                        pattern: arm.pattern.into_arena(arena),
+                        span: arm.span,
+                        body: block(arm.body),
+                    })
+                    .collect();
+                ast::StmtKind::Match(value.into_arena(arena), arms)
+            }
+            StmtKind::FnDecl(name, params, return_type, body) => ast::StmtKind::FnDecl(name, params, return_type, block(body)),
+            StmtKind::StructDecl(name, fields) => ast::StmtKind::StructDecl(name, fields),
+            StmtKind::EnumDecl(name, variants) => ast::StmtKind::EnumDecl(name, variants),
+            StmtKind::TypeAlias(name, ty) => ast::StmtKind::TypeAlias(name, ty),
+            StmtKind::Import(path) => ast::StmtKind::Import(path),
+            StmtKind::Return(value) => ast::StmtKind::Return(value.map(|value| value.into_arena(arena))),
+            StmtKind::Break => ast::StmtKind::Break,
+            StmtKind::Continue => ast::StmtKind::Continue,
+            StmtKind::Block(body) => ast::StmtKind::Block(block(body)),
+        };
+        Spanned::new(node, self.span)
+    }
+}
+
+impl Expr {
+    fn into_arena<'a>(self, arena: &'a Arena<'a>) -> ast::Expr<'a> {
+        let list = |exprs: Vec<Expr>| arena.exprs(exprs.into_iter().map(|expr| expr.into_arena(arena)).collect());
+        let node = match self.node {
+            ExprKind::Number(n) => ast::ExprKind::Number(n),
+            ExprKind::Float(n) => ast::ExprKind::Float(n),
+            ExprKind::Str(s) => ast::ExprKind::Str(s),
+            ExprKind::Char(c) => ast::ExprKind::Char(c),
+            ExprKind::Bool(b) => ast::ExprKind::Bool(b),
+            ExprKind::Null => ast::ExprKind::Null,
+            ExprKind::Variable(name) => ast::ExprKind::Variable(name),
+            ExprKind::Unary(op, operand) => ast::ExprKind::Unary(op, operand.alloc(arena)),
+            ExprKind::Update(name, op, prefix) => ast::ExprKind::Update(name, op, prefix),
+            ExprKind::Binary(lhs, op, rhs) => ast::ExprKind::Binary(lhs.alloc(arena), op, rhs.alloc(arena)),
+            ExprKind::Call(name, args) => ast::ExprKind::Call(name, list(args)),
+            ExprKind::Array(elements) => ast::ExprKind::Array(list(elements)),
+            ExprKind::Map(entries) => {
+                ast::ExprKind::Map(entries.into_iter().map(|(key, value)| (key.into_arena(arena), value.into_arena(arena))).collect())
+            }
+            ExprKind::Index(array, index) => ast::ExprKind::Index(array.alloc(arena), index.alloc(arena)),
+            ExprKind::If(cond, then_value, else_value) => {
+                ast::ExprKind::If(cond.alloc(arena), then_value.alloc(arena), else_value.alloc(arena))
+            }
+            ExprKind::Block(stmts, value) => {
+                ast::ExprKind::Block(arena.stmts(stmts.into_iter().map(|stmt| stmt.into_arena(arena)).collect()), value.alloc(arena))
+            }
+            ExprKind::Match(value, arms) => {
+                let arms = arms
+                    .into_iter()
+                    .map(|arm| ast::ValueArm { pattern: arm.pattern.into_arena(arena), span: arm.span, value: arm.value.into_arena(arena) })
+                    .collect();
+                ast::ExprKind::Match(value.alloc(arena), arms)
+            }
+            ExprKind::StructLit(name, fields) => {
+                ast::ExprKind::StructLit(name, fields.into_iter().map(|(field, value)| (field, value.into_arena(arena))).collect())
+            }
+            ExprKind::Field(object, field) => ast::ExprKind::Field(object.alloc(arena), field),
+            ExprKind::Variant(name, variant) => ast::ExprKind::Variant(name, variant),
+        };
+        Spanned::new(node, self.span)
+    }
+
+    fn alloc<'a>(self, arena: &'a Arena<'a>) -> &'a ast::Expr<'a> {
+        arena.expr(self.into_arena(arena))
+    }
+}
+
+impl Pattern {
+    fn into_arena<'a>(self, arena: &'a Arena<'a>) -> ast::Pattern<'a> {
+        match self {
+            Pattern::Literal(literal) => ast::Pattern::Literal(literal.into_arena(arena)),
+            Pattern::Wildcard => ast::Pattern::Wildcard,
+        }
+    }
+}
\ No newline at end of file
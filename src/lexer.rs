@@ -1,188 +1,748 @@
 use crate::error::CompilerError;
+use crate::span::Span;
+use crate::symbol::Symbol;
+use std::borrow::Cow;
+use unicode_normalization::{IsNormalized, UnicodeNormalization, is_nfc_quick};
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Token {
     Let,
+    Const,
     Fn,
     If,
     Else,
     While,
     Do,
     For,
+    In,
+    Match,
+    Struct,
+    Enum,
+    Type,
+    Import,
     Return,
+    Break,
+    Continue,
     True,
     False,
-    Ident(String),
+    Null,
+    Ident(Symbol),
     Number(i64),
+    Float(f64),
+    Str(String),
+    Char(char),
     Plus,
     Minus,
+    PlusPlus,
+    MinusMinus,
     Star,
+    StarStar,
     Slash,
     Equal,
     Eq,
     Neq,
     Gt,
     Lt,
+    Ge,
+    Le,
+    AndAnd,
+    OrOr,
+    Amp,
+    Pipe,
+    Caret,
+    Tilde,
+    Shl,
+    Shr,
     LParen,
     RParen,
     LBrace,
     RBrace,
+    LBracket,
+    RBracket,
     Semicolon,
     Comma,
     Colon,   // <--- Added Colon token here
+    /// `::` between an enum and one of its variants.
+    ColonColon,
+    Dot,
+    DotDot,
+    DotDotEq,
+    FatArrow,
+    /// `?` after a type, making it optional.
+    Question,
+    /// `_` on its own, the wildcard pattern of a `match` arm.
+    Underscore,
+    /// A character that starts no token, produced only by a lexer in
+    /// recovery mode (see [`Lexer::with_recovery`]).
+    Error(char),
 }
 
-pub struct Lexer {
-    input: Vec<char>,
+/// The words the lexer reads as keywords rather than identifiers.
+pub const KEYWORDS: &[&str] = &[
+    "let", "const", "fn", "if", "else", "while", "do", "for", "in", "match", "struct", "enum", "type", "import", "return",
+    "break", "continue", "true", "false", "null",
+];
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let text = match self {
+            Token::Ident(name) => return write!(f, "identifier `{}`", name),
+            Token::Number(n) => return write!(f, "number `{}`", n),
+            Token::Float(n) => return write!(f, "number `{:?}`", n),
+            Token::Str(s) => return write!(f, "string {:?}", s),
+            Token::Char(c) => return write!(f, "character {:?}", c),
+            Token::Error(c) => return write!(f, "unexpected character '{}'", c),
+            Token::Let => "let",
+            Token::Const => "const",
+            Token::Fn => "fn",
+            Token::If => "if",
+            Token::Else => "else",
+            Token::While => "while",
+            Token::Do => "do",
+            Token::For => "for",
+            Token::In => "in",
+            Token::Match => "match",
+            Token::Struct => "struct",
+            Token::Enum => "enum",
+            Token::Type => "type",
+            Token::Import => "import",
+            Token::Return => "return",
+            Token::Break => "break",
+            Token::Continue => "continue",
+            Token::True => "true",
+            Token::False => "false",
+            Token::Null => "null",
+            Token::Plus => "+",
+            Token::Minus => "-",
+            Token::PlusPlus => "++",
+            Token::MinusMinus => "--",
+            Token::Star => "*",
+            Token::StarStar => "**",
+            Token::Slash => "/",
+            Token::Equal => "=",
+            Token::Eq => "==",
+            Token::Neq => "!=",
+            Token::Gt => ">",
+            Token::Lt => "<",
+            Token::Ge => ">=",
+            Token::Le => "<=",
+            Token::AndAnd => "&&",
+            Token::OrOr => "||",
+            Token::Amp => "&",
+            Token::Pipe => "|",
+            Token::Caret => "^",
+            Token::Tilde => "~",
+            Token::Shl => "<<",
+            Token::Shr => ">>",
+            Token::LParen => "(",
+            Token::RParen => ")",
+            Token::LBrace => "{",
+            Token::RBrace => "}",
+            Token::LBracket => "[",
+            Token::RBracket => "]",
+            Token::Semicolon => ";",
+            Token::Comma => ",",
+            Token::Colon => ":",
+            Token::ColonColon => "::",
+            Token::Dot => ".",
+            Token::DotDot => "..",
+            Token::DotDotEq => "..=",
+            Token::FatArrow => "=>",
+            Token::Question => "?",
+            Token::Underscore => "_",
+        };
+        write!(f, "'{}'", text)
+    }
+}
+
+/// The broad category of a token, for syntax highlighting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenClass {
+    /// Keywords, including `true`, `false` and `null`.
+    Keyword,
+    Identifier,
+    Number,
+    String,
+    Operator,
+    /// Brackets, `;`, `,`, `:`, `::`, `.`, `=>` and `?`.
+    Punctuation,
+    /// A [`Token::Error`] from a lexer in recovery mode.
+    Error,
+}
+
+impl Token {
+    pub fn class(&self) -> TokenClass {
+        match self {
+            Token::Let
+            | Token::Const
+            | Token::Fn
+            | Token::If
+            | Token::Else
+            | Token::While
+            | Token::Do
+            | Token::For
+            | Token::In
+            | Token::Match
+            | Token::Struct
+            | Token::Enum
+            | Token::Type
+            | Token::Import
+            | Token::Return
+            | Token::Break
+            | Token::Continue
+            | Token::True
+            | Token::False
+            | Token::Null
+            | Token::Underscore => TokenClass::Keyword,
+            Token::Ident(_) => TokenClass::Identifier,
+            Token::Number(_) | Token::Float(_) => TokenClass::Number,
+            Token::Str(_) | Token::Char(_) => TokenClass::String,
+            Token::Plus
+            | Token::Minus
+            | Token::PlusPlus
+            | Token::MinusMinus
+            | Token::Star
+            | Token::StarStar
+            | Token::Slash
+            | Token::Equal
+            | Token::Eq
+            | Token::Neq
+            | Token::Gt
+            | Token::Lt
+            | Token::Ge
+            | Token::Le
+            | Token::AndAnd
+            | Token::OrOr
+            | Token::Amp
+            | Token::Pipe
+            | Token::Caret
+            | Token::Tilde
+            | Token::Shl
+            | Token::Shr
+            | Token::DotDot
+            | Token::DotDotEq => TokenClass::Operator,
+            Token::LParen
+            | Token::RParen
+            | Token::LBrace
+            | Token::RBrace
+            | Token::LBracket
+            | Token::RBracket
+            | Token::Semicolon
+            | Token::Comma
+            | Token::Colon
+            | Token::ColonColon
+            | Token::Dot
+            | Token::FatArrow
+            | Token::Question => TokenClass::Punctuation,
+            Token::Error(_) => TokenClass::Error,
+        }
+    }
+}
+
+/// A token together with the source region it was lexed from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpannedToken {
+    pub token: Token,
+    pub span: Span,
+}
+
+/// Splits source text into tokens. A lexer is an iterator that reads one
+/// token at a time straight from the `&str`, so nothing is copied up front;
+/// [`crate::Parser::from_lexer`] consumes it lazily, and `tokenize` collects
+/// it into a `Vec`. After an error the iterator ends.
+pub struct Lexer<'a> {
+    input: &'a str,
+    /// Byte offset of the next character.
     pos: usize,
+    line: usize,
+    col: usize,
+    recover: bool,
+    file: usize,
 }
 
-impl Lexer {
-    pub fn new(input: &str) -> Self {
+impl<'a> Lexer<'a> {
+    pub fn new(input: &'a str) -> Self {
         Self {
-            input: input.chars().collect(),
+            input,
             pos: 0,
+            line: 1,
+            col: 1,
+            recover: false,
+            file: 0,
         }
     }
 
-    pub fn tokenize(&mut self) -> Result<Vec<Token>, CompilerError> {
-        let mut tokens = Vec::new();
-        while let Some(&c) = self.peek() {
-            match c {
-                ' ' | '\n' | '\t' | '\r' => {
-                    self.advance();
-                }
-                '0'..='9' => tokens.push(self.tokenize_number()?),
-                'a'..='z' | 'A'..='Z' | '_' => tokens.push(self.tokenize_ident_or_keyword()?),
-                '+' => {
-                    self.advance();
-                    tokens.push(Token::Plus);
-                }
-                '-' => {
-                    self.advance();
-                    tokens.push(Token::Minus);
-                }
-                '*' => {
-                    self.advance();
-                    tokens.push(Token::Star);
-                }
-                '/' => {
-                    self.advance();
-                    tokens.push(Token::Slash);
+    /// Marks every span produced as being in file `file` of the program
+    /// (see [`Span::file`]) rather than the first.
+    pub fn with_file(mut self, file: usize) -> Self {
+        self.file = file;
+        self
+    }
+
+    /// Makes `tokenize` emit [`Token::Error`] for unexpected characters and
+    /// keep going, instead of failing on the first one. The [`crate::Parser`]
+    /// reports each of them as a syntax error and recovers.
+    pub fn with_recovery(mut self) -> Self {
+        self.recover = true;
+        self
+    }
+
+    fn unexpected(&self, msg: String, c: char, span: Span) -> Result<Token, CompilerError> {
+        if self.recover {
+            Ok(Token::Error(c))
+        } else {
+            Err(CompilerError::SyntaxError(msg, Some(span)))
+        }
+    }
+
+    /// Tokenizes the input into the class and span of each token, for
+    /// highlighting. Whitespace between tokens is not included.
+    pub fn tokenize_with_classes(&mut self) -> Result<Vec<(TokenClass, Span)>, CompilerError> {
+        let tokens = self.tokenize()?;
+        Ok(tokens.iter().map(|t| (t.token.class(), t.span)).collect())
+    }
+
+    pub fn tokenize(&mut self) -> Result<Vec<SpannedToken>, CompilerError> {
+        self.by_ref().collect()
+    }
+
+    /// Lexes the token that starts with `c`, on `line` and `col` at byte
+    /// `start`.
+    fn token(&mut self, c: char, start: usize, line: usize, col: usize) -> Result<Token, CompilerError> {
+        Ok(match c {
+            '0'..='9' => self.tokenize_number()?,
+            '"' => self.tokenize_string()?,
+            '\'' => self.tokenize_char()?,
+            'r' if self.at_raw_string() => self.tokenize_raw_string()?,
+            c if c == '_' || unicode_ident::is_xid_start(c) => self.tokenize_ident_or_keyword()?,
+            '+' => {
+                self.advance();
+                if self.match_char('+') {
+                    Token::PlusPlus
+                } else {
+                    Token::Plus
                 }
-                '=' => {
-                    self.advance();
-                    if self.match_char('=') {
-                        tokens.push(Token::Eq);
-                    } else {
-                        tokens.push(Token::Equal);
-                    }
+            }
+            '-' => {
+                self.advance();
+                if self.match_char('-') {
+                    Token::MinusMinus
+                } else {
+                    Token::Minus
                 }
-                '!' => {
-                    self.advance();
-                    if self.match_char('=') {
-                        tokens.push(Token::Neq);
-                    } else {
-                        return Err(CompilerError::SyntaxError("Unexpected character after '!'".into()));
-                    }
+            }
+            '*' => {
+                self.advance();
+                if self.match_char('*') {
+                    Token::StarStar
+                } else {
+                    Token::Star
                 }
-                '>' => {
-                    self.advance();
-                    tokens.push(Token::Gt);
+            }
+            '/' => {
+                self.advance();
+                Token::Slash
+            }
+            '=' => {
+                self.advance();
+                if self.match_char('=') {
+                    Token::Eq
+                } else if self.match_char('>') {
+                    Token::FatArrow
+                } else {
+                    Token::Equal
                 }
-                '<' => {
-                    self.advance();
-                    tokens.push(Token::Lt);
+            }
+            '!' => {
+                self.advance();
+                if self.match_char('=') {
+                    Token::Neq
+                } else {
+                    let span = Span { start, end: self.pos, line, col, file: self.file };
+                    self.unexpected("Unexpected character after '!'".into(), c, span)?
                 }
-                '(' => {
-                    self.advance();
-                    tokens.push(Token::LParen);
+            }
+            '&' => {
+                self.advance();
+                if self.match_char('&') {
+                    Token::AndAnd
+                } else {
+                    Token::Amp
                 }
-                ')' => {
-                    self.advance();
-                    tokens.push(Token::RParen);
+            }
+            '|' => {
+                self.advance();
+                if self.match_char('|') {
+                    Token::OrOr
+                } else {
+                    Token::Pipe
                 }
-                '{' => {
-                    self.advance();
-                    tokens.push(Token::LBrace);
+            }
+            '^' => {
+                self.advance();
+                Token::Caret
+            }
+            '~' => {
+                self.advance();
+                Token::Tilde
+            }
+            '>' => {
+                self.advance();
+                if self.match_char('=') {
+                    Token::Ge
+                } else if self.match_char('>') {
+                    Token::Shr
+                } else {
+                    Token::Gt
                 }
-                '}' => {
-                    self.advance();
-                    tokens.push(Token::RBrace);
+            }
+            '<' => {
+                self.advance();
+                if self.match_char('=') {
+                    Token::Le
+                } else if self.match_char('<') {
+                    Token::Shl
+                } else {
+                    Token::Lt
                 }
-                ';' => {
-                    self.advance();
-                    tokens.push(Token::Semicolon);
+            }
+            '(' => {
+                self.advance();
+                Token::LParen
+            }
+            ')' => {
+                self.advance();
+                Token::RParen
+            }
+            '{' => {
+                self.advance();
+                Token::LBrace
+            }
+            '}' => {
+                self.advance();
+                Token::RBrace
+            }
+            '[' => {
+                self.advance();
+                Token::LBracket
+            }
+            ']' => {
+                self.advance();
+                Token::RBracket
+            }
+            ';' => {
+                self.advance();
+                Token::Semicolon
+            }
+            ',' => {
+                self.advance();
+                Token::Comma
+            }
+            ':' => {                   // <--- Added this block
+                self.advance();
+                if self.match_char(':') {
+                    Token::ColonColon
+                } else {
+                    Token::Colon
                 }
-                ',' => {
-                    self.advance();
-                    tokens.push(Token::Comma);
+            }
+            '?' => {
+                self.advance();
+                Token::Question
+            }
+            '.' => {
+                self.advance();
+                if !self.match_char('.') {
+                    Token::Dot
+                } else if self.match_char('=') {
+                    Token::DotDotEq
+                } else {
+                    Token::DotDot
                 }
-                ':' => {                   // <--- Added this block
+            }
+            _ => {
+                self.advance();
+                let span = Span { start, end: self.pos, line, col, file: self.file };
+                self.unexpected(format!("Unexpected character: {}", c), c, span)?
+            }
+        })
+    }
+
+    /// Lexes a decimal int or float, or an int in hexadecimal (`0xFF`),
+    /// octal (`0o755`) or binary (`0b1010`). Digits may be separated by
+    /// underscores, as in `1_000_000`.
+    fn tokenize_number(&mut self) -> Result<Token, CompilerError> {
+        let (start, line, col) = (self.pos, self.line, self.col);
+        let (radix, base) = match self.input.as_bytes()[start..] {
+            [b'0', b'x', ..] => (16, "hexadecimal"),
+            [b'0', b'o', ..] => (8, "octal"),
+            [b'0', b'b', ..] => (2, "binary"),
+            _ => (10, "decimal"),
+        };
+        if radix != 10 {
+            self.advance();
+            self.advance();
+        }
+        // `None` once the digits so far no longer fit in an `i64`. That is
+        // only an error if no fraction follows.
+        let mut num = Some(0i64);
+        let mut digits = 0;
+        while let Some(c) = self.peek() {
+            if let Some(d) = c.to_digit(radix) {
+                num = num.and_then(|n| n.checked_mul(radix as i64)?.checked_add(d as i64));
+                digits += 1;
+            } else if c != '_' {
+                break;
+            }
+            self.advance();
+        }
+        if radix != 10 {
+            if let Some(c) = self.peek()
+                && c.is_ascii_alphanumeric()
+            {
+                let span = Span { start: self.pos, end: self.pos + 1, line: self.line, col: self.col, file: self.file };
+                return Err(CompilerError::SyntaxError(format!("Invalid digit '{}' in {} literal", c, base), Some(span)));
+            }
+            if digits == 0 {
+                let span = Span { start, end: self.pos, line, col, file: self.file };
+                return Err(CompilerError::SyntaxError(format!("Expected digits in {} literal", base), Some(span)));
+            }
+        } else {
+            // A '.' only starts a fraction when a digit follows it.
+            let fraction_follows = self.input.as_bytes().get(self.pos + 1).is_some_and(u8::is_ascii_digit);
+            if self.peek() == Some('.') && fraction_follows {
+                self.advance();
+                while self.peek().is_some_and(|c| c.is_ascii_digit() || c == '_') {
                     self.advance();
-                    tokens.push(Token::Colon);
-                }
-                _ => {
-                    return Err(CompilerError::SyntaxError(format!("Unexpected character: {}", c)));
                 }
+                let text = self.input[start..self.pos].replace('_', "");
+                return Ok(Token::Float(text.parse().expect("digits with one '.' form a valid float")));
+            }
+        }
+        match num {
+            Some(num) => Ok(Token::Number(num)),
+            None => {
+                let span = Span { start, end: self.pos, line, col, file: self.file };
+                Err(CompilerError::SyntaxError("integer literal out of range".into(), Some(span)))
             }
         }
-        Ok(tokens)
     }
 
-    fn tokenize_number(&mut self) -> Result<Token, CompilerError> {
-        let mut num = 0i64;
-        while let Some(&c) = self.peek() {
-            if let Some(d) = c.to_digit(10) {
-                num = num * 10 + d as i64;
+    fn tokenize_string(&mut self) -> Result<Token, CompilerError> {
+        let (start, line, col) = (self.pos, self.line, self.col);
+        self.advance(); // opening quote
+        let mut s = String::new();
+        // Where the text since the last escape starts; it is copied over in
+        // one piece rather than a character at a time.
+        let mut run = self.pos;
+        loop {
+            let Some(c) = self.peek() else {
+                let span = Span { start, end: self.pos, line, col, file: self.file };
+                return Err(CompilerError::SyntaxError("Unterminated string literal".into(), Some(span)));
+            };
+            if c != '"' && c != '\\' {
                 self.advance();
-            } else {
+                continue;
+            }
+            s.push_str(&self.input[run..self.pos]);
+            self.advance();
+            if c == '"' {
                 break;
             }
+            if let Some(escaped) = self.escape()? {
+                s.push(escaped);
+            }
+            run = self.pos;
         }
-        Ok(Token::Number(num))
+        Ok(Token::Str(s))
     }
 
-    fn tokenize_ident_or_keyword(&mut self) -> Result<Token, CompilerError> {
-        let mut ident = String::new();
-        while let Some(&c) = self.peek() {
-            if c.is_alphanumeric() || c == '_' {
-                ident.push(c);
+    /// Whether the `r` at the current position starts a raw string, being
+    /// followed by any number of `#` and a `"`.
+    fn at_raw_string(&self) -> bool {
+        let rest = &self.input.as_bytes()[self.pos + 1..];
+        let hashes = rest.iter().take_while(|&&b| b == b'#').count();
+        rest.get(hashes) == Some(&b'"')
+    }
+
+    /// Lexes a raw string such as `r"C:\dir"` or `r#"say "hi""#`, which
+    /// holds its text as written: backslashes are not escapes, and it may
+    /// span lines, each ending in `\n` even where the source has `\r\n`.
+    /// It ends at a `"` followed by as many `#` as the `r` was, so adding
+    /// `#`s lets the text hold `"` and `"#`.
+    fn tokenize_raw_string(&mut self) -> Result<Token, CompilerError> {
+        let (start, line, col) = (self.pos, self.line, self.col);
+        self.advance(); // r
+        let mut hashes = 0;
+        while self.match_char('#') {
+            hashes += 1;
+        }
+        self.advance(); // opening quote
+        let text_start = self.pos;
+        let closing = format!("\"{}", "#".repeat(hashes));
+        let text_end = self.input[text_start..].find(&closing).map(|len| text_start + len);
+        let end = text_end.map_or(self.input.len(), |text_end| text_end + closing.len());
+        while self.pos < end {
+            self.advance();
+        }
+        let Some(text_end) = text_end else {
+            let span = Span { start, end: self.pos, line, col, file: self.file };
+            return Err(CompilerError::SyntaxError("Unterminated raw string literal".into(), Some(span)));
+        };
+        Ok(Token::Str(self.input[text_start..text_end].replace("\r\n", "\n")))
+    }
+
+    /// Lexes a character literal such as `'a'` or `'\n'`, which holds
+    /// exactly one character.
+    fn tokenize_char(&mut self) -> Result<Token, CompilerError> {
+        let (start, line, col) = (self.pos, self.line, self.col);
+        self.advance(); // opening quote
+        let c = match self.peek() {
+            Some('\\') => {
                 self.advance();
-            } else {
-                break;
+                self.escape()?
+            }
+            Some('\'') => {
+                self.advance();
+                let span = Span { start, end: self.pos, line, col, file: self.file };
+                return Err(CompilerError::SyntaxError("Empty character literal".into(), Some(span)));
+            }
+            Some('\n') | None => None,
+            Some(c) => {
+                self.advance();
+                Some(c)
+            }
+        };
+        if let Some(c) = c
+            && self.match_char('\'')
+        {
+            return Ok(Token::Char(c));
+        }
+        // A closing quote later on the line means more than one character
+        // was written; otherwise the literal was never closed.
+        let rest = &self.input[self.pos..];
+        let line_end = rest.find('\n').unwrap_or(rest.len());
+        let message = match rest[..line_end].find('\'') {
+            Some(quote) => {
+                let end = self.pos + quote + 1;
+                while self.pos < end {
+                    self.advance();
+                }
+                "Character literal must hold exactly one character"
             }
+            None => "Unterminated character literal",
+        };
+        let span = Span { start, end: self.pos, line, col, file: self.file };
+        Err(CompilerError::SyntaxError(message.into(), Some(span)))
+    }
+
+    /// Reads the escape sequence after a `\`, which has been consumed, as
+    /// the character it stands for; `None` at the end of the input.
+    fn escape(&mut self) -> Result<Option<char>, CompilerError> {
+        let span = Span { start: self.pos - 1, end: self.pos + 1, line: self.line, col: self.col - 1, file: self.file };
+        let escaped = match self.peek() {
+            Some('n') => '\n',
+            Some('t') => '\t',
+            Some('r') => '\r',
+            Some('0') => '\0',
+            Some('\\') => '\\',
+            Some('"') => '"',
+            Some('\'') => '\'',
+            Some(other) => {
+                return Err(CompilerError::SyntaxError(format!("Unknown escape sequence: \\{}", other), Some(span)));
+            }
+            None => return Ok(None),
+        };
+        self.advance();
+        Ok(Some(escaped))
+    }
+
+    /// Identifiers follow Unicode's default syntax (UAX #31): a letter or
+    /// `_`, then letters, digits, combining marks and `_`, in any script.
+    /// They are put in Normalization Form C, so a name typed with a
+    /// precomposed `é` and one typed with `e` and a combining accent are the
+    /// same name. Keywords are ASCII.
+    fn tokenize_ident_or_keyword(&mut self) -> Result<Token, CompilerError> {
+        let start = self.pos;
+        while self.peek().is_some_and(unicode_ident::is_xid_continue) {
+            self.advance();
         }
-        Ok(match ident.as_str() {
+        let ident = &self.input[start..self.pos];
+        let ident: Cow<str> = match is_nfc_quick(ident.chars()) {
+            IsNormalized::Yes => ident.into(),
+            _ => ident.nfc().collect::<String>().into(),
+        };
+        Ok(match &*ident {
             "let" => Token::Let,
+            "const" => Token::Const,
             "fn" => Token::Fn,
             "if" => Token::If,
             "else" => Token::Else,
             "while" => Token::While,
             "do" => Token::Do,
             "for" => Token::For,
+            "in" => Token::In,
+            "match" => Token::Match,
+            "struct" => Token::Struct,
+            "enum" => Token::Enum,
+            "type" => Token::Type,
+            "import" => Token::Import,
             "return" => Token::Return,
+            "break" => Token::Break,
+            "continue" => Token::Continue,
             "true" => Token::True,
             "false" => Token::False,
-            _ => Token::Ident(ident),
+            "null" => Token::Null,
+            "_" => Token::Underscore,
+            _ => Token::Ident(Symbol::intern(&ident)),
         })
     }
 
-    fn peek(&self) -> Option<&char> {
-        self.input.get(self.pos)
+    fn peek(&self) -> Option<char> {
+        // Most source is ASCII, which needs no UTF-8 decoding.
+        match self.input.as_bytes().get(self.pos) {
+            Some(&b) if b.is_ascii() => Some(b as char),
+            _ => self.input[self.pos..].chars().next(),
+        }
     }
 
     fn advance(&mut self) {
-        self.pos += 1;
+        if let Some(c) = self.peek() {
+            self.pos += c.len_utf8();
+            if c == '\n' {
+                self.line += 1;
+                self.col = 1;
+            } else {
+                self.col += 1;
+            }
+        }
     }
 
     fn match_char(&mut self, expected: char) -> bool {
-        if let Some(&c) = self.peek() {
-            if c == expected {
-                self.advance();
-                return true;
-            }
+        if let Some(c) = self.peek()
+            && c == expected
+        {
+            self.advance();
+            return true;
         }
         false
     }
 }
+
+
+impl Iterator for Lexer<'_> {
+    type Item = Result<SpannedToken, CompilerError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.peek().is_some_and(|c| matches!(c, ' ' | '\n' | '\t' | '\r')) {
+            self.advance();
+        }
+        let c = self.peek()?;
+        let (start, line, col) = (self.pos, self.line, self.col);
+        let result = self.token(c, start, line, col);
+        if result.is_err() {
+            self.pos = self.input.len();
+        }
+        Some(result.map(|token| SpannedToken { token, span: Span { start, end: self.pos, line, col, file: self.file } }))
+    }
+}
\ No newline at end of file
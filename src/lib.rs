@@ -0,0 +1,82 @@
+//! An embeddable compiler and interpreter for a small imperative language.
+//!
+//! The pipeline is exposed stage by stage: [`Lexer`] turns source text into
+//! tokens, [`Parser`] builds a [`Program`], [`TypeChecker`] validates it and
+//! [`Interpreter`] runs it. [`compile_str`] and [`run_str`] chain the stages
+//! for the common cases. The program's nodes live in an [`Arena`], which
+//! has to outlast everything that uses them.
+//!
+//! ```
+//! let arena = ferrum::Arena::new();
+//! let program = ferrum::compile_str("fn add(a, b) { return a + b; } add(1, 2);", &arena).unwrap();
+//! let result = ferrum::Interpreter::new().interpret(&program).unwrap();
+//! assert_eq!(result, Some(ferrum::Value::Int(3)));
+//! ```
+
+pub mod analysis;
+pub mod ast;
+pub mod bcode;
+pub mod builtins;
+pub mod bytecode;
+pub mod cache;
+pub mod codegen_c;
+pub mod codegen_wasm;
+pub mod coverage;
+pub mod debugger;
+pub mod diagnostics;
+pub mod diff;
+pub mod environment;
+pub mod error;
+pub mod inference;
+pub mod interpreter;
+pub mod ir;
+#[cfg(feature = "jit")]
+pub mod jit;
+#[cfg(feature = "serde")]
+pub mod json;
+pub mod lexer;
+#[cfg(feature = "lsp")]
+pub mod lsp;
+pub mod ops;
+pub mod parser;
+pub mod prelude;
+pub mod printer;
+pub mod profiler;
+pub mod source_map;
+pub mod span;
+pub mod symbol;
+pub mod trace;
+pub mod type_checker;
+pub mod value;
+pub mod vm;
+
+pub use ast::Arena;
+pub use error::{CompilerError, Diagnostic, DiagnosticBag, Severity};
+pub use interpreter::{Hook, Interpreter, InterpreterOptions};
+pub use ops::OverflowMode;
+pub use lexer::{Lexer, SpannedToken, Token, TokenClass};
+pub use parser::Parser;
+pub use source_map::{SourceFile, SourceMap};
+pub use span::Span;
+pub use symbol::Symbol;
+pub use type_checker::{Type, TypeChecker};
+pub use value::Value;
+pub use vm::Vm;
+
+/// A parsed program: its top-level statements in source order.
+pub type Program<'a> = Vec<ast::Stmt<'a>>;
+
+/// Lexes and parses `source` into a [`Program`] whose nodes are allocated
+/// in `arena`. Only the first syntax error is returned;
+/// [`Parser::parse_program`] reports all of them.
+pub fn compile_str<'a>(source: &str, arena: &'a Arena<'a>) -> Result<Program<'a>, CompilerError> {
+    Parser::from_lexer(Lexer::new(source), arena).parse_program().map_err(|mut errors| errors.swap_remove(0))
+}
+
+/// Compiles `source` and runs it with a fresh [`Interpreter`], returning
+/// the program's result.
+pub fn run_str(source: &str) -> Result<Option<Value>, CompilerError> {
+    let arena = Arena::new();
+    let program = compile_str(source, &arena)?;
+    Interpreter::new().interpret(&program)
+}
\ No newline at end of file
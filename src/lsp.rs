@@ -0,0 +1,588 @@
+//! A language server speaking the Language Server Protocol over stdio, so
+//! editors such as VS Code show diagnostics as you type, jump to the
+//! declaration of a variable or function, and show its type on hover.
+//!
+//! Documents are synchronized in full on every change and analyzed from
+//! scratch the way the command line does it: lexing with recovery, parsing,
+//! the analysis pass and the type checker.
+
+use crate::analysis;
+use crate::ast::*;
+use crate::error::{Diagnostic, Severity};
+use crate::lexer::{Lexer, SpannedToken, Token, TokenClass};
+use crate::parser::Parser;
+use crate::prelude;
+use crate::span::Span;
+use crate::type_checker::TypeChecker;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+
+/// JSON-RPC error codes.
+const PARSE_ERROR: i64 = -32700;
+const METHOD_NOT_FOUND: i64 = -32601;
+
+/// The semantic token types offered, in the order their indices refer to.
+/// Punctuation is left to the editor's own grammar.
+const TOKEN_TYPES: [&str; 5] = ["keyword", "variable", "number", "string", "operator"];
+
+/// Serves the client on the other end of `input` and `output` until it sends
+/// `exit`. Returns the exit status the protocol asks for: 0 if `shutdown`
+/// came first, otherwise 1.
+pub fn serve(mut input: impl BufRead, mut output: impl Write) -> io::Result<i32> {
+    let mut server = Server::default();
+    while let Some(message) = read_message(&mut input)? {
+        let Ok(message) = message else {
+            let error = json!({ "code": PARSE_ERROR, "message": "invalid JSON" });
+            write_message(&mut output, &json!({ "jsonrpc": "2.0", "id": null, "error": error }))?;
+            continue;
+        };
+        let method = message["method"].as_str().unwrap_or("");
+        if method == "exit" {
+            return Ok(if server.shut_down { 0 } else { 1 });
+        }
+        let params = &message["params"];
+        match message.get("id") {
+            Some(id) => {
+                let response = match server.request(method, params) {
+                    Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+                    Err((code, msg)) => json!({ "jsonrpc": "2.0", "id": id, "error": { "code": code, "message": msg } }),
+                };
+                write_message(&mut output, &response)?;
+            }
+            None => {
+                if let Some(notification) = server.notify(method, params) {
+                    write_message(&mut output, &notification)?;
+                }
+            }
+        }
+    }
+    Ok(1)
+}
+
+/// Reads one `Content-Length`-framed message. Returns `None` at end of input.
+fn read_message(input: &mut impl BufRead) -> io::Result<Option<serde_json::Result<Value>>> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            if length.is_some() {
+                break;
+            }
+            continue;
+        }
+        if let Some((name, value)) = line.split_once(':')
+            && name.eq_ignore_ascii_case("Content-Length")
+        {
+            length = value.trim().parse::<usize>().ok();
+        }
+    }
+    let mut body = vec![0; length.unwrap_or(0)];
+    input.read_exact(&mut body)?;
+    Ok(Some(serde_json::from_slice(&body)))
+}
+
+fn write_message(output: &mut impl Write, message: &Value) -> io::Result<()> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    output.flush()
+}
+
+#[derive(Default)]
+struct Server {
+    documents: HashMap<String, Document>,
+    shut_down: bool,
+}
+
+impl Server {
+    fn request(&mut self, method: &str, params: &Value) -> Result<Value, (i64, String)> {
+        match method {
+            "initialize" => Ok(json!({
+                "capabilities": {
+                    "textDocumentSync": 1,
+                    "definitionProvider": true,
+                    "hoverProvider": true,
+                    "semanticTokensProvider": {
+                        "legend": { "tokenTypes": TOKEN_TYPES, "tokenModifiers": [] },
+                        "full": true,
+                    },
+                },
+                "serverInfo": { "name": "ferrum", "version": env!("CARGO_PKG_VERSION") },
+            })),
+            "shutdown" => {
+                self.shut_down = true;
+                Ok(Value::Null)
+            }
+            "textDocument/definition" => Ok(self.definition(params).unwrap_or(Value::Null)),
+            "textDocument/hover" => Ok(self.hover(params).unwrap_or(Value::Null)),
+            "textDocument/semanticTokens/full" => Ok(self.semantic_tokens(params).unwrap_or(Value::Null)),
+            _ => Err((METHOD_NOT_FOUND, format!("unsupported method: {}", method))),
+        }
+    }
+
+    /// Handles a notification, returning the diagnostics to publish if a
+    /// document changed. Unknown notifications are ignored.
+    fn notify(&mut self, method: &str, params: &Value) -> Option<Value> {
+        let uri = params["textDocument"]["uri"].as_str()?.to_string();
+        let text = match method {
+            "textDocument/didOpen" => params["textDocument"]["text"].as_str()?,
+            // Only full synchronization is offered, so the last change holds the whole text.
+            "textDocument/didChange" => params["contentChanges"].as_array()?.last()?["text"].as_str()?,
+            "textDocument/didClose" => {
+                self.documents.remove(&uri);
+                return Some(publish_diagnostics(&uri, Vec::new()));
+            }
+            _ => return None,
+        };
+        let document = Document::analyze(text.to_string());
+        let diagnostics = document.diagnostics.iter().map(|d| lsp_diagnostic(&document.text, d)).collect();
+        self.documents.insert(uri.clone(), document);
+        Some(publish_diagnostics(&uri, diagnostics))
+    }
+
+    fn definition(&self, params: &Value) -> Option<Value> {
+        let uri = params["textDocument"]["uri"].as_str()?;
+        let document = self.documents.get(uri)?;
+        let (_, symbol) = document.symbol_at(&params["position"])?;
+        Some(json!({ "uri": uri, "range": range(&document.text, symbol.span) }))
+    }
+
+    fn hover(&self, params: &Value) -> Option<Value> {
+        let document = self.documents.get(params["textDocument"]["uri"].as_str()?)?;
+        let (span, symbol) = document.symbol_at(&params["position"])?;
+        Some(json!({
+            "contents": { "kind": "markdown", "value": format!("```ferrum\n{}\n```", symbol.detail) },
+            "range": range(&document.text, span),
+        }))
+    }
+
+    /// Encodes each token as the protocol asks: five numbers giving the line
+    /// and start relative to the previous token, the length, the type and no
+    /// modifiers.
+    fn semantic_tokens(&self, params: &Value) -> Option<Value> {
+        let document = self.documents.get(params["textDocument"]["uri"].as_str()?)?;
+        let mut data = Vec::new();
+        let (mut last_line, mut last_start) = (0, 0);
+        for &(class, span) in &document.classes {
+            let token_type = match class {
+                TokenClass::Keyword => 0,
+                TokenClass::Identifier => 1,
+                TokenClass::Number => 2,
+                TokenClass::String => 3,
+                TokenClass::Operator => 4,
+                TokenClass::Punctuation | TokenClass::Error => continue,
+            };
+            let (line, start) = line_col(&document.text, span.start);
+            let (end_line, end) = line_col(&document.text, span.end);
+            // Multi-line tokens are not supported by every client.
+            if end_line != line {
+                continue;
+            }
+            let delta_start = if line == last_line { start - last_start } else { start };
+            data.extend([line - last_line, delta_start, end - start, token_type, 0]);
+            (last_line, last_start) = (line, start);
+        }
+        Some(json!({ "data": data }))
+    }
+}
+
+fn publish_diagnostics(uri: &str, diagnostics: Vec<Value>) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/publishDiagnostics",
+        "params": { "uri": uri, "diagnostics": diagnostics },
+    })
+}
+
+fn lsp_diagnostic(text: &str, diagnostic: &Diagnostic) -> Value {
+    let mut message = diagnostic.message.clone();
+    for note in &diagnostic.notes {
+        message.push_str("\nnote: ");
+        message.push_str(note);
+    }
+    let severity = match diagnostic.severity {
+        Severity::Error => 1,
+        Severity::Warning => 2,
+    };
+    json!({
+        "range": range(text, diagnostic.span.unwrap_or_default()),
+        "severity": severity,
+        "code": diagnostic.code,
+        "source": "ferrum",
+        "message": message,
+    })
+}
+
+fn range(text: &str, span: Span) -> Value {
+    json!({ "start": position(text, span.start), "end": position(text, span.end) })
+}
+
+fn position(text: &str, offset: usize) -> Value {
+    let (line, character) = line_col(text, offset);
+    json!({ "line": line, "character": character })
+}
+
+/// The zero-based line of byte `offset` and its column counted in UTF-16
+/// code units, as LSP positions give them.
+fn line_col(text: &str, offset: usize) -> (usize, usize) {
+    let before = text.get(..offset).unwrap_or(text);
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    let character = before[line_start..].chars().map(char::len_utf16).sum();
+    (before.matches('\n').count(), character)
+}
+
+/// The byte offset of an LSP position, clamped to the end of its line.
+fn offset(text: &str, position: &Value) -> Option<usize> {
+    let line = usize::try_from(position["line"].as_u64()?).ok()?;
+    let character = usize::try_from(position["character"].as_u64()?).ok()?;
+    let line_start = match line {
+        0 => 0,
+        _ => text.match_indices('\n').nth(line - 1)?.0 + 1,
+    };
+    let mut units = 0;
+    for (i, c) in text[line_start..].char_indices() {
+        if units >= character || c == '\n' {
+            return Some(line_start + i);
+        }
+        units += c.len_utf16();
+    }
+    Some(text.len())
+}
+
+/// An open document and what analyzing it found.
+struct Document {
+    text: String,
+    classes: Vec<(TokenClass, Span)>,
+    diagnostics: Vec<Diagnostic>,
+    index: Index,
+}
+
+impl Document {
+    fn analyze(text: String) -> Document {
+        let mut diagnostics = Vec::new();
+        let mut index = Index::default();
+        let mut classes = Vec::new();
+        match Lexer::new(&text).with_recovery().tokenize() {
+            Err(e) => diagnostics.push((&e).into()),
+            Ok(tokens) => {
+                classes = tokens.iter().map(|t| (t.token.class(), t.span)).collect();
+                let arena = Arena::new();
+                match Parser::new(tokens.clone(), &arena).parse_program() {
+                    Err(errors) => diagnostics.extend(errors.iter().map(Diagnostic::from)),
+                    Ok(program) => {
+                        diagnostics.extend(analysis::check_program(&program));
+                        let mut checker = TypeChecker::new();
+                        // The prelude's spans are in a file of their own, so
+                        // none of its types are mistaken for the document's.
+                        let _ = checker.check_input(&prelude::program(1, &arena, &program));
+                        if let Err(errors) = checker.check_program(&program) {
+                            diagnostics.extend(errors.iter().map(Diagnostic::from));
+                        }
+                        index = Index::build(&program, &tokens, &checker);
+                    }
+                }
+            }
+        }
+        Document { text, classes, diagnostics, index }
+    }
+
+    /// The identifier at `position` and the symbol it names.
+    fn symbol_at(&self, position: &Value) -> Option<(Span, &Symbol)> {
+        let offset = offset(&self.text, position)?;
+        self.index
+            .occurrences
+            .iter()
+            .find(|(span, _)| span.start <= offset && offset <= span.end)
+            .map(|&(span, symbol)| (span, &self.index.symbols[symbol]))
+    }
+}
+
+/// A declared variable, parameter or function.
+struct Symbol {
+    /// The name in the declaration.
+    span: Span,
+    /// What hovering shows, such as `let total: int`.
+    detail: String,
+}
+
+/// Which symbol each identifier in a program names.
+#[derive(Default)]
+struct Index {
+    symbols: Vec<Symbol>,
+    /// Every identifier naming a symbol, declarations included, with the
+    /// symbol's position in `symbols`.
+    occurrences: Vec<(Span, usize)>,
+}
+
+impl Index {
+    fn build(program: &[Stmt], tokens: &[SpannedToken], checker: &TypeChecker) -> Index {
+        let mut resolver = Resolver {
+            tokens,
+            checker,
+            index: Index::default(),
+            names: Vec::new(),
+            scopes: vec![Vec::new()],
+            globals: Vec::new(),
+            functions: HashMap::new(),
+            free: Vec::new(),
+            in_function: false,
+        };
+        for stmt in program {
+            if let StmtKind::FnDecl(name, params, ..) = &stmt.node {
+                resolver.declare_function(name, params, stmt.span);
+            }
+        }
+        resolver.stmts(program);
+        // Functions may run once every global exists, so their free names
+        // refer to globals declared anywhere at top level.
+        for (span, name) in std::mem::take(&mut resolver.free) {
+            if let Some(&symbol) = resolver.globals.iter().find(|&&i| resolver.names[i] == name) {
+                resolver.index.occurrences.push((span, symbol));
+            }
+        }
+        resolver.index
+    }
+}
+
+/// Walks a program with the scoping rules of the type checker, recording
+/// the declaration behind each identifier.
+struct Resolver<'a> {
+    tokens: &'a [SpannedToken],
+    checker: &'a TypeChecker,
+    index: Index,
+    /// The name of each symbol, parallel to `index.symbols`.
+    names: Vec<String>,
+    /// Block scopes of the body being walked, innermost last.
+    scopes: Vec<Vec<usize>>,
+    globals: Vec<usize>,
+    functions: HashMap<String, usize>,
+    /// Names read inside functions without a local declaration.
+    free: Vec<(Span, String)>,
+    in_function: bool,
+}
+
+impl Resolver<'_> {
+    /// The span of the first identifier `name` at or after byte `from`.
+    /// Statements only record their own span, so this locates the names
+    /// within them.
+    fn ident_after(&self, name: &str, from: usize) -> Option<Span> {
+        let first = self.tokens.partition_point(|t| t.span.start < from);
+        self.tokens[first..]
+            .iter()
+            .find(|t| matches!(&t.token, Token::Ident(ident) if ident == name))
+            .map(|t| t.span)
+    }
+
+    fn add_symbol(&mut self, name: &str, span: Span, detail: String) -> usize {
+        self.index.symbols.push(Symbol { span, detail });
+        self.names.push(name.to_string());
+        let symbol = self.index.symbols.len() - 1;
+        self.index.occurrences.push((span, symbol));
+        symbol
+    }
+
+    fn declare_function(&mut self, name: &str, params: &[Param], stmt_span: Span) {
+        if self.functions.contains_key(name) {
+            return;
+        }
+        let span = self.ident_after(name, stmt_span.start).unwrap_or(stmt_span);
+        let detail = match self.checker.function_type(name) {
+            Some((types, result)) => {
+                let params: Vec<String> = params.iter().zip(types).map(|(param, t)| format!("{}: {}", param.name, t)).collect();
+                format!("fn {}({}) -> {}", name, params.join(", "), result)
+            }
+            None => format!("fn {}", name),
+        };
+        let symbol = self.add_symbol(name, span, detail);
+        self.functions.insert(name.to_string(), symbol);
+    }
+
+    /// Declares a variable in the innermost scope; `from` is where to look
+    /// for its name.
+    fn declare(&mut self, name: &str, from: Span, detail: String) -> Span {
+        let span = self.ident_after(name, from.start).unwrap_or(from);
+        let symbol = self.add_symbol(name, span, detail);
+        if !self.in_function && self.scopes.len() == 1 {
+            self.globals.push(symbol);
+        }
+        self.scopes.last_mut().expect("a body always has a scope").push(symbol);
+        span
+    }
+
+    fn reference(&mut self, name: &str, span: Span) {
+        let found = self.scopes.iter().rev().flat_map(|scope| scope.iter().rev()).find(|&&i| self.names[i] == name);
+        match found {
+            Some(&symbol) => self.index.occurrences.push((span, symbol)),
+            None if self.in_function => self.free.push((span, name.to_string())),
+            None => {}
+        }
+    }
+
+    /// A reference whose exact span is not recorded, found from `from` on.
+    fn reference_after(&mut self, name: &str, from: usize) {
+        if let Some(span) = self.ident_after(name, from) {
+            self.reference(name, span);
+        }
+    }
+
+    fn block(&mut self, stmts: &[Stmt]) {
+        self.scopes.push(Vec::new());
+        self.stmts(stmts);
+        self.scopes.pop();
+    }
+
+    fn stmts(&mut self, stmts: &[Stmt]) {
+        for stmt in stmts {
+            self.stmt(stmt);
+        }
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) {
+        match &stmt.node {
+            StmtKind::Let(name, ty, expr) | StmtKind::Const(name, ty, expr) => {
+                self.expr(expr);
+                let ty = match ty {
+                    Some(ty) => ty.to_string(),
+                    None => self.checker.expr_type(expr.span).map_or("{unknown}".to_string(), ToString::to_string),
+                };
+                let keyword = if matches!(stmt.node, StmtKind::Const(..)) { "const" } else { "let" };
+                self.declare(name, stmt.span, format!("{} {}: {}", keyword, name, ty));
+            }
+            StmtKind::Assign(name, expr) => {
+                self.reference_after(name, stmt.span.start);
+                self.expr(expr);
+            }
+            StmtKind::IndexAssign(array, index, value) => {
+                self.expr(array);
+                self.expr(index);
+                self.expr(value);
+            }
+            StmtKind::FieldAssign(object, _, value) => {
+                self.expr(object);
+                self.expr(value);
+            }
+            StmtKind::Expr(expr) => self.expr(expr),
+            StmtKind::If(cond, then_block, else_block) => {
+                self.expr(cond);
+                self.block(then_block);
+                self.block(else_block);
+            }
+            StmtKind::While(cond, body) => {
+                self.expr(cond);
+                self.block(body);
+            }
+            StmtKind::DoWhile(body, cond) => {
+                self.block(body);
+                self.expr(cond);
+            }
+            StmtKind::Match(value, arms) => {
+                self.expr(value);
+                for arm in arms {
+                    self.block(arm.body);
+                }
+            }
+            StmtKind::For(init, cond, step, body) => {
+                self.scopes.push(Vec::new());
+                self.stmt(init);
+                self.expr(cond);
+                self.block(body);
+                self.stmt(step);
+                self.scopes.pop();
+            }
+            StmtKind::ForRange(var, start, end, _, body) => {
+                self.expr(start);
+                self.expr(end);
+                self.scopes.push(Vec::new());
+                self.declare(var, stmt.span, format!("let {}: int", var));
+                self.block(body);
+                self.scopes.pop();
+            }
+            StmtKind::FnDecl(name, params, _, body) => {
+                self.declare_function(name, params, stmt.span);
+                let symbol = self.functions[name.as_str()];
+                let mut after = self.index.symbols[symbol].span;
+                // A function sees its parameters and the globals, not the
+                // locals around its declaration.
+                let outer_scopes = std::mem::replace(&mut self.scopes, vec![Vec::new()]);
+                let outer_in_function = std::mem::replace(&mut self.in_function, true);
+                let param_types = self.checker.function_type(name).map(|(types, _)| types.clone()).unwrap_or_default();
+                for (i, param) in params.iter().enumerate() {
+                    let ty = param_types.get(i).map_or("{unknown}".to_string(), ToString::to_string);
+                    after = self.declare(&param.name, Span { start: after.end, ..after }, format!("{}: {}", param.name, ty));
+                }
+                self.stmts(body);
+                self.scopes = outer_scopes;
+                self.in_function = outer_in_function;
+            }
+            StmtKind::Return(expr) => {
+                if let Some(expr) = expr {
+                    self.expr(expr);
+                }
+            }
+            StmtKind::StructDecl(..) | StmtKind::EnumDecl(..) | StmtKind::TypeAlias(..) | StmtKind::Import(..) | StmtKind::Break | StmtKind::Continue => {}
+            StmtKind::Block(stmts) => self.block(stmts),
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr) {
+        match &expr.node {
+            ExprKind::Number(_) | ExprKind::Float(_) | ExprKind::Str(_) | ExprKind::Char(_) | ExprKind::Bool(_) | ExprKind::Null | ExprKind::Variant(..) => {}
+            ExprKind::Variable(name) => self.reference(name, expr.span),
+            ExprKind::Update(name, ..) => self.reference_after(name, expr.span.start),
+            ExprKind::Unary(_, operand) | ExprKind::Field(operand, _) => self.expr(operand),
+            ExprKind::Binary(lhs, _, rhs) => {
+                self.expr(lhs);
+                self.expr(rhs);
+            }
+            ExprKind::StructLit(_, fields) => {
+                for (_, value) in fields {
+                    self.expr(value);
+                }
+            }
+            ExprKind::Call(name, args) => {
+                if let (Some(&symbol), Some(span)) = (self.functions.get(name.as_str()), self.ident_after(name, expr.span.start)) {
+                    self.index.occurrences.push((span, symbol));
+                }
+                for arg in args.iter() {
+                    self.expr(arg);
+                }
+            }
+            ExprKind::Array(elements) => {
+                for element in elements.iter() {
+                    self.expr(element);
+                }
+            }
+            ExprKind::Map(entries) => {
+                for (key, value) in entries {
+                    self.expr(key);
+                    self.expr(value);
+                }
+            }
+            ExprKind::Index(array, index) => {
+                self.expr(array);
+                self.expr(index);
+            }
+            ExprKind::If(cond, then_value, else_value) => {
+                self.expr(cond);
+                self.expr(then_value);
+                self.expr(else_value);
+            }
+            ExprKind::Block(stmts, value) => {
+                self.scopes.push(Vec::new());
+                self.stmts(stmts);
+                self.expr(value);
+                self.scopes.pop();
+            }
+            ExprKind::Match(value, arms) => {
+                self.expr(value);
+                for arm in arms {
+                    self.expr(&arm.value);
+                }
+            }
+        }
+    }
+}
\ No newline at end of file
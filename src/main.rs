@@ -1,70 +1,636 @@
-mod lexer;
-mod parser;
-mod ast;
-mod error;
+mod cli;
+mod repl;
 
-use lexer::Lexer;
-use parser::Parser;
+use cli::{Command, CoverageReport, Emit, ErrorFormat, TraceTarget};
+use std::path::{Path, PathBuf};
+use ferrum::trace::{self, PhaseLog, Tracer};
+use ferrum::cache::CompileCache;
+use ferrum::coverage::Coverage;
+use ferrum::debugger::Debugger;
+use ferrum::profiler::Profiler;
+use ferrum::{analysis, ast, bcode, bytecode, codegen_c, codegen_wasm, diagnostics, diff, ir, prelude, printer, Arena, CompilerError, Diagnostic, DiagnosticBag, Interpreter, Lexer, Parser, SourceMap, TypeChecker, Value, Vm};
 
 fn main() {
-    let source_code = r#"
-        let x = 10 ;
-        let y = 0 ;
-        
-        if (x > 5) {
-            y = 1 ;
-        } else {
-            y = 2 ;
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let command = match cli::parse_args(&args) {
+        Ok(command) => command,
+        Err(msg) => {
+            eprintln!("error: {}\n\n{}", msg, cli::USAGE);
+            std::process::exit(1);
         }
+    };
+
+    let status = match command {
+        Command::Run(options) => run(&options),
+        Command::Repl(prelude) => on_large_stack(|| repl::run(prelude)),
+        Command::Compile { options, output } => run_compile(&options, output.as_deref()),
+        Command::RunBytecode(path) => run_bytecode(&path),
+        Command::Debug(options) => run_debug(&options),
+        Command::Test(options) => run_test(&options),
+        Command::Diff(old, new) => run_diff(&old, &new),
+        Command::Fmt { files, write } => run_fmt(&files, write),
+        #[cfg(feature = "lsp")]
+        Command::Lsp => run_lsp(),
+    };
+    std::process::exit(status);
+}
+
+/// Compiles and runs the input files as one program, returning the process
+/// exit status: 0 on success, 1 if a file cannot be read, otherwise the
+/// status for the kind of error (see `CompilerError::exit_code`).
+fn run(options: &cli::Options) -> i32 {
+    let log = PhaseLog::new(options.verbose);
+    let arena = Arena::new();
+    let (program, sources, checker) = match load_program(options, &log, &arena) {
+        Ok(loaded) => loaded,
+        Err(status) => return status,
+    };
 
-        while (y < 5) {
-            y = y + 1 ;
+    if options.emit == Emit::Ir {
+        let _phase = trace::enter_phase("lower");
+        match ir::lower(&program) {
+            Ok(lowered) => print!("{}", lowered),
+            Err(e) => return report_in_program(&e, &sources, options.error_format),
         }
+    }
+
+    if let Some(checker) = checker.as_ref().filter(|_| options.emit == Emit::C || options.wasm) {
+        let _phase = trace::enter_phase("codegen");
+        let generated = if options.wasm {
+            codegen_wasm::generate(&program, checker)
+        } else {
+            codegen_c::generate(&program, checker)
+        };
+        return match generated {
+            Ok(c) => {
+                print!("{}", c);
+                0
+            }
+            Err(e) => report_in_program(&e, &sources, options.error_format),
+        };
+    }
 
-        do {
-            y = y - 1 ;
-        } while (y > 0) ;
+    if options.vm {
+        return run_vm(&program, &sources, options, &log);
+    }
 
-        fn add(a, b) {
-            return a + b ;
+    #[cfg(feature = "jit")]
+    if let Some(checker) = checker.filter(|_| options.jit) {
+        let _phase = trace::enter_phase("jit");
+        if let Err(e) = on_large_stack(|| ferrum::jit::run(&program, &checker)) {
+            log.event("jit", format_args!("error: {}", e));
+            return report_in_program(&e, &sources, options.error_format);
         }
+        log.event("jit", "finished");
+        return 0;
+    }
 
-        let z = add(x, y) ;
+    let trace_file = match &options.trace {
+        Some(TraceTarget::File(path)) => match std::fs::File::create(path) {
+            Ok(file) => Some(file),
+            Err(e) => {
+                eprintln!("error: {}: {}", path, e);
+                return 1;
+            }
+        },
+        _ => None,
+    };
+    let _phase = trace::enter_phase("interpret");
+    let (result, profile, coverage) = on_large_stack(|| {
+        let interpreter = Interpreter::new().with_options(options.limits);
+        let interpreter = match options.seed {
+            Some(seed) => interpreter.with_seed(seed),
+            None => interpreter,
+        };
+        let interpreter = match (trace_file, &options.trace) {
+            (Some(file), _) => interpreter.with_hook(Tracer::new(std::io::BufWriter::new(file))),
+            (None, Some(_)) => interpreter.with_hook(Tracer::new(std::io::stderr())),
+            (None, None) => interpreter,
+        };
+        let profiler = options.profile.then(Profiler::new);
+        let interpreter = match &profiler {
+            Some(profiler) => interpreter.with_hook(profiler.clone()),
+            None => interpreter,
+        };
+        let coverage = options.coverage.as_ref().map(|_| Coverage::new());
+        let mut interpreter = match &coverage {
+            Some(coverage) => interpreter.with_hook(coverage.clone()),
+            None => interpreter,
+        };
+        let result = interpreter.interpret(&program).and_then(|value| exit_status(value, options));
+        let main = &sources.files()[0];
+        let coverage = coverage.map(|coverage| match &options.coverage {
+            Some(CoverageReport::Lcov(_)) => coverage.lcov(&program, &main.name),
+            _ => coverage.report(&program, &main.source),
+        });
+        (result, profiler.map(|profiler| profiler.report()), coverage)
+    });
+    if let Some(profile) = profile {
+        eprint!("{}", profile);
+    }
+    match (coverage, &options.coverage) {
+        (Some(lcov), Some(CoverageReport::Lcov(path))) => {
+            if let Err(e) = std::fs::write(path, lcov) {
+                eprintln!("error: {}: {}", path, e);
+                return 1;
+            }
+        }
+        (Some(report), _) => eprint!("{}", report),
+        (None, _) => {}
+    }
+    match result {
+        Ok(status) => {
+            log.event("interpret", "finished");
+            status
+        }
+        Err(e) => {
+            log.event("interpret", format_args!("error: {}", e));
+            report_in_program(&e, &sources, options.error_format)
+        }
+    }
+}
 
-        let w = 100
-    "#;
+/// The exit status for a program that finished with `value`: 0, unless
+/// `--exit-code` asks for its result, which must then be an int that fits.
+fn exit_status(value: Option<Value>, options: &cli::Options) -> Result<i32, CompilerError> {
+    match value.filter(|_| options.exit_code) {
+        None => Ok(0),
+        Some(Value::Int(n)) => i32::try_from(n)
+            .map_err(|_| CompilerError::RuntimeError(format!("the program's result {} does not fit in an exit status", n), None)),
+        Some(value) => {
+            let msg = format!("--exit-code needs an int result, found {}", value.type_name());
+            Err(CompilerError::RuntimeError(msg, None))
+        }
+    }
+}
 
-    // Lexer needs to be mutable for tokenize
-    let mut lexer = Lexer::new(source_code);
+/// Lexes, parses and (unless disabled) type checks the input files and the
+/// files they import as one program, printing whatever `--emit` asks for
+/// along the way. On failure the error has been reported and the exit
+/// status is returned. The checker is returned when type checking ran.
+fn load_program<'a>(options: &cli::Options, log: &PhaseLog, arena: &'a Arena<'a>) -> Result<(Vec<ast::Stmt<'a>>, SourceMap, Option<TypeChecker>), i32> {
+    let mut program = Vec::new();
+    let mut sources = SourceMap::new();
+    let mut loaded = Vec::new();
 
-    // Tokenize source code with error handling
-    let tokens_result = lexer.tokenize();
+    for path in &options.files {
+        let source = match std::fs::read_to_string(path) {
+            Ok(source) => source,
+            Err(e) => {
+                eprintln!("error: {}: {}", path, e);
+                return Err(1);
+            }
+        };
+        if !is_loaded(Path::new(path), &mut loaded) {
+            load_file(path.clone(), source, options, log, arena, &mut program, &mut sources, &mut loaded)?;
+        }
+    }
+    if options.prelude {
+        let file = sources.add(prelude::NAME, prelude::SOURCE);
+        let prelude = prelude::program(file, arena, &program);
+        program.splice(0..0, prelude);
+    }
+    if options.emit == Emit::C || options.wasm || options.jit {
+        // These backends have no `abs`, `min`, `max` or `pow` builtins and
+        // compile the prelude's versions on ints instead.
+        let file = sources.add(prelude::MATH_NAME, prelude::MATH_SOURCE);
+        let math = prelude::math(file, arena, &program);
+        program.splice(0..0, math);
+    }
 
-    match tokens_result {
-        Ok(tokens) => {
-            println!("Tokens:");
-            for token in &tokens {
-                println!("{:?}", token);
+    // Warnings and type errors are reported together, warnings first.
+    let mut diagnostics = DiagnosticBag::new();
+    {
+        let _phase = trace::enter_phase("analysis");
+        // Functions of an imported file or the prelude that the program does
+        // not call are library code rather than mistakes.
+        let imported = |span: Option<ferrum::Span>| span.is_some_and(|span| sources.file(span.file).is_some_and(|file| !options.files.contains(&file.name)));
+        diagnostics.extend(analysis::check_program(&program).into_iter().filter(|warning| warning.code != "W0004" || !imported(warning.span)));
+        log.event("analysis", format_args!("{} warning(s)", diagnostics.warning_count()));
+    }
+    let mut checker = TypeChecker::new();
+    let mut status = 0;
+    if options.typecheck {
+        let _phase = trace::enter_phase("typecheck");
+        match checker.check_program(&program) {
+            Ok(()) => log.event("typecheck", "passed"),
+            Err(errors) => {
+                log.event("typecheck", format_args!("{} error(s)", errors.len()));
+                for e in &errors {
+                    status = e.exit_code();
+                    diagnostics.error(e);
+                }
             }
-            println!("");
+        }
+    }
+    for diagnostic in &diagnostics {
+        emit_in_program(diagnostic, &sources, options.error_format);
+    }
+    if diagnostics.has_errors() {
+        return Err(status);
+    }
+    if options.deny_warnings && diagnostics.warning_count() > 0 {
+        if options.error_format == ErrorFormat::Human {
+            eprintln!("error: {} warning(s) denied by --deny-warnings", diagnostics.warning_count());
+        }
+        return Err(DENIED_WARNINGS_STATUS);
+    }
 
-            // Create parser with tokens
-            let mut parser = Parser::new(tokens);
+    if !options.typecheck {
+        return Ok((program, sources, None));
+    }
+    if options.emit == Emit::TypedAst {
+        print!("{}", printer::print_typed_program(&program, &checker));
+    }
+    Ok((program, sources, Some(checker)))
+}
+
+/// Lexes and parses one file of the program, then loads the files it
+/// imports that are not loaded yet, and appends its statements to `program`
+/// after theirs.
+#[allow(clippy::too_many_arguments)]
+fn load_file<'a>(
+    path: String,
+    source: String,
+    options: &cli::Options,
+    log: &PhaseLog,
+    arena: &'a Arena<'a>,
+    program: &mut Vec<ast::Stmt<'a>>,
+    sources: &mut SourceMap,
+    loaded: &mut Vec<PathBuf>,
+) -> Result<(), i32> {
+    let file = sources.next_file();
+    let mut lexer = Lexer::new(&source).with_recovery().with_file(file);
 
-            match parser.parse_program() {
-                Ok(ast) => {
-                    println!("AST:");
-                    for stmt in &ast {
-                        println!("{:#?}", stmt);
-                    }
+    let ast = {
+        // Printing the tokens needs all of them up front; otherwise the parser
+        // reads them from the lexer as it goes.
+        let mut parser = if options.emit == Emit::Tokens {
+            let tokens_result = {
+                let _phase = trace::enter_phase("lex");
+                let result = lexer.tokenize();
+                match &result {
+                    Ok(tokens) => log.event("lex", format_args!("{}: produced {} tokens", path, tokens.len())),
+                    Err(e) => log.event("lex", format_args!("{}: error: {}", path, e)),
                 }
-                Err(e) => {
-                    println!("Parser error: {}", e);
+                result
+            };
+            let tokens = match tokens_result {
+                Ok(tokens) => tokens,
+                Err(e) => return Err(report(&e, &source, &path, options.error_format)),
+            };
+            for token in &tokens {
+                println!("{}:{}: {:?}", token.span.line, token.span.col, token.token);
+            }
+            Parser::new(tokens, arena)
+        } else {
+            Parser::from_lexer(lexer, arena)
+        };
+        let _phase = trace::enter_phase("parse");
+        match parser.parse_program() {
+            Ok(ast) => ast,
+            Err(errors) => {
+                log.event("parse", format_args!("{}: {} error(s)", path, errors.len()));
+                let mut status = 0;
+                for e in &errors {
+                    status = report(e, &source, &path, options.error_format);
                 }
+                return Err(status);
             }
         }
+    };
+    let functions = ast.iter().filter(|stmt| matches!(stmt.node, ast::StmtKind::FnDecl(..))).count();
+    log.event("parse", format_args!("{}: parsed {} top-level statements ({} functions)", path, ast.len(), functions));
+    if options.emit == Emit::Ast {
+        print!("{}", printer::print_program(&ast));
+    }
+    #[cfg(feature = "serde")]
+    if options.emit == Emit::AstJson {
+        println!("{}", ferrum::json::program_to_json(&ast));
+    }
+    sources.add(path, source);
+
+    for stmt in &ast {
+        let ast::StmtKind::Import(import) = &stmt.node else {
+            continue;
+        };
+        let importer = &sources.files()[file];
+        let import_path = Path::new(&importer.name).parent().unwrap_or(Path::new("")).join(import);
+        let import_source = match std::fs::read_to_string(&import_path) {
+            Ok(source) => source,
+            Err(e) => {
+                let e = CompilerError::SyntaxError(format!("Cannot import {:?}: {}", import, e), Some(stmt.span));
+                report(&e, &importer.source, &importer.name, options.error_format);
+                return Err(1);
+            }
+        };
+        if !is_loaded(&import_path, loaded) {
+            log.event("parse", format_args!("{}: importing {}", importer.name, import_path.display()));
+            load_file(import_path.display().to_string(), import_source, options, log, arena, program, sources, loaded)?;
+        }
+    }
+    program.extend(ast);
+    Ok(())
+}
+
+/// Whether the file at `path` is already part of the program, or is being
+/// loaded further up a chain of imports; records it if not. Each file is
+/// loaded once however often it is imported, which also ends import cycles.
+fn is_loaded(path: &Path, loaded: &mut Vec<PathBuf>) -> bool {
+    let path = std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    if loaded.contains(&path) {
+        return true;
+    }
+    loaded.push(path);
+    false
+}
+
+/// `--vm`: compiles the checked program to bytecode and runs it.
+fn run_vm(program: &[ast::Stmt], sources: &SourceMap, options: &cli::Options, log: &PhaseLog) -> i32 {
+    let format = options.error_format;
+    let module = match compile_bytecode(program, sources, options, log) {
+        Ok(module) => module,
+        Err(status) => return status,
+    };
+    let _phase = trace::enter_phase("vm");
+    let vm = Vm::new();
+    let mut vm = match options.seed {
+        Some(seed) => vm.with_seed(seed),
+        None => vm,
+    };
+    if let Err(e) = vm.run(&module) {
+        log.event("vm", format_args!("error: {}", e));
+        return report_in_program(&e, sources, format);
+    }
+    log.event("vm", "finished");
+    0
+}
+
+fn compile_bytecode(
+    program: &[ast::Stmt],
+    sources: &SourceMap,
+    options: &cli::Options,
+    log: &PhaseLog,
+) -> Result<bytecode::Module, i32> {
+    let _phase = trace::enter_phase("bytecode");
+    let compiled = match &options.cache_dir {
+        Some(dir) => bytecode::compile_cached(program, &CompileCache::new(dir)).map(|(module, stats)| {
+            log.event("bytecode", format_args!("reused {} function(s) from {}, compiled {}", stats.reused, dir, stats.compiled));
+            module
+        }),
+        None => bytecode::compile(program),
+    };
+    match compiled {
+        Ok(module) => {
+            let instructions: usize = module.functions.iter().map(|f| f.code.len()).sum();
+            log.event("bytecode", format_args!("{} function(s), {} instruction(s)", module.functions.len(), instructions));
+            Ok(module)
+        }
         Err(e) => {
-            println!("Lexer error: {}", e);
+            log.event("bytecode", format_args!("error: {}", e));
+            Err(report_in_program(&e, sources, options.error_format))
         }
     }
-}
\ No newline at end of file
+}
+
+/// `compile [-o <out>] <files>`: compiles the input files to a `.bcode` file.
+fn run_compile(options: &cli::Options, output: Option<&str>) -> i32 {
+    let log = PhaseLog::new(options.verbose);
+    let arena = Arena::new();
+    let (program, sources, _) = match load_program(options, &log, &arena) {
+        Ok(loaded) => loaded,
+        Err(status) => return status,
+    };
+    let module = match compile_bytecode(&program, &sources, options, &log) {
+        Ok(module) => module,
+        Err(status) => return status,
+    };
+    let output = match output {
+        Some(output) => PathBuf::from(output),
+        None => Path::new(&options.files[0]).with_extension("bcode"),
+    };
+    if let Err(e) = std::fs::write(&output, bcode::encode(&module)) {
+        eprintln!("error: {}: {}", output.display(), e);
+        return 1;
+    }
+    log.event("bytecode", format_args!("wrote {}", output.display()));
+    0
+}
+
+/// `run <file.bcode>`: runs a program compiled by `compile`. There is no
+/// source to quote, so errors are reported by location only.
+fn run_bytecode(path: &str) -> i32 {
+    let bytes = match std::fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            eprintln!("error: {}: {}", path, e);
+            return 1;
+        }
+    };
+    let result = bcode::decode(&bytes).and_then(|module| Vm::new().run(&module));
+    if let Err(e) = result {
+        eprintln!("{}: {}", path, e);
+        return e.exit_code();
+    }
+    0
+}
+
+/// `debug <file>`: runs the file on the interpreter under the interactive
+/// debugger, which reads its commands from stdin.
+fn run_debug(options: &cli::Options) -> i32 {
+    let log = PhaseLog::new(options.verbose);
+    let arena = Arena::new();
+    let (program, sources, _) = match load_program(options, &log, &arena) {
+        Ok(loaded) => loaded,
+        Err(status) => return status,
+    };
+    let _phase = trace::enter_phase("interpret");
+    println!("type `help` for a list of commands");
+    let result = on_large_stack(|| {
+        let interpreter = Interpreter::new().with_hook(Debugger::for_files(&sources));
+        let mut interpreter = match options.seed {
+            Some(seed) => interpreter.with_seed(seed),
+            None => interpreter,
+        };
+        let value = interpreter.interpret(&program)?;
+        exit_status(value, options)
+    });
+    match result {
+        Ok(status) => {
+            log.event("interpret", "finished");
+            status
+        }
+        // `quit` ends the session as the user asked; there is nothing to report.
+        Err(CompilerError::Stopped(..)) => {
+            log.event("interpret", "stopped");
+            0
+        }
+        Err(e) => {
+            log.event("interpret", format_args!("error: {}", e));
+            report_in_program(&e, &sources, options.error_format)
+        }
+    }
+}
+
+/// `test <file>...`: runs the program's top-level code on the interpreter,
+/// then calls each `test_` function declared in the input files, in order,
+/// on top of the globals it left. A test passes if it returns without a
+/// runtime error, such as a failed `assert`. Returns 1 if any test failed.
+fn run_test(options: &cli::Options) -> i32 {
+    let log = PhaseLog::new(options.verbose);
+    let arena = Arena::new();
+    let (program, sources, _) = match load_program(options, &log, &arena) {
+        Ok(loaded) => loaded,
+        Err(status) => return status,
+    };
+    let tests: Vec<(ferrum::Symbol, usize, ferrum::Span)> = program
+        .iter()
+        .filter(|stmt| sources.file(stmt.span.file).is_some_and(|file| options.files.contains(&file.name)))
+        .filter_map(|stmt| match &stmt.node {
+            ast::StmtKind::FnDecl(name, params, ..) if name.starts_with("test_") => Some((*name, params.len(), stmt.span)),
+            _ => None,
+        })
+        .collect();
+    let _phase = trace::enter_phase("interpret");
+    on_large_stack(|| {
+        let interpreter = Interpreter::new().with_options(options.limits);
+        let mut interpreter = match options.seed {
+            Some(seed) => interpreter.with_seed(seed),
+            None => interpreter,
+        };
+        if let Err(e) = interpreter.interpret(&program) {
+            log.event("interpret", format_args!("error: {}", e));
+            return report_in_program(&e, &sources, options.error_format);
+        }
+        let mut failed = 0;
+        for &(name, params, span) in &tests {
+            let result = if params > 0 {
+                Err(CompilerError::RuntimeError(format!("{} takes parameters, but tests are called without arguments", name), Some(span)))
+            } else {
+                let call = ast::Expr::new(ast::ExprKind::Call(name, &[]), span);
+                interpreter.interpret_input(&[ast::Stmt::new(ast::StmtKind::Expr(call), span)])
+            };
+            match result {
+                Ok(_) => println!("test {} ... ok", name),
+                Err(e) => {
+                    failed += 1;
+                    println!("test {} ... FAILED", name);
+                    report_in_program(&e, &sources, options.error_format);
+                }
+            }
+        }
+        println!("{} passed, {} failed", tests.len() - failed, failed);
+        log.event("interpret", "finished");
+        i32::from(failed > 0)
+    })
+}
+
+/// Reports an error found after all files were combined into one program.
+fn report_in_program(e: &CompilerError, sources: &SourceMap, format: ErrorFormat) -> i32 {
+    emit_in_program(&e.into(), sources, format);
+    e.exit_code()
+}
+
+/// Prints a diagnostic about the combined program to stderr.
+fn emit_in_program(diagnostic: &Diagnostic, sources: &SourceMap, format: ErrorFormat) {
+    match format {
+        ErrorFormat::Human => eprint!("{}", diagnostics::render_in(diagnostic, sources)),
+        ErrorFormat::Json => eprintln!("{}", diagnostics::render_json_in(diagnostic, sources)),
+    }
+}
+
+/// Exit status when `--deny-warnings` turns warnings into a failure.
+const DENIED_WARNINGS_STATUS: i32 = 6;
+
+/// Native stack for the thread that runs the program, sized so the default
+/// call depth limit trips before the stack itself overflows.
+const INTERPRETER_STACK_SIZE: usize = 64 * 1024 * 1024;
+
+/// Runs `f`, which interprets source code, on a thread with a stack of
+/// `INTERPRETER_STACK_SIZE`.
+fn on_large_stack<T: Send>(f: impl FnOnce() -> T + Send) -> T {
+    std::thread::scope(|scope| {
+        let handle = std::thread::Builder::new()
+            .stack_size(INTERPRETER_STACK_SIZE)
+            .spawn_scoped(scope, f)
+            .expect("failed to spawn the interpreter thread");
+        handle.join().unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+    })
+}
+
+fn report(e: &CompilerError, source: &str, path: &str, format: ErrorFormat) -> i32 {
+    match format {
+        ErrorFormat::Human => eprint!("{}", diagnostics::render(&e.into(), source, path)),
+        ErrorFormat::Json => eprintln!("{}", diagnostics::render_json(&e.into(), Some(path))),
+    }
+    e.exit_code()
+}
+
+/// `diff <old> <new>`: prints structural differences between two source files.
+/// Exits with 0 when they are equivalent, 1 when they differ and 2 on error.
+fn run_diff(old_path: &str, new_path: &str) -> i32 {
+    let arena = Arena::new();
+    let (old, new) = match (parse_file(old_path, &arena), parse_file(new_path, &arena)) {
+        (Ok(old), Ok(new)) => (old, new),
+        (Err(e), _) | (_, Err(e)) => {
+            eprintln!("{}", e);
+            return 2;
+        }
+    };
+
+    let changes = diff::diff_programs(&old, &new);
+    if changes.is_empty() {
+        println!("No structural differences");
+        return 0;
+    }
+    for change in &changes {
+        println!("{}", change);
+    }
+    1
+}
+
+/// `lsp`: serves the Language Server Protocol on stdin and stdout.
+#[cfg(feature = "lsp")]
+fn run_lsp() -> i32 {
+    let served = on_large_stack(|| ferrum::lsp::serve(std::io::stdin().lock(), std::io::stdout().lock()));
+    served.unwrap_or_else(|e| {
+        eprintln!("error: {}", e);
+        1
+    })
+}
+
+/// `fmt [--write] <files>`: prints each file in canonical layout, or with
+/// `--write` rewrites it in place. Stops at the first file that fails to parse.
+fn run_fmt(files: &[String], write: bool) -> i32 {
+    for path in files {
+        let source = match std::fs::read_to_string(path) {
+            Ok(source) => source,
+            Err(e) => {
+                eprintln!("error: {}: {}", path, e);
+                return 1;
+            }
+        };
+        let arena = Arena::new();
+        let program = match ferrum::compile_str(&source, &arena) {
+            Ok(program) => program,
+            Err(e) => return report(&e, &source, path, ErrorFormat::Human),
+        };
+        let formatted = printer::print_program(&program);
+        if !write {
+            print!("{}", formatted);
+        } else if formatted != source
+            && let Err(e) = std::fs::write(path, formatted)
+        {
+            eprintln!("error: {}: {}", path, e);
+            return 1;
+        }
+    }
+    0
+}
+
+fn parse_file<'a>(path: &str, arena: &'a Arena<'a>) -> Result<Vec<ast::Stmt<'a>>, String> {
+    let source = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    Parser::from_lexer(Lexer::new(&source), arena).parse_program().map_err(|errors| {
+        errors.iter().map(|e| format!("{}: {}", path, e)).collect::<Vec<_>>().join("\n")
+    })
+}
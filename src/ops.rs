@@ -0,0 +1,301 @@
+use crate::ast::{BinOp, UpdateOp};
+use crate::error::CompilerError;
+use crate::span::Span;
+use crate::symbol::Symbol;
+use crate::value::{ArrayRef, MapRef, StructRef, Value};
+
+/// What integer arithmetic does when a result does not fit in an `i64`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OverflowMode {
+    /// Wrap around in two's complement.
+    Wrap,
+    /// Fail with a runtime error.
+    #[default]
+    Checked,
+    /// Clamp to `i64::MIN` or `i64::MAX`.
+    Saturate,
+}
+
+impl OverflowMode {
+    /// Applies an arithmetic operator. `None` means the result overflowed in
+    /// checked mode; comparison operators are not handled here.
+    fn apply(self, op: BinOp, l: i64, r: i64) -> Option<i64> {
+        match (self, op) {
+            (OverflowMode::Wrap, BinOp::Add) => Some(l.wrapping_add(r)),
+            (OverflowMode::Wrap, BinOp::Sub) => Some(l.wrapping_sub(r)),
+            (OverflowMode::Wrap, BinOp::Mul) => Some(l.wrapping_mul(r)),
+            (OverflowMode::Wrap, BinOp::Div) => Some(l.wrapping_div(r)),
+            (OverflowMode::Checked, BinOp::Add) => l.checked_add(r),
+            (OverflowMode::Checked, BinOp::Sub) => l.checked_sub(r),
+            (OverflowMode::Checked, BinOp::Mul) => l.checked_mul(r),
+            (OverflowMode::Checked, BinOp::Div) => l.checked_div(r),
+            (OverflowMode::Saturate, BinOp::Add) => Some(l.saturating_add(r)),
+            (OverflowMode::Saturate, BinOp::Sub) => Some(l.saturating_sub(r)),
+            (OverflowMode::Saturate, BinOp::Mul) => Some(l.saturating_mul(r)),
+            (OverflowMode::Saturate, BinOp::Div) => Some(l.saturating_div(r)),
+            (OverflowMode::Wrap, BinOp::Pow) => Some(wrapping_pow(l, r)),
+            (OverflowMode::Checked, BinOp::Pow) => checked_pow(l, r),
+            // Only a base of 2 or more in size overflows, so the sign of
+            // the true result follows from the base and the exponent.
+            (OverflowMode::Saturate, BinOp::Pow) => Some(checked_pow(l, r).unwrap_or(if l < 0 && r % 2 == 1 { i64::MIN } else { i64::MAX })),
+            _ => unreachable!("'{}' is not an arithmetic operator", op),
+        }
+    }
+}
+
+/// `base` to the power `exponent`, which is at least 0, by repeated
+/// squaring, or `None` if it overflows.
+pub(crate) fn checked_pow(mut base: i64, mut exponent: i64) -> Option<i64> {
+    let mut result: i64 = 1;
+    while exponent > 0 {
+        if exponent & 1 == 1 {
+            result = result.checked_mul(base)?;
+        }
+        exponent >>= 1;
+        if exponent > 0 {
+            base = base.checked_mul(base)?;
+        }
+    }
+    Some(result)
+}
+
+/// `base` to the power `exponent`, which is at least 0, in two's complement.
+fn wrapping_pow(mut base: i64, mut exponent: i64) -> i64 {
+    let mut result: i64 = 1;
+    while exponent > 0 {
+        if exponent & 1 == 1 {
+            result = result.wrapping_mul(base);
+        }
+        exponent >>= 1;
+        base = base.wrapping_mul(base);
+    }
+    result
+}
+
+/// Integer `+`, `-`, `*`, `/` or `**` under the configured overflow mode.
+pub(crate) fn int_arith(overflow: OverflowMode, op: BinOp, l: i64, r: i64, span: Span) -> Result<i64, CompilerError> {
+    if matches!(op, BinOp::Div) && r == 0 {
+        return Err(CompilerError::RuntimeError("Division by zero".to_string(), Some(span)));
+    }
+    if matches!(op, BinOp::Pow) && r < 0 {
+        return Err(CompilerError::RuntimeError(format!("Operator '**' needs an exponent of at least 0 for ints, found {}", r), Some(span)));
+    }
+    overflow
+        .apply(op, l, r)
+        .ok_or_else(|| CompilerError::RuntimeError(format!("Integer overflow in '{}'", op), Some(span)))
+}
+
+/// `<<` or `>>` on an int. Bits shifted past either end are lost, and `>>`
+/// copies the sign bit into the bits it frees.
+fn shift(op: BinOp, l: i64, r: i64, span: Span) -> Result<i64, CompilerError> {
+    if !(0..64).contains(&r) {
+        return Err(CompilerError::RuntimeError(format!("Shift amount must be from 0 to 63, found {}", r), Some(span)));
+    }
+    Ok(if matches!(op, BinOp::Shl) { l << r } else { l >> r })
+}
+
+/// Applies `op` to two evaluated operands of matching types.
+pub(crate) fn binary(overflow: OverflowMode, op: BinOp, l: Value, r: Value, span: Span) -> Result<Value, CompilerError> {
+    let (l_type, r_type) = (l.type_name(), r.type_name());
+    let unsupported = || {
+        let operands = if l_type == r_type { l_type.to_string() } else { format!("{} and {}", l_type, r_type) };
+        Err(CompilerError::RuntimeError(format!("Operator '{}' is not defined for {}", op, operands), Some(span)))
+    };
+    match (l, r) {
+        (Value::Int(l), Value::Int(r)) => Ok(match op {
+            BinOp::Add | BinOp::Sub | BinOp::Mul | BinOp::Div | BinOp::Pow => Value::Int(int_arith(overflow, op, l, r, span)?),
+            BinOp::BitAnd => Value::Int(l & r),
+            BinOp::BitOr => Value::Int(l | r),
+            BinOp::BitXor => Value::Int(l ^ r),
+            BinOp::Shl | BinOp::Shr => Value::Int(shift(op, l, r, span)?),
+            BinOp::Eq => Value::Bool(l == r),
+            BinOp::Neq => Value::Bool(l != r),
+            BinOp::Gt => Value::Bool(l > r),
+            BinOp::Lt => Value::Bool(l < r),
+            BinOp::Ge => Value::Bool(l >= r),
+            BinOp::Le => Value::Bool(l <= r),
+            BinOp::And | BinOp::Or => return unsupported(),
+        }),
+        (Value::Float(l), Value::Float(r)) => Ok(match op {
+            BinOp::And | BinOp::Or | BinOp::BitAnd | BinOp::BitOr | BinOp::BitXor | BinOp::Shl | BinOp::Shr => return unsupported(),
+            BinOp::Add => Value::Float(l + r),
+            BinOp::Sub => Value::Float(l - r),
+            BinOp::Mul => Value::Float(l * r),
+            BinOp::Div => Value::Float(l / r),
+            BinOp::Pow => Value::Float(l.powf(r)),
+            BinOp::Eq => Value::Bool(l == r),
+            BinOp::Neq => Value::Bool(l != r),
+            BinOp::Gt => Value::Bool(l > r),
+            BinOp::Lt => Value::Bool(l < r),
+            BinOp::Ge => Value::Bool(l >= r),
+            BinOp::Le => Value::Bool(l <= r),
+        }),
+        (Value::Bool(l), Value::Bool(r)) => match op {
+            BinOp::Eq => Ok(Value::Bool(l == r)),
+            BinOp::Neq => Ok(Value::Bool(l != r)),
+            BinOp::And => Ok(Value::Bool(l && r)),
+            BinOp::Or => Ok(Value::Bool(l || r)),
+            _ => unsupported(),
+        },
+        (Value::Str(l), Value::Str(r)) => match op {
+            BinOp::Add => Ok(Value::Str(l + &r)),
+            BinOp::Eq => Ok(Value::Bool(l == r)),
+            BinOp::Neq => Ok(Value::Bool(l != r)),
+            BinOp::Gt => Ok(Value::Bool(l > r)),
+            BinOp::Lt => Ok(Value::Bool(l < r)),
+            BinOp::Ge => Ok(Value::Bool(l >= r)),
+            BinOp::Le => Ok(Value::Bool(l <= r)),
+            _ => unsupported(),
+        },
+        // Characters order by code point.
+        (Value::Char(l), Value::Char(r)) => match op {
+            BinOp::Eq => Ok(Value::Bool(l == r)),
+            BinOp::Neq => Ok(Value::Bool(l != r)),
+            BinOp::Gt => Ok(Value::Bool(l > r)),
+            BinOp::Lt => Ok(Value::Bool(l < r)),
+            BinOp::Ge => Ok(Value::Bool(l >= r)),
+            BinOp::Le => Ok(Value::Bool(l <= r)),
+            _ => unsupported(),
+        },
+        (Value::Array(l), Value::Array(r)) => match op {
+            BinOp::Eq => Ok(Value::Bool(l == r)),
+            BinOp::Neq => Ok(Value::Bool(l != r)),
+            _ => unsupported(),
+        },
+        (Value::Map(l), Value::Map(r)) => match op {
+            BinOp::Eq => Ok(Value::Bool(l == r)),
+            BinOp::Neq => Ok(Value::Bool(l != r)),
+            _ => unsupported(),
+        },
+        (Value::Struct(l), Value::Struct(r)) => match op {
+            BinOp::Eq => Ok(Value::Bool(l == r)),
+            BinOp::Neq => Ok(Value::Bool(l != r)),
+            _ => unsupported(),
+        },
+        (l @ Value::Enum(..), r @ Value::Enum(..)) => match op {
+            BinOp::Eq => Ok(Value::Bool(l == r)),
+            BinOp::Neq => Ok(Value::Bool(l != r)),
+            _ => unsupported(),
+        },
+        // Any value can be compared with null, and equals it only if null.
+        (l, r) if matches!(l, Value::Null) || matches!(r, Value::Null) => match op {
+            BinOp::Eq => Ok(Value::Bool(l == r)),
+            BinOp::Neq => Ok(Value::Bool(l != r)),
+            _ => unsupported(),
+        },
+        _ => unsupported(),
+    }
+}
+
+/// Unary `-`.
+pub(crate) fn negate(overflow: OverflowMode, value: Value, span: Span) -> Result<Value, CompilerError> {
+    match value {
+        Value::Int(n) => Ok(Value::Int(int_arith(overflow, BinOp::Sub, 0, n, span)?)),
+        Value::Float(n) => Ok(Value::Float(-n)),
+        other => Err(CompilerError::RuntimeError(format!("Operator '-' is not defined for {}", other.type_name()), Some(span))),
+    }
+}
+
+/// Unary `~`.
+pub(crate) fn bit_not(value: Value, span: Span) -> Result<Value, CompilerError> {
+    match value {
+        Value::Int(n) => Ok(Value::Int(!n)),
+        other => Err(CompilerError::RuntimeError(format!("Operator '~' is not defined for {}", other.type_name()), Some(span))),
+    }
+}
+
+/// The new value of a variable after `++` or `--`.
+pub(crate) fn update(overflow: OverflowMode, op: UpdateOp, value: &Value, span: Span) -> Result<i64, CompilerError> {
+    match (value, op) {
+        (Value::Int(n), UpdateOp::Inc) => int_arith(overflow, BinOp::Add, *n, 1, span),
+        (Value::Int(n), UpdateOp::Dec) => int_arith(overflow, BinOp::Sub, *n, 1, span),
+        (other, _) => Err(CompilerError::RuntimeError(format!("Operator '{}' is not defined for {}", op, other.type_name()), Some(span))),
+    }
+}
+
+/// What `container[index]` refers to: an array element, already checked to
+/// be in bounds, or a map entry, which may not exist yet.
+pub(crate) enum Slot {
+    Element(ArrayRef, usize),
+    Entry(MapRef, String),
+}
+
+impl Slot {
+    /// The value in the slot; reading a missing map entry is an error at `span`.
+    pub(crate) fn get(&self, span: Span) -> Result<Value, CompilerError> {
+        match self {
+            Slot::Element(elements, i) => Ok(elements.borrow()[*i].clone()),
+            Slot::Entry(entries, key) => {
+                entries.borrow().get(key).cloned().ok_or_else(|| CompilerError::RuntimeError(format!("Key {:?} is not in the map", key), Some(span)))
+            }
+        }
+    }
+
+    /// Stores `value` in the slot, adding the entry to a map if needed.
+    pub(crate) fn set(self, value: Value) {
+        match self {
+            Slot::Element(elements, i) => elements.borrow_mut()[i] = value,
+            Slot::Entry(entries, key) => {
+                entries.borrow_mut().insert(key, value);
+            }
+        }
+    }
+}
+
+/// The string a map key must be.
+pub(crate) fn map_key(key: Value, span: Span) -> Result<String, CompilerError> {
+    match key {
+        Value::Str(key) => Ok(key),
+        other => Err(CompilerError::RuntimeError(format!("Map key must be a string, found {}", other.type_name()), Some(span))),
+    }
+}
+
+/// Finds the slot `container[index]` refers to, checking an array index
+/// against its bounds. Errors point at `container_span` or `index_span`
+/// depending on which operand is wrong.
+pub(crate) fn checked_index(container: Value, index: Value, container_span: Span, index_span: Span) -> Result<Slot, CompilerError> {
+    let (elements, i) = match (container, index) {
+        (Value::Array(elements), Value::Int(i)) => (elements, i),
+        (Value::Array(_), other) => {
+            return Err(CompilerError::RuntimeError(format!("Array index must be an int, found {}", other.type_name()), Some(index_span)));
+        }
+        (Value::Map(entries), key) => return Ok(Slot::Entry(entries, map_key(key, index_span)?)),
+        (other, _) => return Err(CompilerError::RuntimeError(format!("Cannot index into a value of type {}", other.type_name()), Some(container_span))),
+    };
+    let len = elements.borrow().len();
+    match usize::try_from(i) {
+        Ok(i) if i < len => Ok(Slot::Element(elements, i)),
+        _ => Err(CompilerError::RuntimeError(format!("Index {} out of bounds for array of length {}", i, len), Some(index_span))),
+    }
+}
+
+/// Reads `field` of a struct value.
+pub(crate) fn get_field(object: Value, field: Symbol, span: Span) -> Result<Value, CompilerError> {
+    let instance = as_struct(object, field, span)?;
+    let instance = instance.borrow();
+    instance.field(field).cloned().ok_or_else(|| no_field(instance.name, field, span))
+}
+
+/// Stores `value` in `field` of a struct value.
+pub(crate) fn set_field(object: Value, field: Symbol, value: Value, span: Span) -> Result<(), CompilerError> {
+    let instance = as_struct(object, field, span)?;
+    let mut instance = instance.borrow_mut();
+    match instance.field_mut(field) {
+        Some(slot) => {
+            *slot = value;
+            Ok(())
+        }
+        None => Err(no_field(instance.name, field, span)),
+    }
+}
+
+fn as_struct(object: Value, field: Symbol, span: Span) -> Result<StructRef, CompilerError> {
+    match object {
+        Value::Struct(instance) => Ok(instance),
+        other => Err(CompilerError::RuntimeError(format!("Cannot access field {} on a value of type {}", field, other.type_name()), Some(span))),
+    }
+}
+
+fn no_field(name: Symbol, field: Symbol, span: Span) -> CompilerError {
+    CompilerError::RuntimeError(format!("Struct {} has no field {}", name, field), Some(span))
+}
\ No newline at end of file
@@ -1,23 +1,173 @@
-use crate::lexer::Token;
+use crate::lexer::{Lexer, SpannedToken, Token};
 use crate::ast::*;
 use crate::error::CompilerError;
+use crate::span::Span;
+use crate::symbol::Symbol;
+use std::collections::VecDeque;
 
-pub struct Parser {
-    tokens: Vec<Token>,
+/// A statement in a block, or the value the block ends with.
+enum BlockItem<'a> {
+    Stmt(Stmt<'a>),
+    Value(Expr<'a>),
+}
+
+/// How many tokens past the current one any rule looks at.
+const LOOKAHEAD: usize = 3;
+
+/// How deeply expressions, blocks, `else if` chains and types may nest.
+/// The parser and every later stage walk the tree recursively, so deeper
+/// input would overflow the stack instead of failing with an error. A
+/// chain of operators such as `a + b + c` is parsed in a loop, but each
+/// operator puts the ones before it a level deeper in the tree, so each
+/// counts as a level too.
+const MAX_NESTING: usize = 200;
+
+pub struct Parser<'src, 'a> {
+    /// Where tokens come from: a [`Lexer`] read as the parser goes, or the
+    /// tokens handed to [`Parser::new`].
+    source: Box<dyn Iterator<Item = Result<SpannedToken, CompilerError>> + 'src>,
+    /// Where the nodes of the tree are allocated.
+    arena: &'a Arena<'a>,
+    /// The current token and the ones after it, as far as `LOOKAHEAD`.
+    lookahead: VecDeque<SpannedToken>,
+    /// The most recently consumed token.
+    previous: Option<SpannedToken>,
+    /// How many tokens have been consumed.
     pos: usize,
+    /// The error that ended the token stream early, if the lexer failed.
+    lex_error: Option<CompilerError>,
+    /// How many syntax errors had been found when the parser ran out of
+    /// tokens before `lex_error`. Any found after that only complain about
+    /// the input being cut short.
+    errors_before_lex_error: Option<usize>,
+    /// Syntax errors recovered from so far.
+    errors: Vec<CompilerError>,
+    /// Whether `Name {` starts a struct literal. Off in the bounds of a
+    /// `for` loop, where the `{` opens the loop's body.
+    struct_literals: bool,
+    /// How many blocks enclose the current position; functions and structs
+    /// may only be declared at depth 0.
+    block_depth: usize,
+    /// How many nested rules enclose the current position, as counted by
+    /// `nested`.
+    nesting: usize,
+    /// How deep the tree of the innermost chain being parsed reaches so
+    /// far, in the levels `nesting` counts plus one for each operator.
+    deepest: usize,
 }
 
-impl Parser {
-    pub fn new(tokens: Vec<Token>) -> Self {
-        Self { tokens, pos: 0 }
+impl<'a> Parser<'static, 'a> {
+    /// A parser for tokens already lexed, such as by [`Lexer::tokenize`],
+    /// that allocates the tree in `arena`.
+    pub fn new(tokens: Vec<SpannedToken>, arena: &'a Arena<'a>) -> Self {
+        Self::with_source(Box::new(tokens.into_iter().map(Ok)), arena)
+    }
+}
+
+impl<'src, 'a> Parser<'src, 'a> {
+    /// A parser that pulls tokens from `lexer` only as it needs them, so
+    /// the whole token stream is never held at once. A lexer error ends the
+    /// input and is returned by `parse_program` with the syntax errors found
+    /// before it.
+    pub fn from_lexer(lexer: Lexer<'src>, arena: &'a Arena<'a>) -> Self {
+        Self::with_source(Box::new(lexer), arena)
+    }
+
+    fn with_source(source: Box<dyn Iterator<Item = Result<SpannedToken, CompilerError>> + 'src>, arena: &'a Arena<'a>) -> Self {
+        let mut parser = Self {
+            source,
+            arena,
+            lookahead: VecDeque::with_capacity(LOOKAHEAD),
+            previous: None,
+            pos: 0,
+            lex_error: None,
+            errors_before_lex_error: None,
+            errors: Vec::new(),
+            struct_literals: true,
+            block_depth: 0,
+            nesting: 0,
+            deepest: 0,
+        };
+        parser.fill();
+        parser
+    }
+
+    /// Reads tokens from the source until `LOOKAHEAD` are buffered or it
+    /// runs out.
+    fn fill(&mut self) {
+        while self.lookahead.len() < LOOKAHEAD && self.lex_error.is_none() {
+            match self.source.next() {
+                Some(Ok(token)) => self.lookahead.push_back(token),
+                Some(Err(e)) => self.lex_error = Some(e),
+                None => break,
+            }
+        }
+        if self.lookahead.is_empty() && self.lex_error.is_some() && self.errors_before_lex_error.is_none() {
+            self.errors_before_lex_error = Some(self.errors.len());
+        }
     }
 
     fn peek(&self) -> Option<&Token> {
-        self.tokens.get(self.pos)
+        self.lookahead.front().map(|t| &t.token)
+    }
+
+    fn peek_next(&self) -> Option<&Token> {
+        self.lookahead.get(1).map(|t| &t.token)
     }
 
     fn advance(&mut self) {
+        if let Some(token) = self.lookahead.pop_front() {
+            self.previous = Some(token);
+        }
         self.pos += 1;
+        self.fill();
+    }
+
+    /// Span of the most recently consumed token.
+    fn previous_span(&self) -> Span {
+        self.previous.as_ref().expect("a token has been consumed").span
+    }
+
+    /// Span of the current token, or an empty span just past the last token
+    /// once the input is exhausted.
+    fn current_span(&self) -> Span {
+        if let Some(t) = self.lookahead.front() {
+            return t.span;
+        }
+        match &self.previous {
+            Some(last) => Span {
+                start: last.span.end,
+                end: last.span.end,
+                line: last.span.line,
+                col: last.span.col + (last.span.end - last.span.start),
+                file: last.span.file,
+            },
+            None => Span { start: 0, end: 0, line: 1, col: 1, file: 0 },
+        }
+    }
+
+    /// Span from `start` to the end of the most recently consumed token.
+    fn span_from(&self, start: Span) -> Span {
+        match &self.previous {
+            Some(prev) => start.to(prev.span),
+            None => start,
+        }
+    }
+
+    fn found(&self) -> String {
+        match self.peek() {
+            Some(token) => token.to_string(),
+            None => "end of input".to_string(),
+        }
+    }
+
+    fn error(&self, msg: &str) -> CompilerError {
+        // No rule accepts a `Token::Error`, so whatever was expected, the
+        // real problem is the character itself.
+        if let Some(Token::Error(c)) = self.peek() {
+            return unexpected_character(*c, self.current_span());
+        }
+        CompilerError::SyntaxError(format!("{}, found {}", msg, self.found()), Some(self.current_span()))
     }
 
     fn expect(&mut self, expected: Token) -> Result<(), CompilerError> {
@@ -25,94 +175,394 @@ impl Parser {
             self.advance();
             Ok(())
         } else {
-            Err(CompilerError::SyntaxError(format!(
-                "Expected {:?}, found {:?}",
-                expected,
-                self.peek()
-            )))
+            Err(self.error(&format!("Expected {}", expected)))
+        }
+    }
+
+    fn expect_ident(&mut self, msg: &str) -> Result<Symbol, CompilerError> {
+        if let Some(&Token::Ident(name)) = self.peek() {
+            self.advance();
+            Ok(name)
+        } else {
+            Err(self.error(msg))
         }
     }
 
-    pub fn parse_program(&mut self) -> Result<Vec<Stmt>, CompilerError> {
+    /// Parses the whole token stream. After a syntax error the parser skips
+    /// ahead to the end of the broken statement and carries on, so every
+    /// error in the program is returned, in source order, along with any
+    /// characters the lexer could not read.
+    pub fn parse_program(&mut self) -> Result<Vec<Stmt<'a>>, Vec<CompilerError>> {
         let mut stmts = Vec::new();
         while self.peek().is_some() {
-            stmts.push(self.parse_stmt()?);
+            self.parse_stmt_recovering(&mut stmts);
+        }
+        let mut errors = std::mem::take(&mut self.errors);
+        if let Some(e) = self.lex_error.take() {
+            errors.truncate(self.errors_before_lex_error.unwrap_or(errors.len()));
+            errors.push(e);
+        }
+        match errors {
+            errors if errors.is_empty() => Ok(stmts),
+            errors => Err(errors),
+        }
+    }
+
+    /// Parses a statement into `stmts`, or records the error and skips past
+    /// the statement.
+    fn parse_stmt_recovering(&mut self, stmts: &mut Vec<Stmt<'a>>) {
+        let start = self.pos;
+        let result = self.parse_stmt();
+        self.push_or_recover(result, start, stmts);
+    }
+
+    /// Like `parse_stmt_recovering`, but an expression right before the
+    /// block's closing `}` needs no `;` and is returned as the value of the
+    /// block instead.
+    fn parse_stmt_or_tail(&mut self, stmts: &mut Vec<Stmt<'a>>) -> Option<Expr<'a>> {
+        let start = self.pos;
+        match self.parse_block_item() {
+            Ok(BlockItem::Value(value)) => return Some(value),
+            Ok(BlockItem::Stmt(stmt)) => stmts.push(stmt),
+            Err(e) => self.push_or_recover(Err(e), start, stmts),
+        }
+        None
+    }
+
+    fn parse_block_item(&mut self) -> Result<BlockItem<'a>, CompilerError> {
+        let start = self.current_span();
+        match self.peek() {
+            Some(Token::If) => return self.parse_if_or_value(),
+            Some(Token::Match) => return self.parse_match_or_value(),
+            Some(Token::LBrace) if self.at_map_lit() => {}
+            // The other statements `parse_stmt` recognizes by their first token.
+            Some(
+                Token::Let
+                | Token::Const
+                | Token::While
+                | Token::Do
+                | Token::For
+                | Token::Fn
+                | Token::Struct
+                | Token::Enum
+                | Token::Type
+                | Token::Import
+                | Token::Return
+                | Token::LBrace
+                | Token::Break
+                | Token::Continue,
+            ) => return self.parse_stmt().map(BlockItem::Stmt),
+            _ => {}
+        }
+        let mut stmt = self.parse_simple_stmt()?;
+        if self.peek() == Some(&Token::RBrace)
+            && let StmtKind::Expr(value) = stmt.node
+        {
+            return Ok(BlockItem::Value(value));
+        }
+        self.expect(Token::Semicolon)?;
+        stmt.span = self.span_from(start);
+        Ok(BlockItem::Stmt(stmt))
+    }
+
+    /// Pushes a parsed statement, or records the error and skips past the
+    /// statement that began at token `start`.
+    fn push_or_recover(&mut self, result: Result<Stmt<'a>, CompilerError>, start: usize, stmts: &mut Vec<Stmt<'a>>) {
+        match result {
+            Ok(stmt) => stmts.push(stmt),
+            Err(e) => {
+                self.errors.push(e);
+                self.synchronize();
+                // A stray '}' ends no statement; step over it.
+                if self.pos == start {
+                    self.advance();
+                }
+            }
+        }
+    }
+
+    /// Skips to just after the next ';' or balanced '{ ... }' group, or to
+    /// a '}' closing the enclosing block, where parsing can resume. Bad
+    /// characters skipped on the way are still reported.
+    fn synchronize(&mut self) {
+        let mut depth = 0;
+        while let Some(token) = self.peek() {
+            match token {
+                Token::Error(c) => {
+                    let span = self.current_span();
+                    if self.errors.last().and_then(|e| e.span()) != Some(span) {
+                        self.errors.push(unexpected_character(*c, span));
+                    }
+                }
+                Token::Semicolon if depth == 0 => {
+                    self.advance();
+                    return;
+                }
+                Token::LBrace => depth += 1,
+                Token::RBrace if depth == 0 => return,
+                Token::RBrace => {
+                    depth -= 1;
+                    if depth == 0 {
+                        self.advance();
+                        return;
+                    }
+                }
+                _ => {}
+            }
+            self.advance();
         }
-        Ok(stmts)
     }
 
-    fn parse_stmt(&mut self) -> Result<Stmt, CompilerError> {
+    fn parse_stmt(&mut self) -> Result<Stmt<'a>, CompilerError> {
+        let start = self.current_span();
         match self.peek() {
-            Some(Token::Let) => self.parse_let(),
+            Some(Token::Let | Token::Const) => self.parse_let(),
             Some(Token::If) => self.parse_if(),
             Some(Token::While) => self.parse_while(),
             Some(Token::Do) => self.parse_do_while(),
             Some(Token::For) => self.parse_for(),
+            Some(Token::Match) => self.parse_match(),
             Some(Token::Fn) => self.parse_fn_decl(),
+            Some(Token::Struct) => self.parse_struct_decl(),
+            Some(Token::Enum) => self.parse_enum_decl(),
+            Some(Token::Type) => self.parse_type_alias(),
+            Some(Token::Import) => self.parse_import(),
             Some(Token::Return) => self.parse_return(),
-            Some(Token::Ident(name)) => {
-                let name = name.clone();
+            Some(Token::LBrace) if !self.at_map_lit() => {
+                let stmts = self.parse_block()?;
+                Ok(Stmt::new(StmtKind::Block(stmts), self.span_from(start)))
+            }
+            Some(Token::Break) => {
                 self.advance();
-                if self.peek() == Some(&Token::Equal) {
-                    self.advance();
-                    let expr = self.parse_expr()?;
-                    self.expect(Token::Semicolon)?;
-                    Ok(Stmt::Assign(name, expr))
-                } else {
-                    // If it's not an assignment, treat it as an expression
-                    let expr = Expr::Variable(name);
-                    self.expect(Token::Semicolon)?;
-                    Ok(Stmt::Expr(expr))
-                }
+                self.expect(Token::Semicolon)?;
+                Ok(Stmt::new(StmtKind::Break, self.span_from(start)))
+            }
+            Some(Token::Continue) => {
+                self.advance();
+                self.expect(Token::Semicolon)?;
+                Ok(Stmt::new(StmtKind::Continue, self.span_from(start)))
             }
             _ => {
-                let expr = self.parse_expr()?;
+                let mut stmt = self.parse_simple_stmt()?;
                 self.expect(Token::Semicolon)?;
-                Ok(Stmt::Expr(expr))
+                stmt.span = self.span_from(start);
+                Ok(stmt)
             }
         }
     }
 
-    fn parse_let(&mut self) -> Result<Stmt, CompilerError> {
-        self.expect(Token::Let)?;
-        let name = if let Some(Token::Ident(name)) = self.peek() {
-            let name = name.clone();
+    /// Parses an assignment or expression statement without its `;`, as
+    /// also found in the head of a `for` loop.
+    fn parse_simple_stmt(&mut self) -> Result<Stmt<'a>, CompilerError> {
+        let start = self.current_span();
+        if let Some(&Token::Ident(name)) = self.peek()
+            && self.peek_next() == Some(&Token::Equal)
+        {
             self.advance();
-            name
-        } else {
-            return Err(CompilerError::SyntaxError("Expected identifier after let".into()));
+            self.advance();
+            let expr = self.parse_expr()?;
+            return Ok(Stmt::new(StmtKind::Assign(name, expr), self.span_from(start)));
+        }
+        // If it's not an assignment, treat it as an expression
+        let expr = self.parse_expr()?;
+        if self.peek() != Some(&Token::Equal) {
+            return Ok(Stmt::new(StmtKind::Expr(expr), self.span_from(start)));
+        }
+        let node = match expr.node {
+            ExprKind::Index(array, index) => {
+                self.advance();
+                StmtKind::IndexAssign(array, index, self.parse_expr()?)
+            }
+            ExprKind::Field(object, field) => {
+                self.advance();
+                StmtKind::FieldAssign(object, field, self.parse_expr()?)
+            }
+            _ => return Err(CompilerError::SyntaxError("Invalid assignment target".into(), Some(expr.span))),
         };
+        Ok(Stmt::new(node, self.span_from(start)))
+    }
+
+    fn parse_let(&mut self) -> Result<Stmt<'a>, CompilerError> {
+        let start = self.current_span();
+        let mut stmt = self.parse_let_binding()?;
+        self.expect(Token::Semicolon)?;
+        stmt.span = self.span_from(start);
+        Ok(stmt)
+    }
+
+    /// Parses `let name[: type] = value`, or the same with `const`, without
+    /// its `;`.
+    fn parse_let_binding(&mut self) -> Result<Stmt<'a>, CompilerError> {
+        let start = self.current_span();
+        let constant = self.peek() == Some(&Token::Const);
+        if constant {
+            self.advance();
+        } else {
+            self.expect(Token::Let)?;
+        }
+        let name = self.expect_ident(if constant { "Expected identifier after const" } else { "Expected identifier after let" })?;
+        let ty = self.parse_annotation()?;
         self.expect(Token::Equal)?;
         let expr = self.parse_expr()?;
+        let node = if constant { StmtKind::Const(name, ty, expr) } else { StmtKind::Let(name, ty, expr) };
+        Ok(Stmt::new(node, self.span_from(start)))
+    }
+
+    /// Parses an optional `: type` annotation.
+    fn parse_annotation(&mut self) -> Result<Option<TypeExpr>, CompilerError> {
+        if self.peek() != Some(&Token::Colon) {
+            return Ok(None);
+        }
+        self.advance();
+        self.parse_type().map(Some)
+    }
+
+    /// Parses a type, followed by a `?` if it is optional.
+    fn parse_type(&mut self) -> Result<TypeExpr, CompilerError> {
+        let ty = self.nested(Self::parse_required_type)?;
+        if self.peek() == Some(&Token::Question) {
+            self.advance();
+            return Ok(TypeExpr::Option(Box::new(ty)));
+        }
+        Ok(ty)
+    }
+
+    fn parse_required_type(&mut self) -> Result<TypeExpr, CompilerError> {
+        if self.peek() == Some(&Token::LBracket) {
+            self.advance();
+            let element = self.parse_type()?;
+            self.expect(Token::RBracket)?;
+            return Ok(TypeExpr::Array(Box::new(element)));
+        }
+        if self.peek() == Some(&Token::LBrace) {
+            self.advance();
+            let key = self.parse_type()?;
+            self.expect(Token::Colon)?;
+            let value = self.parse_type()?;
+            self.expect(Token::RBrace)?;
+            return Ok(TypeExpr::Map(Box::new(key), Box::new(value)));
+        }
+        Ok(TypeExpr::Named(self.expect_ident("Expected a type")?))
+    }
+
+    fn parse_if(&mut self) -> Result<Stmt<'a>, CompilerError> {
+        let start = self.current_span();
+        self.expect(Token::If)?;
+        let cond = self.parse_paren_expr()?;
+        let then_block = self.parse_block()?;
+        let else_block = self.parse_else()?;
+        Ok(Stmt::new(StmtKind::If(cond, then_block, else_block), self.span_from(start)))
+    }
+
+    /// Parses the optional `else` of an `if` statement.
+    fn parse_else(&mut self) -> Result<&'a [Stmt<'a>], CompilerError> {
+        if self.peek() != Some(&Token::Else) {
+            return Ok(&[]);
+        }
+        self.advance();
+        // `else if` is an `else` block holding just the inner `if`.
+        if let Some(Token::If) = self.peek() {
+            Ok(std::slice::from_ref(self.arena.stmt(self.nested(Self::parse_if)?)))
+        } else {
+            self.parse_block()
+        }
+    }
+
+    /// Parses an `if` in a block that may end with a value. If its first
+    /// branch ends with a value it is an `if` expression, which is the
+    /// block's value when the block ends right after it; otherwise it is an
+    /// `if` statement.
+    fn parse_if_or_value(&mut self) -> Result<BlockItem<'a>, CompilerError> {
+        let start = self.current_span();
+        self.expect(Token::If)?;
+        let cond = self.parse_paren_expr()?;
+        let then_start = self.current_span();
+        let (then_block, then_value) = self.parse_block_with_tail()?;
+        let Some(then_value) = then_value else {
+            let else_block = self.parse_else()?;
+            let then_block = self.arena.stmts(then_block);
+            return Ok(BlockItem::Stmt(Stmt::new(StmtKind::If(cond, then_block, else_block), self.span_from(start))));
+        };
+        // Without an `else`, unless the enclosing block ends here, the value
+        // was more likely a statement missing its `;`.
+        if self.peek() != Some(&Token::Else) && self.peek() != Some(&Token::RBrace) {
+            let brace = self.previous_span();
+            return Err(CompilerError::SyntaxError("Expected ';', found '}'".into(), Some(brace)));
+        }
+        let then_value = self.block_value(then_block, then_value, then_start);
+        let else_value = self.parse_else_value()?;
+        let node = ExprKind::If(self.arena.expr(cond), self.arena.expr(then_value), self.arena.expr(else_value));
+        let expr = Expr::new(node, self.span_from(start));
+        if self.peek() == Some(&Token::RBrace) {
+            return Ok(BlockItem::Value(expr));
+        }
         self.expect(Token::Semicolon)?;
-        Ok(Stmt::Let(name, expr))
+        Ok(BlockItem::Stmt(Stmt::new(StmtKind::Expr(expr), self.span_from(start))))
     }
 
-    fn parse_if(&mut self) -> Result<Stmt, CompilerError> {
+    /// Parses `if (cond) { value } else { value }` where a value is
+    /// expected. The `else` is required and may be another `if`.
+    fn parse_if_expr(&mut self) -> Result<Expr<'a>, CompilerError> {
+        let start = self.current_span();
         self.expect(Token::If)?;
+        let cond = self.parse_paren_expr()?;
+        let then_value = self.parse_block_expr()?;
+        let else_value = self.parse_else_value()?;
+        let node = ExprKind::If(self.arena.expr(cond), self.arena.expr(then_value), self.arena.expr(else_value));
+        Ok(Expr::new(node, self.span_from(start)))
+    }
+
+    /// Parses the `else` of an `if` expression.
+    fn parse_else_value(&mut self) -> Result<Expr<'a>, CompilerError> {
+        if self.peek() != Some(&Token::Else) {
+            return Err(self.error("Expected 'else' after an 'if' used as a value"));
+        }
+        self.advance();
+        if self.peek() == Some(&Token::If) { self.nested(Self::parse_if_expr) } else { self.parse_block_expr() }
+    }
+
+    /// Parses `(expr)`, as in the head of an `if` or a loop.
+    fn parse_paren_expr(&mut self) -> Result<Expr<'a>, CompilerError> {
         self.expect(Token::LParen)?;
-        let cond = self.parse_expr()?;
+        let expr = self.parse_expr()?;
         self.expect(Token::RParen)?;
-        let then_block = self.parse_block()?;
-        let else_block = if let Some(Token::Else) = self.peek() {
-            self.advance();
-            self.parse_block()?
-        } else {
-            Vec::new()
+        Ok(expr)
+    }
+
+    /// Parses `{ stmts value }`, a block whose value is the expression it
+    /// ends with.
+    fn parse_block_expr(&mut self) -> Result<Expr<'a>, CompilerError> {
+        let start = self.current_span();
+        let (stmts, value) = self.parse_block_with_tail()?;
+        let Some(value) = value else {
+            // Point at the `}` where the value was missing.
+            let span = self.previous_span();
+            return Err(CompilerError::SyntaxError("Expected a value before '}' in a block used as a value".into(), Some(span)));
         };
-        Ok(Stmt::If(cond, then_block, else_block))
+        Ok(self.block_value(stmts, value, start))
     }
 
-    fn parse_while(&mut self) -> Result<Stmt, CompilerError> {
+    /// The expression for a block starting at `start` that holds `stmts`
+    /// and ends with `value`; just `value` if there are no statements.
+    fn block_value(&self, stmts: Vec<Stmt<'a>>, value: Expr<'a>, start: Span) -> Expr<'a> {
+        if stmts.is_empty() {
+            return value;
+        }
+        Expr::new(ExprKind::Block(self.arena.stmts(stmts), self.arena.expr(value)), self.span_from(start))
+    }
+
+    fn parse_while(&mut self) -> Result<Stmt<'a>, CompilerError> {
+        let start = self.current_span();
         self.expect(Token::While)?;
         self.expect(Token::LParen)?;
         let cond = self.parse_expr()?;
         self.expect(Token::RParen)?;
         let body = self.parse_block()?;
-        Ok(Stmt::While(cond, body))
+        Ok(Stmt::new(StmtKind::While(cond, body), self.span_from(start)))
     }
 
-    fn parse_do_while(&mut self) -> Result<Stmt, CompilerError> {
+    fn parse_do_while(&mut self) -> Result<Stmt<'a>, CompilerError> {
+        let start = self.current_span();
         self.expect(Token::Do)?;
         let body = self.parse_block()?;
         self.expect(Token::While)?;
@@ -120,49 +570,188 @@ impl Parser {
         let cond = self.parse_expr()?;
         self.expect(Token::RParen)?;
         self.expect(Token::Semicolon)?;
-        Ok(Stmt::DoWhile(body, cond))
+        Ok(Stmt::new(StmtKind::DoWhile(body, cond), self.span_from(start)))
     }
 
-    fn parse_for(&mut self) -> Result<Stmt, CompilerError> {
+    fn parse_for(&mut self) -> Result<Stmt<'a>, CompilerError> {
+        let start_span = self.current_span();
         self.expect(Token::For)?;
+        if let Some(Token::Ident(_)) = self.peek()
+            && self.peek_next() == Some(&Token::In)
+        {
+            return self.parse_for_range(start_span);
+        }
         self.expect(Token::LParen)?;
-        let var = if let Some(Token::Ident(name)) = self.peek() {
-            let name = name.clone();
-            self.advance();
-            name
+        let init = if self.peek() == Some(&Token::Let) {
+            self.parse_let_binding()?
         } else {
-            return Err(CompilerError::SyntaxError("Expected identifier in for loop".into()));
+            self.parse_simple_stmt()?
         };
-        self.expect(Token::Equal)?;
-        let start = self.parse_expr()?;
         self.expect(Token::Semicolon)?;
         let cond = self.parse_expr()?;
         self.expect(Token::Semicolon)?;
-        let step = self.parse_expr()?;
+        let step = self.parse_simple_stmt()?;
         self.expect(Token::RParen)?;
         let body = self.parse_block()?;
-        Ok(Stmt::For(var, start, cond, step, body))
+        Ok(Stmt::new(StmtKind::For(self.arena.stmt(init), cond, self.arena.stmt(step), body), self.span_from(start_span)))
     }
 
-    fn parse_fn_decl(&mut self) -> Result<Stmt, CompilerError> {
-        self.expect(Token::Fn)?;
-        let name = if let Some(Token::Ident(name)) = self.peek() {
-            let name = name.clone();
+    /// Parses the rest of `for name in start..end { ... }`, or `..=` for a
+    /// range that includes `end`.
+    fn parse_for_range(&mut self, start_span: Span) -> Result<Stmt<'a>, CompilerError> {
+        let var = self.expect_ident("Expected identifier in for loop")?;
+        self.expect(Token::In)?;
+        let start = self.parse_bound()?;
+        let inclusive = match self.peek() {
+            Some(Token::DotDot) => false,
+            Some(Token::DotDotEq) => true,
+            _ => return Err(self.error("Expected '..' or '..='")),
+        };
+        self.advance();
+        let end = self.parse_bound()?;
+        let body = self.parse_block()?;
+        Ok(Stmt::new(StmtKind::ForRange(var, start, end, inclusive, body), self.span_from(start_span)))
+    }
+
+    /// Parses a bound of a range, where `{` ends the expression instead of
+    /// starting a struct literal.
+    fn parse_bound(&mut self) -> Result<Expr<'a>, CompilerError> {
+        let outer = std::mem::replace(&mut self.struct_literals, false);
+        let bound = self.parse_expr();
+        self.struct_literals = outer;
+        bound
+    }
+
+    /// Parses `match (value) { pattern => { ... }, ... }`. The commas
+    /// between arms are optional.
+    fn parse_match(&mut self) -> Result<Stmt<'a>, CompilerError> {
+        let start = self.current_span();
+        let value = self.parse_match_head()?;
+        let mut arms = Vec::new();
+        self.parse_match_arms(&mut arms)?;
+        Ok(Stmt::new(StmtKind::Match(value, arms), self.span_from(start)))
+    }
+
+    /// Parses a `match` in a block that may end with a value. As with
+    /// [`Parser::parse_if_or_value`], if its first arm ends with a value it
+    /// is a `match` expression and every arm must; otherwise it is a
+    /// `match` statement.
+    fn parse_match_or_value(&mut self) -> Result<BlockItem<'a>, CompilerError> {
+        let start = self.current_span();
+        let value = self.parse_match_head()?;
+        if self.peek() == Some(&Token::RBrace) {
             self.advance();
-            name
-        } else {
-            return Err(CompilerError::SyntaxError("Expected function name".into()));
+            return Ok(BlockItem::Stmt(Stmt::new(StmtKind::Match(value, Vec::new()), self.span_from(start))));
+        }
+        let (pattern, span) = self.parse_arm_head()?;
+        let body_start = self.current_span();
+        let (body, tail) = self.parse_block_with_tail()?;
+        self.skip_arm_comma();
+        let Some(tail) = tail else {
+            let mut arms = vec![MatchArm { pattern, span, body: self.arena.stmts(body) }];
+            self.parse_match_arms(&mut arms)?;
+            return Ok(BlockItem::Stmt(Stmt::new(StmtKind::Match(value, arms), self.span_from(start))));
         };
+        let mut arms = vec![ValueArm { pattern, span, value: self.block_value(body, tail, body_start) }];
+        self.parse_value_arms(&mut arms)?;
+        let expr = Expr::new(ExprKind::Match(self.arena.expr(value), arms), self.span_from(start));
+        if self.peek() == Some(&Token::RBrace) {
+            return Ok(BlockItem::Value(expr));
+        }
+        self.expect(Token::Semicolon)?;
+        Ok(BlockItem::Stmt(Stmt::new(StmtKind::Expr(expr), self.span_from(start))))
+    }
+
+    /// Parses `match (value) { pattern => { value }, ... }` where a value is
+    /// expected.
+    fn parse_match_expr(&mut self) -> Result<Expr<'a>, CompilerError> {
+        let start = self.current_span();
+        let value = self.parse_match_head()?;
+        let mut arms = Vec::new();
+        self.parse_value_arms(&mut arms)?;
+        Ok(Expr::new(ExprKind::Match(self.arena.expr(value), arms), self.span_from(start)))
+    }
+
+    /// Parses `match (value) {`, up to the first arm.
+    fn parse_match_head(&mut self) -> Result<Expr<'a>, CompilerError> {
+        self.expect(Token::Match)?;
+        let value = self.parse_paren_expr()?;
+        self.expect(Token::LBrace)?;
+        Ok(value)
+    }
+
+    /// Parses the `pattern =>` an arm starts with, returning the pattern and
+    /// where it was written.
+    fn parse_arm_head(&mut self) -> Result<(Pattern<'a>, Span), CompilerError> {
+        let start = self.current_span();
+        let pattern = self.parse_pattern()?;
+        let span = self.span_from(start);
+        self.expect(Token::FatArrow)?;
+        Ok((pattern, span))
+    }
+
+    fn skip_arm_comma(&mut self) {
+        if self.peek() == Some(&Token::Comma) {
+            self.advance();
+        }
+    }
+
+    /// Parses the remaining arms of a `match` statement and its `}`.
+    fn parse_match_arms(&mut self, arms: &mut Vec<MatchArm<'a>>) -> Result<(), CompilerError> {
+        while self.peek() != Some(&Token::RBrace) {
+            let (pattern, span) = self.parse_arm_head()?;
+            let body = self.parse_block()?;
+            arms.push(MatchArm { pattern, span, body });
+            self.skip_arm_comma();
+        }
+        self.expect(Token::RBrace)
+    }
+
+    /// Parses the remaining arms of a `match` expression and its `}`.
+    fn parse_value_arms(&mut self, arms: &mut Vec<ValueArm<'a>>) -> Result<(), CompilerError> {
+        while self.peek() != Some(&Token::RBrace) {
+            let (pattern, span) = self.parse_arm_head()?;
+            let value = self.parse_block_expr()?;
+            arms.push(ValueArm { pattern, span, value });
+            self.skip_arm_comma();
+        }
+        self.expect(Token::RBrace)
+    }
+
+    fn parse_pattern(&mut self) -> Result<Pattern<'a>, CompilerError> {
+        if self.peek() == Some(&Token::Underscore) {
+            self.advance();
+            return Ok(Pattern::Wildcard);
+        }
+        let literal = match self.peek() {
+            Some(Token::Number(_) | Token::Float(_) | Token::Str(_) | Token::Char(_) | Token::True | Token::False | Token::Null) => self.parse_primary()?,
+            Some(Token::Minus) if matches!(self.peek_next(), Some(Token::Number(_) | Token::Float(_))) => self.parse_unary()?,
+            Some(Token::Ident(_)) if self.peek_next() == Some(&Token::ColonColon) => self.parse_primary()?,
+            _ => return Err(self.error("Expected a literal, a variant or '_' in match arm")),
+        };
+        Ok(Pattern::Literal(literal))
+    }
+
+    /// Records an error for a declaration found inside a block. It is
+    /// parsed in full first, so parsing carries on right after it.
+    fn check_top_level(&mut self, what: &str, span: Span) {
+        if self.block_depth > 0 {
+            self.errors.push(CompilerError::SyntaxError(format!("{} can only be declared at top level", what), Some(span)));
+        }
+    }
+
+    fn parse_fn_decl(&mut self) -> Result<Stmt<'a>, CompilerError> {
+        let start = self.current_span();
+        self.expect(Token::Fn)?;
+        let name = self.expect_ident("Expected function name")?;
+        let _span = crate::trace::enter_function(&name);
         self.expect(Token::LParen)?;
         let mut params = Vec::new();
         if self.peek() != Some(&Token::RParen) {
             loop {
-                if let Some(Token::Ident(param)) = self.peek() {
-                    params.push(param.clone());
-                    self.advance();
-                } else {
-                    return Err(CompilerError::SyntaxError("Expected parameter name".into()));
-                }
+                let name = self.expect_ident("Expected parameter name")?;
+                let ty = self.parse_annotation()?;
+                params.push(Param { name, ty });
                 if self.peek() == Some(&Token::Comma) {
                     self.advance();
                 } else {
@@ -171,139 +760,520 @@ impl Parser {
             }
         }
         self.expect(Token::RParen)?;
-        let body = self.parse_block()?;
-        Ok(Stmt::FnDecl(name, params, body))
+        let return_type = self.parse_annotation()?;
+        let (mut body, tail) = self.parse_block_with_tail()?;
+        // A body ending in a value returns it.
+        if let Some(value) = tail {
+            let span = value.span;
+            body.push(Stmt::new(StmtKind::Return(Some(value)), span));
+        }
+        let span = self.span_from(start);
+        self.check_top_level("Functions", span);
+        Ok(Stmt::new(StmtKind::FnDecl(name, params, return_type, self.arena.stmts(body)), span))
     }
 
-    fn parse_return(&mut self) -> Result<Stmt, CompilerError> {
-        self.expect(Token::Return)?;
-        let expr = self.parse_expr()?;
-        self.expect(Token::Semicolon)?;
-        Ok(Stmt::Return(expr))
+    /// Parses `struct Name { field: type, ... }`. A comma after the last
+    /// field is optional.
+    fn parse_struct_decl(&mut self) -> Result<Stmt<'a>, CompilerError> {
+        let start = self.current_span();
+        self.expect(Token::Struct)?;
+        let name = self.expect_ident("Expected struct name")?;
+        self.expect(Token::LBrace)?;
+        let mut fields = Vec::new();
+        while self.peek() != Some(&Token::RBrace) {
+            let name = self.expect_ident("Expected field name")?;
+            self.expect(Token::Colon)?;
+            let ty = self.parse_type()?;
+            fields.push(Field { name, ty });
+            if self.peek() == Some(&Token::Comma) {
+                self.advance();
+            } else {
+                break;
+            }
+        }
+        self.expect(Token::RBrace)?;
+        let span = self.span_from(start);
+        self.check_top_level("Structs", span);
+        Ok(Stmt::new(StmtKind::StructDecl(name, fields), span))
     }
 
-    fn parse_block(&mut self) -> Result<Vec<Stmt>, CompilerError> {
+    /// Parses `enum Name { Variant, ... }`. A comma after the last variant
+    /// is optional.
+    fn parse_enum_decl(&mut self) -> Result<Stmt<'a>, CompilerError> {
+        let start = self.current_span();
+        self.expect(Token::Enum)?;
+        let name = self.expect_ident("Expected enum name")?;
         self.expect(Token::LBrace)?;
-        let mut stmts = Vec::new();
+        let mut variants = Vec::new();
         while self.peek() != Some(&Token::RBrace) {
-            stmts.push(self.parse_stmt()?);
+            variants.push(self.expect_ident("Expected variant name")?);
+            if self.peek() == Some(&Token::Comma) {
+                self.advance();
+            } else {
+                break;
+            }
         }
         self.expect(Token::RBrace)?;
-        Ok(stmts)
+        let span = self.span_from(start);
+        self.check_top_level("Enums", span);
+        Ok(Stmt::new(StmtKind::EnumDecl(name, variants), span))
     }
 
-    fn parse_expr(&mut self) -> Result<Expr, CompilerError> {
-        self.parse_equality()
+    /// Parses `type Name = type;`.
+    fn parse_type_alias(&mut self) -> Result<Stmt<'a>, CompilerError> {
+        let start = self.current_span();
+        self.expect(Token::Type)?;
+        let name = self.expect_ident("Expected type name")?;
+        self.expect(Token::Equal)?;
+        let ty = self.parse_type()?;
+        self.expect(Token::Semicolon)?;
+        let span = self.span_from(start);
+        self.check_top_level("Type aliases", span);
+        Ok(Stmt::new(StmtKind::TypeAlias(name, ty), span))
     }
 
-    fn parse_equality(&mut self) -> Result<Expr, CompilerError> {
-        let mut expr = self.parse_comparison()?;
-        while let Some(token) = self.peek() {
-            match token {
-                Token::Eq | Token::Neq => {
-                    let op = match token {
-                        Token::Eq => BinOp::Eq,
-                        Token::Neq => BinOp::Neq,
-                        _ => unreachable!(),
-                    };
-                    self.advance();
-                    let right = self.parse_comparison()?;
-                    expr = Expr::Binary(Box::new(expr), op, Box::new(right));
+    /// Parses `import "path";`.
+    fn parse_import(&mut self) -> Result<Stmt<'a>, CompilerError> {
+        let start = self.current_span();
+        self.expect(Token::Import)?;
+        let Some(Token::Str(path)) = self.peek() else {
+            return Err(self.error("Expected the path of the file to import as a string"));
+        };
+        let path = path.clone();
+        self.advance();
+        self.expect(Token::Semicolon)?;
+        let span = self.span_from(start);
+        if self.block_depth > 0 {
+            self.errors.push(CompilerError::SyntaxError("Imports can only appear at top level".to_string(), Some(span)));
+        }
+        Ok(Stmt::new(StmtKind::Import(path), span))
+    }
+
+    fn parse_return(&mut self) -> Result<Stmt<'a>, CompilerError> {
+        let start = self.current_span();
+        self.expect(Token::Return)?;
+        let expr = if self.peek() == Some(&Token::Semicolon) { None } else { Some(self.parse_expr()?) };
+        self.expect(Token::Semicolon)?;
+        Ok(Stmt::new(StmtKind::Return(expr), self.span_from(start)))
+    }
+
+    /// Runs `parse` one block deeper.
+    fn in_block<T>(&mut self, parse: impl FnOnce(&mut Self) -> Result<T, CompilerError>) -> Result<T, CompilerError> {
+        self.block_depth += 1;
+        let result = self.nested(parse);
+        self.block_depth -= 1;
+        result
+    }
+
+    /// Runs `parse` one level of nesting deeper, or fails if that is more
+    /// than `MAX_NESTING` levels.
+    fn nested<T>(&mut self, parse: impl FnOnce(&mut Self) -> Result<T, CompilerError>) -> Result<T, CompilerError> {
+        if self.nesting == MAX_NESTING {
+            return Err(Self::too_deep(self.current_span()));
+        }
+        self.nesting += 1;
+        self.deepest = self.deepest.max(self.nesting);
+        let result = parse(self);
+        self.nesting -= 1;
+        result
+    }
+
+    /// Runs `parse`, which builds a chain such as `a + b + c` or `a[0].x`
+    /// in a loop and calls `link` for each operator, keeping track of how
+    /// deep the chain reaches apart from what was parsed before it.
+    fn chain(&mut self, parse: impl FnOnce(&mut Self) -> Result<Expr<'a>, CompilerError>) -> Result<Expr<'a>, CompilerError> {
+        let outer = std::mem::replace(&mut self.deepest, self.nesting);
+        let result = parse(self);
+        self.deepest = self.deepest.max(outer);
+        result
+    }
+
+    /// Adds an operator to the chain being parsed. It becomes the root of
+    /// everything in the chain so far, which moves one level deeper; that
+    /// fails if it would be more than `MAX_NESTING` levels.
+    fn link(&mut self, span: Span) -> Result<(), CompilerError> {
+        if self.deepest == MAX_NESTING {
+            return Err(Self::too_deep(span));
+        }
+        self.deepest += 1;
+        Ok(())
+    }
+
+    fn too_deep(span: Span) -> CompilerError {
+        CompilerError::SyntaxError(format!("Nested more than {} levels deep", MAX_NESTING), Some(span))
+    }
+
+    fn parse_block(&mut self) -> Result<&'a [Stmt<'a>], CompilerError> {
+        self.expect(Token::LBrace)?;
+        let stmts = self.in_block(|parser| {
+            let mut stmts = Vec::new();
+            while parser.peek() != Some(&Token::RBrace) {
+                if parser.peek().is_none() {
+                    return Err(parser.error("Expected '}'"));
                 }
-                _ => break,
+                parser.parse_stmt_recovering(&mut stmts);
             }
-        }
-        Ok(expr)
+            Ok(stmts)
+        })?;
+        self.expect(Token::RBrace)?;
+        Ok(self.arena.stmts(stmts))
     }
 
-    fn parse_comparison(&mut self) -> Result<Expr, CompilerError> {
-        let mut expr = self.parse_term()?;
-        while let Some(token) = self.peek() {
-            match token {
-                Token::Gt | Token::Lt => {
-                    let op = match token {
-                        Token::Gt => BinOp::Gt,
-                        Token::Lt => BinOp::Lt,
-                        _ => unreachable!(),
-                    };
-                    self.advance();
-                    let right = self.parse_term()?;
-                    expr = Expr::Binary(Box::new(expr), op, Box::new(right));
+    /// Parses a block that may end with an expression without a `;`,
+    /// returned along with the statements before it.
+    fn parse_block_with_tail(&mut self) -> Result<(Vec<Stmt<'a>>, Option<Expr<'a>>), CompilerError> {
+        self.expect(Token::LBrace)?;
+        let (stmts, tail) = self.in_block(|parser| {
+            let mut stmts = Vec::new();
+            let mut tail = None;
+            while parser.peek() != Some(&Token::RBrace) {
+                if parser.peek().is_none() {
+                    return Err(parser.error("Expected '}'"));
+                }
+                tail = parser.parse_stmt_or_tail(&mut stmts);
+                if tail.is_some() {
+                    break;
                 }
-                _ => break,
             }
-        }
-        Ok(expr)
+            Ok((stmts, tail))
+        })?;
+        self.expect(Token::RBrace)?;
+        Ok((stmts, tail))
     }
 
-    fn parse_term(&mut self) -> Result<Expr, CompilerError> {
-        let mut expr = self.parse_factor()?;
-        while let Some(token) = self.peek() {
-            match token {
-                Token::Plus | Token::Minus => {
-                    let op = match token {
-                        Token::Plus => BinOp::Add,
-                        Token::Minus => BinOp::Sub,
-                        _ => unreachable!(),
-                    };
-                    self.advance();
-                    let right = self.parse_factor()?;
-                    expr = Expr::Binary(Box::new(expr), op, Box::new(right));
+    fn parse_expr(&mut self) -> Result<Expr<'a>, CompilerError> {
+        self.nested(Self::parse_or)
+    }
+
+    /// Joins `lhs` and `rhs` with `op` as the next link of the chain being
+    /// parsed.
+    fn binary(&mut self, lhs: Expr<'a>, op: BinOp, rhs: Expr<'a>) -> Result<Expr<'a>, CompilerError> {
+        self.link(rhs.span)?;
+        let span = lhs.span.to(rhs.span);
+        Ok(Expr::new(ExprKind::Binary(self.arena.expr(lhs), op, self.arena.expr(rhs)), span))
+    }
+
+    fn parse_or(&mut self) -> Result<Expr<'a>, CompilerError> {
+        self.chain(|parser| {
+            let mut expr = parser.parse_and()?;
+            while parser.peek() == Some(&Token::OrOr) {
+                parser.advance();
+                let right = parser.parse_and()?;
+                expr = parser.binary(expr, BinOp::Or, right)?;
+            }
+            Ok(expr)
+        })
+    }
+
+    fn parse_and(&mut self) -> Result<Expr<'a>, CompilerError> {
+        self.chain(|parser| {
+            let mut expr = parser.parse_equality()?;
+            while parser.peek() == Some(&Token::AndAnd) {
+                parser.advance();
+                let right = parser.parse_equality()?;
+                expr = parser.binary(expr, BinOp::And, right)?;
+            }
+            Ok(expr)
+        })
+    }
+
+    fn parse_equality(&mut self) -> Result<Expr<'a>, CompilerError> {
+        self.chain(|parser| {
+            let mut expr = parser.parse_comparison()?;
+            while let Some(token) = parser.peek() {
+                match token {
+                    Token::Eq | Token::Neq => {
+                        let op = match token {
+                            Token::Eq => BinOp::Eq,
+                            Token::Neq => BinOp::Neq,
+                            _ => unreachable!(),
+                        };
+                        parser.advance();
+                        let right = parser.parse_comparison()?;
+                        expr = parser.binary(expr, op, right)?;
+                    }
+                    _ => break,
                 }
-                _ => break,
             }
-        }
-        Ok(expr)
+            Ok(expr)
+        })
     }
 
-    fn parse_factor(&mut self) -> Result<Expr, CompilerError> {
-        let mut expr = self.parse_unary()?;
-        while let Some(token) = self.peek() {
-            match token {
-                Token::Star | Token::Slash => {
-                    let op = match token {
-                        Token::Star => BinOp::Mul,
-                        Token::Slash => BinOp::Div,
-                        _ => unreachable!(),
-                    };
-                    self.advance();
-                    let right = self.parse_unary()?;
-                    expr = Expr::Binary(Box::new(expr), op, Box::new(right));
+    fn parse_comparison(&mut self) -> Result<Expr<'a>, CompilerError> {
+        self.chain(|parser| {
+            let mut expr = parser.parse_bit_or()?;
+            while let Some(token) = parser.peek() {
+                match token {
+                    Token::Gt | Token::Lt | Token::Ge | Token::Le => {
+                        let op = match token {
+                            Token::Gt => BinOp::Gt,
+                            Token::Lt => BinOp::Lt,
+                            Token::Ge => BinOp::Ge,
+                            Token::Le => BinOp::Le,
+                            _ => unreachable!(),
+                        };
+                        parser.advance();
+                        let right = parser.parse_bit_or()?;
+                        expr = parser.binary(expr, op, right)?;
+                    }
+                    _ => break,
                 }
-                _ => break,
             }
-        }
-        Ok(expr)
+            Ok(expr)
+        })
+    }
+
+    fn parse_bit_or(&mut self) -> Result<Expr<'a>, CompilerError> {
+        self.chain(|parser| {
+            let mut expr = parser.parse_bit_xor()?;
+            while parser.peek() == Some(&Token::Pipe) {
+                parser.advance();
+                let right = parser.parse_bit_xor()?;
+                expr = parser.binary(expr, BinOp::BitOr, right)?;
+            }
+            Ok(expr)
+        })
+    }
+
+    fn parse_bit_xor(&mut self) -> Result<Expr<'a>, CompilerError> {
+        self.chain(|parser| {
+            let mut expr = parser.parse_bit_and()?;
+            while parser.peek() == Some(&Token::Caret) {
+                parser.advance();
+                let right = parser.parse_bit_and()?;
+                expr = parser.binary(expr, BinOp::BitXor, right)?;
+            }
+            Ok(expr)
+        })
+    }
+
+    fn parse_bit_and(&mut self) -> Result<Expr<'a>, CompilerError> {
+        self.chain(|parser| {
+            let mut expr = parser.parse_shift()?;
+            while parser.peek() == Some(&Token::Amp) {
+                parser.advance();
+                let right = parser.parse_shift()?;
+                expr = parser.binary(expr, BinOp::BitAnd, right)?;
+            }
+            Ok(expr)
+        })
     }
 
-    fn parse_unary(&mut self) -> Result<Expr, CompilerError> {
+    fn parse_shift(&mut self) -> Result<Expr<'a>, CompilerError> {
+        self.chain(|parser| {
+            let mut expr = parser.parse_term()?;
+            while let Some(token) = parser.peek() {
+                match token {
+                    Token::Shl | Token::Shr => {
+                        let op = match token {
+                            Token::Shl => BinOp::Shl,
+                            Token::Shr => BinOp::Shr,
+                            _ => unreachable!(),
+                        };
+                        parser.advance();
+                        let right = parser.parse_term()?;
+                        expr = parser.binary(expr, op, right)?;
+                    }
+                    _ => break,
+                }
+            }
+            Ok(expr)
+        })
+    }
+
+    fn parse_term(&mut self) -> Result<Expr<'a>, CompilerError> {
+        self.chain(|parser| {
+            let mut expr = parser.parse_factor()?;
+            while let Some(token) = parser.peek() {
+                match token {
+                    Token::Plus | Token::Minus => {
+                        let op = match token {
+                            Token::Plus => BinOp::Add,
+                            Token::Minus => BinOp::Sub,
+                            _ => unreachable!(),
+                        };
+                        parser.advance();
+                        let right = parser.parse_factor()?;
+                        expr = parser.binary(expr, op, right)?;
+                    }
+                    _ => break,
+                }
+            }
+            Ok(expr)
+        })
+    }
+
+    fn parse_factor(&mut self) -> Result<Expr<'a>, CompilerError> {
+        self.chain(|parser| {
+            let mut expr = parser.parse_unary()?;
+            while let Some(token) = parser.peek() {
+                match token {
+                    Token::Star | Token::Slash => {
+                        let op = match token {
+                            Token::Star => BinOp::Mul,
+                            Token::Slash => BinOp::Div,
+                            _ => unreachable!(),
+                        };
+                        parser.advance();
+                        let right = parser.parse_unary()?;
+                        expr = parser.binary(expr, op, right)?;
+                    }
+                    _ => break,
+                }
+            }
+            Ok(expr)
+        })
+    }
+
+    /// The `++` or `--` operator at the current position, if any.
+    fn update_op(&self) -> Option<UpdateOp> {
         match self.peek() {
-            Some(Token::Minus) => {
+            Some(Token::PlusPlus) => Some(UpdateOp::Inc),
+            Some(Token::MinusMinus) => Some(UpdateOp::Dec),
+            _ => None,
+        }
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr<'a>, CompilerError> {
+        if let Some(op) = self.update_op() {
+            let start = self.current_span();
+            self.advance();
+            let name = self.expect_ident(&format!("Expected variable after '{}'", op))?;
+            return Ok(Expr::new(ExprKind::Update(name, op, true), self.span_from(start)));
+        }
+        let op = match self.peek() {
+            Some(Token::Minus) => UnOp::Neg,
+            Some(Token::Tilde) => UnOp::BitNot,
+            _ => return self.parse_power(),
+        };
+        let start = self.current_span();
+        self.advance();
+        let expr = self.nested(Self::parse_unary)?;
+        let span = start.to(expr.span);
+        Ok(Expr::new(ExprKind::Unary(op, self.arena.expr(expr)), span))
+    }
+
+    /// `base ** exponent`, where the exponent may itself be negated or a
+    /// power, so `-2 ** 2` is `-(2 ** 2)` and `2 ** 3 ** 2` is
+    /// `2 ** (3 ** 2)`.
+    fn parse_power(&mut self) -> Result<Expr<'a>, CompilerError> {
+        self.chain(|parser| {
+            let base = parser.parse_postfix()?;
+            if parser.peek() != Some(&Token::StarStar) {
+                return Ok(base);
+            }
+            parser.advance();
+            let exponent = parser.nested(Self::parse_unary)?;
+            parser.binary(base, BinOp::Pow, exponent)
+        })
+    }
+
+    fn parse_postfix(&mut self) -> Result<Expr<'a>, CompilerError> {
+        self.chain(|parser| {
+            let start = parser.current_span();
+            let mut expr = parser.parse_primary()?;
+            loop {
+                let operator = parser.current_span();
+                let node = match parser.peek() {
+                    Some(Token::LBracket) => {
+                        parser.advance();
+                        let index = parser.parse_expr()?;
+                        parser.expect(Token::RBracket)?;
+                        ExprKind::Index(parser.arena.expr(expr), parser.arena.expr(index))
+                    }
+                    Some(Token::Dot) => {
+                        parser.advance();
+                        let field = parser.expect_ident("Expected field name after '.'")?;
+                        ExprKind::Field(parser.arena.expr(expr), field)
+                    }
+                    _ => return Ok(expr),
+                };
+                parser.link(operator)?;
+                expr = Expr::new(node, parser.span_from(start));
+            }
+        })
+    }
+
+    /// Parses the `{ field: value, ... }` of a struct literal whose name,
+    /// starting at `start`, has been consumed.
+    fn parse_struct_lit(&mut self, name: Symbol, start: Span) -> Result<Expr<'a>, CompilerError> {
+        self.expect(Token::LBrace)?;
+        let mut fields = Vec::new();
+        while self.peek() != Some(&Token::RBrace) {
+            let field = self.expect_ident("Expected field name")?;
+            self.expect(Token::Colon)?;
+            fields.push((field, self.parse_expr()?));
+            if self.peek() == Some(&Token::Comma) {
                 self.advance();
-                let expr = self.parse_primary()?;
-                Ok(Expr::Binary(Box::new(Expr::Number(0)), BinOp::Sub, Box::new(expr)))
+            } else {
+                break;
             }
-            _ => self.parse_primary(),
         }
+        self.expect(Token::RBrace)?;
+        Ok(Expr::new(ExprKind::StructLit(name, fields), self.span_from(start)))
+    }
+
+    /// Whether a `{` starts a map literal rather than a block: a map
+    /// literal opens with a string key and a `:`.
+    fn at_map_lit(&self) -> bool {
+        matches!(self.peek_next(), Some(Token::Str(_))) && self.lookahead.get(2).map(|t| &t.token) == Some(&Token::Colon)
     }
 
-    fn parse_primary(&mut self) -> Result<Expr, CompilerError> {
+    /// Parses `{ key: value, ... }`; every key after the first may be any
+    /// expression.
+    fn parse_map_lit(&mut self) -> Result<Expr<'a>, CompilerError> {
+        let start = self.current_span();
+        self.expect(Token::LBrace)?;
+        let mut entries = Vec::new();
+        while self.peek() != Some(&Token::RBrace) {
+            let key = self.parse_expr()?;
+            self.expect(Token::Colon)?;
+            entries.push((key, self.parse_expr()?));
+            if self.peek() == Some(&Token::Comma) {
+                self.advance();
+            } else {
+                break;
+            }
+        }
+        self.expect(Token::RBrace)?;
+        Ok(Expr::new(ExprKind::Map(entries), self.span_from(start)))
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr<'a>, CompilerError> {
+        let start = self.current_span();
         match self.peek() {
             Some(Token::Number(n)) => {
                 let n = *n;
                 self.advance();
-                Ok(Expr::Number(n))
+                Ok(Expr::new(ExprKind::Number(n), start))
+            }
+            Some(Token::Float(n)) => {
+                let n = *n;
+                self.advance();
+                Ok(Expr::new(ExprKind::Float(n), start))
+            }
+            Some(Token::Str(s)) => {
+                let s = s.clone();
+                self.advance();
+                Ok(Expr::new(ExprKind::Str(s), start))
+            }
+            Some(&Token::Char(c)) => {
+                self.advance();
+                Ok(Expr::new(ExprKind::Char(c), start))
             }
             Some(Token::True) => {
                 self.advance();
-                Ok(Expr::Bool(true))
+                Ok(Expr::new(ExprKind::Bool(true), start))
             }
             Some(Token::False) => {
                 self.advance();
-                Ok(Expr::Bool(false))
+                Ok(Expr::new(ExprKind::Bool(false), start))
             }
-            Some(Token::Ident(name)) => {
-                let name = name.clone();
+            Some(Token::Null) => {
+                self.advance();
+                Ok(Expr::new(ExprKind::Null, start))
+            }
+            Some(&Token::Ident(name)) => {
                 self.advance();
                 if self.peek() == Some(&Token::LParen) {
                     // function call
@@ -320,21 +1290,53 @@ impl Parser {
                         }
                     }
                     self.expect(Token::RParen)?;
-                    Ok(Expr::Call(name, args))
+                    Ok(Expr::new(ExprKind::Call(name, self.arena.exprs(args)), self.span_from(start)))
+                } else if let Some(op) = self.update_op() {
+                    self.advance();
+                    Ok(Expr::new(ExprKind::Update(name, op, false), self.span_from(start)))
+                } else if self.peek() == Some(&Token::ColonColon) {
+                    self.advance();
+                    let variant = self.expect_ident("Expected variant name after '::'")?;
+                    Ok(Expr::new(ExprKind::Variant(name, variant), self.span_from(start)))
+                } else if self.peek() == Some(&Token::LBrace) && self.struct_literals {
+                    self.parse_struct_lit(name, start)
                 } else {
-                    Ok(Expr::Variable(name))
+                    Ok(Expr::new(ExprKind::Variable(name), start))
                 }
             }
             Some(Token::LParen) => {
                 self.advance();
-                let expr = self.parse_expr()?;
+                // Parentheses let a `for` range bound hold a struct literal.
+                let outer = std::mem::replace(&mut self.struct_literals, true);
+                let expr = self.parse_expr();
+                self.struct_literals = outer;
+                let expr = expr?;
                 self.expect(Token::RParen)?;
                 Ok(expr)
             }
-            other => Err(CompilerError::SyntaxError(format!(
-                "Unexpected token {:?} in expression",
-                other
-            ))),
+            Some(Token::If) => self.parse_if_expr(),
+            Some(Token::Match) => self.parse_match_expr(),
+            Some(Token::LBrace) if self.at_map_lit() => self.parse_map_lit(),
+            Some(Token::LBrace) => self.parse_block_expr(),
+            Some(Token::LBracket) => {
+                self.advance();
+                let mut elements = Vec::new();
+                while self.peek() != Some(&Token::RBracket) {
+                    elements.push(self.parse_expr()?);
+                    if self.peek() == Some(&Token::Comma) {
+                        self.advance();
+                    } else {
+                        break;
+                    }
+                }
+                self.expect(Token::RBracket)?;
+                Ok(Expr::new(ExprKind::Array(self.arena.exprs(elements)), self.span_from(start)))
+            }
+            _ => Err(self.error("Expected expression")),
         }
     }
+}
+
+fn unexpected_character(c: char, span: Span) -> CompilerError {
+    CompilerError::SyntaxError(format!("Unexpected character: {}", c), Some(span))
 }
\ No newline at end of file
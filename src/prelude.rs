@@ -0,0 +1,43 @@
+//! The prelude: `clamp`, written in the language itself and built into the
+//! binary. The driver, the REPL and the language server load it ahead of
+//! the program unless told not to.
+//!
+//! `abs`, `min`, `max` and `pow` are builtins, which the C, wasm and JIT
+//! backends do not provide. For those the driver also loads [`MATH_SOURCE`],
+//! the same functions on ints written in the language.
+
+use crate::ast::StmtKind;
+use crate::{Arena, Lexer, Parser, Program};
+
+/// The prelude's source.
+pub const SOURCE: &str = include_str!("prelude.lang");
+
+/// The name the prelude is reported under, in a [`crate::SourceMap`] and
+/// elsewhere.
+pub const NAME: &str = "<prelude>";
+
+/// `abs`, `min`, `max` and `pow` on ints, for the backends that do not
+/// provide the builtins of those names. Unlike the builtin, this `pow`
+/// returns 1 for a negative exponent.
+pub const MATH_SOURCE: &str = include_str!("prelude_math.lang");
+
+/// The name [`MATH_SOURCE`] is reported under.
+pub const MATH_NAME: &str = "<prelude math>";
+
+/// The prelude's functions with their spans in file `file`, allocated in
+/// `arena`, leaving out any that `program` declares itself, so a program
+/// may define its own `clamp`.
+pub fn program<'a>(file: usize, arena: &'a Arena<'a>, program: &[crate::ast::Stmt]) -> Program<'a> {
+    functions(SOURCE, file, arena, program)
+}
+
+/// Like [`program`], for the functions of [`MATH_SOURCE`].
+pub fn math<'a>(file: usize, arena: &'a Arena<'a>, program: &[crate::ast::Stmt]) -> Program<'a> {
+    functions(MATH_SOURCE, file, arena, program)
+}
+
+fn functions<'a>(source: &str, file: usize, arena: &'a Arena<'a>, program: &[crate::ast::Stmt]) -> Program<'a> {
+    let prelude = Parser::from_lexer(Lexer::new(source).with_file(file), arena).parse_program().expect("the prelude parses");
+    let declared = |name: &str| program.iter().any(|stmt| matches!(&stmt.node, StmtKind::FnDecl(declared, ..) if declared == name));
+    prelude.into_iter().filter(|stmt| !matches!(&stmt.node, StmtKind::FnDecl(name, ..) if declared(name))).collect()
+}
\ No newline at end of file
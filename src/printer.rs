@@ -0,0 +1,528 @@
+use crate::ast::*;
+use crate::type_checker::TypeChecker;
+
+/// Renders `program` back into source text: one statement per line, blocks
+/// indented by four spaces and only the parentheses precedence requires.
+pub fn print_program(program: &[Stmt]) -> String {
+    let mut printer = Printer { out: String::new(), indent: 0, types: None };
+    printer.stmts(program);
+    printer.out
+}
+
+/// Like [`print_program`], but spells out what `checker` inferred: bindings
+/// and function signatures get full annotations and each expression
+/// statement is followed by a `// type` comment. `checker` must already have
+/// checked `program`.
+pub fn print_typed_program(program: &[Stmt], checker: &TypeChecker) -> String {
+    let mut printer = Printer { out: String::new(), indent: 0, types: Some(checker) };
+    printer.stmts(program);
+    printer.out
+}
+
+/// Binding strength of a binary operator; higher binds tighter.
+fn precedence(op: BinOp) -> u8 {
+    match op {
+        BinOp::Or => 1,
+        BinOp::And => 2,
+        BinOp::Eq | BinOp::Neq => 3,
+        BinOp::Gt | BinOp::Lt | BinOp::Ge | BinOp::Le => 4,
+        BinOp::BitOr => 5,
+        BinOp::BitXor => 6,
+        BinOp::BitAnd => 7,
+        BinOp::Shl | BinOp::Shr => 8,
+        BinOp::Add | BinOp::Sub => 9,
+        BinOp::Mul | BinOp::Div => 10,
+        BinOp::Pow => 12,
+    }
+}
+
+const UNARY: u8 = 11;
+const POSTFIX: u8 = 13;
+
+/// Quotes `s` as a string literal the lexer reads back unchanged.
+fn quote(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        push_escaped(&mut out, c, '"');
+    }
+    out.push('"');
+    out
+}
+
+/// Quotes `c` as a character literal the lexer reads back unchanged.
+fn quote_char(c: char) -> String {
+    let mut out = String::from("'");
+    push_escaped(&mut out, c, '\'');
+    out.push('\'');
+    out
+}
+
+/// Appends `c` as written inside a literal that ends at `delimiter`.
+fn push_escaped(out: &mut String, c: char, delimiter: char) {
+    match c {
+        '\n' => out.push_str("\\n"),
+        '\t' => out.push_str("\\t"),
+        '\r' => out.push_str("\\r"),
+        '\0' => out.push_str("\\0"),
+        '\\' => out.push_str("\\\\"),
+        c if c == delimiter => {
+            out.push('\\');
+            out.push(c);
+        }
+        c => out.push(c),
+    }
+}
+
+/// The innermost expression `expr` is written starting with.
+fn leftmost<'a>(expr: &'a Expr<'a>) -> &'a Expr<'a> {
+    match &expr.node {
+        ExprKind::Binary(lhs, ..) | ExprKind::Index(lhs, _) | ExprKind::Field(lhs, _) => leftmost(lhs),
+        _ => expr,
+    }
+}
+
+/// Whether `expr` is written starting with an `if`, a `match` or a `{`.
+fn starts_like_stmt(expr: &Expr) -> bool {
+    matches!(leftmost(expr).node, ExprKind::If(..) | ExprKind::Match(..) | ExprKind::Block(..))
+}
+
+/// Whether `expr` holds a struct literal, which a `for` range bound cannot
+/// without parentheses.
+fn has_struct_lit(expr: &Expr) -> bool {
+    match &expr.node {
+        ExprKind::StructLit(..) => true,
+        ExprKind::Unary(_, operand) | ExprKind::Field(operand, _) | ExprKind::Block(_, operand) => has_struct_lit(operand),
+        ExprKind::Binary(lhs, _, rhs) | ExprKind::Index(lhs, rhs) => has_struct_lit(lhs) || has_struct_lit(rhs),
+        ExprKind::Call(_, args) | ExprKind::Array(args) => args.iter().any(has_struct_lit),
+        ExprKind::Map(entries) => entries.iter().any(|(key, value)| has_struct_lit(key) || has_struct_lit(value)),
+        ExprKind::If(cond, then_value, else_value) => [cond, then_value, else_value].into_iter().any(|e| has_struct_lit(e)),
+        ExprKind::Match(value, arms) => has_struct_lit(value) || arms.iter().any(|arm| has_struct_lit(&arm.value)),
+        _ => false,
+    }
+}
+
+struct Printer<'a> {
+    out: String,
+    indent: usize,
+    types: Option<&'a TypeChecker>,
+}
+
+impl Printer<'_> {
+    fn start_line(&mut self) {
+        for _ in 0..self.indent {
+            self.out.push_str("    ");
+        }
+    }
+
+    fn stmts(&mut self, stmts: &[Stmt]) {
+        for (i, stmt) in stmts.iter().enumerate() {
+            // Functions, structs and enums are set off from their neighbours
+            // by a blank line.
+            let is_fn = |stmt: &Stmt| matches!(stmt.node, StmtKind::FnDecl(..) | StmtKind::StructDecl(..) | StmtKind::EnumDecl(..));
+            if i > 0 && (is_fn(stmt) || is_fn(&stmts[i - 1])) {
+                self.out.push('\n');
+            }
+            self.start_line();
+            self.stmt(stmt);
+            self.out.push('\n');
+        }
+    }
+
+    /// Writes `{`, the indented statements and a closing `}` with no newline.
+    fn block(&mut self, stmts: &[Stmt]) {
+        if stmts.is_empty() {
+            self.out.push_str("{}");
+            return;
+        }
+        self.out.push_str("{\n");
+        self.indent += 1;
+        self.stmts(stmts);
+        self.indent -= 1;
+        self.start_line();
+        self.out.push('}');
+    }
+
+    /// Writes a block ending with `value`, which needs no `;`.
+    fn block_value(&mut self, stmts: &[Stmt], value: &Expr) {
+        self.out.push_str("{\n");
+        self.indent += 1;
+        self.stmts(stmts);
+        self.start_line();
+        self.final_value(value);
+        self.out.push('\n');
+        self.indent -= 1;
+        self.start_line();
+        self.out.push('}');
+    }
+
+    /// Writes the value a block ends with. Only an `if` or a `match` itself
+    /// reads back as one; anything else starting like a statement needs
+    /// parentheses.
+    fn final_value(&mut self, value: &Expr) {
+        if starts_like_stmt(value) && !matches!(value.node, ExprKind::If(..) | ExprKind::Match(..)) {
+            self.out.push('(');
+            self.expr(value, 0);
+            self.out.push(')');
+        } else {
+            self.expr(value, 0);
+        }
+    }
+
+    fn annotation(&mut self, written: Option<&TypeExpr>, inferred: Option<String>) {
+        if let Some(ty) = written.map(TypeExpr::to_string).or(inferred) {
+            self.out.push_str(": ");
+            self.out.push_str(&ty);
+        }
+    }
+
+    /// Writes a `let`, `const`, assignment or expression statement without
+    /// its `;`, as it appears in the head of a `for` loop.
+    fn simple_stmt(&mut self, stmt: &Stmt) {
+        match &stmt.node {
+            StmtKind::Let(name, ty, expr) | StmtKind::Const(name, ty, expr) => {
+                self.out.push_str(if matches!(stmt.node, StmtKind::Const(..)) { "const " } else { "let " });
+                self.out.push_str(name);
+                let inferred = self.types.and_then(|types| types.expr_type(expr.span)).map(|t| t.to_string());
+                self.annotation(ty.as_ref(), inferred);
+                self.out.push_str(" = ");
+                self.expr(expr, 0);
+            }
+            StmtKind::Assign(name, expr) => {
+                self.out.push_str(name);
+                self.out.push_str(" = ");
+                self.expr(expr, 0);
+            }
+            StmtKind::IndexAssign(array, index, value) => {
+                self.expr(array, POSTFIX);
+                self.out.push('[');
+                self.expr(index, 0);
+                self.out.push_str("] = ");
+                self.expr(value, 0);
+            }
+            StmtKind::FieldAssign(object, field, value) => {
+                self.expr(object, POSTFIX);
+                self.out.push_str(&format!(".{} = ", field));
+                self.expr(value, 0);
+            }
+            StmtKind::Expr(expr) => self.expr(expr, 0),
+            _ => self.stmt(stmt),
+        }
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) {
+        match &stmt.node {
+            StmtKind::Let(..) | StmtKind::Const(..) | StmtKind::Assign(..) | StmtKind::IndexAssign(..) | StmtKind::FieldAssign(..) => {
+                self.simple_stmt(stmt);
+                self.out.push(';');
+            }
+            StmtKind::Expr(expr) => {
+                // A statement starting with `if` or `{` would read back as
+                // an `if` or block statement rather than an expression.
+                if starts_like_stmt(expr) {
+                    self.out.push('(');
+                    self.expr(expr, 0);
+                    self.out.push(')');
+                } else {
+                    self.simple_stmt(stmt);
+                }
+                self.out.push(';');
+                if let Some(t) = self.types.and_then(|types| types.expr_type(expr.span)) {
+                    self.out.push_str(&format!(" // {}", t));
+                }
+            }
+            StmtKind::If(cond, then_block, else_block) => {
+                self.out.push_str("if (");
+                self.expr(cond, 0);
+                self.out.push_str(") ");
+                self.block(then_block);
+                match *else_block {
+                    [] => {}
+                    [else_if @ Stmt { node: StmtKind::If(..), .. }] => {
+                        self.out.push_str(" else ");
+                        self.stmt(else_if);
+                    }
+                    _ => {
+                        self.out.push_str(" else ");
+                        self.block(else_block);
+                    }
+                }
+            }
+            StmtKind::While(cond, body) => {
+                self.out.push_str("while (");
+                self.expr(cond, 0);
+                self.out.push_str(") ");
+                self.block(body);
+            }
+            StmtKind::DoWhile(body, cond) => {
+                self.out.push_str("do ");
+                self.block(body);
+                self.out.push_str(" while (");
+                self.expr(cond, 0);
+                self.out.push_str(");");
+            }
+            StmtKind::For(init, cond, step, body) => {
+                self.out.push_str("for (");
+                self.simple_stmt(init);
+                self.out.push_str("; ");
+                self.expr(cond, 0);
+                self.out.push_str("; ");
+                self.simple_stmt(step);
+                self.out.push_str(") ");
+                self.block(body);
+            }
+            StmtKind::ForRange(var, start, end, inclusive, body) => {
+                self.out.push_str(&format!("for {} in ", var));
+                self.bound(start);
+                self.out.push_str(if *inclusive { "..=" } else { ".." });
+                self.bound(end);
+                self.out.push(' ');
+                self.block(body);
+            }
+            StmtKind::Match(value, arms) => {
+                self.out.push_str("match (");
+                self.expr(value, 0);
+                self.out.push_str(") {\n");
+                self.indent += 1;
+                for (i, arm) in arms.iter().enumerate() {
+                    self.start_line();
+                    match &arm.pattern {
+                        Pattern::Literal(literal) => self.expr(literal, 0),
+                        Pattern::Wildcard => self.out.push('_'),
+                    }
+                    self.out.push_str(" => ");
+                    self.block(arm.body);
+                    self.out.push_str(if i + 1 < arms.len() { ",\n" } else { "\n" });
+                }
+                self.indent -= 1;
+                self.start_line();
+                self.out.push('}');
+            }
+            StmtKind::FnDecl(name, params, return_type, body) => {
+                let signature = self.types.and_then(|types| types.function_type(name)).cloned();
+                self.out.push_str(&format!("fn {}(", name));
+                for (i, param) in params.iter().enumerate() {
+                    if i > 0 {
+                        self.out.push_str(", ");
+                    }
+                    self.out.push_str(&param.name);
+                    let inferred = signature.as_ref().map(|(param_types, _)| param_types[i].to_string());
+                    self.annotation(param.ty.as_ref(), inferred);
+                }
+                self.out.push(')');
+                let inferred = signature.as_ref().map(|(_, return_type)| return_type.to_string());
+                self.annotation(return_type.as_ref(), inferred);
+                self.out.push(' ');
+                match body.split_last() {
+                    // A final value written without `return` parses to a
+                    // `return` spanning just the value.
+                    Some((Stmt { node: StmtKind::Return(Some(value)), span }, stmts)) if *span == value.span => {
+                        self.block_value(stmts, value)
+                    }
+                    _ => self.block(body),
+                }
+            }
+            StmtKind::StructDecl(name, fields) => {
+                self.out.push_str(&format!("struct {} {{", name));
+                if !fields.is_empty() {
+                    self.out.push('\n');
+                    self.indent += 1;
+                    for (i, field) in fields.iter().enumerate() {
+                        self.start_line();
+                        self.out.push_str(&format!("{}: {}", field.name, field.ty));
+                        self.out.push_str(if i + 1 < fields.len() { ",\n" } else { "\n" });
+                    }
+                    self.indent -= 1;
+                    self.start_line();
+                }
+                self.out.push('}');
+            }
+            StmtKind::EnumDecl(name, variants) if variants.is_empty() => self.out.push_str(&format!("enum {} {{}}", name)),
+            StmtKind::EnumDecl(name, variants) => self.out.push_str(&format!("enum {} {{ {} }}", name, variants.iter().map(|variant| variant.as_str()).collect::<Vec<_>>().join(", "))),
+            StmtKind::TypeAlias(name, ty) => self.out.push_str(&format!("type {} = {};", name, ty)),
+            StmtKind::Import(path) => self.out.push_str(&format!("import {};", quote(path))),
+            StmtKind::Return(expr) => {
+                self.out.push_str("return");
+                if let Some(expr) = expr {
+                    self.out.push(' ');
+                    self.expr(expr, 0);
+                }
+                self.out.push(';');
+            }
+            StmtKind::Break => self.out.push_str("break;"),
+            StmtKind::Continue => self.out.push_str("continue;"),
+            StmtKind::Block(stmts) => self.block(stmts),
+        }
+    }
+
+    /// Writes `expr`, parenthesized if it binds more loosely than `min_precedence`.
+    fn expr(&mut self, expr: &Expr, min_precedence: u8) {
+        match &expr.node {
+            ExprKind::Number(n) => self.out.push_str(&n.to_string()),
+            ExprKind::Float(n) => self.out.push_str(&format!("{:?}", n)),
+            ExprKind::Str(s) => self.out.push_str(&quote(s)),
+            ExprKind::Char(c) => self.out.push_str(&quote_char(*c)),
+            ExprKind::Bool(b) => self.out.push_str(&b.to_string()),
+            ExprKind::Null => self.out.push_str("null"),
+            ExprKind::Variant(name, variant) => self.out.push_str(&format!("{}::{}", name, variant)),
+            ExprKind::Variable(name) => self.out.push_str(name),
+            ExprKind::Unary(op, operand) => {
+                let parens = min_precedence > UNARY;
+                if parens {
+                    self.out.push('(');
+                }
+                self.out.push_str(&op.to_string());
+                // `-(-x)` and `-(--x)` must not run together into `--`.
+                match (op, &operand.node) {
+                    (UnOp::Neg, ExprKind::Unary(UnOp::Neg, _) | ExprKind::Update(_, _, true)) => self.expr(operand, POSTFIX + 1),
+                    _ => self.expr(operand, UNARY),
+                }
+                if parens {
+                    self.out.push(')');
+                }
+            }
+            ExprKind::Update(name, op, prefix) => {
+                if *prefix {
+                    self.out.push_str(&format!("{}{}", op, name));
+                } else {
+                    self.out.push_str(&format!("{}{}", name, op));
+                }
+            }
+            ExprKind::Binary(lhs, op, rhs) => {
+                let precedence = precedence(*op);
+                let parens = precedence < min_precedence;
+                if parens {
+                    self.out.push('(');
+                }
+                // Operators are left-associative, so only the right operand
+                // needs parentheses at equal precedence. `**` groups to the
+                // right instead and takes a negated exponent as it is.
+                let (lhs_precedence, rhs_precedence) = match op {
+                    BinOp::Pow => (POSTFIX, UNARY),
+                    _ => (precedence, precedence + 1),
+                };
+                self.expr(lhs, lhs_precedence);
+                self.out.push_str(&format!(" {} ", op));
+                self.expr(rhs, rhs_precedence);
+                if parens {
+                    self.out.push(')');
+                }
+            }
+            ExprKind::Call(name, args) => {
+                self.out.push_str(name);
+                self.out.push('(');
+                self.list(args);
+                self.out.push(')');
+            }
+            ExprKind::Array(elements) => {
+                self.out.push('[');
+                self.list(elements);
+                self.out.push(']');
+            }
+            ExprKind::Map(entries) => {
+                self.out.push_str("{ ");
+                for (i, (key, value)) in entries.iter().enumerate() {
+                    if i > 0 {
+                        self.out.push_str(", ");
+                    }
+                    self.expr(key, 0);
+                    self.out.push_str(": ");
+                    self.expr(value, 0);
+                }
+                self.out.push_str(" }");
+            }
+            ExprKind::Index(array, index) => {
+                self.expr(array, POSTFIX);
+                self.out.push('[');
+                self.expr(index, 0);
+                self.out.push(']');
+            }
+            ExprKind::StructLit(name, fields) => {
+                self.out.push_str(name);
+                if fields.is_empty() {
+                    self.out.push_str(" {}");
+                    return;
+                }
+                self.out.push_str(" { ");
+                for (i, (field, value)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        self.out.push_str(", ");
+                    }
+                    self.out.push_str(&format!("{}: ", field));
+                    self.expr(value, 0);
+                }
+                self.out.push_str(" }");
+            }
+            ExprKind::Field(object, field) => {
+                self.expr(object, POSTFIX);
+                self.out.push_str(&format!(".{}", field));
+            }
+            ExprKind::If(cond, then_value, else_value) => {
+                self.out.push_str("if (");
+                self.expr(cond, 0);
+                self.out.push_str(") ");
+                self.branch(then_value);
+                self.out.push_str(" else ");
+                if matches!(else_value.node, ExprKind::If(..)) {
+                    self.expr(else_value, 0);
+                } else {
+                    self.branch(else_value);
+                }
+            }
+            ExprKind::Block(stmts, value) => self.block_value(stmts, value),
+            ExprKind::Match(value, arms) => {
+                self.out.push_str("match (");
+                self.expr(value, 0);
+                self.out.push_str(") {\n");
+                self.indent += 1;
+                for (i, arm) in arms.iter().enumerate() {
+                    self.start_line();
+                    match &arm.pattern {
+                        Pattern::Literal(literal) => self.expr(literal, 0),
+                        Pattern::Wildcard => self.out.push('_'),
+                    }
+                    self.out.push_str(" => ");
+                    self.branch(&arm.value);
+                    self.out.push_str(if i + 1 < arms.len() { ",\n" } else { "\n" });
+                }
+                self.indent -= 1;
+                self.start_line();
+                self.out.push('}');
+            }
+        }
+    }
+
+    /// Writes a branch of an `if` expression or an arm of a `match`
+    /// expression: `{ value }` on one line, or a block holding statements
+    /// over several.
+    fn branch(&mut self, value: &Expr) {
+        match &value.node {
+            ExprKind::Block(stmts, value) => self.block_value(stmts, value),
+            _ => {
+                self.out.push_str("{ ");
+                self.final_value(value);
+                self.out.push_str(" }");
+            }
+        }
+    }
+
+    /// Writes a `for` range bound, parenthesized if it holds a struct
+    /// literal the parser would take the `{` of for the loop body.
+    fn bound(&mut self, bound: &Expr) {
+        if has_struct_lit(bound) {
+            self.out.push('(');
+            self.expr(bound, 0);
+            self.out.push(')');
+        } else {
+            self.expr(bound, 0);
+        }
+    }
+
+    fn list(&mut self, exprs: &[Expr]) {
+        for (i, expr) in exprs.iter().enumerate() {
+            if i > 0 {
+                self.out.push_str(", ");
+            }
+            self.expr(expr, 0);
+        }
+    }
+}
\ No newline at end of file
@@ -0,0 +1,252 @@
+use ferrum::{diagnostics, prelude, Arena, CompilerError, Interpreter, Program, SourceMap, Token, Type, TypeChecker};
+use std::io::{self, Write};
+
+/// Runs an interactive session on standard input until end of input.
+/// Variables and functions persist between entries, the value of a trailing
+/// expression is printed, and an entry continues onto the next line while
+/// it has unclosed braces, brackets or parentheses. With `load_prelude`
+/// the prelude's functions are declared before the first entry. With the
+/// `readline` feature, a terminal gets line editing, history and tab
+/// completion; see [`Input`].
+pub fn run(load_prelude: bool) -> i32 {
+    // Every entry is parsed into this arena, since functions declared by
+    // one entry are called by later ones.
+    let arena = Arena::new();
+    let mut checker = TypeChecker::new();
+    let mut interpreter = Interpreter::new();
+    if load_prelude {
+        let functions = prelude::program(PRELUDE_FILE, &arena, &[]);
+        checker.check_input(&functions).expect("the prelude type checks");
+        interpreter.interpret_input(&functions).expect("the prelude declares functions only");
+    }
+    let mut input = Input::new();
+    input.declared(&checker);
+    let mut entry = String::new();
+    loop {
+        match input.read_line(if entry.is_empty() { "> " } else { ". " }) {
+            Ok(Line::Text(line)) => entry.push_str(&line),
+            #[cfg(feature = "readline")]
+            Ok(Line::Interrupted) => {
+                entry.clear();
+                continue;
+            }
+            Ok(Line::End) => break,
+            Err(e) => {
+                eprintln!("error: {}", e);
+                return 1;
+            }
+        }
+        if is_incomplete(&entry) {
+            continue;
+        }
+        let source = std::mem::take(&mut entry);
+        if !source.trim().is_empty() {
+            input.add_history(&source);
+            evaluate(&source, &arena, &mut checker, &mut interpreter);
+            input.declared(&checker);
+        }
+    }
+    input.finish();
+    println!();
+    0
+}
+
+/// What one read from the input gave.
+enum Line {
+    /// A line, ending with its newline if it had one.
+    Text(String),
+    /// Ctrl-C, which drops the entry typed so far.
+    #[cfg(feature = "readline")]
+    Interrupted,
+    /// The end of the input, or Ctrl-D.
+    End,
+}
+
+/// Where entries come from. Standard input is read as it is, unless it is
+/// a terminal and the `readline` feature is on: then a line editor reads
+/// it, with Ctrl-R searching the history, which is kept in
+/// `~/.ferrum_history` between sessions, and Tab completing keywords,
+/// builtins and the names declared so far.
+enum Input {
+    Plain,
+    #[cfg(feature = "readline")]
+    Editor(Box<rustyline::Editor<Completion, rustyline::history::DefaultHistory>>),
+}
+
+impl Input {
+    #[cfg(not(feature = "readline"))]
+    fn new() -> Self {
+        Input::Plain
+    }
+
+    #[cfg(feature = "readline")]
+    fn new() -> Self {
+        use std::io::IsTerminal;
+        if !io::stdin().is_terminal() {
+            return Input::Plain;
+        }
+        let Ok(mut editor) = rustyline::Editor::new() else {
+            return Input::Plain;
+        };
+        if let Some(path) = history_path() {
+            // There is no history before the first session.
+            let _ = editor.load_history(&path);
+        }
+        Input::Editor(Box::new(editor))
+    }
+
+    fn read_line(&mut self, prompt: &str) -> io::Result<Line> {
+        match self {
+            Input::Plain => {
+                print!("{}", prompt);
+                let _ = io::stdout().flush();
+                let mut line = String::new();
+                match io::stdin().read_line(&mut line)? {
+                    0 => Ok(Line::End),
+                    _ => Ok(Line::Text(line)),
+                }
+            }
+            #[cfg(feature = "readline")]
+            Input::Editor(editor) => match editor.readline(prompt) {
+                Ok(line) => Ok(Line::Text(line + "\n")),
+                Err(rustyline::error::ReadlineError::Interrupted) => Ok(Line::Interrupted),
+                Err(rustyline::error::ReadlineError::Eof) => Ok(Line::End),
+                Err(e) => Err(io::Error::other(e)),
+            },
+        }
+    }
+
+    /// Records a complete entry in the history.
+    #[cfg_attr(not(feature = "readline"), allow(unused_variables))]
+    fn add_history(&mut self, entry: &str) {
+        match self {
+            Input::Plain => {}
+            #[cfg(feature = "readline")]
+            Input::Editor(editor) => {
+                let _ = editor.add_history_entry(entry.trim_end());
+            }
+        }
+    }
+
+    /// Updates the names Tab completes to those `checker` has seen declared.
+    #[cfg_attr(not(feature = "readline"), allow(unused_variables))]
+    fn declared(&mut self, checker: &TypeChecker) {
+        match self {
+            Input::Plain => {}
+            #[cfg(feature = "readline")]
+            Input::Editor(editor) => {
+                let builtins = ferrum::builtins::BUILTINS.iter().map(|builtin| builtin.name);
+                let mut names: Vec<String> = ferrum::lexer::KEYWORDS.iter().copied().chain(builtins).chain(checker.names()).map(String::from).collect();
+                names.sort();
+                names.dedup();
+                editor.set_helper(Some(Completion { names }));
+            }
+        }
+    }
+
+    /// Saves the history for the next session.
+    fn finish(&mut self) {
+        match self {
+            Input::Plain => {}
+            #[cfg(feature = "readline")]
+            Input::Editor(editor) => {
+                if let Some(path) = history_path()
+                    && let Err(e) = editor.save_history(&path)
+                {
+                    eprintln!("warning: could not save the history to {}: {}", path.display(), e);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(feature = "readline")]
+fn history_path() -> Option<std::path::PathBuf> {
+    std::env::var_os("HOME").map(|home| std::path::Path::new(&home).join(".ferrum_history"))
+}
+
+/// Completes the word before the cursor to any of `names` it starts.
+#[cfg(feature = "readline")]
+struct Completion {
+    /// Sorted and without duplicates.
+    names: Vec<String>,
+}
+
+#[cfg(feature = "readline")]
+impl rustyline::completion::Completer for Completion {
+    type Candidate = String;
+
+    fn complete(&self, line: &str, pos: usize, _: &rustyline::Context<'_>) -> rustyline::Result<(usize, Vec<String>)> {
+        let start = line[..pos].char_indices().rev().take_while(|&(_, c)| unicode_ident::is_xid_continue(c)).last().map_or(pos, |(i, _)| i);
+        let word = &line[start..pos];
+        let names = if word.is_empty() { Vec::new() } else { self.names.iter().filter(|name| name.starts_with(word)).cloned().collect() };
+        Ok((start, names))
+    }
+}
+
+#[cfg(feature = "readline")]
+impl rustyline::hint::Hinter for Completion {
+    type Hint = String;
+}
+
+#[cfg(feature = "readline")]
+impl rustyline::highlight::Highlighter for Completion {}
+
+#[cfg(feature = "readline")]
+impl rustyline::validate::Validator for Completion {}
+
+#[cfg(feature = "readline")]
+impl rustyline::Helper for Completion {}
+
+/// Whether `source` opens more delimiters than it closes. Input that does
+/// not lex is complete as far as this is concerned; `evaluate` reports it.
+fn is_incomplete(source: &str) -> bool {
+    let Ok(tokens) = ferrum::Lexer::new(source).tokenize() else {
+        return false;
+    };
+    let depth: i64 = tokens
+        .iter()
+        .map(|t| match t.token {
+            Token::LBrace | Token::LBracket | Token::LParen => 1,
+            Token::RBrace | Token::RBracket | Token::RParen => -1,
+            _ => 0,
+        })
+        .sum();
+    depth > 0
+}
+
+fn evaluate<'a>(source: &str, arena: &'a Arena<'a>, checker: &mut TypeChecker, interpreter: &mut Interpreter<'a>) {
+    let program = match parse_entry(source, arena) {
+        Ok(program) => program,
+        Err(e) => return report(&e, source),
+    };
+    let result_type = match checker.check_input(&program) {
+        Ok(t) => t,
+        Err(errors) => {
+            for e in &errors {
+                report(e, source);
+            }
+            return;
+        }
+    };
+    match interpreter.interpret_input(&program) {
+        Ok(Some(value)) if result_type != Some(Type::Void) => println!("{}", value),
+        Ok(_) => {}
+        Err(e) => report(&e, source),
+    }
+}
+
+/// Parses an entry, also accepting a final expression without its `;`.
+fn parse_entry<'a>(source: &str, arena: &'a Arena<'a>) -> Result<Program<'a>, CompilerError> {
+    ferrum::compile_str(source, arena).or_else(|e| ferrum::compile_str(&format!("{};", source.trim_end()), arena).map_err(|_| e))
+}
+
+/// The prelude's file number; each entry is file 0.
+const PRELUDE_FILE: usize = 1;
+
+fn report(e: &CompilerError, source: &str) {
+    let mut sources = SourceMap::new();
+    sources.add("<repl>", source);
+    sources.add(prelude::NAME, prelude::SOURCE);
+    eprint!("{}", diagnostics::render_in(&e.into(), &sources));
+}
\ No newline at end of file
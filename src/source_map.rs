@@ -0,0 +1,52 @@
+use crate::span::Span;
+
+/// One source file of a program: the name it is reported under, usually
+/// its path, and its text.
+#[derive(Debug, Clone)]
+pub struct SourceFile {
+    pub name: String,
+    pub source: String,
+}
+
+/// The source files of a program, numbered from 0 in the order they are
+/// added. A [`Span`]'s `file` is that number; lex each file with
+/// [`crate::Lexer::with_file`] to get spans that say where they are.
+#[derive(Debug, Clone, Default)]
+pub struct SourceMap {
+    files: Vec<SourceFile>,
+}
+
+impl SourceMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a file and returns its number.
+    pub fn add(&mut self, name: impl Into<String>, source: impl Into<String>) -> usize {
+        self.files.push(SourceFile { name: name.into(), source: source.into() });
+        self.files.len() - 1
+    }
+
+    /// The number the next file added will get.
+    pub fn next_file(&self) -> usize {
+        self.files.len()
+    }
+
+    pub fn file(&self, file: usize) -> Option<&SourceFile> {
+        self.files.get(file)
+    }
+
+    pub fn files(&self) -> &[SourceFile] {
+        &self.files
+    }
+
+    /// The file `span` is in. Without a span this is the only file, if
+    /// there is just one.
+    pub fn file_of(&self, span: Option<Span>) -> Option<&SourceFile> {
+        match span {
+            Some(span) => self.file(span.file),
+            None if self.files.len() == 1 => self.files.first(),
+            None => None,
+        }
+    }
+}
\ No newline at end of file
@@ -0,0 +1,26 @@
+/// A region of source text. `start` and `end` are byte offsets (end
+/// exclusive); `line` and `col` are the 1-based position of `start`.
+/// `file` tells which of the program's source files they refer to, numbered
+/// from 0 in the order they were loaded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub col: usize,
+    pub file: usize,
+}
+
+impl Span {
+    /// The span covering both `self` and `other`, which must come later.
+    pub fn to(self, other: Span) -> Span {
+        Span { end: other.end, ..self }
+    }
+}
+
+impl std::fmt::Display for Span {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}, column {}", self.line, self.col)
+    }
+}
\ No newline at end of file
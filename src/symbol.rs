@@ -0,0 +1,117 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{LazyLock, RwLock};
+
+/// An identifier, stored once in a process-wide string table and passed
+/// around as its index there. Tokens, the AST and environments hold these
+/// instead of `String`s, so copying a name allocates nothing and comparing
+/// or hashing one is as cheap as a `u32`.
+///
+/// Interned text is never freed. A program has few distinct names, but a
+/// long-lived process that interns unbounded input keeps all of it.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Symbol(u32);
+
+#[derive(Default)]
+struct Interner {
+    symbols: HashMap<&'static str, Symbol>,
+    names: Vec<&'static str>,
+}
+
+static INTERNER: LazyLock<RwLock<Interner>> = LazyLock::new(Default::default);
+
+impl Symbol {
+    /// The symbol for `name`, adding it to the table the first time.
+    pub fn intern(name: &str) -> Self {
+        if let Some(&symbol) = INTERNER.read().expect("the interner lock is never poisoned").symbols.get(name) {
+            return symbol;
+        }
+        let mut interner = INTERNER.write().expect("the interner lock is never poisoned");
+        if let Some(&symbol) = interner.symbols.get(name) {
+            return symbol;
+        }
+        let name: &'static str = Box::leak(name.into());
+        let symbol = Symbol(u32::try_from(interner.names.len()).expect("fewer than 2^32 distinct names"));
+        interner.names.push(name);
+        interner.symbols.insert(name, symbol);
+        symbol
+    }
+
+    pub fn as_str(self) -> &'static str {
+        INTERNER.read().expect("the interner lock is never poisoned").names[self.0 as usize]
+    }
+}
+
+impl std::ops::Deref for Symbol {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl AsRef<str> for Symbol {
+    fn as_ref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl From<&str> for Symbol {
+    fn from(name: &str) -> Self {
+        Symbol::intern(name)
+    }
+}
+
+impl PartialEq<str> for Symbol {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl PartialEq<&str> for Symbol {
+    fn eq(&self, other: &&str) -> bool {
+        self.as_str() == *other
+    }
+}
+
+/// Symbols sort by their text, not by when they were interned, so sorted
+/// output does not depend on the order names were first seen.
+impl Ord for Symbol {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.as_str().cmp(other.as_str())
+    }
+}
+
+impl PartialOrd for Symbol {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+/// A symbol serializes as its text, which is interned again on the way in.
+#[cfg(feature = "serde")]
+impl serde::Serialize for Symbol {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Symbol {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        Ok(Symbol::intern(&name))
+    }
+}
\ No newline at end of file
@@ -0,0 +1,193 @@
+use crate::ast::{Expr, ExprKind, Stmt, StmtKind};
+use crate::environment::Environment;
+use crate::error::CompilerError;
+use crate::interpreter::Hook;
+use crate::printer;
+use crate::span::Span;
+use crate::symbol::Symbol;
+use crate::value::Value;
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::io::Write;
+
+/// Phase-by-phase account of what the compiler did, printed with `--verbose`.
+/// With the `tracing` feature enabled the same events go through a `tracing`
+/// subscriber instead, nested under per-phase and per-function spans.
+pub struct PhaseLog {
+    verbose: bool,
+}
+
+impl PhaseLog {
+    pub fn new(verbose: bool) -> Self {
+        #[cfg(feature = "tracing")]
+        if verbose {
+            tracing_subscriber::fmt()
+                .with_max_level(tracing::Level::DEBUG)
+                .with_writer(std::io::stderr)
+                .init();
+        }
+        Self { verbose }
+    }
+
+    pub fn event(&self, phase: &str, message: impl Display) {
+        if !self.verbose {
+            return;
+        }
+        #[cfg(feature = "tracing")]
+        tracing::info!(phase, "{}", message);
+        #[cfg(not(feature = "tracing"))]
+        eprintln!("[{}] {}", phase, message);
+    }
+}
+
+/// Keeps a tracing span entered until dropped. Without the `tracing`
+/// feature this is a no-op.
+#[cfg(feature = "tracing")]
+pub struct SpanGuard(#[allow(dead_code)] tracing::span::EnteredSpan);
+#[cfg(not(feature = "tracing"))]
+pub struct SpanGuard;
+
+pub fn enter_phase(name: &'static str) -> SpanGuard {
+    #[cfg(feature = "tracing")]
+    return SpanGuard(tracing::info_span!("phase", name).entered());
+    #[cfg(not(feature = "tracing"))]
+    {
+        let _ = name;
+        SpanGuard
+    }
+}
+
+pub fn enter_function(name: &str) -> SpanGuard {
+    #[cfg(feature = "tracing")]
+    return SpanGuard(tracing::debug_span!("fn", name).entered());
+    #[cfg(not(feature = "tracing"))]
+    {
+        let _ = name;
+        SpanGuard
+    }
+}
+
+
+/// Logs every statement the interpreter runs, for `--trace`: its position,
+/// the call depth, its first line of source and the current values of the
+/// variables it mentions, indented by call depth.
+///
+/// ```text
+/// 7:5 [depth 0]     total = total + square(i); | total = 0, i = 1
+/// 2:5 [depth 1]       let r = n * n; | n = 1
+/// ```
+pub struct Tracer {
+    output: Box<dyn Write>,
+    /// The printed first line of each statement seen so far.
+    text: HashMap<Span, String>,
+}
+
+impl Tracer {
+    pub fn new(output: impl Write + 'static) -> Self {
+        Self { output: Box::new(output), text: HashMap::new() }
+    }
+}
+
+impl Hook for Tracer {
+    fn before_stmt(&mut self, stmt: &Stmt, env: &Environment<Value>, call_depth: usize) -> Result<(), CompilerError> {
+        // Declarations run nothing.
+        if matches!(stmt.node, StmtKind::FnDecl(..)) {
+            return Ok(());
+        }
+        let text = self.text.entry(stmt.span).or_insert_with(|| {
+            let printed = printer::print_program(std::slice::from_ref(stmt));
+            printed.lines().next().unwrap_or("").to_string()
+        });
+        let mut names = Vec::new();
+        mentioned_names(stmt, &mut names);
+        let values: Vec<String> = names
+            .iter()
+            .filter_map(|&name| env.get(name).map(|value| format!("{} = {}", name, value)))
+            .collect();
+        let span = stmt.span;
+        let indent = "  ".repeat(call_depth);
+        let written = if values.is_empty() {
+            writeln!(self.output, "{}:{} [depth {}] {}{}", span.line, span.col, call_depth, indent, text)
+        } else {
+            writeln!(self.output, "{}:{} [depth {}] {}{} | {}", span.line, span.col, call_depth, indent, text, values.join(", "))
+        };
+        written.map_err(|e| CompilerError::RuntimeError(format!("Failed to write the trace: {}", e), Some(span)))
+    }
+}
+
+/// The variables `stmt` reads or writes before entering any nested block,
+/// in order of appearance and without repeats.
+fn mentioned_names(stmt: &Stmt, names: &mut Vec<Symbol>) {
+    match &stmt.node {
+        StmtKind::Let(_, _, expr) | StmtKind::Const(_, _, expr) | StmtKind::Expr(expr) | StmtKind::Return(Some(expr)) => expr_names(expr, names),
+        StmtKind::Assign(name, expr) => {
+            add_name(*name, names);
+            expr_names(expr, names);
+        }
+        StmtKind::IndexAssign(array, index, value) => {
+            expr_names(array, names);
+            expr_names(index, names);
+            expr_names(value, names);
+        }
+        StmtKind::FieldAssign(object, _, value) => {
+            expr_names(object, names);
+            expr_names(value, names);
+        }
+        StmtKind::If(cond, ..) | StmtKind::While(cond, _) | StmtKind::DoWhile(_, cond) => expr_names(cond, names),
+        StmtKind::Match(value, _) => expr_names(value, names),
+        StmtKind::For(init, ..) => mentioned_names(init, names),
+        StmtKind::ForRange(_, start, end, ..) => {
+            expr_names(start, names);
+            expr_names(end, names);
+        }
+        StmtKind::FnDecl(..) | StmtKind::StructDecl(..) | StmtKind::EnumDecl(..) | StmtKind::TypeAlias(..) | StmtKind::Import(..) | StmtKind::Return(None) | StmtKind::Break | StmtKind::Continue | StmtKind::Block(_) => {}
+    }
+}
+
+fn expr_names(expr: &Expr, names: &mut Vec<Symbol>) {
+    match &expr.node {
+        ExprKind::Number(_) | ExprKind::Float(_) | ExprKind::Str(_) | ExprKind::Char(_) | ExprKind::Bool(_) | ExprKind::Null | ExprKind::Variant(..) => {}
+        ExprKind::Variable(name) | ExprKind::Update(name, ..) => add_name(*name, names),
+        ExprKind::Unary(_, operand) | ExprKind::Field(operand, _) => expr_names(operand, names),
+        ExprKind::Binary(lhs, _, rhs) | ExprKind::Index(lhs, rhs) => {
+            expr_names(lhs, names);
+            expr_names(rhs, names);
+        }
+        ExprKind::Call(_, args) | ExprKind::Array(args) => {
+            for arg in args.iter() {
+                expr_names(arg, names);
+            }
+        }
+        ExprKind::Map(entries) => {
+            for (key, value) in entries {
+                expr_names(key, names);
+                expr_names(value, names);
+            }
+        }
+        ExprKind::StructLit(_, fields) => {
+            for (_, value) in fields {
+                expr_names(value, names);
+            }
+        }
+        ExprKind::If(cond, then_value, else_value) => {
+            expr_names(cond, names);
+            expr_names(then_value, names);
+            expr_names(else_value, names);
+        }
+        ExprKind::Match(value, arms) => {
+            expr_names(value, names);
+            for arm in arms {
+                expr_names(&arm.value, names);
+            }
+        }
+        // Like a nested block statement, its contents are left to their own
+        // statements; the value at the end may use names declared there.
+        ExprKind::Block(..) => {}
+    }
+}
+
+fn add_name(name: Symbol, names: &mut Vec<Symbol>) {
+    if !names.contains(&name) {
+        names.push(name);
+    }
+}
\ No newline at end of file
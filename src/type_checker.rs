@@ -1,144 +1,1152 @@
 use crate::ast::*;
+use crate::builtins;
+use crate::diagnostics::with_suggestion;
+use crate::environment::Environment;
 use crate::error::CompilerError;
+use crate::inference::{self, Evidence};
+use crate::span::Span;
+use crate::symbol::Symbol;
 use std::collections::HashMap;
 
+/// Static types. Arithmetic never mixes `Int` and `Float`: an expression
+/// combining the two is a type error rather than an implicit widening.
 #[derive(Debug, Clone, PartialEq)]
 pub enum Type {
     Int,
+    Float,
     Bool,
+    String,
+    Char,
+    Array(Box<Type>),
+    /// A map from strings to values of the given type.
+    Map(Box<Type>),
+    /// A declared struct, by name.
+    Struct(Symbol),
+    /// A declared enum, by name.
+    Enum(Symbol),
+    /// A value of the given type or `null`.
+    Option(Box<Type>),
     Void,
+    Error,   // Result of an ill-typed expression; never reported twice
+}
+
+impl Type {
+    /// Whether a value of type `other` can be stored where `self` is expected.
+    /// `Error` is compatible with everything so a mistake is reported once;
+    /// it also stands in for the unknown element type of `[]` and value type
+    /// of `map()`, and for the type `null` has on its own. An optional
+    /// type accepts its non-null type, but not the other way round.
+    pub fn accepts(&self, other: &Type) -> bool {
+        match (self, other) {
+            (Type::Error, _) | (_, Type::Error) => true,
+            (Type::Array(a), Type::Array(b)) | (Type::Map(a), Type::Map(b)) | (Type::Option(a), Type::Option(b)) => a.accepts(b),
+            (Type::Option(a), b) => a.accepts(b),
+            _ => self == other,
+        }
+    }
+
+    /// The narrowest type accepting both `self` and `other`, as needed for
+    /// the elements of an array literal or the branches of an `if`: `null`
+    /// and an `int` make an `int?`. `None` if there is no such type.
+    pub fn join(&self, other: &Type) -> Option<Type> {
+        match (self, other) {
+            (Type::Error, t) | (t, Type::Error) => Some(t.clone()),
+            (Type::Option(a), Type::Option(b)) => Some(Type::Option(Box::new(a.join(b)?))),
+            (Type::Option(a), b) | (b, Type::Option(a)) => Some(Type::Option(Box::new(a.join(b)?))),
+            (Type::Array(a), Type::Array(b)) => Some(Type::Array(Box::new(a.join(b)?))),
+            (Type::Map(a), Type::Map(b)) => Some(Type::Map(Box::new(a.join(b)?))),
+            _ if self == other => Some(self.clone()),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for Type {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Type::Array(element) => return write!(f, "[{}]", element),
+            Type::Map(value) => return write!(f, "{{string: {}}}", value),
+            Type::Struct(name) | Type::Enum(name) => return write!(f, "{}", name),
+            Type::Option(value) if **value == Type::Error => "null",
+            Type::Option(value) => return write!(f, "{}?", value),
+            Type::Int => "int",
+            Type::Float => "float",
+            Type::Bool => "bool",
+            Type::String => "string",
+            Type::Char => "char",
+            Type::Void => "void",
+            Type::Error => "{error}",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// The type a built-in type name such as `int` stands for.
+pub(crate) fn builtin_type(name: &str) -> Option<Type> {
+    match name {
+        "int" => Some(Type::Int),
+        "float" => Some(Type::Float),
+        "bool" => Some(Type::Bool),
+        "string" => Some(Type::String),
+        "char" => Some(Type::Char),
+        "void" => Some(Type::Void),
+        _ => None,
+    }
+}
+
+/// A variable in scope: its type and whether it was declared with `const`.
+#[derive(Clone)]
+struct Binding {
+    ty: Type,
+    constant: bool,
+    /// Where the input being checked declares it; `None` for globals the
+    /// host or an earlier input declared, which may be declared again.
+    declared: Option<Span>,
+}
+
+impl Binding {
+    fn variable(ty: Type) -> Self {
+        Self { ty, constant: false, declared: None }
+    }
 }
 
 pub struct TypeChecker {
-    env: HashMap<String, Type>,
-    functions: HashMap<String, (Vec<Type>, Type)>,
+    env: Environment<Binding>,
+    functions: HashMap<Symbol, (Vec<Type>, Type)>,
+    /// The fields of each declared struct, in declaration order.
+    structs: HashMap<Symbol, Vec<(Symbol, Type)>>,
+    /// The variants of each declared enum, in declaration order.
+    enums: HashMap<Symbol, Vec<Symbol>>,
+    /// The type each alias declared with `type Name = ...;` stands for.
+    aliases: HashMap<Symbol, Type>,
+    /// Aliases declared in the input being checked whose types are not
+    /// resolved yet, and the ones being resolved, to catch cycles.
+    pending_aliases: HashMap<Symbol, (TypeExpr, Span)>,
+    resolving: Vec<Symbol>,
+    errors: Vec<CompilerError>,
+    loop_depth: usize,
+    /// Result type of the function being checked; `None` at top level.
+    /// `Error` while an unannotated function's type is still being inferred.
+    return_type: Option<Type>,
+    /// The first `return` of an unannotated function, once one has given
+    /// its result a type; later ones widen it or conflict with it.
+    first_return: Option<Span>,
+    /// The type of every expression checked so far, keyed by its span.
+    expr_types: HashMap<Span, Type>,
+    /// Variables known not to be null, innermost last: the name, the scope
+    /// binding it to its non-null type and its declared optional type.
+    narrowed: Vec<(Symbol, usize, Type)>,
+}
+
+impl Default for TypeChecker {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl TypeChecker {
     pub fn new() -> Self {
         Self {
-            env: HashMap::new(),
+            env: Environment::new(),
             functions: HashMap::new(),
+            structs: HashMap::new(),
+            enums: HashMap::new(),
+            aliases: HashMap::new(),
+            pending_aliases: HashMap::new(),
+            resolving: Vec::new(),
+            errors: Vec::new(),
+            loop_depth: 0,
+            return_type: None,
+            first_return: None,
+            expr_types: HashMap::new(),
+            narrowed: Vec::new(),
         }
     }
 
-    pub fn check_program(&mut self, program: &[Stmt]) -> Result<(), CompilerError> {
-        for stmt in program {
-            self.check_stmt(stmt)?;
+    /// Declares a host function registered with
+    /// [`crate::Interpreter::register_native`], so calls to it are checked
+    /// against `params` and have type `result`. A user function of the same
+    /// name replaces it.
+    pub fn register_native(&mut self, name: &str, params: Vec<Type>, result: Type) {
+        self.functions.insert(Symbol::intern(name), (params, result));
+    }
+
+    /// Declares a global variable of type `ty` set by the host with
+    /// [`crate::Interpreter::set_global`].
+    pub fn declare_global(&mut self, name: &str, ty: Type) {
+        self.env.define(Symbol::intern(name), Binding::variable(ty));
+    }
+
+    /// Checks the whole program and returns every type error found, not just the first.
+    pub fn check_program(&mut self, program: &[Stmt]) -> Result<(), Vec<CompilerError>> {
+        self.check_input(program).map(|_| ())
+    }
+
+    /// Checks one more piece of a program, such as a REPL entry, against the
+    /// declarations of earlier calls. Returns the type of a trailing
+    /// expression statement.
+    pub fn check_input(&mut self, input: &[Stmt]) -> Result<Option<Type>, Vec<CompilerError>> {
+        for binding in self.env.values_mut() {
+            binding.declared = None;
+        }
+        self.declare_types(input);
+        self.declare_functions(input);
+        let mut last = None;
+        for stmt in input {
+            last = match &stmt.node {
+                StmtKind::Expr(expr) => Some(self.check_expr(expr)),
+                StmtKind::StructDecl(..) | StmtKind::EnumDecl(..) | StmtKind::TypeAlias(..) | StmtKind::Import(..) => None,
+                _ => {
+                    self.check_stmt(stmt);
+                    None
+                }
+            };
+        }
+        if self.errors.is_empty() {
+            Ok(last)
+        } else {
+            Err(std::mem::take(&mut self.errors))
         }
-        Ok(())
     }
 
-    fn check_stmt(&mut self, stmt: &Stmt) -> Result<(), CompilerError> {
-        match stmt {
-            Stmt::Let(name, expr) => {
-                let t = self.check_expr(expr)?;
-                self.env.insert(name.clone(), t);
+    fn error(&mut self, msg: String, span: Span) -> Type {
+        self.errors.push(CompilerError::TypeError(msg, Some(span)));
+        Type::Error
+    }
+
+    fn undeclared_variable(&mut self, name: &str, span: Span) -> Type {
+        let msg = with_suggestion(format!("Undeclared variable: {}", name), name, self.env.names());
+        self.error(msg, span)
+    }
+
+    fn undefined_function(&mut self, name: &str, span: Span) -> Type {
+        let candidates = self.functions.keys().map(|name| name.as_str()).chain(builtins::BUILTINS.iter().map(|builtin| builtin.name));
+        let msg = with_suggestion(format!("Undefined function: {}", name), name, candidates);
+        self.error(msg, span)
+    }
+
+    fn check_stmt(&mut self, stmt: &Stmt) {
+        match &stmt.node {
+            StmtKind::Let(name, ty, expr) | StmtKind::Const(name, ty, expr) => {
+                let t = self.check_expr(expr);
+                let declared = match ty {
+                    Some(ty) => {
+                        let declared = self.resolve_type(ty, stmt.span);
+                        if !declared.accepts(&t) {
+                            self.error(format!("Cannot initialize {} of type {} with a value of type {}", name, declared, t), expr.span);
+                        }
+                        declared
+                    }
+                    None => t,
+                };
+                let constant = matches!(stmt.node, StmtKind::Const(..));
+                self.declare_variable(*name, Binding { ty: declared, constant, declared: Some(stmt.span) }, stmt.span);
             }
-            Stmt::Assign(name, expr) => {
-                let t = self.check_expr(expr)?;
-                if let Some(var_type) = self.env.get(name) {
-                    if *var_type != t {
-                        return Err(CompilerError::TypeError(format!("Type mismatch in assignment to {}", name)));
+            StmtKind::Assign(name, expr) => {
+                let t = self.check_expr(expr);
+                match self.env.get(*name) {
+                    Some(binding) if binding.constant => {
+                        self.error(format!("Cannot assign to constant {}", name), stmt.span);
+                    }
+                    Some(binding) if binding.ty.accepts(&t) => {}
+                    Some(_) => match self.narrowing_of(*name) {
+                        // Storing a possibly null value ends the narrowing.
+                        Some(declared) if declared.accepts(&t) => {
+                            self.env.assign(*name, Binding::variable(declared));
+                        }
+                        _ => {
+                            self.error(format!("Type mismatch in assignment to {}", name), stmt.span);
+                        }
+                    },
+                    None => {
+                        self.undeclared_variable(name, stmt.span);
                     }
-                } else {
-                    return Err(CompilerError::TypeError(format!("Undeclared variable: {}", name)));
                 }
             }
-            Stmt::If(cond, then_block, else_block) => {
-                let cond_type = self.check_expr(cond)?;
-                if cond_type != Type::Bool {
-                    return Err(CompilerError::TypeError("Condition in 'if' must be a boolean".to_string()));
+            StmtKind::IndexAssign(array, index, value) => {
+                let element = self.check_index(array, index);
+                let t = self.check_expr(value);
+                if !element.accepts(&t) {
+                    let container = if matches!(self.expr_type(array.span), Some(Type::Map(_))) { "a map" } else { "an array" };
+                    self.error(format!("Cannot store {} in {} of {}", t, container, element), value.span);
                 }
-                for stmt in then_block {
-                    self.check_stmt(stmt)?;
+            }
+            StmtKind::FieldAssign(object, field, value) => {
+                let field_type = self.check_field(object, *field, stmt.span);
+                let t = self.check_expr(value);
+                if !field_type.accepts(&t) {
+                    self.error(format!("Cannot store {} in field {} of type {}", t, field, field_type), value.span);
                 }
-                for stmt in else_block {
-                    self.check_stmt(stmt)?;
+            }
+            // Only found at top level, where `declare_types` registers them.
+            StmtKind::StructDecl(..) | StmtKind::EnumDecl(..) | StmtKind::TypeAlias(..) | StmtKind::Import(..) => {}
+            StmtKind::If(cond, then_block, else_block) => {
+                let cond_type = self.check_expr(cond);
+                if cond_type != Type::Bool && cond_type != Type::Error {
+                    self.error("Condition in 'if' must be a boolean".to_string(), cond.span);
                 }
+                let narrowed = self.narrow(cond, true);
+                self.check_block(then_block);
+                self.end_narrowing(narrowed);
+                let narrowed = self.narrow(cond, false);
+                self.check_block(else_block);
+                self.end_narrowing(narrowed);
             }
-            Stmt::While(cond, body) | Stmt::DoWhile(body, cond) => {
-                let cond_type = self.check_expr(cond)?;
-                if cond_type != Type::Bool {
-                    return Err(CompilerError::TypeError("Condition in loop must be a boolean".to_string()));
+            StmtKind::While(cond, body) | StmtKind::DoWhile(body, cond) => {
+                let cond_type = self.check_expr(cond);
+                if cond_type != Type::Bool && cond_type != Type::Error {
+                    self.error("Condition in loop must be a boolean".to_string(), cond.span);
                 }
-                for stmt in body {
-                    self.check_stmt(stmt)?;
+                // A `do`-`while` body runs once before the condition is checked.
+                let narrowed = matches!(stmt.node, StmtKind::While(..)) && self.narrow(cond, true);
+                self.check_loop_body(body);
+                self.end_narrowing(narrowed);
+            }
+            StmtKind::For(init, cond, step, body) => {
+                self.env.push_scope();
+                self.check_stmt(init);
+                let cond_type = self.check_expr(cond);
+                if cond_type != Type::Bool && cond_type != Type::Error {
+                    self.error("Condition in loop must be a boolean".to_string(), cond.span);
                 }
+                self.check_stmt(step);
+                self.check_loop_body(body);
+                self.env.pop_scope();
             }
-            Stmt::For(var, start, cond, step, body) => {
-                let t_start = self.check_expr(start)?;
-                let t_cond = self.check_expr(cond)?;
-                let t_step = self.check_expr(step)?;
-                if t_start != Type::Int || t_cond != Type::Bool || t_step != Type::Int {
-                    return Err(CompilerError::TypeError("Invalid types in 'for' loop".to_string()));
+            StmtKind::ForRange(var, start, end, _, body) => {
+                for bound in [start, end] {
+                    let t = self.check_expr(bound);
+                    if t != Type::Int && t != Type::Error {
+                        self.error(format!("Range bounds must be int, found {}", t), bound.span);
+                    }
                 }
-                self.env.insert(var.clone(), Type::Int);
-                for stmt in body {
-                    self.check_stmt(stmt)?;
+                self.env.push_scope();
+                self.env.define(*var, Binding { declared: Some(stmt.span), ..Binding::variable(Type::Int) });
+                self.check_loop_body(body);
+                self.env.pop_scope();
+            }
+            StmtKind::Match(value, arms) => {
+                let t = self.check_expr(value);
+                for arm in arms {
+                    self.check_pattern(&t, &arm.pattern, arm.span);
+                    self.check_block(arm.body);
                 }
+                let patterns: Vec<&Pattern> = arms.iter().map(|arm| &arm.pattern).collect();
+                self.check_exhaustive(&t, &patterns, stmt.span);
             }
-            Stmt::FnDecl(name, params, body) => {
-                let param_types = vec![Type::Int; params.len()];
-                self.functions.insert(name.clone(), (param_types.clone(), Type::Int));
-                for (i, param) in params.iter().enumerate() {
-                    self.env.insert(param.clone(), param_types[i].clone());
+            StmtKind::FnDecl(name, params, _, body) => {
+                let (param_types, return_type) = self.functions[name].clone();
+                // The parameters and locals get a frame of their own over the
+                // globals, as in a call, and are gone once the body is checked.
+                self.env.enter_call();
+                for (param, t) in params.iter().zip(param_types) {
+                    self.declare_variable(param.name, Binding { declared: Some(stmt.span), ..Binding::variable(t) }, stmt.span);
+                }
+                // A loop around the declaration does not make break/continue valid inside it.
+                let outer_loop_depth = std::mem::replace(&mut self.loop_depth, 0);
+                let outer_return_type = self.return_type.replace(return_type);
+                let outer_first_return = self.first_return.take();
+                let outer_narrowed = std::mem::take(&mut self.narrowed);
+                self.check_stmts(body);
+                self.narrowed = outer_narrowed;
+                self.first_return = outer_first_return;
+                self.env.exit_call();
+                self.loop_depth = outer_loop_depth;
+                let return_type = std::mem::replace(&mut self.return_type, outer_return_type).unwrap_or(Type::Error);
+                if let Some(signature) = self.functions.get_mut(name) {
+                    signature.1 = return_type.clone();
                 }
-                for stmt in body {
-                    self.check_stmt(stmt)?;
+                if !matches!(return_type, Type::Void | Type::Error) && !always_returns(body) {
+                    self.error(format!("Function {} must return a value of type {} on every path", name, return_type), stmt.span);
                 }
             }
-            Stmt::Return(expr) => {
-                self.check_expr(expr)?;
+            StmtKind::Return(expr) => self.check_return(expr.as_ref(), stmt.span),
+            StmtKind::Expr(expr) => {
+                self.check_expr(expr);
             }
-            Stmt::Expr(expr) => {
-                self.check_expr(expr)?;
+            StmtKind::Block(stmts) => self.check_block(stmts),
+            StmtKind::Break | StmtKind::Continue => {
+                if self.loop_depth == 0 {
+                    let keyword = if matches!(stmt.node, StmtKind::Break) { "break" } else { "continue" };
+                    self.error(format!("'{}' outside of a loop", keyword), stmt.span);
+                }
             }
         }
-        Ok(())
     }
 
-    fn check_expr(&mut self, expr: &Expr) -> Result<Type, CompilerError> {
-        match expr {
-            Expr::Number(_) => Ok(Type::Int),
-            Expr::Bool(_) => Ok(Type::Bool),
-            Expr::Variable(name) => self.env.get(name).cloned().ok_or_else(|| CompilerError::TypeError(format!("Undeclared variable: {}", name))),
-            Expr::Binary(lhs, op, rhs) => {
-                let lt = self.check_expr(lhs)?;
-                let rt = self.check_expr(rhs)?;
+    /// Registers the signature of every function declared directly in
+    /// `stmts` before any of them is checked, so calls may precede the
+    /// declaration and functions may be mutually recursive.
+    fn declare_functions(&mut self, stmts: &[Stmt]) {
+        let evidence = inference::gather(stmts, self);
+        let mut declared = Vec::new();
+        for stmt in stmts {
+            let StmtKind::FnDecl(name, params, return_type, body) = &stmt.node else {
+                continue;
+            };
+            if declared.contains(&name) {
+                self.error(format!("Function {} is already declared", name), stmt.span);
+                continue;
+            }
+            declared.push(name);
+            // An unannotated result is void if no `return` carries a value,
+            // otherwise it is inferred from them when the body is checked.
+            let param_types: Vec<Type> = params
+                .iter()
+                .enumerate()
+                .map(|(index, param)| match &param.ty {
+                    Some(ty) => self.resolve_type(ty, stmt.span),
+                    None => self.infer_param(*name, index, param.name, &evidence),
+                })
+                .collect();
+            let return_type = match return_type {
+                Some(ty) => self.resolve_type(ty, stmt.span),
+                None if returns_value(body) => Type::Error,
+                None => Type::Void,
+            };
+            self.functions.insert(*name, (param_types, return_type));
+        }
+    }
+
+    /// The type of an unannotated parameter: the one its uses in the body
+    /// agree on, else the one the calls pass, else `int`.
+    fn infer_param(&mut self, function: Symbol, index: usize, param: Symbol, evidence: &Evidence) -> Type {
+        let key = (function, index);
+        if let Some(((first, first_span), rest)) = evidence.uses.get(&key).and_then(|uses| uses.split_first()) {
+            let mut t = first.clone();
+            for (used, span) in rest {
+                match t.join(used) {
+                    Some(joined) => t = joined,
+                    None => {
+                        let msg = format!(
+                            "Parameter {} of {} is used as {} on line {} but as {} here; annotate its type",
+                            param, function, t, first_span.line, used
+                        );
+                        return self.error(msg, *span);
+                    }
+                }
+            }
+            return t;
+        }
+        // Calls that disagree are reported when they are checked.
+        let calls = evidence.calls.get(&key).map_or(&[][..], Vec::as_slice);
+        calls.iter().fold(None, |t: Option<Type>, (passed, _)| match t {
+            None => Some(passed.clone()),
+            Some(t) => Some(t.join(passed).unwrap_or(t)),
+        })
+        .unwrap_or(Type::Int)
+    }
+
+    /// Registers every struct, enum and type alias declared in `stmts`:
+    /// first the struct names and the enums, so any type may mention them,
+    /// then the aliases, then the fields of the structs.
+    fn declare_types(&mut self, stmts: &[Stmt]) {
+        let mut declared = Vec::new();
+        for stmt in stmts {
+            let StmtKind::StructDecl(name, fields) = &stmt.node else {
+                continue;
+            };
+            if declared.iter().any(|(other, _, _)| *other == name) {
+                self.error(format!("Struct {} is already declared", name), stmt.span);
+                continue;
+            }
+            if builtin_type(name).is_some() {
+                self.error(format!("Cannot declare a struct named {}, a built-in type", name), stmt.span);
+                continue;
+            }
+            if self.aliases.contains_key(name) || self.enums.contains_key(name) {
+                self.error(format!("Type {} is already declared", name), stmt.span);
+                continue;
+            }
+            self.structs.insert(*name, Vec::new());
+            declared.push((name, fields, stmt.span));
+        }
+        self.declare_enums(stmts);
+        self.declare_aliases(stmts);
+        for (name, fields, span) in declared {
+            let mut resolved: Vec<(Symbol, Type)> = Vec::new();
+            for field in fields {
+                if resolved.iter().any(|(other, _)| *other == field.name) {
+                    self.error(format!("Field {} is declared twice in struct {}", field.name, name), span);
+                    continue;
+                }
+                let t = self.resolve_type(&field.ty, span);
+                resolved.push((field.name, t));
+            }
+            self.structs.insert(*name, resolved);
+        }
+    }
+
+    fn declare_enums(&mut self, stmts: &[Stmt]) {
+        let mut declared = Vec::new();
+        for stmt in stmts {
+            let StmtKind::EnumDecl(name, variants) = &stmt.node else {
+                continue;
+            };
+            if builtin_type(name).is_some() {
+                self.error(format!("Cannot declare an enum named {}, a built-in type", name), stmt.span);
+                continue;
+            }
+            if declared.contains(&name) || self.structs.contains_key(name) || self.aliases.contains_key(name) {
+                self.error(format!("Type {} is already declared", name), stmt.span);
+                continue;
+            }
+            declared.push(name);
+            let mut unique: Vec<Symbol> = Vec::new();
+            for variant in variants {
+                if unique.contains(variant) {
+                    self.error(format!("Variant {} is declared twice in enum {}", variant, name), stmt.span);
+                } else {
+                    unique.push(*variant);
+                }
+            }
+            self.enums.insert(*name, unique);
+        }
+    }
+
+    /// Resolves every alias declared in `stmts`, in any order, as aliases
+    /// may mention ones declared after them.
+    fn declare_aliases(&mut self, stmts: &[Stmt]) {
+        let mut declared = Vec::new();
+        for stmt in stmts {
+            let StmtKind::TypeAlias(name, ty) = &stmt.node else {
+                continue;
+            };
+            if builtin_type(name).is_some() {
+                self.error(format!("Cannot declare a type named {}, a built-in type", name), stmt.span);
+            } else if self.structs.contains_key(name)
+                || self.enums.contains_key(name)
+                || self.aliases.contains_key(name)
+                || self.pending_aliases.contains_key(name)
+            {
+                self.error(format!("Type {} is already declared", name), stmt.span);
+            } else {
+                self.pending_aliases.insert(*name, (ty.clone(), stmt.span));
+                declared.push(*name);
+            }
+        }
+        for name in declared {
+            // Resolving an earlier alias may have resolved this one already.
+            if let Some((ty, span)) = self.pending_aliases.remove(&name) {
+                self.resolve_alias(name, &ty, span);
+            }
+        }
+    }
+
+    fn resolve_alias(&mut self, name: Symbol, ty: &TypeExpr, span: Span) -> Type {
+        self.resolving.push(name);
+        let t = self.resolve_type(ty, span);
+        self.resolving.pop();
+        self.aliases.insert(name, t.clone());
+        t
+    }
+
+    fn check_block(&mut self, stmts: &[Stmt]) {
+        self.env.push_scope();
+        self.check_stmts(stmts);
+        self.env.pop_scope();
+    }
+
+    /// Checks `stmts` in order. After `if (x == null) { return; }` and the
+    /// like, `x` is not null for the rest of them.
+    fn check_stmts(&mut self, stmts: &[Stmt]) {
+        let mut narrowings = Vec::new();
+        for stmt in stmts {
+            self.check_stmt(stmt);
+            if let StmtKind::If(cond, then_block, else_block) = &stmt.node
+                && else_block.is_empty()
+                && always_exits(then_block)
+            {
+                narrowings.push(self.narrow(cond, false));
+            }
+        }
+        for narrowed in narrowings.into_iter().rev() {
+            self.end_narrowing(narrowed);
+        }
+    }
+
+    /// Binds `name` in the innermost scope, unless the input being checked
+    /// already declares it in the same block. An inner block may shadow it.
+    fn declare_variable(&mut self, name: Symbol, binding: Binding, span: Span) {
+        // The scopes `narrow` opens after an early exit are part of the
+        // block around them.
+        let same_block = self.env.scope_of(name).is_some_and(|at| (at + 1..self.env.depth()).all(|scope| self.narrowed.iter().any(|(_, narrowed, _)| *narrowed == scope)));
+        if let Some(earlier) = self.env.get(name).and_then(|earlier| earlier.declared).filter(|_| same_block) {
+            self.error(format!("Variable {} is already declared on line {}; assign to it without `let` or pick another name", name, earlier.line), span);
+            return;
+        }
+        self.env.define(name, binding);
+    }
+
+    /// If `cond` evaluating to `holds` means a variable of optional type is
+    /// not null, opens a scope in which it has its non-null type and
+    /// returns true. Close the scope with `end_narrowing`.
+    fn narrow(&mut self, cond: &Expr, holds: bool) -> bool {
+        let Some((name, not_null)) = null_check(cond) else {
+            return false;
+        };
+        let Some(Binding { ty: Type::Option(value), constant, declared }) = self.env.get(name).cloned() else {
+            return false;
+        };
+        if not_null != holds {
+            return false;
+        }
+        self.env.push_scope();
+        self.env.define(name, Binding { ty: (*value).clone(), constant, declared });
+        let scope = self.env.scope_of(name).unwrap_or_default();
+        self.narrowed.push((name, scope, Type::Option(value)));
+        true
+    }
+
+    fn end_narrowing(&mut self, narrowed: bool) {
+        if narrowed {
+            self.env.pop_scope();
+            self.narrowed.pop();
+        }
+    }
+
+    /// The declared type of `name` if the binding in view is one `narrow`
+    /// made, rather than a variable declared inside the narrowed code.
+    fn narrowing_of(&self, name: Symbol) -> Option<Type> {
+        let scope = self.env.scope_of(name)?;
+        self.narrowed.iter().rev().find(|(narrowed, at, _)| *narrowed == name && *at == scope).map(|(_, _, declared)| declared.clone())
+    }
+
+    /// Reports a use of a possibly null value where a non-null one is needed.
+    fn maybe_null(&mut self, t: &Type, span: Span) -> Type {
+        self.error(format!("Value of type {} may be null; compare it with null first", t), span)
+    }
+
+    fn check_loop_body(&mut self, body: &[Stmt]) {
+        self.loop_depth += 1;
+        self.check_block(body);
+        self.loop_depth -= 1;
+    }
+
+    /// Checks a `return` against the enclosing function's result type.
+    fn check_return(&mut self, expr: Option<&Expr>, span: Span) {
+        let t = expr.map_or(Type::Void, |expr| self.check_expr(expr));
+        let expected = match &self.return_type {
+            // A top-level `return` just ends the program.
+            None => return,
+            Some(Type::Error) if self.first_return.is_none() => {
+                self.return_type = Some(t);
+                self.first_return = Some(span);
+                return;
+            }
+            Some(expected) => expected.clone(),
+        };
+        // An inferred result widens to cover every `return`, so `null` and
+        // an `int` make an `int?`.
+        if let Some(first) = self.first_return {
+            match expected.join(&t) {
+                Some(joined) if (joined == Type::Void) == (expected == Type::Void) => self.return_type = Some(joined),
+                _ => {
+                    self.error(format!("Function returns {} on line {} but {} here; annotate its return type", expected, first.line, t), span);
+                }
+            }
+            return;
+        }
+        if expected == Type::Void && t != Type::Void {
+            self.error("Cannot return a value from a void function".to_string(), span);
+        } else if expected != Type::Void && t == Type::Void && expr.is_none() {
+            self.error(format!("Missing return value of type {}", expected), span);
+        } else if !expected.accepts(&t) {
+            self.error(format!("Expected a return value of type {}, found {}", expected, t), span);
+        }
+    }
+
+    /// Turns a written type into a `Type`, reporting unknown names.
+    fn resolve_type(&mut self, ty: &TypeExpr, span: Span) -> Type {
+        match ty {
+            TypeExpr::Named(name) => match self.named_type(*name) {
+                Some(t) => t,
+                None => match self.pending_aliases.remove(name) {
+                    Some((ty, alias_span)) => self.resolve_alias(*name, &ty, alias_span),
+                    None if self.resolving.contains(name) => self.error(format!("Type alias {} refers to itself", name), span),
+                    None => self.error(format!("Unknown type '{}'", name), span),
+                },
+            },
+            TypeExpr::Array(element) => Type::Array(Box::new(self.resolve_type(element, span))),
+            TypeExpr::Option(value) => match self.resolve_type(value, span) {
+                Type::Void => self.error("Type void cannot be optional".to_string(), span),
+                value => Type::Option(Box::new(value)),
+            },
+            TypeExpr::Map(key, value) => {
+                let key = self.resolve_type(key, span);
+                let value = self.resolve_type(value, span);
+                if !Type::String.accepts(&key) {
+                    self.error(format!("Map keys must be strings, found {}", key), span);
+                }
+                Type::Map(Box::new(value))
+            }
+        }
+    }
+
+    /// Checks `array[index]` or `map[key]` and returns the element or value
+    /// type.
+    fn check_index(&mut self, array: &Expr, index: &Expr) -> Type {
+        let array_type = self.check_expr(array);
+        let index_type = self.check_expr(index);
+        match array_type {
+            Type::Array(element) => {
+                if !Type::Int.accepts(&index_type) {
+                    self.error(format!("Array index must be an int, found {}", index_type), index.span);
+                }
+                *element
+            }
+            Type::Map(value) => {
+                if !Type::String.accepts(&index_type) {
+                    self.error(format!("Map key must be a string, found {}", index_type), index.span);
+                }
+                *value
+            }
+            Type::Error => Type::Error,
+            Type::Option(_) => self.maybe_null(&array_type, array.span),
+            other => self.error(format!("Cannot index into a value of type {}", other), array.span),
+        }
+    }
+
+    /// Checks that a `match` without a wildcard arm on a value of type `t`
+    /// has an arm for every variant, which needs `t` to be an enum.
+    /// Checks that an arm's `pattern`, written at `span`, can match a value
+    /// of type `t`.
+    fn check_pattern(&mut self, t: &Type, pattern: &Pattern, span: Span) {
+        if let Pattern::Literal(literal) = pattern {
+            let pattern_type = self.check_expr(literal);
+            if !t.accepts(&pattern_type) {
+                self.error(format!("Pattern of type {} cannot match a value of type {}", pattern_type, t), span);
+            }
+        }
+    }
+
+    fn check_exhaustive(&mut self, t: &Type, patterns: &[&Pattern], span: Span) {
+        if patterns.iter().any(|pattern| matches!(pattern, Pattern::Wildcard)) {
+            return;
+        }
+        let name = match t {
+            Type::Enum(name) => name,
+            Type::Error => return,
+            _ => {
+                self.error("Match must have a wildcard arm `_ => { ... }`".to_string(), span);
+                return;
+            }
+        };
+        let variants = self.enums.get(name).cloned().unwrap_or_default();
+        let covered = |variant: &Symbol| {
+            patterns.iter().any(|pattern| matches!(pattern, Pattern::Literal(Expr { node: ExprKind::Variant(_, v), .. }) if v == variant))
+        };
+        let missing: Vec<&str> = variants.iter().filter(|variant| !covered(variant)).map(|variant| variant.as_str()).collect();
+        if !missing.is_empty() {
+            let msg = format!("Match on {} does not cover {}; add an arm for each or a wildcard arm `_ => {{ ... }}`", name, missing.join(", "));
+            self.error(msg, span);
+        }
+    }
+
+    /// Checks a struct literal against the declaration of `name`.
+    fn check_struct_lit(&mut self, name: Symbol, fields: &[(Symbol, Expr)], span: Span) -> Type {
+        let types: Vec<Type> = fields.iter().map(|(_, value)| self.check_expr(value)).collect();
+        let Some(declared) = self.structs.get(&name).cloned() else {
+            let msg = with_suggestion(format!("Unknown struct: {}", name), &name, self.structs.keys().map(|name| name.as_str()));
+            return self.error(msg, span);
+        };
+        for (i, ((field, value), t)) in fields.iter().zip(types).enumerate() {
+            if fields[..i].iter().any(|(earlier, _)| earlier == field) {
+                self.error(format!("Field {} is given twice", field), value.span);
+                continue;
+            }
+            match declared.iter().find(|(name, _)| name == field) {
+                Some((_, expected)) if !expected.accepts(&t) => {
+                    self.error(format!("Field {} of {} has type {}, found {}", field, name, expected, t), value.span);
+                }
+                Some(_) => {}
+                None => {
+                    let msg = format!("Struct {} has no field {}", name, field);
+                    let msg = with_suggestion(msg, field, declared.iter().map(|(name, _)| name.as_str()));
+                    self.error(msg, value.span);
+                }
+            }
+        }
+        let missing: Vec<&str> =
+            declared.iter().map(|(field, _)| field.as_str()).filter(|field| !fields.iter().any(|(given, _)| given == field)).collect();
+        if !missing.is_empty() {
+            self.error(format!("Missing {} in {} literal", missing.join(", "), name), span);
+        }
+        Type::Struct(name)
+    }
+
+    /// Matches a literal `format` template against the number of values
+    /// given after it; a computed template is only checked when it runs.
+    fn check_format_template(&mut self, args: &[Expr], t: Type) -> Type {
+        let ExprKind::Str(template) = &args[0].node else {
+            return t;
+        };
+        match builtins::format_placeholders(template) {
+            Ok(expected) if expected == args.len() - 1 => t,
+            Ok(expected) => self.error(builtins::placeholder_mismatch(expected, args.len() - 1), args[0].span),
+            Err(msg) => self.error(msg, args[0].span),
+        }
+    }
+
+    /// Checks `object.field` and returns the field's type.
+    fn check_field(&mut self, object: &Expr, field: Symbol, span: Span) -> Type {
+        let name = match self.check_expr(object) {
+            Type::Struct(name) => name,
+            Type::Error => return Type::Error,
+            t @ Type::Option(_) => return self.maybe_null(&t, object.span),
+            other => return self.error(format!("Cannot access field {} on a value of type {}", field, other), span),
+        };
+        let fields = self.structs.get(&name).cloned().unwrap_or_default();
+        match fields.iter().find(|(declared, _)| *declared == field) {
+            Some((_, t)) => t.clone(),
+            None => {
+                let msg = format!("Struct {} has no field {}", name, field);
+                let msg = with_suggestion(msg, &field, fields.iter().map(|(name, _)| name.as_str()));
+                self.error(msg, span)
+            }
+        }
+    }
+
+    /// The type found for the expression at `span`, once it has been checked.
+    pub fn expr_type(&self, span: Span) -> Option<&Type> {
+        self.expr_types.get(&span)
+    }
+
+    /// The type a name written as a type stands for, if it is built in or
+    /// declared. Aliases still being resolved are not found.
+    pub(crate) fn named_type(&self, name: Symbol) -> Option<Type> {
+        builtin_type(&name).or_else(|| {
+            if self.structs.contains_key(&name) {
+                Some(Type::Struct(name))
+            } else if self.enums.contains_key(&name) {
+                Some(Type::Enum(name))
+            } else {
+                self.aliases.get(&name).cloned()
+            }
+        })
+    }
+
+    /// The fields of the struct called `name`, in declaration order.
+    pub(crate) fn struct_fields(&self, name: Symbol) -> Option<&[(Symbol, Type)]> {
+        self.structs.get(&name).map(Vec::as_slice)
+    }
+
+    /// The names of the structs with a field called `field`.
+    pub(crate) fn structs_with_field(&self, field: Symbol) -> impl Iterator<Item = Symbol> + '_ {
+        self.structs.iter().filter(move |(_, fields)| fields.iter().any(|(name, _)| *name == field)).map(|(name, _)| *name)
+    }
+
+    /// Every name declared so far that is in scope: variables, functions,
+    /// including natives, structs, enums and type aliases.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        let types = self.structs.keys().chain(self.enums.keys()).chain(self.aliases.keys());
+        self.env.names().chain(self.functions.keys().chain(types).map(|name| name.as_str()))
+    }
+
+    /// The parameter and return types of the function called `name`.
+    pub fn function_type(&self, name: &str) -> Option<&(Vec<Type>, Type)> {
+        self.functions.get(&Symbol::intern(name))
+    }
+
+    fn check_expr(&mut self, expr: &Expr) -> Type {
+        let t = self.infer_expr(expr);
+        self.expr_types.insert(expr.span, t.clone());
+        t
+    }
+
+    fn infer_expr(&mut self, expr: &Expr) -> Type {
+        match &expr.node {
+            ExprKind::Number(_) => Type::Int,
+            ExprKind::Float(_) => Type::Float,
+            ExprKind::Str(_) => Type::String,
+            ExprKind::Char(_) => Type::Char,
+            ExprKind::Bool(_) => Type::Bool,
+            ExprKind::Null => Type::Option(Box::new(Type::Error)),
+            ExprKind::Variable(name) => match self.env.get(*name) {
+                Some(binding) => binding.ty.clone(),
+                None => self.undeclared_variable(name, expr.span),
+            },
+            ExprKind::Unary(UnOp::BitNot, operand) => {
+                let t = self.check_expr(operand);
+                match t {
+                    Type::Int | Type::Error => t,
+                    Type::Option(_) => self.maybe_null(&t, operand.span),
+                    _ => self.error(format!("Operand of '~' must be an int, found {}", t), expr.span),
+                }
+            }
+            ExprKind::Unary(op, operand) => {
+                let t = self.check_expr(operand);
+                match t {
+                    Type::Int | Type::Float | Type::Error => t,
+                    Type::Option(_) => self.maybe_null(&t, operand.span),
+                    _ => self.error(format!("Operand of '{}' must be a number, found {}", op, t), expr.span),
+                }
+            }
+            ExprKind::Update(name, op, _) => match self.env.get(*name) {
+                Some(binding) if binding.constant => self.error(format!("Cannot assign to constant {}", name), expr.span),
+                Some(Binding { ty: Type::Int, .. }) => Type::Int,
+                Some(Binding { ty: Type::Error, .. }) => Type::Error,
+                Some(binding) => {
+                    let t = binding.ty.clone();
+                    self.error(format!("Operand of '{}' must be an int variable, found {}", op, t), expr.span)
+                }
+                None => self.undeclared_variable(name, expr.span),
+            },
+            ExprKind::Binary(lhs, op, rhs) => {
+                let lt = self.check_expr(lhs);
+                // `x != null && x > 0` and `x == null || x > 0` only check
+                // their right side when `x` is not null.
+                let narrowed = match op {
+                    BinOp::And => self.narrow(lhs, true),
+                    BinOp::Or => self.narrow(lhs, false),
+                    _ => false,
+                };
+                let rt = self.check_expr(rhs);
+                self.end_narrowing(narrowed);
+                if lt == Type::Error || rt == Type::Error {
+                    return Type::Error;
+                }
+                if matches!(op, BinOp::Eq | BinOp::Neq) && (matches!(lt, Type::Option(_)) || matches!(rt, Type::Option(_))) {
+                    return if lt.accepts(&rt) || rt.accepts(&lt) {
+                        Type::Bool
+                    } else {
+                        self.error("Operands must be of the same type".to_string(), expr.span)
+                    };
+                }
+                if matches!(lt, Type::Option(_)) {
+                    return self.maybe_null(&lt, lhs.span);
+                }
+                if matches!(rt, Type::Option(_)) {
+                    return self.maybe_null(&rt, rhs.span);
+                }
                 match op {
-                    BinOp::Add | BinOp::Sub | BinOp::Mul | BinOp::Div => {
+                    BinOp::Add if lt == Type::String && rt == Type::String => Type::String,
+                    BinOp::Add | BinOp::Sub | BinOp::Mul | BinOp::Div | BinOp::Pow => match (&lt, &rt) {
+                        (Type::Int, Type::Int) => Type::Int,
+                        (Type::Float, Type::Float) => Type::Float,
+                        _ => self.error(
+                            format!("Operands of '{}' must both be int or both be float, found {} and {}", op, lt, rt),
+                            expr.span,
+                        ),
+                    },
+                    BinOp::BitAnd | BinOp::BitOr | BinOp::BitXor | BinOp::Shl | BinOp::Shr => {
                         if lt == Type::Int && rt == Type::Int {
-                            Ok(Type::Int)
+                            Type::Int
                         } else {
-                            Err(CompilerError::TypeError("Operands must be integers".to_string()))
+                            self.error(format!("Operands of '{}' must be int, found {} and {}", op, lt, rt), expr.span)
                         }
                     }
-                    BinOp::Eq | BinOp::Neq | BinOp::Gt | BinOp::Lt => {
-                        if lt == rt {
-                            Ok(Type::Bool)
+                    BinOp::And | BinOp::Or => {
+                        if lt == Type::Bool && rt == Type::Bool {
+                            Type::Bool
                         } else {
-                            Err(CompilerError::TypeError("Operands must be of the same type".to_string()))
+                            self.error(format!("Operands of '{}' must be bool, found {} and {}", op, lt, rt), expr.span)
+                        }
+                    }
+                    BinOp::Eq | BinOp::Neq | BinOp::Gt | BinOp::Lt | BinOp::Ge | BinOp::Le if lt != rt => {
+                        self.error("Operands must be of the same type".to_string(), expr.span)
+                    }
+                    BinOp::Eq | BinOp::Neq => Type::Bool,
+                    // Only numbers, strings and chars have an order, as in
+                    // `ops::binary`.
+                    BinOp::Gt | BinOp::Lt | BinOp::Ge | BinOp::Le => match lt {
+                        Type::Int | Type::Float | Type::String | Type::Char => Type::Bool,
+                        Type::Enum(_) => {
+                            self.error(format!("Operator '{}' is not defined for enums; compare them with '==' or '!='", op), expr.span)
+                        }
+                        Type::Array(_) | Type::Map(_) | Type::Struct(_) => {
+                            self.error(format!("Operator '{}' is not defined for {}; compare them with '==' or '!='", op, lt), expr.span)
+                        }
+                        _ => self.error(format!("Operator '{}' is not defined for {}", op, lt), expr.span),
+                    },
+                }
+            }
+            ExprKind::Array(elements) => {
+                let mut element_type = Type::Error;
+                for element in elements.iter() {
+                    let t = self.check_expr(element);
+                    match element_type.join(&t) {
+                        Some(joined) => element_type = joined,
+                        None => {
+                            self.error(format!("Array elements must all be {}, found {}", element_type, t), element.span);
                         }
                     }
                 }
+                Type::Array(Box::new(element_type))
             }
-            Expr::Call(name, args) => {
-                if let Some((param_types, return_type)) = self.functions.get(name) {
-                    if args.len() != param_types.len() {
-                        return Err(CompilerError::TypeError(format!("Incorrect number of arguments in call to {}", name)));
+            ExprKind::Map(entries) => {
+                let mut value_type = Type::Error;
+                for (key, value) in entries {
+                    let key_type = self.check_expr(key);
+                    if !Type::String.accepts(&key_type) {
+                        self.error(format!("Map keys must be strings, found {}", key_type), key.span);
                     }
-                    for (arg, expected) in args.iter().zip(param_types) {
-                        let arg_type = self.check_expr(arg)?;
-                        if arg_type != *expected {
-                            return Err(CompilerError::TypeError("Argument type mismatch".to_string()));
+                    let t = self.check_expr(value);
+                    match value_type.join(&t) {
+                        Some(joined) => value_type = joined,
+                        None => {
+                            self.error(format!("Map values must all be {}, found {}", value_type, t), value.span);
                         }
                     }
-                    Ok(return_type.clone())
-                } else {
-                    Err(CompilerError::TypeError(format!("Undefined function: {}", name)))
+                }
+                Type::Map(Box::new(value_type))
+            }
+            ExprKind::Index(array, index) => self.check_index(array, index),
+            ExprKind::If(cond, then_value, else_value) => {
+                let cond_type = self.check_expr(cond);
+                if cond_type != Type::Bool && cond_type != Type::Error {
+                    self.error("Condition in 'if' must be a boolean".to_string(), cond.span);
+                }
+                let narrowed = self.narrow(cond, true);
+                let then_type = self.check_expr(then_value);
+                self.end_narrowing(narrowed);
+                let narrowed = self.narrow(cond, false);
+                let else_type = self.check_expr(else_value);
+                self.end_narrowing(narrowed);
+                match then_type.join(&else_type) {
+                    Some(t) => t,
+                    None => self.error(format!("Branches of 'if' must have the same type, found {} and {}", then_type, else_type), expr.span),
                 }
             }
+            ExprKind::Match(value, arms) => {
+                let t = self.check_expr(value);
+                let mut result: Option<Type> = None;
+                for arm in arms {
+                    self.check_pattern(&t, &arm.pattern, arm.span);
+                    let arm_type = self.check_expr(&arm.value);
+                    result = match result {
+                        None => Some(arm_type),
+                        Some(so_far) => match so_far.join(&arm_type) {
+                            Some(joined) => Some(joined),
+                            None => {
+                                self.error(format!("Arms of 'match' must have the same type, found {} and {}", so_far, arm_type), arm.value.span);
+                                Some(so_far)
+                            }
+                        },
+                    };
+                }
+                let patterns: Vec<&Pattern> = arms.iter().map(|arm| &arm.pattern).collect();
+                self.check_exhaustive(&t, &patterns, expr.span);
+                result.unwrap_or(Type::Error)
+            }
+            ExprKind::StructLit(name, fields) => self.check_struct_lit(*name, fields, expr.span),
+            ExprKind::Field(object, field) => self.check_field(object, *field, expr.span),
+            ExprKind::Variant(name, variant) => match self.enums.get(name) {
+                Some(variants) if variants.contains(variant) => Type::Enum(*name),
+                Some(variants) => {
+                    let msg = with_suggestion(format!("Enum {} has no variant {}", name, variant), variant, variants.iter().map(|variant| variant.as_str()));
+                    self.error(msg, expr.span)
+                }
+                None => {
+                    let msg = with_suggestion(format!("Unknown enum: {}", name), name, self.enums.keys().map(|name| name.as_str()));
+                    self.error(msg, expr.span)
+                }
+            },
+            ExprKind::Block(stmts, value) => {
+                if let Some(stmt) = misplaced_in_value_block(stmts, false) {
+                    let msg = match stmt.node {
+                        StmtKind::Break => "'break' cannot leave a block used as a value".to_string(),
+                        StmtKind::Continue => "'continue' cannot leave a block used as a value".to_string(),
+                        _ => "'return' cannot leave a block used as a value".to_string(),
+                    };
+                    self.error(msg, stmt.span);
+                }
+                self.env.push_scope();
+                self.check_stmts(stmts);
+                let t = self.check_expr(value);
+                self.env.pop_scope();
+                t
+            }
+            ExprKind::Call(name, args) => {
+                let arg_types: Vec<Type> = args.iter().map(|arg| self.check_expr(arg)).collect();
+                let Some((param_types, return_type)) = self.functions.get(name).cloned() else {
+                    return match builtins::lookup(name).map(|builtin| (builtin.check)(&arg_types)) {
+                        Some(Ok(t)) if name == "format" => self.check_format_template(args, t),
+                        Some(Ok(t)) => t,
+                        Some(Err(msg)) => self.error(msg, expr.span),
+                        None => self.undefined_function(name, expr.span),
+                    };
+                };
+                if args.len() != param_types.len() {
+                    return self.error(format!("Incorrect number of arguments in call to {}", name), expr.span);
+                }
+                for ((arg, arg_type), expected) in args.iter().zip(&arg_types).zip(&param_types) {
+                    if !expected.accepts(arg_type) {
+                        self.error("Argument type mismatch".to_string(), arg.span);
+                    }
+                }
+                return_type
+            }
+        }
+    }
+}
+
+/// Whether any `return` in `stmts`, outside nested functions, carries a value.
+fn returns_value(stmts: &[Stmt]) -> bool {
+    stmts.iter().any(|stmt| match &stmt.node {
+        StmtKind::Return(expr) => expr.is_some(),
+        StmtKind::If(_, then_block, else_block) => returns_value(then_block) || returns_value(else_block),
+        StmtKind::While(_, body)
+        | StmtKind::DoWhile(body, _)
+        | StmtKind::For(.., body)
+        | StmtKind::ForRange(.., body)
+        | StmtKind::Block(body) => returns_value(body),
+        StmtKind::Match(_, arms) => arms.iter().any(|arm| returns_value(arm.body)),
+        _ => false,
+    })
+}
+
+/// The first statement in `stmts` that a block used as a value cannot
+/// hold: a `return`, or a `break` or `continue` outside a loop within the
+/// block.
+fn misplaced_in_value_block<'a>(stmts: &'a [Stmt<'a>], in_loop: bool) -> Option<&'a Stmt<'a>> {
+    stmts.iter().find_map(|stmt| match &stmt.node {
+        StmtKind::Return(_) => Some(stmt),
+        StmtKind::Break | StmtKind::Continue if !in_loop => Some(stmt),
+        StmtKind::If(_, then_block, else_block) => misplaced_in_value_block(then_block, in_loop).or_else(|| misplaced_in_value_block(else_block, in_loop)),
+        StmtKind::Block(body) => misplaced_in_value_block(body, in_loop),
+        StmtKind::Match(_, arms) => arms.iter().find_map(|arm| misplaced_in_value_block(arm.body, in_loop)),
+        StmtKind::While(_, body) | StmtKind::DoWhile(body, _) | StmtKind::For(.., body) | StmtKind::ForRange(.., body) => {
+            misplaced_in_value_block(body, true)
         }
+        _ => None,
+    })
+}
+
+/// The variable that `cond` compares with `null`, and whether the
+/// comparison is `!=`, so that the variable is not null when `cond` holds.
+fn null_check(cond: &Expr) -> Option<(Symbol, bool)> {
+    let ExprKind::Binary(lhs, op @ (BinOp::Eq | BinOp::Neq), rhs) = &cond.node else {
+        return None;
+    };
+    match (&lhs.node, &rhs.node) {
+        (ExprKind::Variable(name), ExprKind::Null) | (ExprKind::Null, ExprKind::Variable(name)) => Some((*name, matches!(op, BinOp::Neq))),
+        _ => None,
     }
 }
+
+/// Whether running `stmts` always ends in a `return`, `break` or `continue`,
+/// never reaching the code after them.
+fn always_exits(stmts: &[Stmt]) -> bool {
+    stmts.iter().any(|stmt| match &stmt.node {
+        StmtKind::Return(_) | StmtKind::Break | StmtKind::Continue => true,
+        StmtKind::If(_, then_block, else_block) => always_exits(then_block) && always_exits(else_block),
+        StmtKind::Block(body) => always_exits(body),
+        StmtKind::Match(_, arms) => arms.iter().all(|arm| always_exits(arm.body)),
+        _ => false,
+    })
+}
+
+/// Whether running `stmts` always ends in a `return`. Loops are assumed to
+/// possibly run zero times or exit with `break`.
+fn always_returns(stmts: &[Stmt]) -> bool {
+    stmts.iter().any(|stmt| match &stmt.node {
+        StmtKind::Return(_) => true,
+        StmtKind::If(_, then_block, else_block) => always_returns(then_block) && always_returns(else_block),
+        StmtKind::Block(body) => always_returns(body),
+        // The checker makes sure some arm matches: a wildcard, or one for
+        // each variant of an enum.
+        StmtKind::Match(_, arms) => arms.iter().all(|arm| always_returns(arm.body)),
+        _ => false,
+    })
+}
\ No newline at end of file
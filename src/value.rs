@@ -0,0 +1,233 @@
+use crate::symbol::Symbol;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::rc::Rc;
+
+/// Shared, mutable storage behind an array value.
+pub type ArrayRef = Rc<RefCell<Vec<Value>>>;
+
+/// Shared, mutable storage behind a struct value.
+pub type StructRef = Rc<RefCell<Struct>>;
+
+/// Shared, mutable storage behind a map value, kept sorted by key.
+pub type MapRef = Rc<RefCell<BTreeMap<String, Value>>>;
+
+/// Pairs of shared values an equality test is comparing.
+type Visited = HashSet<(*const (), *const ())>;
+
+/// An instance of a declared struct, with its fields in declaration order.
+#[derive(Debug, Clone)]
+pub struct Struct {
+    pub name: Symbol,
+    pub fields: Vec<(Symbol, Value)>,
+}
+
+impl Struct {
+    pub fn field(&self, name: Symbol) -> Option<&Value> {
+        self.fields.iter().find(|(field, _)| *field == name).map(|(_, value)| value)
+    }
+
+    pub fn field_mut(&mut self, name: Symbol) -> Option<&mut Value> {
+        self.fields.iter_mut().find(|(field, _)| *field == name).map(|(_, value)| value)
+    }
+
+    fn equals(&self, other: &Struct, visited: &mut Visited) -> bool {
+        self.name == other.name
+            && self.fields.len() == other.fields.len()
+            && self.fields.iter().zip(&other.fields).all(|((a, x), (b, y))| a == b && x.equals(y, visited))
+    }
+}
+
+impl PartialEq for Struct {
+    fn eq(&self, other: &Struct) -> bool {
+        self.equals(other, &mut HashSet::new())
+    }
+}
+
+/// A runtime value produced by the interpreter.
+#[derive(Debug, Clone)]
+pub enum Value {
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    Str(String),
+    /// A Unicode scalar value.
+    Char(char),
+    /// Arrays live on the heap and are shared by reference, so an element
+    /// assigned through one binding is seen through every other.
+    Array(ArrayRef),
+    /// Structs are shared by reference like arrays.
+    Struct(StructRef),
+    /// Maps from strings to values, shared by reference like arrays.
+    Map(MapRef),
+    /// The absence of a value, held by variables of optional type.
+    Null,
+    /// A variant of a declared enum: the enum's name and the variant's.
+    Enum(Symbol, Symbol),
+}
+
+impl Value {
+    /// The name used for this value's type in runtime errors, matching the
+    /// type checker's spelling.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Int(_) => "int",
+            Value::Float(_) => "float",
+            Value::Bool(_) => "bool",
+            Value::Str(_) => "string",
+            Value::Char(_) => "char",
+            Value::Array(_) => "array",
+            Value::Struct(_) => "struct",
+            Value::Map(_) => "map",
+            Value::Null => "null",
+            Value::Enum(..) => "enum",
+        }
+    }
+
+    /// Approximate bytes this value keeps alive on the heap. Arrays, structs
+    /// and maps already in `seen` are shared with something measured before
+    /// and count as 0.
+    pub(crate) fn heap_size(&self, seen: &mut HashSet<*const ()>) -> usize {
+        match self {
+            Value::Int(_) | Value::Float(_) | Value::Bool(_) | Value::Char(_) | Value::Null | Value::Enum(..) => 0,
+            Value::Str(s) => s.capacity(),
+            Value::Array(elements) => {
+                if !seen.insert(Rc::as_ptr(elements).cast()) {
+                    return 0;
+                }
+                let elements = elements.borrow();
+                let own = elements.capacity() * std::mem::size_of::<Value>();
+                own + elements.iter().map(|element| element.heap_size(seen)).sum::<usize>()
+            }
+            Value::Struct(instance) => {
+                if !seen.insert(Rc::as_ptr(instance).cast()) {
+                    return 0;
+                }
+                let instance = instance.borrow();
+                let own = instance.fields.capacity() * std::mem::size_of::<(Symbol, Value)>();
+                own + instance.fields.iter().map(|(_, value)| value.heap_size(seen)).sum::<usize>()
+            }
+            Value::Map(entries) => {
+                if !seen.insert(Rc::as_ptr(entries).cast()) {
+                    return 0;
+                }
+                let entries = entries.borrow();
+                let own = entries.len() * std::mem::size_of::<(String, Value)>();
+                own + entries.iter().map(|(key, value)| key.capacity() + value.heap_size(seen)).sum::<usize>()
+            }
+        }
+    }
+}
+
+/// Compares two shared values by what they hold; the same allocation is
+/// equal to itself. A pair already in `visited` is being compared further
+/// up, so it is taken as equal, which lets values that contain themselves
+/// be compared without recursing forever.
+fn shared_equals<T>(
+    a: &Rc<RefCell<T>>,
+    b: &Rc<RefCell<T>>,
+    visited: &mut Visited,
+    equals: impl FnOnce(&T, &T, &mut Visited) -> bool,
+) -> bool {
+    Rc::ptr_eq(a, b) || !visited.insert((Rc::as_ptr(a).cast(), Rc::as_ptr(b).cast())) || equals(&a.borrow(), &b.borrow(), visited)
+}
+
+impl Value {
+    fn equals(&self, other: &Value, visited: &mut Visited) -> bool {
+        match (self, other) {
+            (Value::Int(a), Value::Int(b)) => a == b,
+            (Value::Float(a), Value::Float(b)) => a == b,
+            (Value::Bool(a), Value::Bool(b)) => a == b,
+            (Value::Str(a), Value::Str(b)) => a == b,
+            (Value::Char(a), Value::Char(b)) => a == b,
+            (Value::Null, Value::Null) => true,
+            (Value::Enum(a, x), Value::Enum(b, y)) => a == b && x == y,
+            (Value::Array(a), Value::Array(b)) => shared_equals(a, b, visited, |a, b, visited| {
+                a.len() == b.len() && a.iter().zip(b).all(|(x, y)| x.equals(y, visited))
+            }),
+            (Value::Struct(a), Value::Struct(b)) => shared_equals(a, b, visited, |a, b, visited| a.equals(b, visited)),
+            (Value::Map(a), Value::Map(b)) => shared_equals(a, b, visited, |a, b, visited| {
+                a.len() == b.len() && a.iter().zip(b).all(|((j, x), (k, y))| j == k && x.equals(y, visited))
+            }),
+            _ => false,
+        }
+    }
+
+    /// Writes the value as `Display` does, with an array, struct or map
+    /// that contains itself shown as `[...]`, `Name { ... }` or `{...}`
+    /// where it recurs. `ancestors` holds the ones being written.
+    fn write(&self, f: &mut fmt::Formatter<'_>, ancestors: &mut Vec<*const ()>) -> fmt::Result {
+        match self {
+            Value::Array(elements) => {
+                if ancestors.contains(&Rc::as_ptr(elements).cast()) {
+                    return write!(f, "[...]");
+                }
+                ancestors.push(Rc::as_ptr(elements).cast());
+                write!(f, "[")?;
+                for (i, element) in elements.borrow().iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    element.write(f, ancestors)?;
+                }
+                ancestors.pop();
+                write!(f, "]")
+            }
+            Value::Struct(instance) => {
+                let pointer = Rc::as_ptr(instance).cast();
+                let instance = instance.borrow();
+                if ancestors.contains(&pointer) {
+                    return write!(f, "{} {{ ... }}", instance.name);
+                }
+                ancestors.push(pointer);
+                write!(f, "{} {{", instance.name)?;
+                for (i, (name, value)) in instance.fields.iter().enumerate() {
+                    let separator = if i > 0 { ", " } else { " " };
+                    write!(f, "{}{}: ", separator, name)?;
+                    value.write(f, ancestors)?;
+                }
+                ancestors.pop();
+                write!(f, "{}}}", if instance.fields.is_empty() { "" } else { " " })
+            }
+            Value::Map(entries) => {
+                if ancestors.contains(&Rc::as_ptr(entries).cast()) {
+                    return write!(f, "{{...}}");
+                }
+                ancestors.push(Rc::as_ptr(entries).cast());
+                write!(f, "{{")?;
+                for (i, (key, value)) in entries.borrow().iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{:?}: ", key)?;
+                    value.write(f, ancestors)?;
+                }
+                ancestors.pop();
+                write!(f, "}}")
+            }
+            other => write!(f, "{}", other),
+        }
+    }
+}
+
+impl PartialEq for Value {
+    fn eq(&self, other: &Value) -> bool {
+        self.equals(other, &mut HashSet::new())
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Int(n) => write!(f, "{}", n),
+            Value::Float(n) => write!(f, "{:?}", n),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Str(s) => write!(f, "{}", s),
+            Value::Char(c) => write!(f, "{}", c),
+            Value::Null => write!(f, "null"),
+            Value::Enum(name, variant) => write!(f, "{}::{}", name, variant),
+            Value::Array(_) | Value::Struct(_) | Value::Map(_) => self.write(f, &mut Vec::new()),
+        }
+    }
+}
\ No newline at end of file
@@ -0,0 +1,225 @@
+use crate::builtins::{self, Io};
+use crate::bytecode::{Instr, Module};
+use crate::error::CompilerError;
+use crate::interpreter::DEFAULT_MAX_CALL_DEPTH;
+use crate::ops::{self, OverflowMode};
+use crate::symbol::Symbol;
+use crate::value::{Struct, Value};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+use std::rc::Rc;
+
+/// An activation record: which function is running, where, and where its
+/// locals start on the value stack.
+struct Frame {
+    function: usize,
+    ip: usize,
+    base: usize,
+}
+
+/// Runs [`Module`]s produced by [`crate::bytecode::compile`]. It gives the
+/// same results as the [`crate::Interpreter`], but calls do not recurse on
+/// the native stack, so deep recursion is limited only by the call depth.
+pub struct Vm {
+    io: Io,
+    max_call_depth: usize,
+    overflow: OverflowMode,
+}
+
+impl Default for Vm {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Vm {
+    pub fn new() -> Self {
+        Self { io: Io::default(), max_call_depth: DEFAULT_MAX_CALL_DEPTH, overflow: OverflowMode::default() }
+    }
+
+    /// Limits how deeply user functions may recurse before the program fails
+    /// with a "stack overflow" runtime error.
+    pub fn with_max_call_depth(mut self, max_call_depth: usize) -> Self {
+        self.max_call_depth = max_call_depth;
+        self
+    }
+
+    /// Chooses how integer overflow is handled; checked by default.
+    pub fn with_overflow_mode(mut self, overflow: OverflowMode) -> Self {
+        self.overflow = overflow;
+        self
+    }
+
+    /// Sends `print`/`println` output to `output` instead of standard output.
+    pub fn with_output(mut self, output: impl Write + 'static) -> Self {
+        self.io.set_output(output);
+        self
+    }
+
+    /// Makes `read_int`/`read_line` read from `input` instead of standard input.
+    pub fn with_input(mut self, input: impl Read + 'static) -> Self {
+        self.io.set_input(input);
+        self
+    }
+
+    /// Seeds the generator behind `random`, so the program draws the same
+    /// numbers on every run; by default the seed comes from the clock.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.io.set_seed(seed);
+        self
+    }
+
+    /// Runs the module's top-level code to completion.
+    pub fn run(&mut self, module: &Module) -> Result<(), CompilerError> {
+        let result = self.execute(module);
+        // Output printed before a runtime error still reaches the sink.
+        let flushed = self.io.flush();
+        result?;
+        flushed.map_err(|msg| CompilerError::RuntimeError(msg, None))
+    }
+
+    fn execute(&mut self, module: &Module) -> Result<(), CompilerError> {
+        let mut globals: Vec<Option<Value>> = vec![None; module.globals.len()];
+        let mut stack: Vec<Value> = Vec::new();
+        let mut frames = vec![Frame { function: module.main, ip: 0, base: 0 }];
+        stack.resize(module.functions[module.main].locals, Value::Int(0));
+
+        loop {
+            let frame = frames.last_mut().expect("main returns before its frame is popped");
+            let function = &module.functions[frame.function];
+            let instr = &function.code[frame.ip];
+            let span = function.spans[frame.ip];
+            frame.ip += 1;
+            let error = |msg: String| CompilerError::RuntimeError(msg, Some(span));
+
+            match instr {
+                Instr::Const(i) => stack.push(module.constants[*i].clone()),
+                Instr::Pop => {
+                    stack.pop();
+                }
+                Instr::Dup => {
+                    let top = stack.last().expect("stack underflow").clone();
+                    stack.push(top);
+                }
+                Instr::Load(slot) => stack.push(stack[frame.base + slot].clone()),
+                Instr::Store(slot) => {
+                    let value = pop(&mut stack);
+                    stack[frame.base + slot] = value;
+                }
+                Instr::LoadGlobal(i) => match &globals[*i] {
+                    Some(value) => stack.push(value.clone()),
+                    None => return Err(error(format!("Undefined variable: {}", module.globals[*i]))),
+                },
+                Instr::StoreGlobal(i) => {
+                    let value = pop(&mut stack);
+                    match &mut globals[*i] {
+                        Some(slot) => *slot = value,
+                        None => return Err(error(format!("Undefined variable: {}", module.globals[*i]))),
+                    }
+                }
+                Instr::DefineGlobal(i) => globals[*i] = Some(pop(&mut stack)),
+                Instr::Binary(op) => {
+                    let r = pop(&mut stack);
+                    let l = pop(&mut stack);
+                    stack.push(ops::binary(self.overflow, *op, l, r, span)?);
+                }
+                Instr::Neg => {
+                    let value = pop(&mut stack);
+                    stack.push(ops::negate(self.overflow, value, span)?);
+                }
+                Instr::BitNot => {
+                    let value = pop(&mut stack);
+                    stack.push(ops::bit_not(value, span)?);
+                }
+                Instr::Update(op) => {
+                    let value = pop(&mut stack);
+                    stack.push(Value::Int(ops::update(self.overflow, *op, &value, span)?));
+                }
+                Instr::Jump(target) => frame.ip = *target,
+                Instr::JumpIfFalse(target) => match pop(&mut stack) {
+                    Value::Bool(true) => {}
+                    Value::Bool(false) => frame.ip = *target,
+                    other => return Err(error(format!("Condition must be a boolean, found {}", other.type_name()))),
+                },
+                Instr::Call(id, argc) => {
+                    let callee = &module.functions[*id];
+                    if *argc != callee.arity {
+                        return Err(error("Incorrect argument count".to_string()));
+                    }
+                    if frames.len() > self.max_call_depth {
+                        return Err(error("stack overflow".to_string()));
+                    }
+                    let base = stack.len() - argc;
+                    stack.resize(base + callee.locals, Value::Int(0));
+                    frames.push(Frame { function: *id, ip: 0, base });
+                }
+                Instr::CallBuiltin(b, argc) => {
+                    let args = stack.split_off(stack.len() - argc);
+                    let result = (builtins::BUILTINS[*b].call)(&mut self.io, args).map_err(error)?;
+                    stack.push(result);
+                }
+                Instr::Return => {
+                    let result = pop(&mut stack);
+                    let finished = frames.pop().expect("returning frame exists");
+                    if frames.is_empty() {
+                        return Ok(());
+                    }
+                    stack.truncate(finished.base);
+                    stack.push(result);
+                }
+                Instr::MakeArray(n) => {
+                    let elements = stack.split_off(stack.len() - n);
+                    stack.push(Value::Array(Rc::new(RefCell::new(elements))));
+                }
+                Instr::Index => {
+                    let index = pop(&mut stack);
+                    let array = pop(&mut stack);
+                    let slot = ops::checked_index(array, index, span, span)?;
+                    stack.push(slot.get(span)?);
+                }
+                Instr::StoreIndex => {
+                    let value = pop(&mut stack);
+                    let index = pop(&mut stack);
+                    let array = pop(&mut stack);
+                    ops::checked_index(array, index, span, span)?.set(value);
+                }
+                Instr::MakeMap(n) => {
+                    let values = stack.split_off(stack.len() - 2 * n);
+                    let mut entries = BTreeMap::new();
+                    for pair in values.chunks_exact(2) {
+                        entries.insert(ops::map_key(pair[0].clone(), span)?, pair[1].clone());
+                    }
+                    stack.push(Value::Map(Rc::new(RefCell::new(entries))));
+                }
+                Instr::MakeStruct(s) => {
+                    let layout = &module.structs[*s];
+                    let values = stack.split_off(stack.len() - layout.fields.len());
+                    let fields = layout.fields.iter().copied().zip(values).collect();
+                    stack.push(Value::Struct(Rc::new(RefCell::new(Struct { name: layout.name, fields }))));
+                }
+                Instr::GetField(i) => {
+                    let object = pop(&mut stack);
+                    stack.push(ops::get_field(object, field_name(module, *i), span)?);
+                }
+                Instr::SetField(i) => {
+                    let value = pop(&mut stack);
+                    let object = pop(&mut stack);
+                    ops::set_field(object, field_name(module, *i), value, span)?;
+                }
+            }
+        }
+    }
+}
+
+/// The field name a `GetField` or `SetField` refers to.
+fn field_name(module: &Module, i: usize) -> Symbol {
+    match &module.constants[i] {
+        Value::Str(name) => Symbol::intern(name),
+        other => unreachable!("field names are string constants, found {:?}", other),
+    }
+}
+
+fn pop(stack: &mut Vec<Value>) -> Value {
+    stack.pop().expect("stack underflow")
+}
\ No newline at end of file
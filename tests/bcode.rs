@@ -0,0 +1,101 @@
+//! Reading `.bcode` files.
+
+use ferrum::bcode;
+use ferrum::bytecode::{self, Function, Instr, Module};
+use ferrum::value::Value;
+use ferrum::{Arena, Span, Vm};
+use std::cell::RefCell;
+use std::io::Write;
+use std::rc::Rc;
+
+fn function(name: &str, arity: usize, code: Vec<Instr>) -> Function {
+    let spans = vec![Span::default(); code.len()];
+    Function { name: name.to_string(), arity, locals: arity, code, spans }
+}
+
+fn module(functions: Vec<Function>) -> Module {
+    Module { constants: vec![Value::Int(1), Value::Bool(true)], functions, globals: Vec::new(), structs: Vec::new(), main: 0 }
+}
+
+#[derive(Clone, Default)]
+struct Output(Rc<RefCell<Vec<u8>>>);
+
+impl Write for Output {
+    fn write(&mut self, bytes: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().write(bytes)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// What `module` prints, followed by the error that stopped it, if any.
+fn run(module: &Module) -> String {
+    let output = Output::default();
+    let result = Vm::new().with_output(output.clone()).run(module);
+    let mut printed = String::from_utf8(output.0.take()).unwrap();
+    if let Err(e) = result {
+        printed.push_str(&e.to_string());
+    }
+    printed
+}
+
+/// Why decoding the encoded `module` fails.
+fn rejection(module: &Module) -> String {
+    bcode::decode(&bcode::encode(module)).expect_err("the file is rejected").to_string()
+}
+
+#[test]
+fn popping_an_empty_stack_is_rejected() {
+    let main = function("<main>", 0, vec![Instr::Pop, Instr::Return]);
+    assert!(rejection(&module(vec![main])).contains("stack underflow at instruction 0 of <main>"));
+}
+
+#[test]
+fn underflow_on_a_jumped_to_path_is_rejected() {
+    let code = vec![Instr::Const(1), Instr::JumpIfFalse(4), Instr::Const(0), Instr::Return, Instr::Binary(ferrum::ast::BinOp::Add), Instr::Return];
+    let main = function("<main>", 0, code);
+    assert!(rejection(&module(vec![main])).contains("stack underflow at instruction 4 of <main>"));
+}
+
+#[test]
+fn paths_must_agree_on_the_stack_depth() {
+    let code = vec![Instr::Const(1), Instr::JumpIfFalse(3), Instr::Const(0), Instr::Const(0), Instr::Return];
+    let main = function("<main>", 0, code);
+    assert!(rejection(&module(vec![main])).contains("different depths"));
+}
+
+#[test]
+fn calls_must_pass_the_callee_its_arguments() {
+    let main = function("<main>", 0, vec![Instr::Const(0), Instr::Call(1, 1), Instr::Return]);
+    let callee = function("f", 2, vec![Instr::Load(0), Instr::Return]);
+    assert!(rejection(&module(vec![main, callee])).contains("wrong number of arguments"));
+    let len = ferrum::builtins::BUILTINS.iter().position(|builtin| builtin.name == "len").unwrap();
+    let main = function("<main>", 0, vec![Instr::CallBuiltin(len, 0), Instr::Return]);
+    assert!(rejection(&module(vec![main])).contains("wrong number of arguments"));
+}
+
+#[test]
+fn a_decoded_module_runs_like_the_original() {
+    let source = "\
+struct P { x: int, y: float }
+enum Color { Red, Green }
+const NAMES = { \"a\": [1, 2], \"b\": [] };
+fn describe(p: P, c: Color): string {
+    if (c == Color::Red) { return format(\"{} {}\", p.x, p.y); }
+    return \"green\";
+}
+let total = 0;
+for i in 0..=3 { total = total + i; }
+println(describe(P { x: 1, y: -0.5 }, Color::Red), describe(P { x: 0, y: 0.0 }, Color::Green), total, NAMES, 'c', null);
+println([1, 2, 3][5]);
+";
+    let arena = Arena::new();
+    let module = bytecode::compile(&ferrum::compile_str(source, &arena).unwrap()).unwrap();
+    let decoded = bcode::decode(&bcode::encode(&module)).unwrap();
+    let printed = run(&module);
+    assert_eq!(printed, "1 -0.5 green 6 {\"a\": [1, 2], \"b\": []} c null\nRuntime error at line 11, column 19: Index 5 out of bounds for array of length 3");
+    assert_eq!(run(&decoded), printed);
+    assert_eq!(bcode::encode(&decoded), bcode::encode(&module));
+}
@@ -0,0 +1,107 @@
+//! Reusing compiled functions across compilations.
+
+use ferrum::bytecode::{self, Module};
+use ferrum::cache::{self, CacheStats, CompileCache};
+use ferrum::{Arena, Vm};
+use std::cell::RefCell;
+use std::io::Write;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+/// An empty cache directory of its own for each test.
+fn cache_dir(test: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("ferrum-cache-{}-{}", std::process::id(), test));
+    let _ = std::fs::remove_dir_all(&dir);
+    dir
+}
+
+fn compile(source: &str, cache: &CompileCache) -> (Module, CacheStats) {
+    let arena = Arena::new();
+    let program = ferrum::compile_str(source, &arena).unwrap();
+    bytecode::compile_cached(&program, cache).unwrap()
+}
+
+#[derive(Clone, Default)]
+struct Output(Rc<RefCell<Vec<u8>>>);
+
+impl Write for Output {
+    fn write(&mut self, bytes: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().write(bytes)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// What `module` prints, or its error.
+fn run(module: &Module) -> String {
+    let output = Output::default();
+    match Vm::new().with_output(output.clone()).run(module) {
+        Ok(()) => String::from_utf8(output.0.take()).unwrap(),
+        Err(e) => e.to_string(),
+    }
+}
+
+const PROGRAM: &str = "\
+struct P { x: int, y: int }
+enum Color { Red, Green }
+fn sq(n: int): int { return n * n; }
+fn dist(p: P): int { return sq(p.x) + sq(p.y); }
+fn origin(): P { return P { y: 0, x: 0 }; }
+fn name(c: Color): string { if (c == Color::Red) { return \"red\"; } return \"green\"; }
+println(dist(P { x: 3, y: 4 }), dist(origin()), name(Color::Green), -0.0);
+";
+
+#[test]
+fn unchanged_functions_are_reused() {
+    let cache = CompileCache::new(cache_dir("unchanged"));
+    let (first, stats) = compile(PROGRAM, &cache);
+    assert_eq!(stats, CacheStats { reused: 0, compiled: 4 });
+    let (second, stats) = compile(PROGRAM, &cache);
+    assert_eq!(stats, CacheStats { reused: 4, compiled: 0 });
+    assert_eq!(run(&first), "25 0 green -0.0\n");
+    assert_eq!(run(&second), run(&first));
+}
+
+#[test]
+fn only_changed_functions_are_compiled_again() {
+    let cache = CompileCache::new(cache_dir("changed"));
+    compile(PROGRAM, &cache);
+    let changed = PROGRAM.replace("return n * n;", "return n * n + 1;");
+    let (module, stats) = compile(&changed, &cache);
+    assert_eq!(stats, CacheStats { reused: 3, compiled: 1 });
+    assert_eq!(run(&module), "27 2 green -0.0\n");
+}
+
+#[test]
+fn reused_functions_report_errors_where_they_now_are() {
+    let cache = CompileCache::new(cache_dir("moved"));
+    let source = "fn at(a: [int], i: int): int { return a[i]; }\nprintln(at([1], 0));\nprintln(at([1], 5));\n";
+    compile(source, &cache);
+    let moved = format!("let unused = 0;\n\n{}", source.replace("{ return", "{\n    return"));
+    let (module, stats) = compile(&moved, &cache);
+    assert_eq!(stats, CacheStats { reused: 1, compiled: 0 });
+    assert!(run(&module).contains("line 4, column 14"), "{}", run(&module));
+}
+
+#[test]
+fn entries_that_no_longer_fit_are_not_used() {
+    let cache = CompileCache::new(cache_dir("stale"));
+    compile(PROGRAM, &cache);
+    let reordered = PROGRAM.replace("struct P { x: int, y: int }", "struct P { y: int, x: int }");
+    let (module, stats) = compile(&reordered, &cache);
+    assert_eq!(stats, CacheStats { reused: 3, compiled: 1 });
+    assert_eq!(run(&module), "25 0 green -0.0\n");
+}
+
+#[test]
+fn hashes_ignore_layout_but_not_names() {
+    let hashes = |source: &str| {
+        let arena = Arena::new();
+        cache::function_hashes(&ferrum::compile_str(source, &arena).unwrap())
+    };
+    let compact = hashes("fn f(a) { return a + 1; }");
+    assert_eq!(hashes("fn f(a)\n{\n    return (a + 1);\n}\n"), compact);
+    assert_ne!(hashes("fn f(b) { return b + 1; }"), compact);
+}
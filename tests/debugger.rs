@@ -0,0 +1,33 @@
+//! Running a program under the debugger.
+
+use ferrum::debugger::Debugger;
+use ferrum::{Arena, CompilerError, Interpreter};
+use std::cell::RefCell;
+use std::io::Write;
+use std::rc::Rc;
+
+#[derive(Clone, Default)]
+struct Output(Rc<RefCell<Vec<u8>>>);
+
+impl Write for Output {
+    fn write(&mut self, bytes: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().write(bytes)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn quit_stops_the_program_without_an_error() {
+    let source = "println(1);\nprintln(2);\n";
+    let arena = Arena::new();
+    let program = ferrum::compile_str(source, &arena).unwrap();
+    let debugger = Debugger::new(source).with_input("step\nquit\n".as_bytes()).with_output(std::io::sink());
+    let output = Output::default();
+    let result = Interpreter::new().with_hook(debugger).with_output(output.clone()).interpret(&program);
+    assert!(matches!(result, Err(CompilerError::Stopped(..))), "{:?}", result);
+    assert_eq!(result.unwrap_err().exit_code(), 0);
+    assert_eq!(String::from_utf8(output.0.take()).unwrap(), "1\n");
+}
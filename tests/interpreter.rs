@@ -0,0 +1,122 @@
+//! What a program evaluates to when it finishes, and what it prints.
+
+use ferrum::{Arena, Interpreter, InterpreterOptions, OverflowMode, TypeChecker, Value};
+use std::cell::RefCell;
+use std::io::Write;
+use std::rc::Rc;
+
+#[derive(Clone, Default)]
+struct Output(Rc<RefCell<Vec<u8>>>);
+
+impl Write for Output {
+    fn write(&mut self, bytes: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().write(bytes)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// What `source`, which must type-check, prints when run by an interpreter
+/// set up by `configure`, followed by the error that stopped it, if any.
+fn output(source: &str, configure: impl for<'a> FnOnce(Interpreter<'a>) -> Interpreter<'a>) -> String {
+    let arena = Arena::new();
+    let program = ferrum::compile_str(source, &arena).unwrap();
+    TypeChecker::new().check_program(&program).expect("the program type-checks");
+    let out = Output::default();
+    let result = configure(Interpreter::new()).with_output(out.clone()).interpret(&program);
+    let mut printed = String::from_utf8(out.0.take()).unwrap();
+    if let Err(e) = result {
+        printed.push_str(&e.to_string());
+    }
+    printed
+}
+
+#[test]
+fn return_without_a_value_yields_nothing() {
+    assert_eq!(ferrum::run_str("return;").unwrap(), None);
+    assert_eq!(ferrum::run_str("if (true) { return; }\n5;").unwrap(), None);
+    assert_eq!(ferrum::run_str("while (true) { return; }").unwrap(), None);
+    assert_eq!(ferrum::run_str("if (true) { return 7; }").unwrap(), Some(Value::Int(7)));
+}
+
+#[test]
+fn a_function_without_a_return_value_yields_zero() {
+    let source = "fn f() { return; }\nreturn f();";
+    assert_eq!(ferrum::run_str(source).unwrap(), Some(Value::Int(0)));
+}
+
+#[test]
+fn typeof_names_the_runtime_type() {
+    let source = "let x: int? = null;\nreturn format(\"{} {} {}\", typeof(1), typeof([\"a\"]), typeof(x));";
+    assert_eq!(ferrum::run_str(source).unwrap(), Some(Value::Str("int array null".to_string())));
+}
+
+#[test]
+fn a_struct_that_contains_itself_prints_and_compares() {
+    let source = "\
+struct N { v: int, next: N? }
+fn cycle(v: int): N { let n = N { v: v, next: null }; n.next = n; return n; }
+let a = cycle(1);
+return format(\"{} {} {} {}\", a, a == a, a == cycle(1), a == cycle(2));";
+    let expected = "N { v: 1, next: N { ... } } true true false";
+    assert_eq!(ferrum::run_str(source).unwrap(), Some(Value::Str(expected.to_string())));
+}
+
+#[test]
+fn a_match_used_as_a_value_yields_the_arm_that_matches() {
+    let source = "\
+enum Color { Red, Green }
+fn name(c: Color): string {
+    match (c) {
+        Color::Red => { \"red\" },
+        Color::Green => { let s = \"gr\"; s + \"een\" }
+    }
+}
+let n = match (2) { 1 => { 10 }, 2 => { 20 }, _ => { 30 } };
+return format(\"{} {} {}\", name(Color::Red), name(Color::Green), n);";
+    assert_eq!(ferrum::run_str(source).unwrap(), Some(Value::Str("red green 20".to_string())));
+}
+
+#[test]
+fn print_and_println_write_to_the_output() {
+    let source = "print(1, \"a\");\nprint('b');\nprintln();\nprintln(2.5, true, [1, 2], null);";
+    assert_eq!(output(source, |interpreter| interpreter), "1 ab\n2.5 true [1, 2] null\n");
+}
+
+#[test]
+fn overflow_follows_the_mode() {
+    let source = "println(1);\nprintln(9223372036854775807 + 1);";
+    assert_eq!(output(source, |interpreter| interpreter.with_overflow_mode(OverflowMode::Wrap)), "1\n-9223372036854775808\n");
+    assert_eq!(output(source, |interpreter| interpreter.with_overflow_mode(OverflowMode::Saturate)), "1\n9223372036854775807\n");
+    assert!(output(source, |interpreter| interpreter).starts_with("1\nRuntime error at line 2, column 9: Integer overflow"));
+}
+
+#[test]
+fn limits_stop_a_runaway_program() {
+    let endless = "println(\"start\");\nlet i = 0;\nwhile (true) { i = i + 1; }";
+    let limited = |options: InterpreterOptions| output(endless, move |interpreter| interpreter.with_options(options));
+    let steps = limited(InterpreterOptions { max_steps: Some(1000), ..InterpreterOptions::default() });
+    assert!(steps.starts_with("start\nLimit exceeded"), "{}", steps);
+    let millis = limited(InterpreterOptions { max_millis: Some(10), ..InterpreterOptions::default() });
+    assert!(millis.starts_with("start\nLimit exceeded"), "{}", millis);
+    let growing = "let s = \"x\";\nwhile (true) { s = s + s; }";
+    let options = InterpreterOptions { max_memory: Some(1 << 20), ..InterpreterOptions::default() };
+    assert!(output(growing, |interpreter| interpreter.with_options(options)).starts_with("Limit exceeded"));
+}
+
+#[test]
+fn a_value_checked_against_null_can_be_used() {
+    let source = "\
+fn next(x: int?): int { if (x == null) { return 0; } return x + 1; }
+let y: int? = null;
+println(next(y), next(2), y == null);";
+    assert_eq!(output(source, |interpreter| interpreter), "0 3 true\n");
+}
+
+#[test]
+fn constants_are_visible_everywhere_they_are_in_scope() {
+    let source = "const K = 3;\nfn twice(): int { return K * 2; }\nprintln(K, twice());";
+    assert_eq!(output(source, |interpreter| interpreter), "3 6\n");
+}
@@ -0,0 +1,85 @@
+//! How the lexer splits source text into tokens.
+
+use ferrum::{Lexer, Symbol, Token};
+
+/// The tokens of `source`, or the message of the error that stopped the
+/// lexer.
+fn lex(source: &str) -> Result<Vec<Token>, String> {
+    match Lexer::new(source).tokenize() {
+        Ok(tokens) => Ok(tokens.into_iter().map(|t| t.token).collect()),
+        Err(e) => Err(e.message().to_string()),
+    }
+}
+
+#[test]
+fn numbers_in_every_radix() {
+    assert_eq!(lex("0xFF"), Ok(vec![Token::Number(255)]));
+    assert_eq!(lex("0xff"), Ok(vec![Token::Number(255)]));
+    assert_eq!(lex("0o755"), Ok(vec![Token::Number(0o755)]));
+    assert_eq!(lex("0b1010"), Ok(vec![Token::Number(10)]));
+    assert_eq!(lex("1_000_000"), Ok(vec![Token::Number(1_000_000)]));
+    assert_eq!(lex("0xFF_FF"), Ok(vec![Token::Number(0xFFFF)]));
+    assert_eq!(lex("1_000.5"), Ok(vec![Token::Float(1000.5)]));
+}
+
+#[test]
+fn digits_must_belong_to_the_radix() {
+    assert_eq!(lex("0b102"), Err("Invalid digit '2' in binary literal".to_string()));
+    assert_eq!(lex("0o8"), Err("Invalid digit '8' in octal literal".to_string()));
+    assert_eq!(lex("0x1G"), Err("Invalid digit 'G' in hexadecimal literal".to_string()));
+}
+
+#[test]
+fn a_prefix_needs_digits() {
+    assert_eq!(lex("0x"), Err("Expected digits in hexadecimal literal".to_string()));
+    assert_eq!(lex("0o;"), Err("Expected digits in octal literal".to_string()));
+    assert_eq!(lex("0b_"), Err("Expected digits in binary literal".to_string()));
+}
+
+#[test]
+fn ints_must_fit_in_64_bits() {
+    let out_of_range = Err("integer literal out of range".to_string());
+    assert_eq!(lex("9223372036854775807"), Ok(vec![Token::Number(i64::MAX)]));
+    assert_eq!(lex("9223372036854775808"), out_of_range);
+    assert_eq!(lex("0x7FFF_FFFF_FFFF_FFFF"), Ok(vec![Token::Number(i64::MAX)]));
+    assert_eq!(lex("0x8000_0000_0000_0000"), out_of_range);
+    assert_eq!(lex("0o777777777777777777777"), Ok(vec![Token::Number(i64::MAX)]));
+    assert_eq!(lex("0o1000000000000000000000"), out_of_range);
+    assert_eq!(lex(&format!("0b{}", "1".repeat(63))), Ok(vec![Token::Number(i64::MAX)]));
+    assert_eq!(lex(&format!("0b1{}", "0".repeat(63))), out_of_range);
+    // A fraction makes the literal a float, which has room for it.
+    assert_eq!(lex("9223372036854775808.0"), Ok(vec![Token::Float(9223372036854775808.0)]));
+}
+
+#[test]
+fn identifiers_in_other_scripts() {
+    let ident = |name: &str| Token::Ident(Symbol::intern(name));
+    assert_eq!(lex("let имя = 1;").unwrap()[1], ident("имя"));
+    assert_eq!(lex("fn 合計(数) {}").unwrap()[1..4], [ident("合計"), Token::LParen, ident("数")]);
+    assert_eq!(lex("λ_2 + Ωμέγα").unwrap(), [ident("λ_2"), Token::Plus, ident("Ωμέγα")]);
+}
+
+#[test]
+fn names_are_compared_after_nfc() {
+    // "café" with a precomposed 'é', then with 'e' and a combining acute.
+    let composed = lex("caf\u{e9}").unwrap();
+    let decomposed = lex("cafe\u{301}").unwrap();
+    assert_eq!(composed, decomposed);
+    assert_eq!(composed, [Token::Ident(Symbol::intern("caf\u{e9}"))]);
+}
+
+#[test]
+fn keywords_are_ascii_only() {
+    // A Cyrillic 'е' in "let" and a Greek 'ο' in "for".
+    assert_eq!(lex("l\u{435}t").unwrap(), [Token::Ident(Symbol::intern("l\u{435}t"))]);
+    assert_eq!(lex("f\u{3bf}r").unwrap(), [Token::Ident(Symbol::intern("f\u{3bf}r"))]);
+    assert_eq!(lex("let for").unwrap(), [Token::Let, Token::For]);
+}
+
+#[test]
+fn the_keyword_list_matches_the_lexer() {
+    for &keyword in ferrum::lexer::KEYWORDS {
+        let tokens = lex(keyword).unwrap();
+        assert!(matches!(tokens[..], [ref token] if !matches!(token, Token::Ident(_))), "{}", keyword);
+    }
+}
@@ -0,0 +1,52 @@
+//! Input nested too deeply for the later stages is rejected by the parser.
+
+use ferrum::{Arena, Lexer, Parser, TypeChecker, analysis, printer};
+
+/// The messages of the syntax errors in `source`.
+fn syntax_errors(source: &str) -> Vec<String> {
+    let arena = Arena::new();
+    match Parser::from_lexer(Lexer::new(source), &arena).parse_program() {
+        Ok(_) => Vec::new(),
+        Err(errors) => errors.iter().map(|e| e.message().to_string()).collect(),
+    }
+}
+
+/// Runs `f` on a thread with as much stack as the main thread of `ferrum`
+/// gets, 8 MiB, rather than the 2 MiB of a test thread.
+fn on_main_stack(f: impl FnOnce() + Send + 'static) {
+    let thread = std::thread::Builder::new().stack_size(8 << 20);
+    thread.spawn(f).unwrap().join().unwrap();
+}
+
+const TOO_DEEP: [&str; 1] = ["Nested more than 200 levels deep"];
+
+#[test]
+fn long_chains_count_as_nesting() {
+    for (operand, link) in [("1", " + 1"), ("1", " * 1"), ("true", " || true"), ("1", " << 1"), ("a", "[0]"), ("a", ".x")] {
+        let source = format!("let x = {}{};", operand, link.repeat(5000));
+        assert_eq!(syntax_errors(&source), TOO_DEEP, "{}", link);
+    }
+}
+
+#[test]
+fn parentheses_do_not_reset_the_count() {
+    // 100 groups of 99 additions: a tree 9900 levels deep, though no chain
+    // or group is long on its own.
+    let mut expr = "1".to_string();
+    for _ in 0..100 {
+        expr = format!("({}){}", expr, " + 1".repeat(99));
+    }
+    on_main_stack(move || assert_eq!(syntax_errors(&format!("let x = {};", expr)), TOO_DEEP));
+}
+
+#[test]
+fn the_longest_chain_can_be_checked_and_printed() {
+    let source = format!("let x = 1{};\nprintln(x);", " + 1".repeat(199));
+    on_main_stack(move || {
+        let arena = Arena::new();
+        let program = ferrum::compile_str(&source, &arena).unwrap();
+        assert!(analysis::check_program(&program).is_empty());
+        assert!(TypeChecker::new().check_program(&program).is_ok());
+        printer::print_program(&program);
+    });
+}
@@ -0,0 +1,33 @@
+//! The functions loaded ahead of a program.
+
+use ferrum::{Arena, TypeChecker, Value, codegen_c, codegen_wasm, prelude};
+
+const SOURCE: &str = "println(abs(-3) + min(4, 9) + max(1, 2) + pow(2, 10) + clamp(50, 0, 10));";
+
+#[test]
+fn the_backends_compile_the_math_builtins_from_the_prelude() {
+    let arena = Arena::new();
+    let mut program = ferrum::compile_str(SOURCE, &arena).unwrap();
+    let functions = prelude::program(1, &arena, &program);
+    program.splice(0..0, functions);
+    let math = prelude::math(2, &arena, &program);
+    program.splice(0..0, math);
+    let mut checker = TypeChecker::new();
+    checker.check_program(&program).unwrap();
+    assert!(codegen_c::generate(&program, &checker).unwrap().contains("f_pow("));
+    assert!(codegen_wasm::generate(&program, &checker).is_ok());
+}
+
+#[test]
+fn a_program_keeps_its_own_definitions() {
+    let arena = Arena::new();
+    let program = ferrum::compile_str("fn abs(n: int): int { return n; }", &arena).unwrap();
+    let math = prelude::math(1, &arena, &program);
+    assert_eq!(math.len(), 3);
+}
+
+#[test]
+fn the_builtin_pow_rejects_a_negative_exponent() {
+    assert!(ferrum::run_str("return pow(2, -1);").is_err());
+    assert_eq!(ferrum::run_str("return pow(2.0, -1.0);").unwrap(), Some(Value::Float(0.5)));
+}
@@ -0,0 +1,46 @@
+//! Printing a program and parsing the result gives back the same program.
+
+use ferrum::{Arena, Lexer, Parser, diff, printer};
+use std::fs;
+use std::path::Path;
+
+/// Parses `source`, prints it, and parses the printed text again. The two
+/// parses must agree, and printing the second must give the same text.
+/// `name` says which program failed.
+fn assert_round_trip(source: &str, name: &str) {
+    let arena = Arena::new();
+    let parsed = Parser::from_lexer(Lexer::new(source), &arena)
+        .parse_program()
+        .unwrap_or_else(|errors| panic!("{} does not parse: {:?}", name, errors));
+    let printed = printer::print_program(&parsed);
+    let reparsed = Parser::from_lexer(Lexer::new(&printed), &arena)
+        .parse_program()
+        .unwrap_or_else(|errors| panic!("{} printed does not parse: {:?}\n{}", name, errors, printed));
+    assert_eq!(diff::diff_programs(&parsed, &reparsed), [], "{} changed when printed:\n{}", name, printed);
+    assert_eq!(printer::print_program(&reparsed), printed, "printing {} is not stable", name);
+}
+
+#[test]
+fn example_programs_round_trip() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let mut paths = vec![root.join("examples/demo.lang")];
+    for entry in fs::read_dir(root.join("benches/programs")).unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().is_some_and(|e| e == "lang") {
+            paths.push(path);
+        }
+    }
+    for path in paths {
+        assert_round_trip(&fs::read_to_string(&path).unwrap(), &path.display().to_string());
+    }
+}
+
+#[test]
+fn match_expressions_round_trip() {
+    let source = "\
+fn f(n: int): int { match (n) { 0 => { 1 }, _ => { let m = n * 2; m } } }
+let x = 1 + match (f(2)) { 4 => { 10 } _ => { 20 } };
+(match (x) { 11 => { 1 }, _ => { 2 } });
+";
+    assert_round_trip(source, "a match expression");
+}
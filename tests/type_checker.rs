@@ -0,0 +1,52 @@
+//! Programs the type checker must reject before they can fail at runtime.
+
+use ferrum::{Arena, TypeChecker};
+
+/// The messages of the type errors in `source`, which must parse.
+fn type_errors(source: &str) -> Vec<String> {
+    let arena = Arena::new();
+    let program = ferrum::compile_str(source, &arena).expect("the program parses");
+    match TypeChecker::new().check_program(&program) {
+        Ok(()) => Vec::new(),
+        Err(errors) => errors.iter().map(|e| e.message().to_string()).collect(),
+    }
+}
+
+#[test]
+fn ordering_needs_an_ordered_type() {
+    for source in ["1 < 2;", "1.5 >= 2.5;", "\"a\" <= \"b\";", "'a' > 'b';"] {
+        assert_eq!(type_errors(source), Vec::<String>::new(), "{}", source);
+    }
+    assert_eq!(type_errors("true < false;"), ["Operator '<' is not defined for bool"]);
+}
+
+#[test]
+fn containers_have_no_order() {
+    let source = "struct P { x: int }\n[1] < [2];\nP { x: 1 } < P { x: 2 };\n{ \"a\": 1 } >= { \"a\": 2 };\n[1] == [2];";
+    assert_eq!(
+        type_errors(source),
+        [
+            "Operator '<' is not defined for [int]; compare them with '==' or '!='",
+            "Operator '<' is not defined for P; compare them with '==' or '!='",
+            "Operator '>=' is not defined for {string: int}; compare them with '==' or '!='",
+        ]
+    );
+}
+
+#[test]
+fn the_arms_of_a_match_used_as_a_value_agree() {
+    assert_eq!(type_errors("let x = match (1) { 1 => { 2 }, _ => { 3 } };\nx;"), Vec::<String>::new());
+    assert_eq!(type_errors("let x = match (1) { 1 => { 2 }, _ => { \"3\" } };\nx;"), ["Arms of 'match' must have the same type, found int and string"]);
+    assert_eq!(type_errors("let x = match (1) { 1 => { 2 } };\nx;"), ["Match must have a wildcard arm `_ => { ... }`"]);
+}
+
+#[test]
+fn nullable_values_are_checked_before_use() {
+    assert_eq!(type_errors("let x: int? = null;\nx + 1;"), ["Value of type int? may be null; compare it with null first"]);
+    assert_eq!(type_errors("let x: int? = null;\nif (x != null) { x + 1; }"), Vec::<String>::new());
+}
+
+#[test]
+fn constants_cannot_be_reassigned() {
+    assert_eq!(type_errors("const K = 3;\nK = 4;"), ["Cannot assign to constant K"]);
+}